@@ -0,0 +1,30 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rand::Rng;
+use replicache_client::util::uuid::{make_random_numbers, uuid};
+
+// How make_random_numbers filled the buffer before, for comparison.
+fn make_random_numbers_bytewise(numbers: &mut [u8]) {
+    for n in numbers.iter_mut() {
+        *n = rand::thread_rng().gen();
+    }
+}
+
+fn bench_random_numbers(c: &mut Criterion) {
+    let mut group = c.benchmark_group("make_random_numbers");
+    group.bench_function("bytewise", |b| {
+        let mut numbers = [0u8; 36];
+        b.iter(|| make_random_numbers_bytewise(black_box(&mut numbers)))
+    });
+    group.bench_function("fill", |b| {
+        let mut numbers = [0u8; 36];
+        b.iter(|| make_random_numbers(black_box(&mut numbers)).unwrap())
+    });
+    group.finish();
+}
+
+fn bench_uuid(c: &mut Criterion) {
+    c.bench_function("uuid", |b| b.iter(|| uuid().unwrap()));
+}
+
+criterion_group!(benches, bench_random_numbers, bench_uuid);
+criterion_main!(benches);
@@ -57,6 +57,18 @@ impl<'a> Read<'_> {
         }
         Ok(None)
     }
+
+    // Returns the names of all the heads in the store, in key order.
+    pub async fn head_names(&self) -> Result<Vec<String>> {
+        let prefix = Key::Head("").to_string();
+        Ok(self
+            .kvr
+            .keys(&prefix)
+            .await?
+            .into_iter()
+            .map(|key| key[prefix.len()..].to_string())
+            .collect())
+    }
 }
 
 #[cfg(test)]
@@ -119,4 +131,26 @@ mod tests {
         test(vec![1], &vec![], true).await;
         test(vec![1], &vec!["r1", "r2"], false).await;
     }
+
+    #[async_std::test]
+    async fn test_head_names() {
+        let kv = MemStore::new();
+        let kvw = kv.write(LogContext::new()).await.unwrap();
+        for name in &["sync", "main", "sync-org"] {
+            kvw.put(&Key::Head(name).to_string(), b"hash")
+                .await
+                .unwrap();
+        }
+        kvw.put(&Key::ChunkData("hash").to_string(), &[1])
+            .await
+            .unwrap();
+        kvw.commit().await.unwrap();
+
+        let kvr = kv.read(LogContext::new()).await.unwrap();
+        let r = Read { kvr: kvr.as_ref() };
+        assert_eq!(
+            vec!["main", "sync", "sync-org"],
+            r.head_names().await.unwrap()
+        );
+    }
 }
@@ -21,6 +21,18 @@ impl Store {
         Ok(Write::new(self.kv.write(lc).await?))
     }
 
+    // Returns the underlying kv store, for the few bits of metadata that are
+    // kept outside the dag (eg the client id). Keys must not collide with the
+    // dag's own (see dag::Key); such metadata lives under "sys/".
+    pub fn kv(&self) -> &dyn kv::Store {
+        self.kv.as_ref()
+    }
+
+    // See kv::Store::flush.
+    pub async fn flush(&self) -> Result<()> {
+        Ok(self.kv.flush().await?)
+    }
+
     pub async fn close(&self) {
         self.kv.close().await;
     }
@@ -43,7 +43,8 @@ impl<'a> Write<'_> {
         read::Read::new(self.kvw.as_read())
     }
 
-    pub async fn put_chunk(&mut self, c: &Chunk) -> Result<()> {
+    // Takes &self so that independent chunks can be put concurrently.
+    pub async fn put_chunk(&self, c: &Chunk) -> Result<()> {
         let data_key = Key::ChunkData(c.hash()).to_string();
         try_join!(
             self.kvw.put(&data_key, c.data()).map_err(Error::Storage),
@@ -225,7 +226,7 @@ mod tests {
         async fn test(data: &[u8], refs: &[&str]) {
             let kv = MemStore::new();
             let kvw = kv.write(LogContext::new()).await.unwrap();
-            let mut w = Write::new(kvw);
+            let w = Write::new(kvw);
 
             let c = Chunk::new((data.to_vec(), 0), refs);
             w.put_chunk(&c).await.unwrap();
@@ -333,7 +334,7 @@ mod tests {
             let kv = MemStore::new();
             {
                 let kvw = kv.write(LogContext::new()).await.unwrap();
-                let mut w = Write::new(kvw);
+                let w = Write::new(kvw);
                 let c = Chunk::new((vec![0, 1], 0), &vec![]);
                 w.put_chunk(&c).await.unwrap();
 
@@ -367,7 +368,7 @@ mod tests {
             let c = Chunk::new((data.to_vec(), 0), refs);
             {
                 let kvw = kv.write(LogContext::new()).await.unwrap();
-                let mut w = Write::new(kvw);
+                let w = Write::new(kvw);
                 w.put_chunk(&c).await.unwrap();
                 w.set_head(name, Some(c.hash())).await.unwrap();
 
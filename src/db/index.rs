@@ -44,7 +44,7 @@ impl Index {
 
     // Note: does not update self.meta.value_hash (doesn't need to at this point as flush
     // is only called during commit.)
-    pub async fn flush(&self, write: &mut dag::Write<'_>) -> Result<String, IndexFlushError> {
+    pub async fn flush(&self, write: &dag::Write<'_>) -> Result<String, IndexFlushError> {
         use IndexFlushError::*;
         let mut guard = self.map.write().await;
         match &mut *guard {
@@ -0,0 +1,446 @@
+use super::commit::{self, Commit, IndexDefinition, MetaTyped};
+use super::write::{reset_db, InitDBError};
+use crate::dag;
+use crate::prolly;
+use crate::util::rlog::LogContext;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashSet;
+
+// ChunkKind says how to decode a chunk. Chunks do not record their own type,
+// so a tool walking the dag has to know it from where the hash came from:
+// heads and basis hashes point at commits, and value hashes (of a commit or
+// of one of its indexes) point at leaves.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum ChunkKind {
+    #[serde(rename = "commit")]
+    Commit,
+    #[serde(rename = "leaf")]
+    Leaf,
+    #[serde(rename = "raw")]
+    Raw,
+}
+
+// InspectedChunk is the structured form of a chunk, for tooling that needs to
+// look below the map API (eg to diagnose a corrupt store).
+#[derive(Debug, PartialEq, Serialize)]
+pub struct InspectedChunk {
+    pub hash: String,
+    pub refs: Vec<String>,
+    #[serde(flatten)]
+    pub contents: ChunkContents,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "type")]
+pub enum ChunkContents {
+    #[serde(rename = "commit")]
+    Commit {
+        #[serde(rename = "valueHash")]
+        value_hash: String,
+        #[serde(rename = "basisHash")]
+        basis_hash: Option<String>,
+        meta: serde_json::Value,
+        // Index name and value hash of each index.
+        indexes: Vec<(String, String)>,
+    },
+    // Keys and values are decoded lossily as UTF-8 since index keys are
+    // binary.
+    #[serde(rename = "leaf")]
+    Leaf { entries: Vec<(String, String)> },
+    #[serde(rename = "raw")]
+    Raw { data: Vec<u8> },
+}
+
+// Returns the chunk with the given hash as stored, without decoding it.
+pub async fn get_chunk(
+    store: &dag::Store,
+    hash: &str,
+    lc: LogContext,
+) -> Result<Option<dag::Chunk>, GetChunkError> {
+    use GetChunkError::*;
+    let read = store.read(lc).await.map_err(ReadError)?;
+    let chunk = read.read().get_chunk(hash).await.map_err(GetError)?;
+    Ok(chunk)
+}
+
+pub fn inspect_chunk(
+    chunk: dag::Chunk,
+    kind: ChunkKind,
+) -> Result<InspectedChunk, InspectChunkError> {
+    use InspectChunkError::*;
+    let hash = chunk.hash().to_string();
+    let refs = chunk.refs().map(str::to_string).collect();
+    let contents = match kind {
+        ChunkKind::Commit => {
+            let commit = Commit::from_chunk(chunk).map_err(InvalidCommit)?;
+            let meta = commit.meta();
+            let typed = match meta.typed() {
+                MetaTyped::IndexChange(icm) => json!({
+                    "type": "indexChange",
+                    "lastMutationID": icm.last_mutation_id(),
+                }),
+                MetaTyped::Local(lm) => json!({
+                    "type": "local",
+                    "mutationID": lm.mutation_id(),
+                    "mutatorName": lm.mutator_name(),
+                    "mutatorArgsJSON": String::from_utf8_lossy(lm.mutator_args_json()),
+                    "originalHash": lm.original_hash(),
+                }),
+                MetaTyped::Snapshot(sm) => json!({
+                    "type": "snapshot",
+                    "lastMutationID": sm.last_mutation_id(),
+                    "cookieJSON": String::from_utf8_lossy(sm.cookie_json()),
+                }),
+            };
+            ChunkContents::Commit {
+                value_hash: commit.value_hash().to_string(),
+                basis_hash: meta.basis_hash().map(str::to_string),
+                meta: typed,
+                indexes: commit
+                    .indexes()
+                    .into_iter()
+                    .map(|idx| (idx.definition.name, idx.value_hash))
+                    .collect(),
+            }
+        }
+        ChunkKind::Leaf => {
+            let map = prolly::Map::from_chunk(chunk).map_err(InvalidLeaf)?;
+            let entries = map
+                .iter()
+                .map(|e| {
+                    (
+                        String::from_utf8_lossy(e.key).into_owned(),
+                        String::from_utf8_lossy(e.val).into_owned(),
+                    )
+                })
+                .collect();
+            ChunkContents::Leaf { entries }
+        }
+        ChunkKind::Raw => ChunkContents::Raw {
+            data: chunk.data().to_vec(),
+        },
+    };
+    Ok(InspectedChunk {
+        hash,
+        refs,
+        contents,
+    })
+}
+
+// CorruptChunk reports a chunk that is reachable from a head but that is
+// missing from the store or cannot be decoded as the kind it is referenced as.
+// This happens when the browser evicts part of the store under storage
+// pressure or a write was only partially persisted.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct CorruptChunk {
+    pub hash: String,
+    pub kind: ChunkKind,
+    // None if the chunk is missing, otherwise why it failed to decode.
+    pub error: Option<String>,
+}
+
+// Walks every commit reachable from the given heads, along with the value
+// and index maps of each, and reports the chunks that are missing or corrupt.
+// The walk does not stop at the first problem: a missing commit ends the walk
+// of that chain, but all of the chunks before it are still checked. The walk
+// of a chain ends at its first snapshot, whose basis is only weakly held and
+// may have been collected. Heads that do not exist are skipped.
+pub async fn verify(
+    store: &dag::Store,
+    head_names: &[&str],
+    lc: LogContext,
+) -> Result<Vec<CorruptChunk>, VerifyError> {
+    use VerifyError::*;
+    let read = store.read(lc).await.map_err(ReadError)?;
+    let read = read.read();
+
+    let mut corrupt = Vec::new();
+    let mut seen = HashSet::new();
+    for head_name in head_names {
+        let mut next = read.get_head(head_name).await.map_err(GetHeadError)?;
+        while let Some(hash) = next.take() {
+            if !seen.insert(hash.clone()) {
+                break;
+            }
+            let chunk = read.get_chunk(&hash).await.map_err(GetChunkError)?;
+            let error = match chunk.map(Commit::from_chunk) {
+                Some(Ok(commit)) => {
+                    let value_hashes = std::iter::once(commit.value_hash().to_string())
+                        .chain(commit.indexes().into_iter().map(|idx| idx.value_hash));
+                    for value_hash in value_hashes {
+                        if seen.insert(value_hash.clone()) {
+                            let chunk = read.get_chunk(&value_hash).await.map_err(GetChunkError)?;
+                            check_leaf(value_hash, chunk, &mut corrupt);
+                        }
+                    }
+                    if !commit.meta().is_snapshot() {
+                        next = commit.meta().basis_hash().map(str::to_string);
+                    }
+                    continue;
+                }
+                Some(Err(e)) => Some(format!("{:?}", e)),
+                None => None,
+            };
+            corrupt.push(CorruptChunk {
+                hash,
+                kind: ChunkKind::Commit,
+                error,
+            });
+        }
+    }
+    Ok(corrupt)
+}
+
+// Repairs the head head_name after verify found corrupt chunks under it, by
+// resetting it to an empty snapshot that the next pull fills in again (see
+// reset_db). The new snapshot takes the last mutation id of the head's base
+// snapshot, which is the last one the server acknowledged, so that the next
+// pull does not look like time travel to the client. Index definitions are
+// kept if the head commit can still be read. Local mutations that were not
+// yet pulled back are lost. Returns the hash of the new commit.
+pub async fn repair_head(
+    store: &dag::Store,
+    head_name: &str,
+    lc: LogContext,
+) -> Result<String, RepairHeadError> {
+    use RepairHeadError::*;
+    let write = store.write(lc).await.map_err(WriteError)?;
+    let read = write.read();
+    let (last_mutation_id, definitions) =
+        match read.get_head(head_name).await.map_err(GetHeadError)? {
+            Some(head) => salvage(&head, &read).await,
+            None => (0, vec![]),
+        };
+    reset_db(write, head_name, last_mutation_id, definitions)
+        .await
+        .map_err(ResetError)
+}
+
+async fn salvage(head: &str, read: &dag::Read<'_>) -> (u64, Vec<IndexDefinition>) {
+    let last_mutation_id = match Commit::base_snapshot(head, read).await {
+        Ok(snapshot) => snapshot.mutation_id(),
+        Err(_) => 0,
+    };
+    let definitions = match Commit::from_hash(head, read).await {
+        Ok(commit) => commit
+            .indexes()
+            .into_iter()
+            .map(|idx| idx.definition)
+            .collect(),
+        Err(_) => vec![],
+    };
+    (last_mutation_id, definitions)
+}
+
+fn check_leaf(hash: String, chunk: Option<dag::Chunk>, corrupt: &mut Vec<CorruptChunk>) {
+    let error = match chunk.map(prolly::Map::from_chunk) {
+        Some(Ok(_)) => return,
+        Some(Err(e)) => Some(format!("{:?}", e)),
+        None => None,
+    };
+    corrupt.push(CorruptChunk {
+        hash,
+        kind: ChunkKind::Leaf,
+        error,
+    });
+}
+
+#[derive(Debug)]
+pub enum GetChunkError {
+    GetError(dag::Error),
+    ReadError(dag::Error),
+}
+
+#[derive(Debug)]
+pub enum VerifyError {
+    GetChunkError(dag::Error),
+    GetHeadError(dag::Error),
+    ReadError(dag::Error),
+}
+
+#[derive(Debug)]
+pub enum RepairHeadError {
+    GetHeadError(dag::Error),
+    ResetError(InitDBError),
+    WriteError(dag::Error),
+}
+
+#[derive(Debug)]
+pub enum InspectChunkError {
+    InvalidCommit(commit::LoadError),
+    InvalidLeaf(prolly::LoadError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_helpers::*;
+    use crate::db::{OwnedRead, ScanError, ScanOptions, Whence, DEFAULT_HEAD_NAME};
+    use crate::kv::memstore::MemStore;
+    use str_macro::str;
+
+    #[async_std::test]
+    async fn test_inspect_chunk() {
+        let store = dag::Store::new(Box::new(MemStore::new()));
+        let mut chain: Chain = vec![];
+        add_genesis(&mut chain, &store).await;
+        add_local(&mut chain, &store).await;
+        let commit = &chain[1];
+
+        assert_eq!(
+            None,
+            get_chunk(&store, "nope", LogContext::new()).await.unwrap()
+        );
+        let get = |hash: String| {
+            let store = &store;
+            async move {
+                get_chunk(store, &hash, LogContext::new())
+                    .await
+                    .unwrap()
+                    .unwrap()
+            }
+        };
+
+        let chunk = get(commit.chunk().hash().to_string()).await;
+        let inspected = inspect_chunk(chunk, ChunkKind::Commit).unwrap();
+        assert_eq!(commit.chunk().hash(), inspected.hash);
+        assert!(inspected.refs.contains(&commit.value_hash().to_string()));
+        assert_eq!(
+            ChunkContents::Commit {
+                value_hash: commit.value_hash().to_string(),
+                basis_hash: Some(chain[0].chunk().hash().to_string()),
+                meta: json!({
+                    "type": "local",
+                    "mutationID": 1,
+                    "mutatorName": "mutator_name_1",
+                    "mutatorArgsJSON": "[1]",
+                    "originalHash": null,
+                }),
+                indexes: vec![],
+            },
+            inspected.contents
+        );
+
+        let chunk = get(commit.value_hash().to_string()).await;
+        let inspected = inspect_chunk(chunk, ChunkKind::Leaf).unwrap();
+        assert_eq!(
+            ChunkContents::Leaf {
+                entries: vec![(str!("local"), str!("\"1\""))],
+            },
+            inspected.contents
+        );
+
+        let chunk = get(commit.value_hash().to_string()).await;
+        let data = chunk.data().to_vec();
+        let inspected = inspect_chunk(chunk, ChunkKind::Raw).unwrap();
+        assert_eq!(ChunkContents::Raw { data }, inspected.contents);
+    }
+
+    #[async_std::test]
+    async fn test_verify() {
+        let store = dag::Store::new(Box::new(MemStore::new()));
+        let mut chain: Chain = vec![];
+        add_genesis(&mut chain, &store).await;
+        add_local(&mut chain, &store).await;
+        add_snapshot(&mut chain, &store, None).await;
+        add_local(&mut chain, &store).await;
+        add_index_change(&mut chain, &store).await;
+        let heads = &[DEFAULT_HEAD_NAME, "broken", "absent"];
+        assert_eq!(
+            Vec::<CorruptChunk>::new(),
+            verify(&store, heads, LogContext::new()).await.unwrap()
+        );
+
+        // Overwrite the index map with garbage and point a head at a commit
+        // that does not exist.
+        let index_hash = chain[4].indexes()[0].value_hash.clone();
+        let w = store.write(LogContext::new()).await.unwrap();
+        w.put_chunk(&dag::Chunk::read(index_hash.clone(), vec![1, 2, 3], None))
+            .await
+            .unwrap();
+        w.set_head("broken", Some("nope")).await.unwrap();
+        w.commit().await.unwrap();
+
+        let corrupt = verify(&store, heads, LogContext::new()).await.unwrap();
+        assert_eq!(2, corrupt.len());
+        assert_eq!(index_hash, corrupt[0].hash);
+        assert_eq!(ChunkKind::Leaf, corrupt[0].kind);
+        assert!(corrupt[0].error.is_some());
+        assert_eq!(
+            CorruptChunk {
+                hash: str!("nope"),
+                kind: ChunkKind::Commit,
+                error: None,
+            },
+            corrupt[1]
+        );
+
+        // Scanning the index names the chunk rather than failing opaquely.
+        let read = OwnedRead::from_whence(
+            Whence::Head(str!(DEFAULT_HEAD_NAME)),
+            store.read(LogContext::new()).await.unwrap(),
+        )
+        .await
+        .unwrap();
+        let opts = || ScanOptions {
+            prefix: None,
+            start_secondary_key: None,
+            start_key: None,
+            start_exclusive: None,
+            limit: None,
+            index_name: Some(str!("4")),
+        };
+        match read.as_read().scan(opts(), |_| {}).await {
+            Err(ScanError::CorruptChunk(hash)) => assert_eq!(index_hash, hash),
+            r => panic!("unexpected {:?}", r),
+        }
+        drop(read);
+
+        // Repairing resets the heads but keeps the last acknowledged mutation
+        // id and index definitions of those that can still be read.
+        for head_name in &[DEFAULT_HEAD_NAME, "broken"] {
+            repair_head(&store, head_name, LogContext::new())
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            Vec::<CorruptChunk>::new(),
+            verify(&store, heads, LogContext::new()).await.unwrap()
+        );
+        let read = store.read(LogContext::new()).await.unwrap();
+        let get_commit = |head_name: &'static str| {
+            let read = &read;
+            async move {
+                let hash = read.read().get_head(head_name).await.unwrap().unwrap();
+                Commit::from_hash(&hash, &read.read()).await.unwrap()
+            }
+        };
+        let main = get_commit(DEFAULT_HEAD_NAME).await;
+        assert!(main.meta().is_snapshot());
+        assert_eq!(None, main.meta().basis_hash());
+        // The base snapshot's last mutation id is kept, not the head's local
+        // one, which the server has not acknowledged.
+        assert_eq!(chain[2].mutation_id(), main.mutation_id());
+        assert_ne!(0, main.mutation_id());
+        assert!(main.mutation_id() < chain[4].mutation_id());
+        let names: Vec<_> = main
+            .indexes()
+            .into_iter()
+            .map(|idx| idx.definition.name)
+            .collect();
+        assert_eq!(vec![str!("4")], names);
+        assert!(get_commit("broken").await.indexes().is_empty());
+        drop(read);
+        let read = OwnedRead::from_whence(
+            Whence::Head(str!(DEFAULT_HEAD_NAME)),
+            store.read(LogContext::new()).await.unwrap(),
+        )
+        .await
+        .unwrap();
+        read.as_read()
+            .scan(opts(), |_| panic!("not empty"))
+            .await
+            .unwrap();
+    }
+}
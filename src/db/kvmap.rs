@@ -0,0 +1,201 @@
+use super::commit::{BaseSnapshotError, Commit, InternalProgrammerError};
+use super::read::{read_commit, read_indexes, ReadCommitError, ScanError, Whence};
+use super::scan::{ScanOptions, ScanResult};
+use super::write::{init_db, CommitError, DelError, InitDBError, PutError, Write};
+use crate::dag;
+use crate::kv;
+use crate::util::rlog::LogContext;
+
+// KeyValueMap is a plain map over a head, for Rust callers that want to
+// read and write values without dealing with commits, chunks and dag
+// transactions. Dropping it discards its changes. Each commit is
+// a snapshot that keeps the last mutation id and cookie of the head, so it
+// is meant for heads of one's own rather than ones with pending mutations
+// (eg the default head of a Replicache db).
+pub struct KeyValueMap<'a> {
+    write: Write<'a>,
+    head_name: String,
+    lc: LogContext,
+}
+
+impl<'a> KeyValueMap<'a> {
+    // Opens the map at head_name in the dag kept in store, creating an empty
+    // one if the head does not exist yet. The map holds the dag's write lock
+    // (that is, store's) from open until it is committed or dropped, so other
+    // writers, including other maps over the same store, wait until then.
+    pub async fn open(
+        store: &'a dyn kv::Store,
+        head_name: &str,
+        lc: LogContext,
+    ) -> Result<KeyValueMap<'a>, KeyValueMapOpenError> {
+        use KeyValueMapOpenError::*;
+        let open_write = |lc| async move {
+            Ok(dag::Write::new(
+                store.write(lc).await.map_err(dag::Error::from)?,
+            ))
+        };
+        let mut dag_write = open_write(lc.clone()).await.map_err(DagWriteError)?;
+        let head = dag_write
+            .read()
+            .get_head(head_name)
+            .await
+            .map_err(GetHeadError)?;
+        let head = match head {
+            Some(head) => head,
+            None => {
+                let head = init_db(dag_write, head_name).await.map_err(InitError)?;
+                dag_write = open_write(lc.clone()).await.map_err(DagWriteError)?;
+                head
+            }
+        };
+
+        let read = dag_write.read();
+        let (_, basis, _) = read_commit(Whence::Hash(head.clone()), &read)
+            .await
+            .map_err(ReadError)?;
+        let snapshot = Commit::base_snapshot(&head, &read)
+            .await
+            .map_err(NoBaseSnapshot)?;
+        let (_, cookie) = Commit::snapshot_meta_parts(&snapshot).map_err(NotASnapshot)?;
+        let (last_mutation_id, indexes) = (basis.mutation_id(), read_indexes(&basis));
+        let write = Write::new_snapshot(
+            Whence::Hash(head),
+            last_mutation_id,
+            cookie,
+            dag_write,
+            indexes,
+        )
+        .await
+        .map_err(ReadError)?;
+        Ok(KeyValueMap {
+            write,
+            head_name: head_name.to_string(),
+            lc,
+        })
+    }
+
+    pub fn has(&self, key: &[u8]) -> bool {
+        self.write.as_read().has(key)
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.write.get(key)
+    }
+
+    pub async fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), PutError> {
+        self.write
+            .put(self.lc.clone(), key.to_vec(), value.to_vec())
+            .await
+    }
+
+    pub async fn del(&mut self, key: &[u8]) -> Result<(), DelError> {
+        self.write.del(self.lc.clone(), key.to_vec()).await
+    }
+
+    // Calls callback with each entry the scan yields, in key order. Scans see
+    // uncommitted changes.
+    pub async fn scan(
+        &self,
+        opts: ScanOptions,
+        callback: impl Fn(ScanResult<'_>),
+    ) -> Result<(), ScanError> {
+        self.write.as_read().scan(opts, callback).await
+    }
+
+    // Commits the changes and points the head at them. Returns the hash of
+    // the new commit.
+    pub async fn commit(self) -> Result<String, CommitError> {
+        self.write.commit(&self.head_name).await
+    }
+}
+
+#[derive(Debug)]
+pub enum KeyValueMapOpenError {
+    DagWriteError(dag::Error),
+    GetHeadError(dag::Error),
+    InitError(InitDBError),
+    NoBaseSnapshot(BaseSnapshotError),
+    NotASnapshot(InternalProgrammerError),
+    ReadError(ReadCommitError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::memstore::MemStore;
+    use crate::kv::Store;
+    use std::cell::RefCell;
+    use str_macro::str;
+
+    async fn exercise(kv: Box<dyn Store>) {
+        let store = kv.as_ref();
+
+        // A new head starts out empty.
+        let mut m = KeyValueMap::open(store, "kv", LogContext::new())
+            .await
+            .unwrap();
+        assert!(!m.has(b"a"));
+        m.put(b"a", b"1").await.unwrap();
+        m.put(b"b", b"2").await.unwrap();
+        m.put(b"c", b"3").await.unwrap();
+        m.del(b"b").await.unwrap();
+        assert_eq!(Some(&b"1"[..]), m.get(b"a"));
+        assert_eq!(None, m.get(b"b"));
+        let first = m.commit().await.unwrap();
+
+        // Dropping discards changes.
+        let mut m = KeyValueMap::open(store, "kv", LogContext::new())
+            .await
+            .unwrap();
+        m.put(b"d", b"4").await.unwrap();
+        drop(m);
+
+        let mut m = KeyValueMap::open(store, "kv", LogContext::new())
+            .await
+            .unwrap();
+        assert!(!m.has(b"d"));
+        m.put(b"ab", b"5").await.unwrap();
+        let got = RefCell::new(vec![]);
+        m.scan(
+            ScanOptions {
+                prefix: Some(str!("a")),
+                start_secondary_key: None,
+                start_key: None,
+                start_exclusive: None,
+                limit: None,
+                index_name: None,
+            },
+            |r| match r {
+                ScanResult::Item(item) => got
+                    .borrow_mut()
+                    .push((item.key.to_vec(), item.val.to_vec())),
+                ScanResult::Error(e) => panic!("{:?}", e),
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            vec![
+                (b"a".to_vec(), b"1".to_vec()),
+                (b"ab".to_vec(), b"5".to_vec())
+            ],
+            got.into_inner()
+        );
+        let second = m.commit().await.unwrap();
+        assert_ne!(first, second);
+        let read = store.read(LogContext::new()).await.unwrap();
+        let read = dag::Read::new(read.as_ref());
+        assert_eq!(Some(second), read.get_head("kv").await.unwrap());
+    }
+
+    #[async_std::test]
+    async fn test_key_value_map() {
+        exercise(MemStore::new_async().await).await;
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    #[wasm_bindgen_test::wasm_bindgen_test]
+    async fn test_key_value_map_js_store() {
+        exercise(Box::new(crate::kv::jsstore::new_test_store())).await;
+    }
+}
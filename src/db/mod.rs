@@ -2,12 +2,14 @@ mod commit;
 #[allow(warnings)]
 mod commit_generated;
 pub mod index;
+mod inspect;
+mod kvmap;
 mod read;
 mod root;
 mod scan;
 mod write;
 
-#[cfg(test)]
+#[cfg(any(test, feature = "test-helpers"))]
 pub mod test_helpers;
 
 pub use root::{get_root, GetRootError};
@@ -19,9 +21,18 @@ pub use commit::{
 pub use index::{
     decode_index_key, encode_index_key, encode_index_scan_key, GetIndexKeysError, IndexKey,
 };
-pub use read::{read_commit, read_indexes, OwnedRead, Read, ReadCommitError, ScanError, Whence};
+pub use inspect::{
+    get_chunk, inspect_chunk, repair_head, verify, ChunkContents, ChunkKind, CorruptChunk,
+    GetChunkError, InspectChunkError, InspectedChunk, RepairHeadError, VerifyError,
+};
+pub use kvmap::{KeyValueMap, KeyValueMapOpenError};
+pub use read::{
+    changed_since, read_commit, read_indexes, ChangedSinceError, OwnedRead, Read, ReadCommitError,
+    ScanError, Whence,
+};
 pub use scan::{ScanItem, ScanOptions, ScanResult, ScanResultError};
 pub use write::{
-    init_db, ChangedKeysMap, ClearError, CommitError, CreateIndexError, DelError, DropIndexError,
-    InitDBError, PutError, Write,
+    init_db, reset_db, ChangedKeysMap, ClearError, CommitError, CreateIndexError, DelError,
+    DropIndexError, InitDBError, PutError, ResumeSnapshotError, ValueChange, Write,
+    DEFAULT_INDEX_FLUSH_CONCURRENCY,
 };
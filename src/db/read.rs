@@ -1,9 +1,11 @@
 use super::commit::{Commit, FromHashError};
 use super::index;
+use super::write::ValueChange;
 use crate::dag;
 use crate::prolly;
 use std::collections::hash_map::HashMap;
 use std::convert::TryInto;
+use std::string::FromUtf8Error;
 
 #[derive(Debug)]
 pub enum Whence {
@@ -49,6 +51,18 @@ pub async fn read_commit(
     whence: Whence,
     read: &dag::Read<'_>,
 ) -> Result<(String, Commit, prolly::Map), ReadCommitError> {
+    use ReadCommitError::*;
+    let (hash, commit) = resolve_commit(whence, read).await?;
+    let map = prolly::Map::load(commit.value_hash(), read)
+        .await
+        .map_err(MapLoadError)?;
+    Ok((hash, commit, map))
+}
+
+async fn resolve_commit(
+    whence: Whence,
+    read: &dag::Read<'_>,
+) -> Result<(String, Commit), ReadCommitError> {
     use ReadCommitError::*;
     let hash = match whence {
         Whence::Hash(s) => s,
@@ -61,10 +75,49 @@ pub async fn read_commit(
     let commit = Commit::from_hash(&hash, read)
         .await
         .map_err(CommitFromHeadError)?;
-    let map = prolly::Map::load(commit.value_hash(), read)
+    Ok((hash, commit))
+}
+
+// Returns the keys whose values differ between the base and head commits,
+// in key order, with their values in each (eg the local changes to push
+// since the last state the server acknowledged). Commits that share a value
+// map have no changes and their maps are not loaded.
+pub async fn changed_since(
+    base: Whence,
+    head: Whence,
+    read: &dag::Read<'_>,
+) -> Result<Vec<ValueChange>, ChangedSinceError> {
+    use ChangedSinceError::*;
+    let (_, base) = resolve_commit(base, read).await.map_err(ReadBaseError)?;
+    let (_, head) = resolve_commit(head, read).await.map_err(ReadHeadError)?;
+    if base.value_hash() == head.value_hash() {
+        return Ok(vec![]);
+    }
+    let base_map = prolly::Map::load(base.value_hash(), read)
         .await
-        .map_err(MapLoadError)?;
-    Ok((hash, commit, map))
+        .map_err(|e| ReadBaseError(ReadCommitError::MapLoadError(e)))?;
+    let head_map = prolly::Map::load(head.value_hash(), read)
+        .await
+        .map_err(|e| ReadHeadError(ReadCommitError::MapLoadError(e)))?;
+    let string = |v: Option<&[u8]>| v.map(|v| String::from_utf8(v.to_vec())).transpose();
+    prolly::Map::changes(&base_map, &head_map)
+        .into_iter()
+        .map(|(key, old, new)| {
+            Ok(ValueChange {
+                key: String::from_utf8(key.to_vec())?,
+                old_value: string(old)?,
+                new_value: string(new)?,
+            })
+        })
+        .collect::<Result<Vec<_>, FromUtf8Error>>()
+        .map_err(InvalidUtf8)
+}
+
+#[derive(Debug)]
+pub enum ChangedSinceError {
+    InvalidUtf8(FromUtf8Error),
+    ReadBaseError(ReadCommitError),
+    ReadHeadError(ReadCommitError),
 }
 
 pub fn read_indexes(commit: &Commit) -> HashMap<String, index::Index> {
@@ -124,7 +177,14 @@ impl<'a> Read<'a> {
                     .indexes
                     .get(name)
                     .ok_or_else(|| UnknownIndexName(name.to_string()))?;
-                let guard = idx.get_map(&self.dag_read).await.map_err(GetMapError)?;
+                let guard = idx.get_map(&self.dag_read).await.map_err(|e| match e {
+                    // Name the chunk so the caller can tell a damaged store
+                    // (see verify) from a programming error.
+                    index::GetMapError::MapLoadError(
+                        prolly::LoadError::UnknownHash | prolly::LoadError::CorruptChunk(_),
+                    ) => CorruptChunk(idx.meta.value_hash.clone()),
+                    e => GetMapError(e),
+                })?;
                 super::scan::scan(guard.get_map(), opts_internal).for_each(callback)
             }
             None => super::scan::scan(self.map, opts_internal).for_each(callback),
@@ -135,6 +195,7 @@ impl<'a> Read<'a> {
 
 #[derive(Debug)]
 pub enum ScanError {
+    CorruptChunk(String),
     GetMapError(index::GetMapError),
     ScanOptionsError(super::scan::ScanOptionsError),
     UnknownIndexName(String),
@@ -186,4 +247,70 @@ mod tests {
         let val = rr.get("foo".as_bytes());
         assert_eq!(Some("bar".as_bytes()), val);
     }
+
+    #[async_std::test]
+    async fn test_changed_since() {
+        let ds = dag::Store::new(Box::new(MemStore::new()));
+        init_db(
+            ds.write(LogContext::new()).await.unwrap(),
+            db::DEFAULT_HEAD_NAME,
+        )
+        .await
+        .unwrap();
+        let commit = |puts: Vec<(&'static str, &'static str)>, dels: Vec<&'static str>| {
+            let ds = &ds;
+            async move {
+                let mut w = write::Write::new_local(
+                    Whence::Head(str!(db::DEFAULT_HEAD_NAME)),
+                    str!("mutator_name"),
+                    str!("[]"),
+                    None,
+                    ds.write(LogContext::new()).await.unwrap(),
+                )
+                .await
+                .unwrap();
+                for (k, v) in puts {
+                    w.put(LogContext::new(), k.into(), v.into()).await.unwrap();
+                }
+                for k in dels {
+                    w.del(LogContext::new(), k.into()).await.unwrap();
+                }
+                w.commit(db::DEFAULT_HEAD_NAME).await.unwrap()
+            }
+        };
+        let base = commit(vec![("a", "1"), ("b", "2"), ("c", "3")], vec![]).await;
+        let unchanged = commit(vec![("a", "1")], vec![]).await;
+        let head = commit(vec![("a", "10"), ("d", "4")], vec!["b"]).await;
+
+        let dr = ds.read(LogContext::new()).await.unwrap();
+        let read = dr.read();
+        let changes = |base: &str, head: &str| {
+            changed_since(Whence::Hash(str!(base)), Whence::Hash(str!(head)), &read)
+        };
+        assert!(changes(&base, &unchanged).await.unwrap().is_empty());
+        let change = |key: &str, old: Option<&str>, new: Option<&str>| ValueChange {
+            key: str!(key),
+            old_value: old.map(str::to_string),
+            new_value: new.map(str::to_string),
+        };
+        assert_eq!(
+            vec![
+                change("a", Some("1"), Some("10")),
+                change("b", Some("2"), None),
+                change("d", None, Some("4")),
+            ],
+            changes(&base, &head).await.unwrap()
+        );
+        assert!(matches!(
+            changed_since(
+                Whence::Head(str!("nope")),
+                Whence::Hash(head.clone()),
+                &read
+            )
+            .await,
+            Err(ChangedSinceError::ReadBaseError(
+                ReadCommitError::UnknownHead(_)
+            ))
+        ));
+    }
 }
@@ -3,6 +3,9 @@ use super::{commit, index, read, scan, ReadCommitError, Whence};
 use crate::dag;
 use crate::prolly;
 use crate::util::rlog;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use futures::{try_join, TryFutureExt};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::string::FromUtf8Error;
 use str_macro::str;
@@ -36,8 +39,12 @@ pub struct Write<'a> {
     basis: Option<commit::Commit>,
     meta: Meta,
     indexes: HashMap<String, index::Index>,
+    index_flush_concurrency: usize,
 }
 
+// The number of index maps flushed at once by commit by default.
+pub const DEFAULT_INDEX_FLUSH_CONCURRENCY: usize = 8;
+
 #[derive(Debug)]
 pub enum InitDBError {
     CommitError(CommitError),
@@ -46,16 +53,42 @@ pub enum InitDBError {
 // Return value is the hash of the commit.
 #[allow(dead_code)]
 pub async fn init_db(dag_write: dag::Write<'_>, head_name: &str) -> Result<String, InitDBError> {
+    reset_db(dag_write, head_name, 0, vec![]).await
+}
+
+// Points head_name at a new genesis snapshot with no values and a null
+// cookie, so that the next pull fetches everything again. The snapshot keeps
+// last_mutation_id and has an empty index for each of index_definitions.
+// Whatever the head pointed at before is dropped, pending mutations
+// included. Return value is the hash of the commit.
+pub async fn reset_db(
+    dag_write: dag::Write<'_>,
+    head_name: &str,
+    last_mutation_id: u64,
+    index_definitions: Vec<commit::IndexDefinition>,
+) -> Result<String, InitDBError> {
     use InitDBError::*;
+    let indexes = index_definitions
+        .into_iter()
+        .map(|definition| {
+            let name = definition.name.clone();
+            let meta = commit::IndexRecord {
+                definition,
+                value_hash: str!(""),
+            };
+            (name, index::Index::new(meta, Some(prolly::Map::new())))
+        })
+        .collect();
     let w = Write {
         dag_write,
         map: prolly::Map::new(),
         basis: None,
         meta: Meta::Snapshot(SnapshotMeta {
-            last_mutation_id: 0,
+            last_mutation_id,
             cookie: serde_json::Value::default(), // Value::Null()
         }),
-        indexes: HashMap::new(),
+        indexes,
+        index_flush_concurrency: DEFAULT_INDEX_FLUSH_CONCURRENCY,
     };
     w.commit(head_name).await.map_err(CommitError)
 }
@@ -65,6 +98,19 @@ pub async fn init_db(dag_write: dag::Write<'_>, head_name: &str) -> Result<Strin
 // value of the map is the keys that changed in the last pull/mutations.
 pub type ChangedKeysMap = HashMap<String, Vec<String>>;
 
+// ValueChange is a key whose value a commit changed, with its JSON value
+// before and after the commit. A value is None where the key is absent.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ValueChange {
+    pub key: String,
+    #[serde(rename = "oldValue")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old_value: Option<String>,
+    #[serde(rename = "newValue")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_value: Option<String>,
+}
+
 #[allow(dead_code)]
 impl<'a> Write<'a> {
     pub async fn new_local(
@@ -88,6 +134,7 @@ impl<'a> Write<'a> {
                 original_hash,
             }),
             indexes,
+            index_flush_concurrency: DEFAULT_INDEX_FLUSH_CONCURRENCY,
         })
     }
 
@@ -108,6 +155,38 @@ impl<'a> Write<'a> {
                 cookie,
             }),
             indexes,
+            index_flush_concurrency: DEFAULT_INDEX_FLUSH_CONCURRENCY,
+        })
+    }
+
+    // Continues a snapshot that was checkpointed as the commit at whence. The
+    // map, indexes and meta are taken from the checkpoint but its basis is
+    // reused, so the checkpoint itself does not end up in the chain.
+    pub async fn resume_snapshot(
+        whence: Whence,
+        dag_write: dag::Write<'a>,
+    ) -> Result<Write<'a>, ResumeSnapshotError> {
+        use ResumeSnapshotError::*;
+        let (_, checkpoint, map) = read::read_commit(whence, &dag_write.read())
+            .await
+            .map_err(ReadCommitError)?;
+        let (last_mutation_id, cookie) =
+            commit::Commit::snapshot_meta_parts(&checkpoint).map_err(NotASnapshot)?;
+        let basis_hash = checkpoint.meta().basis_hash().ok_or(NoBasis)?.to_string();
+        let basis = commit::Commit::from_hash(&basis_hash, &dag_write.read())
+            .await
+            .map_err(LoadBasisError)?;
+        let indexes = read::read_indexes(&checkpoint);
+        Ok(Write {
+            basis: basis.into(),
+            dag_write,
+            map,
+            meta: Meta::Snapshot(SnapshotMeta {
+                last_mutation_id,
+                cookie,
+            }),
+            indexes,
+            index_flush_concurrency: DEFAULT_INDEX_FLUSH_CONCURRENCY,
         })
     }
 
@@ -124,13 +203,37 @@ impl<'a> Write<'a> {
             map,
             meta: Meta::IndexChange(IndexChangeMeta { last_mutation_id }),
             indexes,
+            index_flush_concurrency: DEFAULT_INDEX_FLUSH_CONCURRENCY,
         })
     }
 
-    pub fn as_read(&'a self) -> super::Read<'a> {
+    pub fn as_read(&self) -> super::Read<'_> {
         super::Read::new(self.dag_write.read(), &self.map, &self.indexes)
     }
 
+    // Sets how many index maps commit flushes at once. Zero is treated as one.
+    pub fn set_index_flush_concurrency(&mut self, n: usize) {
+        self.index_flush_concurrency = n;
+    }
+
+    pub fn dag_read(&self) -> dag::Read<'_> {
+        self.dag_write.read()
+    }
+
+    // Returns the keys whose values this write has changed from the basis.
+    pub fn pending_changed_keys(&self) -> Result<Vec<String>, FromUtf8Error> {
+        self.map.pending_changed_keys()
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.map.get(key)
+    }
+
+    // Returns the value of key in the basis, ignoring changes made by this write.
+    pub fn basis_get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.map.base_get(key)
+    }
+
     pub fn is_rebase(&self) -> bool {
         match &self.meta {
             Meta::Local(lm) => lm.original_hash.is_some(),
@@ -138,6 +241,35 @@ impl<'a> Write<'a> {
         }
     }
 
+    // Returns whether committing this write adds a new local mutation, ie it
+    // is neither a rebase nor a snapshot or index change.
+    pub fn is_local_mutation(&self) -> bool {
+        match &self.meta {
+            Meta::Local(lm) => lm.original_hash.is_none(),
+            _ => false,
+        }
+    }
+
+    // put_if_changed is put, except that if key already has the value val it
+    // does nothing (in particular it does not touch the indexes) and returns
+    // false.
+    pub async fn put_if_changed(
+        &mut self,
+        lc: rlog::LogContext,
+        key: Vec<u8>,
+        val: Vec<u8>,
+    ) -> Result<bool, PutError> {
+        match &self.meta {
+            Meta::Local(_) | Meta::Snapshot(_) => {}
+            _ => return Err(PutError::NotAllowed),
+        }
+        if self.map.get(&key) == Some(&val[..]) {
+            return Ok(false);
+        }
+        self.put(lc, key, val).await?;
+        Ok(true)
+    }
+
     pub async fn put(
         &mut self,
         lc: rlog::LogContext,
@@ -352,46 +484,84 @@ impl<'a> Write<'a> {
 
     // Return value is the hash of the new commit and the diff compared to before the commit.
     pub async fn commit_with_changed_keys(
-        mut self,
+        self,
         head_name: &str,
         generate_changed_keys: bool,
     ) -> Result<(String, ChangedKeysMap), CommitError> {
+        self.commit_with_changes(head_name, generate_changed_keys, false)
+            .await
+            .map(|(hash, changed_keys, _)| (hash, changed_keys))
+    }
+
+    // commit_with_changes is commit_with_changed_keys that can also return
+    // the old and new value of each key the commit changes (in the values,
+    // not the indexes). Both values are still in the map until it is
+    // flushed, so this costs a copy of each changed value but no reads.
+    pub async fn commit_with_changes(
+        mut self,
+        head_name: &str,
+        generate_changed_keys: bool,
+        generate_changed_values: bool,
+    ) -> Result<(String, ChangedKeysMap, Vec<ValueChange>), CommitError> {
         use CommitError::*;
+        let changed_values = if generate_changed_values {
+            let string = |v: Option<&[u8]>| v.map(|v| String::from_utf8(v.to_vec())).transpose();
+            self.map
+                .pending_changes()
+                .map(|(key, old, new)| {
+                    Ok(ValueChange {
+                        key: String::from_utf8(key.to_vec())?,
+                        old_value: string(old)?,
+                        new_value: string(new)?,
+                    })
+                })
+                .collect::<Result<Vec<_>, FromUtf8Error>>()
+                .map_err(InvalidUtf8)?
+        } else {
+            Vec::new()
+        };
         let value_changed_keys = if generate_changed_keys {
             self.map.pending_changed_keys().map_err(InvalidUtf8)?
         } else {
             Vec::new()
         };
-        let value_hash = self
-            .map
-            .flush(&mut self.dag_write)
-            .await
-            .map_err(FlushError)?;
-        let mut index_metas = Vec::new();
         let mut key_changes = ChangedKeysMap::new();
         if !value_changed_keys.is_empty() {
             key_changes.insert(str!(""), value_changed_keys);
         }
-        for (name, index) in self.indexes.into_iter() {
-            {
-                let guard = index
-                    .get_map(&self.dag_write.read())
-                    .await
-                    .map_err(GetMapError)?;
-                let map = guard.get_map();
-                let index_changed_keys = map.pending_changed_keys().map_err(InvalidUtf8)?;
-                if !index_changed_keys.is_empty() {
-                    key_changes.insert(name, index_changed_keys);
-                }
-            }
-            let value_hash = index
-                .flush(&mut self.dag_write)
+        for (name, index) in self.indexes.iter() {
+            let guard = index
+                .get_map(&self.dag_write.read())
                 .await
-                .map_err(IndexFlushError)?;
-            let index::Index { mut meta, .. } = index;
-            meta.value_hash = value_hash;
-            index_metas.push(meta);
+                .map_err(GetMapError)?;
+            let map = guard.get_map();
+            let index_changed_keys = map.pending_changed_keys().map_err(InvalidUtf8)?;
+            if !index_changed_keys.is_empty() {
+                key_changes.insert(name.clone(), index_changed_keys);
+            }
         }
+
+        // The value map and the index maps flush to independent chunks so we
+        // write them concurrently.
+        let dag_write = &self.dag_write;
+        let (value_hash, index_hashes) = try_join!(
+            self.map.flush(dag_write).map_err(FlushError),
+            stream::iter(self.indexes.values())
+                .map(|index| index.flush(dag_write))
+                .buffered(self.index_flush_concurrency.max(1))
+                .map_err(IndexFlushError)
+                .try_collect::<Vec<_>>()
+        )?;
+        let index_metas: Vec<_> = self
+            .indexes
+            .into_iter()
+            .zip(index_hashes)
+            .map(|((_, index), value_hash)| {
+                let index::Index { mut meta, .. } = index;
+                meta.value_hash = value_hash;
+                meta
+            })
+            .collect();
         let basis_hash = self.basis.as_ref().map(|b| b.chunk().hash().to_string());
         let commit = match &self.meta {
             Meta::Local(meta) => {
@@ -461,7 +631,11 @@ impl<'a> Write<'a> {
 
         self.dag_write.commit().await.map_err(DagCommitError)?;
 
-        Ok((commit.chunk().hash().to_string(), key_changes))
+        Ok((
+            commit.chunk().hash().to_string(),
+            key_changes,
+            changed_values,
+        ))
     }
 }
 
@@ -494,6 +668,14 @@ pub enum CommitError {
     SerializeCookieError(serde_json::error::Error),
 }
 
+#[derive(Debug)]
+pub enum ResumeSnapshotError {
+    LoadBasisError(commit::FromHashError),
+    NoBasis,
+    NotASnapshot(commit::InternalProgrammerError),
+    ReadCommitError(ReadCommitError),
+}
+
 #[derive(Debug, PartialEq)]
 pub enum PutError {
     AddNewIndexEntriesError(UpdateIndexesError),
@@ -608,6 +790,47 @@ mod tests {
         assert!(val.is_none());
     }
 
+    #[async_std::test]
+    async fn test_put_if_changed() {
+        let ds = dag::Store::new(Box::new(MemStore::new()));
+        init_db(
+            ds.write(LogContext::new()).await.unwrap(),
+            db::DEFAULT_HEAD_NAME,
+        )
+        .await
+        .unwrap();
+
+        let mut w = Write::new_local(
+            Whence::Head(str!(db::DEFAULT_HEAD_NAME)),
+            str!("mutator_name"),
+            serde_json::Value::Array(vec![]).to_string(),
+            None,
+            ds.write(LogContext::new()).await.unwrap(),
+        )
+        .await
+        .unwrap();
+        for (val, exp) in &[(b"bar", true), (b"bar", false), (b"baz", true)] {
+            let changed = w
+                .put_if_changed(LogContext::new(), b"foo".to_vec(), val.to_vec())
+                .await
+                .unwrap();
+            assert_eq!(*exp, changed);
+        }
+        w.commit(db::DEFAULT_HEAD_NAME).await.unwrap();
+
+        let mut w = Write::new_index_change(
+            Whence::Head(str!(db::DEFAULT_HEAD_NAME)),
+            ds.write(LogContext::new()).await.unwrap(),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            w.put_if_changed(LogContext::new(), b"foo".to_vec(), b"baz".to_vec())
+                .await,
+            Err(PutError::NotAllowed)
+        ));
+    }
+
     #[async_std::test]
     async fn index_commit_type_constraints() {
         let ds = dag::Store::new(Box::new(MemStore::new()));
@@ -714,6 +937,41 @@ mod tests {
         );
     }
 
+    #[async_std::test]
+    async fn test_is_local_mutation() {
+        let ds = dag::Store::new(Box::new(MemStore::new()));
+        init_db(
+            ds.write(LogContext::new()).await.unwrap(),
+            db::DEFAULT_HEAD_NAME,
+        )
+        .await
+        .unwrap();
+
+        for original_hash in [None, Some(str!("original"))] {
+            let rebase = original_hash.is_some();
+            let w = Write::new_local(
+                Whence::Head(str!(db::DEFAULT_HEAD_NAME)),
+                str!("mutator_name"),
+                serde_json::Value::Array(vec![]).to_string(),
+                original_hash,
+                ds.write(LogContext::new()).await.unwrap(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(rebase, w.is_rebase());
+            assert_eq!(!rebase, w.is_local_mutation());
+        }
+
+        let w = Write::new_index_change(
+            Whence::Head(str!(db::DEFAULT_HEAD_NAME)),
+            ds.write(LogContext::new()).await.unwrap(),
+        )
+        .await
+        .unwrap();
+        assert!(!w.is_rebase());
+        assert!(!w.is_local_mutation());
+    }
+
     #[async_std::test]
     async fn test_clear() {
         let lc = rlog::LogContext::new();
@@ -806,6 +1064,133 @@ mod tests {
         );
     }
 
+    #[async_std::test]
+    async fn test_commit_with_changes() {
+        let lc = rlog::LogContext::new();
+        let ds = dag::Store::new(Box::new(MemStore::new()));
+        init_db(ds.write(lc.clone()).await.unwrap(), db::DEFAULT_HEAD_NAME)
+            .await
+            .unwrap();
+        let new_local = || async {
+            Write::new_local(
+                Whence::Head(str!(db::DEFAULT_HEAD_NAME)),
+                str!("mutator_name"),
+                serde_json::Value::Array(vec![]).to_string(),
+                None,
+                ds.write(lc.clone()).await.unwrap(),
+            )
+            .await
+            .unwrap()
+        };
+
+        let mut w = new_local().await;
+        for key in &["a", "b"] {
+            w.put(lc.clone(), key.as_bytes().to_vec(), b"1".to_vec())
+                .await
+                .unwrap();
+        }
+        let (_, _, changed_values) = w
+            .commit_with_changes(db::DEFAULT_HEAD_NAME, false, false)
+            .await
+            .unwrap();
+        assert!(changed_values.is_empty());
+
+        let mut w = new_local().await;
+        w.put(lc.clone(), b"a".to_vec(), b"2".to_vec())
+            .await
+            .unwrap();
+        w.put(lc.clone(), b"b".to_vec(), b"1".to_vec())
+            .await
+            .unwrap();
+        w.del(lc.clone(), b"b".to_vec()).await.unwrap();
+        w.put(lc.clone(), b"c".to_vec(), b"3".to_vec())
+            .await
+            .unwrap();
+        let (_, changed_keys, changed_values) = w
+            .commit_with_changes(db::DEFAULT_HEAD_NAME, true, true)
+            .await
+            .unwrap();
+        assert_eq!(vec!["a", "b", "c"], changed_keys[""]);
+        let change = |key: &str, old: Option<&str>, new: Option<&str>| ValueChange {
+            key: key.to_string(),
+            old_value: old.map(str::to_string),
+            new_value: new.map(str::to_string),
+        };
+        assert_eq!(
+            vec![
+                change("a", Some("1"), Some("2")),
+                change("b", Some("1"), None),
+                change("c", None, Some("3")),
+            ],
+            changed_values
+        );
+    }
+
+    #[async_std::test]
+    async fn test_index_flush_concurrency() {
+        async fn test(concurrency: usize) -> (String, Vec<(String, String)>, ChangedKeysMap) {
+            let lc = rlog::LogContext::new();
+            let ds = dag::Store::new(Box::new(MemStore::new()));
+            init_db(ds.write(lc.clone()).await.unwrap(), db::DEFAULT_HEAD_NAME)
+                .await
+                .unwrap();
+            let mut w = Write::new_index_change(
+                Whence::Head(str!(db::DEFAULT_HEAD_NAME)),
+                ds.write(lc.clone()).await.unwrap(),
+            )
+            .await
+            .unwrap();
+            for name in &["a", "b", "c"] {
+                w.create_index(lc.clone(), name.to_string(), name.as_bytes(), "")
+                    .await
+                    .unwrap();
+            }
+            w.commit(db::DEFAULT_HEAD_NAME).await.unwrap();
+
+            let mut w = Write::new_local(
+                Whence::Head(str!(db::DEFAULT_HEAD_NAME)),
+                str!("mutator_name"),
+                serde_json::Value::Array(vec![]).to_string(),
+                None,
+                ds.write(lc.clone()).await.unwrap(),
+            )
+            .await
+            .unwrap();
+            for key in &["a1", "b1", "c1", "c2"] {
+                w.put(lc.clone(), key.as_bytes().to_vec(), b"\"v\"".to_vec())
+                    .await
+                    .unwrap();
+            }
+            w.set_index_flush_concurrency(concurrency);
+            let (_, changed_keys) = w
+                .commit_with_changed_keys(db::DEFAULT_HEAD_NAME, true)
+                .await
+                .unwrap();
+
+            let owned_read = ds.read(lc).await.unwrap();
+            let (_, c, _) = read::read_commit(
+                Whence::Head(str!(db::DEFAULT_HEAD_NAME)),
+                &owned_read.read(),
+            )
+            .await
+            .unwrap();
+            let mut index_hashes: Vec<_> = c
+                .indexes()
+                .into_iter()
+                .map(|i| (i.definition.name, i.value_hash))
+                .collect();
+            index_hashes.sort();
+            (c.value_hash().to_string(), index_hashes, changed_keys)
+        }
+
+        let (value_hash, index_hashes, changed_keys) = test(1).await;
+        assert_eq!(3, index_hashes.len());
+        assert_eq!(4, changed_keys.len());
+        assert_eq!(2, changed_keys["c"].len());
+        assert_eq!((value_hash, index_hashes, changed_keys), test(0).await);
+        assert_eq!(test(1).await, test(8).await);
+    }
+
     #[async_std::test]
     async fn test_create_and_drop_index() {
         async fn test(write_before_indexing: bool) {
@@ -4,6 +4,7 @@ use crate::dag;
 use crate::db;
 use crate::sync;
 use crate::sync::JsPusher;
+use crate::util::clock::{Clock, SystemClock};
 use crate::util::rlog;
 use crate::util::rlog::LogContext;
 use crate::util::to_debug;
@@ -13,7 +14,9 @@ use futures::stream::futures_unordered::FuturesUnordered;
 use js_sys::{Function, Reflect, Uint8Array};
 use std::collections::HashMap;
 use std::mem;
+use std::rc::Rc;
 use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
 use wasm_bindgen::{JsCast, JsValue};
 
 lazy_static! {
@@ -35,10 +38,62 @@ impl<'a> Transaction<'a> {
     }
 }
 
-type TransactionsMap<'a> = RwLock<HashMap<u32, RwLock<Transaction<'a>>>>;
+struct OpenTransaction<'a> {
+    txn: RwLock<Transaction<'a>>,
+    opened: rlog::Timer,
+    // Kept outside txn so that listing open transactions does not wait on
+    // their locks.
+    mode: TransactionMode,
+    // For a rebase, the sync head it commits to.
+    sync_head_name: Option<String>,
+    // Whether committing adds a new local mutation to the main chain, as
+    // opposed to replaying one (a rebase) or changing indexes.
+    local_mutation: bool,
+}
+
+impl<'a> OpenTransaction<'a> {
+    fn new(txn: Transaction<'a>) -> OpenTransaction<'a> {
+        let (mode, local_mutation) = match &txn {
+            Transaction::Read(_) => (TransactionMode::Read, false),
+            Transaction::Write(w) => (TransactionMode::Write, w.is_local_mutation()),
+        };
+        OpenTransaction {
+            txn: RwLock::new(txn),
+            opened: rlog::Timer::new(),
+            mode,
+            sync_head_name: None,
+            local_mutation,
+        }
+    }
+}
+
+type TransactionsMap<'a> = RwLock<HashMap<u32, OpenTransaction<'a>>>;
 
 fn from_js<T: serde::de::DeserializeOwned>(data: JsValue) -> Result<T, JsValue> {
-    serde_wasm_bindgen::from_value(data).map_err(JsValue::from)
+    serde_wasm_bindgen::from_value(data).map_err(invalid_json)
+}
+
+// Returns the error thrown for a request that does not deserialize. Besides
+// the message, it sets kind to "InvalidJson" and, when serde names one, field
+// to the offending field so that callers need not parse the message.
+fn invalid_json(err: serde_wasm_bindgen::Error) -> JsValue {
+    let err = JsValue::from(err);
+    if let Some(e) = err.dyn_ref::<js_sys::Error>() {
+        let message: String = e.message().into();
+        let _ = Reflect::set(e, &"kind".into(), &"InvalidJson".into());
+        if let Some(field) = json_error_field(&message) {
+            let _ = Reflect::set(e, &"field".into(), &field.into());
+        }
+    }
+    err
+}
+
+// Returns the field named by a serde error message like "missing field `key`".
+fn json_error_field(message: &str) -> Option<&str> {
+    ["missing field `", "unknown field `", "duplicate field `"]
+        .iter()
+        .find_map(|p| message.find(p).map(|i| &message[i + p.len()..]))
+        .and_then(|rest| rest.find('`').map(|end| &rest[..end]))
 }
 
 #[derive(Debug)]
@@ -73,6 +128,11 @@ async fn connection_future<'a, 'b>(
     };
 
     if req.rpc == Rpc::Close {
+        // The connection loop keeps running until every pending future is
+        // done, so a debounced push would otherwise still fire against the
+        // closed store. Disabling auto-push cancels it, and waits out a push
+        // that is already under way.
+        ctx.auto_push.configure(None).await;
         ctx.store.close().await;
         req.response.send(Ok("".into())).await;
         return UnorderedResult::Stop();
@@ -85,12 +145,42 @@ async fn connection_future<'a, 'b>(
         response,
         ..
     } = req;
-    let res = execute(ctx, rpc, data, lc).await;
+    let mutation = is_mutation(&ctx, &rpc, &data).await;
+    let (store, auto_push, client_id) = (ctx.store, ctx.auto_push, ctx.client_id.clone());
+    let res = execute(ctx, rpc, data, lc.clone()).await;
+    let ok = res.is_ok();
     response.send(res).await;
 
+    // The caller already has its response; the connection keeps this future
+    // around only until the debounced push fires or is superseded.
+    if mutation && ok {
+        match auto_push.mutated(store, &client_id, lc.clone()).await {
+            Ok(Some(info)) => debug!(lc, "Auto-push status {}", info.http_status_code),
+            Ok(None) => (),
+            Err(err) => error!(lc, "Auto-push failed: {:?}", err),
+        }
+    }
+
     UnorderedResult::None()
 }
 
+// Returns whether rpc commits a local mutation to the main chain if it
+// succeeds, ie whether it should schedule an auto-push. Commits of rebases
+// and index changes add nothing to push.
+async fn is_mutation(ctx: &Context<'_, '_>, rpc: &Rpc, data: &JsValue) -> bool {
+    if *rpc != Rpc::CommitTransaction {
+        return false;
+    }
+    let req: CommitTransactionRequest = match from_js(data.clone()) {
+        Ok(req) => req,
+        Err(_) => return false,
+    };
+    match ctx.txns.read().await.get(&req.transaction_id) {
+        Some(open) => open.local_mutation,
+        None => false,
+    }
+}
+
 pub async fn process(
     store: dag::Store,
     receiver: Receiver<Request>,
@@ -103,12 +193,23 @@ pub async fn process(
     }
 
     let txns = RwLock::new(HashMap::new());
+    let auto_push = sync::AutoPush::new();
+    let pulls = sync::PullCoalescer::new();
+    let clock = SystemClock;
     let mut futures = FuturesUnordered::new();
     let mut recv = true;
 
     futures.push(Box::pin(connection_future(
         &receiver,
-        Context::new(&store, &txns, client_id.clone(), LogContext::new()),
+        Context::new(
+            &store,
+            &txns,
+            &auto_push,
+            &pulls,
+            &clock,
+            client_id.clone(),
+            LogContext::new(),
+        ),
         None,
     )));
     while let Some(value) = futures.next().await {
@@ -119,13 +220,29 @@ pub async fn process(
                     if recv {
                         futures.push(Box::pin(connection_future(
                             &receiver,
-                            Context::new(&store, &txns, client_id.clone(), LogContext::new()),
+                            Context::new(
+                                &store,
+                                &txns,
+                                &auto_push,
+                                &pulls,
+                                &clock,
+                                client_id.clone(),
+                                LogContext::new(),
+                            ),
                             None,
                         )));
                     }
                     futures.push(Box::pin(connection_future(
                         &receiver,
-                        Context::new(&store, &txns, client_id.clone(), req.lc.clone()),
+                        Context::new(
+                            &store,
+                            &txns,
+                            &auto_push,
+                            &pulls,
+                            &clock,
+                            client_id.clone(),
+                            req.lc.clone(),
+                        ),
                         Some(req),
                     )));
                 }
@@ -139,6 +256,9 @@ pub async fn process(
 struct Context<'a, 'b> {
     store: &'a dag::Store,
     txns: &'b TransactionsMap<'a>,
+    auto_push: &'b sync::AutoPush,
+    pulls: &'b sync::PullCoalescer,
+    clock: &'b dyn Clock,
     client_id: String,
     lc: LogContext,
 }
@@ -147,12 +267,18 @@ impl<'a, 'b> Context<'a, 'b> {
     fn new(
         store: &'a dag::Store,
         txns: &'b TransactionsMap<'a>,
+        auto_push: &'b sync::AutoPush,
+        pulls: &'b sync::PullCoalescer,
+        clock: &'b dyn Clock,
         client_id: String,
         lc: LogContext,
     ) -> Context<'a, 'b> {
         Context {
             store,
             txns,
+            auto_push,
+            pulls,
+            clock,
             client_id,
             lc,
         }
@@ -189,11 +315,16 @@ pub enum Rpc {
     Scan = 17,
     SetLogLevel = 18,
     TryPush = 19,
+    MutationState = 20,
+    CloseAll = 21,
+    SetAutoPush = 22,
+    GetChunk = 23,
+    Verify = 24,
 }
 
 impl Rpc {
     pub fn from_u8(n: u8) -> Option<Rpc> {
-        if n >= Self::BeginTryPull as u8 && n <= Self::TryPush as u8 {
+        if n >= Self::BeginTryPull as u8 && n <= Self::Verify as u8 {
             Some(unsafe { mem::transmute(n) })
         } else {
             None
@@ -212,11 +343,19 @@ async fn execute<'a, 'b>(
     // transaction-less
     match rpc {
         Rpc::GetRoot => return to_js(do_get_root(ctx, from_js(data)?).await),
+        Rpc::GetChunk => return to_js(do_get_chunk(ctx, from_js(data)?).await),
+        Rpc::Verify => return to_js(do_verify(ctx, from_js(data)?).await),
         Rpc::OpenIndexTransaction => {
             return to_js(do_open_index_transaction(ctx, from_js(data)?).await)
         }
         Rpc::OpenTransaction => return to_js(do_open_transaction(ctx, from_js(data)?).await),
-        Rpc::CommitTransaction => return to_js(do_commit(ctx, from_js(data)?).await),
+        Rpc::CommitTransaction => {
+            // Without an onConflict callback a rebased mutation always wins.
+            let on_conflict = Reflect::get(&data, &JsValue::from_str("onConflict"))
+                .ok()
+                .and_then(|f| f.dyn_into::<Function>().ok());
+            return to_js(do_commit(ctx, from_js(data)?, on_conflict).await);
+        }
         Rpc::CloseTransaction => return to_js(do_close_transaction(ctx, from_js(data)?).await),
         Rpc::SetLogLevel => return to_js(do_set_log_level(ctx, from_js(data)?).await),
 
@@ -225,21 +364,28 @@ async fn execute<'a, 'b>(
             return to_js(do_begin_try_pull(ctx, from_js(data.clone())?, data).await)
         }
         Rpc::MaybeEndTryPull => return to_js(do_maybe_end_try_pull(ctx, from_js(data)?).await),
+        Rpc::MutationState => return to_js(do_mutation_state(ctx).await),
+        Rpc::Debug => return to_js(do_debug(ctx, from_js(data)?).await),
+        Rpc::SetAutoPush => {
+            return to_js(do_set_auto_push(ctx, from_js(data.clone())?, data).await)
+        }
 
         _ => (),
     };
 
     // require read txn
     let txn_req: TransactionRequest = from_js(data.clone())?;
-    let txn_id = txn_req
-        .transaction_id
-        .ok_or(TransactionIdRequired)
-        .map_err(to_debug)?;
+    let txn_id = match (txn_req.transaction_id, &rpc) {
+        (Some(txn_id), _) => txn_id,
+        (None, Rpc::Has) | (None, Rpc::Get) => return execute_implicit(ctx, rpc, data, lc).await,
+        (None, _) => return Err(JsValue::from_str(&to_debug(TransactionIdRequired))),
+    };
     let txn_id_string = txn_id.to_string();
     lc.add_context("txid", &txn_id_string);
     let txns = ctx.txns.read().await;
     let txn = txns
         .get(&txn_id)
+        .map(|open| &open.txn)
         .ok_or(TransactionNotFound(txn_id))
         .map_err(to_debug)?;
 
@@ -278,6 +424,30 @@ async fn execute<'a, 'b>(
     Err(JsValue::from_str(&to_debug(UnknownRpc(rpc))))
 }
 
+// Runs a has or get that was sent without a transactionId in its own read
+// transaction. There is no implicit put or del: a write has to be a local
+// mutation so that it can be pushed and replayed, and only the caller knows
+// which of its mutators it is.
+async fn execute_implicit<'a, 'b>(
+    ctx: Context<'a, 'b>,
+    rpc: Rpc,
+    data: JsValue,
+    lc: LogContext,
+) -> Result<JsValue, JsValue> {
+    let dag_read = ctx.store.read(lc.clone()).await.map_err(to_debug)?;
+    let read = db::OwnedRead::from_whence(
+        db::Whence::Head(db::DEFAULT_HEAD_NAME.to_string()),
+        dag_read,
+    )
+    .await
+    .map_err(to_debug)?;
+    match rpc {
+        Rpc::Has => to_js(do_has(read.as_read(), from_js(data)?).await),
+        Rpc::Get => to_js(do_get(read.as_read(), from_js(data)?).await),
+        _ => Err(JsValue::from_str(&to_debug(ExecuteError::UnknownRpc(rpc)))),
+    }
+}
+
 #[derive(Debug)]
 #[allow(clippy::enum_variant_names)]
 pub enum DoInitError {
@@ -309,12 +479,19 @@ async fn do_open_transaction<'a, 'b>(
 ) -> Result<OpenTransactionResponse, OpenTransactionError> {
     use OpenTransactionError::*;
 
-    let txn = match req.name {
-        Some(mutator_name) => {
+    let mode = req.mode.unwrap_or(match req.name {
+        Some(_) => TransactionMode::Write,
+        None => TransactionMode::Read,
+    });
+    let mut sync_head_name = None;
+    let txn = match (mode, req.name) {
+        (TransactionMode::Write, None) => return Err(NameRequired),
+        (TransactionMode::Read, Some(_)) => return Err(NameNotAllowed),
+        (TransactionMode::Write, Some(mutator_name)) => {
             let OpenTransactionRequest {
-                name: _,
                 args: mutator_args,
                 rebase_opts,
+                ..
             } = req;
             let mutator_args = mutator_args.ok_or(ArgsRequired)?;
 
@@ -334,7 +511,11 @@ async fn do_open_transaction<'a, 'b>(
             let (whence, original_hash) = match rebase_opts {
                 None => (db::Whence::Head(db::DEFAULT_HEAD_NAME.to_string()), None),
                 Some(opts) => {
-                    validate_rebase(&opts, dag_write.read(), &mutator_name, &mutator_args).await?;
+                    let name = sync::sync_head_name(opts.sync_head_name.as_deref())
+                        .map_err(InvalidSyncHeadName)?;
+                    validate_rebase(&opts, name, dag_write.read(), &mutator_name, &mutator_args)
+                        .await?;
+                    sync_head_name = Some(name.to_string());
                     (db::Whence::Hash(opts.basis), Some(opts.original_hash))
                 }
             };
@@ -345,7 +526,7 @@ async fn do_open_transaction<'a, 'b>(
                     .map_err(DBWriteError)?;
             Transaction::Write(write)
         }
-        None => {
+        (TransactionMode::Read, None) => {
             let dag_read = ctx.store.read(ctx.lc.clone()).await.map_err(DagReadError)?;
             let read = db::OwnedRead::from_whence(
                 db::Whence::Head(db::DEFAULT_HEAD_NAME.to_string()),
@@ -358,9 +539,12 @@ async fn do_open_transaction<'a, 'b>(
     };
 
     let txn_id = TRANSACTION_COUNTER.fetch_add(1, Ordering::SeqCst);
-    ctx.txns.write().await.insert(txn_id, RwLock::new(txn));
+    let mut open = OpenTransaction::new(txn);
+    open.sync_head_name = sync_head_name;
+    ctx.txns.write().await.insert(txn_id, open);
     Ok(OpenTransactionResponse {
         transaction_id: txn_id,
+        mode,
     })
 }
 
@@ -392,7 +576,10 @@ async fn do_open_index_transaction<'a, 'b>(
     let txn = Transaction::Write(write);
 
     let txn_id = TRANSACTION_COUNTER.fetch_add(1, Ordering::SeqCst);
-    ctx.txns.write().await.insert(txn_id, RwLock::new(txn));
+    ctx.txns
+        .write()
+        .await
+        .insert(txn_id, OpenTransaction::new(txn));
     Ok(OpenIndexTransactionResponse {
         transaction_id: txn_id,
     })
@@ -400,6 +587,7 @@ async fn do_open_index_transaction<'a, 'b>(
 
 async fn validate_rebase<'a>(
     opts: &'a RebaseOpts,
+    sync_head_name: &str,
     dag_read: dag::Read<'_>,
     mutator_name: &'a str,
     _args: &'a str,
@@ -408,7 +596,7 @@ async fn validate_rebase<'a>(
 
     // Ensure the rebase commit is going on top of the current sync head.
     let sync_head_hash = dag_read
-        .get_head(sync::SYNC_HEAD_NAME)
+        .get_head(sync_head_name)
         .await
         .map_err(GetHeadError)?;
     if sync_head_hash.as_ref() != Some(&opts.basis) {
@@ -460,25 +648,70 @@ async fn validate_rebase<'a>(
 async fn do_commit<'a, 'b>(
     ctx: Context<'a, 'b>,
     req: CommitTransactionRequest,
+    on_conflict: Option<Function>,
 ) -> Result<CommitTransactionResponse, CommitTransactionError> {
     use CommitTransactionError::*;
     let txn_id = req.transaction_id;
     let mut txns = ctx.txns.write().await;
-    let txn = txns.remove(&txn_id).ok_or(UnknownTransaction)?;
-    let txn = match txn.into_inner() {
+    let open = txns.remove(&txn_id).ok_or(UnknownTransaction)?;
+    let mut txn = match open.txn.into_inner() {
         Transaction::Write(w) => Ok(w),
         Transaction::Read(_) => Err(TransactionIsReadOnly),
     }?;
+    let sync_head_name = open
+        .sync_head_name
+        .unwrap_or_else(|| sync::SYNC_HEAD_NAME.to_string());
     let head_name = if txn.is_rebase() {
-        sync::SYNC_HEAD_NAME
+        if let Some(on_conflict) = on_conflict {
+            let lc = ctx.lc.clone();
+            sync::resolve_conflicts(
+                &mut txn,
+                &sync_head_name,
+                &|c| js_resolve_conflict(&on_conflict, c, &lc),
+                ctx.lc.clone(),
+            )
+            .await
+            .map_err(ResolveConflictsError)?;
+        }
+        sync_head_name.as_str()
     } else {
         db::DEFAULT_HEAD_NAME
     };
-    let (hash, changed_keys) = txn
-        .commit_with_changed_keys(head_name, req.generate_changed_keys)
+    let (hash, changed_keys, changed_values) = txn
+        .commit_with_changes(
+            head_name,
+            req.generate_changed_keys,
+            req.generate_changed_values,
+        )
         .await
         .map_err(CommitError)?;
-    Ok(CommitTransactionResponse { hash, changed_keys })
+    // The mutation is only reported as recorded once it is durable.
+    ctx.store.flush().await.map_err(FlushError)?;
+    Ok(CommitTransactionResponse {
+        hash,
+        changed_keys,
+        changed_values,
+    })
+}
+
+// Calls the JS onConflict callback, falling back to keeping the local write if
+// it throws or returns something other than a resolution.
+fn js_resolve_conflict(
+    on_conflict: &Function,
+    conflict: &sync::MutationConflict,
+    lc: &LogContext,
+) -> sync::Resolution {
+    let res = serde_wasm_bindgen::to_value(conflict)
+        .map_err(to_debug)
+        .and_then(|c| on_conflict.call1(&JsValue::null(), &c).map_err(to_debug))
+        .and_then(|r| serde_wasm_bindgen::from_value(r).map_err(to_debug));
+    match res {
+        Ok(resolution) => resolution,
+        Err(e) => {
+            error!(lc, "onConflict failed for {}: {}", conflict.key, e);
+            sync::keep_local(conflict)
+        }
+    }
 }
 
 async fn do_close_transaction<'a, 'b>(
@@ -511,6 +744,72 @@ async fn do_get_root<'a, 'b>(
     })
 }
 
+async fn do_get_chunk<'a, 'b>(
+    ctx: Context<'a, 'b>,
+    req: GetChunkRequest,
+) -> Result<GetChunkResponse, GetChunkError> {
+    use GetChunkError::*;
+    let chunk = db::get_chunk(ctx.store, &req.hash, ctx.lc.clone())
+        .await
+        .map_err(GetError)?;
+    let kind = req.kind.unwrap_or(db::ChunkKind::Raw);
+    Ok(GetChunkResponse {
+        chunk: chunk
+            .map(|chunk| db::inspect_chunk(chunk, kind))
+            .transpose()
+            .map_err(InspectError)?,
+    })
+}
+
+// Checks the chunks under each of our heads and, if asked to, repairs the
+// heads that have corrupt chunks under them.
+async fn do_verify<'a, 'b>(
+    ctx: Context<'a, 'b>,
+    req: VerifyRequest,
+) -> Result<VerifyResponse, VerifyError> {
+    use VerifyError::*;
+    // Every head in the store is checked, including those of pulls onto
+    // custom sync heads.
+    let head_names = {
+        let read = ctx.store.read(ctx.lc.clone()).await.map_err(ReadError)?;
+        let names = read.read().head_names().await.map_err(GetHeadError)?;
+        names
+    };
+    let mut heads = Vec::new();
+    for head_name in &head_names {
+        let corrupt = db::verify(ctx.store, &[head_name.as_str()], ctx.lc.clone())
+            .await
+            .map_err(DBVerifyError)?;
+        let repaired = req.repair && !corrupt.is_empty();
+        if repaired {
+            info!(
+                ctx.lc,
+                "Repairing head {} with {} corrupt chunks",
+                head_name,
+                corrupt.len()
+            );
+            if head_name == db::DEFAULT_HEAD_NAME {
+                db::repair_head(ctx.store, head_name, ctx.lc.clone())
+                    .await
+                    .map_err(RepairError)?;
+            } else {
+                let write = ctx.store.write(ctx.lc.clone()).await.map_err(WriteError)?;
+                write
+                    .set_head(head_name, None)
+                    .await
+                    .map_err(SetHeadError)?;
+                write.commit().await.map_err(WriteError)?;
+            }
+        }
+        heads.push(HeadReport {
+            head_name: head_name.clone(),
+            corrupt,
+            repaired,
+        });
+    }
+    Ok(VerifyResponse { heads })
+}
+
 async fn do_has(txn: db::Read<'_>, req: HasRequest) -> Result<HasResponse, ()> {
     Ok(HasResponse {
         has: txn.has(req.key.as_bytes()),
@@ -677,7 +976,19 @@ async fn do_maybe_end_try_pull<'a, 'b>(
     req: sync::MaybeEndTryPullRequest,
 ) -> Result<sync::MaybeEndTryPullResponse, sync::MaybeEndTryPullError> {
     ctx.lc.add_context("request_id", &req.request_id);
-    sync::maybe_end_try_pull(ctx.store, ctx.lc.clone(), req).await
+    let resp = sync::maybe_end_try_pull(ctx.store, ctx.lc.clone(), req).await?;
+    // The pull is only done once there is nothing left to replay.
+    if resp.replay_mutations.is_empty() {
+        record_sync_time(&ctx).await;
+    }
+    Ok(resp)
+}
+
+// A failure to record the time is logged rather than failing the sync.
+async fn record_sync_time(ctx: &Context<'_, '_>) {
+    if let Err(e) = sync::record_sync_time(ctx.store, ctx.clock).await {
+        error!(ctx.lc, "Could not record sync time: {:?}", e);
+    }
 }
 
 async fn do_set_log_level<'a, 'b>(
@@ -703,8 +1014,19 @@ async fn do_try_push<'a, 'b>(
     let request_id = sync::request_id::new(&ctx.client_id);
     ctx.lc.add_context("request_id", &request_id);
 
-    let http_request_info =
-        sync::push(&request_id, ctx.store, ctx.lc, ctx.client_id, &pusher, req).await?;
+    let http_request_info = sync::push(
+        &request_id,
+        ctx.store,
+        ctx.lc.clone(),
+        ctx.client_id.clone(),
+        &pusher,
+        req,
+    )
+    .await?;
+    // None means there was nothing to push.
+    if http_request_info.as_ref().map(|i| i.http_status_code) == Some(200) {
+        record_sync_time(&ctx).await;
+    }
     Ok(sync::TryPushResponse { http_request_info })
 }
 
@@ -714,11 +1036,67 @@ async fn do_begin_try_pull<'a, 'b>(
     req_raw: JsValue,
 ) -> Result<sync::BeginTryPullResponse, sync::BeginTryPullError> {
     let puller = sync::JsPuller::new(req_raw).map_err(sync::BeginTryPullError::InvalidPuller)?;
+    let puller = ctx.pulls.puller(Rc::new(puller));
     let request_id = sync::request_id::new(&ctx.client_id);
     ctx.lc.add_context("request_id", &request_id);
     sync::begin_pull(ctx.client_id, req, &puller, request_id, ctx.store, ctx.lc).await
 }
 
+async fn do_set_auto_push<'a, 'b>(
+    ctx: Context<'a, 'b>,
+    req: SetAutoPushRequest,
+    req_raw: JsValue,
+) -> Result<SetAutoPushResponse, SetAutoPushError> {
+    use SetAutoPushError::*;
+    let config = match req.debounce_ms {
+        None => None,
+        Some(debounce_ms) => Some(sync::AutoPushConfig {
+            debounce: Duration::from_millis(debounce_ms),
+            req: from_js(req_raw.clone()).map_err(InvalidPushRequest)?,
+            pusher: Box::new(JsPusher::new(req_raw).map_err(InvalidPusher)?),
+        }),
+    };
+    ctx.auto_push.configure(config).await;
+    Ok(SetAutoPushResponse {})
+}
+
+// Dispatch forwards debug commands it cannot answer itself to each open
+// connection.
+async fn do_debug<'a, 'b>(
+    ctx: Context<'a, 'b>,
+    command: String,
+) -> Result<Vec<OpenTransactionInfo>, DebugError> {
+    use DebugError::*;
+    match command.as_str() {
+        "open_transactions" => {
+            let txns = ctx.txns.read().await;
+            let mut infos: Vec<OpenTransactionInfo> = txns
+                .iter()
+                .map(|(id, open)| OpenTransactionInfo {
+                    transaction_id: *id,
+                    age_ms: open.opened.elapsed_ms(),
+                    mode: open.mode,
+                })
+                .collect();
+            infos.sort_by_key(|info| info.transaction_id);
+            Ok(infos)
+        }
+        _ => Err(UnknownCommand(command)),
+    }
+}
+
+async fn do_mutation_state<'a, 'b>(
+    ctx: Context<'a, 'b>,
+) -> Result<sync::MutationState, sync::MutationStateError> {
+    sync::mutation_state(ctx.store, ctx.lc).await
+}
+
+#[derive(Debug)]
+enum GetChunkError {
+    GetError(db::GetChunkError),
+    InspectError(db::InspectChunkError),
+}
+
 #[derive(Debug)]
 #[allow(clippy::enum_variant_names)]
 enum GetRootError {
@@ -737,6 +1115,9 @@ enum OpenTransactionError {
     InconsistentMutationId(String),
     InconsistentMutator(String),
     InternalProgrammerError(String),
+    InvalidSyncHeadName(sync::SyncHeadNameError),
+    NameNotAllowed,
+    NameRequired,
     NoSuchBasis(db::ReadCommitError),
     NoSuchOriginal(db::ReadCommitError),
     WrongSyncHeadJSLogInfo(String), // "JSLogInfo" is a signal to bindings to not log this alarmingly.
@@ -745,10 +1126,28 @@ enum OpenTransactionError {
 #[derive(Debug)]
 enum CommitTransactionError {
     CommitError(db::CommitError),
+    FlushError(dag::Error),
+    ResolveConflictsError(sync::ResolveConflictsError),
     TransactionIsReadOnly,
     UnknownTransaction,
 }
 
+#[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
+enum VerifyError {
+    DBVerifyError(db::VerifyError),
+    GetHeadError(dag::Error),
+    ReadError(dag::Error),
+    RepairError(db::RepairHeadError),
+    SetHeadError(dag::Error),
+    WriteError(dag::Error),
+}
+
+#[derive(Debug)]
+enum DebugError {
+    UnknownCommand(String),
+}
+
 #[derive(Debug)]
 enum CloseTransactionError {
     UnknownTransaction,
@@ -763,15 +1162,66 @@ mod tests {
     use crate::db::test_helpers::*;
     use crate::kv::memstore::MemStore;
     use crate::sync::test_helpers::*;
+    use crate::util::clock::MockClock;
     use crate::util::rlog::LogContext;
     use str_macro::str;
 
+    // Fixture owns what a Context borrows besides the store.
+    struct Fixture<'a> {
+        store: &'a dag::Store,
+        txns: TransactionsMap<'a>,
+        auto_push: sync::AutoPush,
+        pulls: sync::PullCoalescer,
+        clock: MockClock,
+    }
+
+    impl<'a> Fixture<'a> {
+        fn new(store: &'a dag::Store, now_ms: u64) -> Fixture<'a> {
+            Fixture {
+                store,
+                txns: RwLock::new(HashMap::new()),
+                auto_push: sync::AutoPush::new(),
+                pulls: sync::PullCoalescer::new(),
+                clock: MockClock::new(now_ms),
+            }
+        }
+
+        fn ctx(&self) -> Context<'a, '_> {
+            Context::new(
+                self.store,
+                &self.txns,
+                &self.auto_push,
+                &self.pulls,
+                &self.clock,
+                str!("client_id"),
+                LogContext::new(),
+            )
+        }
+    }
+
+    #[test]
+    fn test_json_error_field() {
+        assert_eq!(
+            Some("transactionId"),
+            json_error_field("missing field `transactionId`")
+        );
+        assert_eq!(
+            Some("foo"),
+            json_error_field("unknown field `foo`, expected `key`")
+        );
+        assert_eq!(
+            None,
+            json_error_field("invalid type: string \"x\", expected u32")
+        );
+        assert_eq!(None, json_error_field("missing field `oops"));
+    }
+
     #[async_std::test]
     async fn test_open_transaction_rebase_opts() {
         // Note: store needs to outlive txns.
         let store = dag::Store::new(Box::new(MemStore::new()));
         {
-            let txns = RwLock::new(HashMap::new());
+            let f = Fixture::new(&store, 0);
             let mut main_chain: Chain = vec![];
             add_genesis(&mut main_chain, &store).await;
             add_local(&mut main_chain, &store).await;
@@ -792,14 +1242,16 @@ mod tests {
 
             // Error: rebase commit's basis must be sync head.
             let result = do_open_transaction(
-                Context::new(&store, &txns, str!("client_id"), LogContext::new()),
+                f.ctx(),
                 OpenTransactionRequest {
                     name: Some(original_name.clone()),
                     args: Some(original_args.clone()),
                     rebase_opts: Some(RebaseOpts {
                         basis: original_hash.clone(), // <-- not the sync head
                         original_hash: original_hash.clone(),
+                        sync_head_name: None,
                     }),
+                    mode: None,
                 },
             )
             .await;
@@ -807,14 +1259,16 @@ mod tests {
 
             // Error: rebase commit's name should not change.
             let result = do_open_transaction(
-                Context::new(&store, &txns, str!("client_id"), LogContext::new()),
+                f.ctx(),
                 OpenTransactionRequest {
                     name: Some(str!("different!")),
                     args: Some(original_args.clone()),
                     rebase_opts: Some(RebaseOpts {
                         basis: str!(sync_chain[0].chunk().hash()),
                         original_hash: original_hash.clone(),
+                        sync_head_name: None,
                     }),
+                    mode: None,
                 },
             )
             .await;
@@ -836,14 +1290,16 @@ mod tests {
                 _ => panic!("not local"),
             };
             let result = do_open_transaction(
-                Context::new(&store, &txns, str!("client_id"), LogContext::new()),
+                f.ctx(),
                 OpenTransactionRequest {
                     name: Some(new_local_name),
                     args: Some(new_local_args),
                     rebase_opts: Some(RebaseOpts {
                         basis: str!(sync_chain[0].chunk().hash()),
                         original_hash: new_local_hash, // <-- has different mutation id
+                        sync_head_name: None,
                     }),
+                    mode: None,
                 },
             )
             .await;
@@ -852,24 +1308,28 @@ mod tests {
 
             // Correct rebase_opt (test this last because it affects the chain).
             let otr = do_open_transaction(
-                Context::new(&store, &txns, str!("client_id"), LogContext::new()),
+                f.ctx(),
                 OpenTransactionRequest {
                     name: Some(original_name.clone()),
                     args: Some(original_args.clone()),
                     rebase_opts: Some(RebaseOpts {
                         basis: str!(sync_chain[0].chunk().hash()),
                         original_hash: original_hash.clone(),
+                        sync_head_name: None,
                     }),
+                    mode: None,
                 },
             )
             .await
             .unwrap();
             let ctr = do_commit(
-                Context::new(&store, &txns, str!("client_id"), LogContext::new()),
+                f.ctx(),
                 CommitTransactionRequest {
                     transaction_id: otr.transaction_id,
                     generate_changed_keys: false,
+                    generate_changed_values: false,
                 },
+                None,
             )
             .await
             .unwrap();
@@ -883,4 +1343,23 @@ mod tests {
             assert_eq!(ctr.hash, sync_head_hash);
         }
     }
+
+    #[async_std::test]
+    async fn test_last_sync_time() {
+        let store = dag::Store::new(Box::new(MemStore::new()));
+        let f = Fixture::new(&store, 1000);
+        add_genesis(&mut vec![], &store).await;
+        assert_eq!(
+            None,
+            do_mutation_state(f.ctx()).await.unwrap().last_sync_time
+        );
+
+        // The sync time comes from the connection's clock.
+        record_sync_time(&f.ctx()).await;
+        f.clock.advance(500);
+        assert_eq!(
+            Some(1000),
+            do_mutation_state(f.ctx()).await.unwrap().last_sync_time
+        );
+    }
 }
@@ -9,6 +9,7 @@ use crate::util::rlog;
 use crate::util::rlog::LogContext;
 use crate::util::to_debug;
 use async_std::sync::{channel, Mutex, Receiver, Sender};
+use js_sys::Object;
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicU32, Ordering};
 use wasm_bindgen::JsValue;
@@ -38,7 +39,20 @@ lazy_static! {
     };
 }
 
-type ConnMap = HashMap<String, Sender<Request>>;
+// Conn is an open database. Each open of an already open database shares the
+// connection and bumps open_count; it is only closed when the count drops back
+// to zero.
+struct Conn {
+    sender: Sender<Request>,
+    client_id: String,
+    open_count: usize,
+    // The store and backend options it was opened with, which a repeated
+    // open must match.
+    store: JsValue,
+    backend: JsValue,
+}
+
+type ConnMap = HashMap<String, Conn>;
 
 async fn dispatch_loop(rx: Receiver<Request>) {
     let mut conns: ConnMap = HashMap::new();
@@ -55,6 +69,7 @@ async fn dispatch_loop(rx: Receiver<Request>) {
         let response = match req.rpc {
             Rpc::Open => Some(do_open(&mut conns, &req).await),
             Rpc::Close => Some(do_close(&mut conns, &req).await),
+            Rpc::CloseAll => Some(do_close_all(&mut conns, &req).await),
             Rpc::Debug => Some(do_debug(&conns, &req).await),
             _ => None,
         };
@@ -63,20 +78,14 @@ async fn dispatch_loop(rx: Receiver<Request>) {
             continue;
         }
         match conns.get(&req.db_name[..]) {
-            Some(tx) => tx.send(req).await,
-            None => {
-                req.response
-                    .send(Err(JsValue::from(js_sys::Error::new(&format!(
-                        "\"{}\" not open",
-                        req.db_name
-                    )))))
-                    .await;
-            }
+            Some(conn) => conn.sender.send(req).await,
+            None => req.response.send(Err(not_open(&req.db_name))).await,
         };
     }
 }
 
 pub async fn dispatch(db_name: String, rpc: Rpc, data: JsValue) -> Response {
+    let db_name = normalize_db_name(&db_name);
     let lc = LogContext::new();
     let rpc_id = RPC_COUNTER.fetch_add(1, Ordering::Relaxed).to_string();
     lc.add_context("rpc_id", rpc_id.as_str());
@@ -95,8 +104,10 @@ pub async fn dispatch(db_name: String, rpc: Rpc, data: JsValue) -> Response {
     };
     SENDER.lock().await.send(request).await;
     let receive_result = receiver.recv().await;
+    // The response is only dropped unsent if the connection was closed while
+    // the request was queued on it.
     let result = match receive_result {
-        Err(e) => Err(JsValue::from_str(&e.to_string())),
+        Err(_) => Err(not_open(&db_name)),
         Ok(v) => v,
     };
     debug!(
@@ -108,29 +119,107 @@ pub async fn dispatch(db_name: String, rpc: Rpc, data: JsValue) -> Response {
     result
 }
 
-async fn do_open(conns: &mut ConnMap, req: &Request) -> Response {
-    if req.db_name.is_empty() {
-        return Err("db_name must be non-empty".into());
+// Returns the error a request to a database that is not open rejects with.
+// Only this error has kind "NotOpen"; a missing key is not an error and
+// resolves with has false.
+fn not_open(db_name: &str) -> JsValue {
+    let err = js_sys::Error::new(&format!("\"{}\" not open", db_name));
+    let _ = js_sys::Reflect::set(&err, &"kind".into(), &"NotOpen".into());
+    err.into()
+}
+
+// Names starting with this are reserved for databases we open ourselves.
+pub const RESERVED_DB_NAME_PREFIX: &str = "__";
+pub const MAX_DB_NAME_LEN: usize = 256;
+
+#[derive(Debug, PartialEq)]
+pub enum DbNameError {
+    ControlChar(usize),
+    Empty,
+    Reserved,
+    TooLong(usize),
+}
+
+impl std::fmt::Display for DbNameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use DbNameError::*;
+        match self {
+            ControlChar(i) => write!(f, "db_name must not contain control characters (at {})", i),
+            Empty => write!(f, "db_name must be non-empty"),
+            Reserved => write!(
+                f,
+                "db_name must not start with \"{}\"",
+                RESERVED_DB_NAME_PREFIX
+            ),
+            TooLong(len) => write!(
+                f,
+                "db_name must be at most {} bytes, got {}",
+                MAX_DB_NAME_LEN, len
+            ),
+        }
     }
-    if conns.contains_key(&req.db_name[..]) {
-        return Err(format!(
-            "Database \"{}\" has already been opened. Please close it before opening it again",
-            req.db_name
-        )
-        .into());
+}
+
+// Applied to the db_name of every request so that, eg, "foo" and "foo\n"
+// refer to the same database.
+fn normalize_db_name(db_name: &str) -> String {
+    db_name.trim().to_string()
+}
+
+fn validate_db_name(db_name: &str) -> Result<(), DbNameError> {
+    use DbNameError::*;
+    if db_name.is_empty() {
+        return Err(Empty);
+    }
+    if db_name.len() > MAX_DB_NAME_LEN {
+        return Err(TooLong(db_name.len()));
     }
+    if let Some((i, _)) = db_name.char_indices().find(|(_, c)| c.is_control()) {
+        return Err(ControlChar(i));
+    }
+    if db_name.starts_with(RESERVED_DB_NAME_PREFIX) {
+        return Err(Reserved);
+    }
+    Ok(())
+}
 
+async fn do_open(conns: &mut ConnMap, req: &Request) -> Response {
+    validate_db_name(&req.db_name).map_err(|e| e.to_string())?;
     let js_store = js_sys::Reflect::get(&req.data, &JsValue::from("store"))?;
+    let backend = js_sys::Reflect::get(&req.data, &JsValue::from("backend"))?;
 
-    let kv: Box<dyn Store> = if !js_store.is_undefined() {
-        Box::new(JsStore::new(js_store))
-    } else {
-        Box::new(MemStore::new())
+    // A repeated open shares the connection, so it has to ask for the same
+    // store (the same object, not just an equal one) and backend.
+    if let Some(conn) = conns.get_mut(&req.db_name[..]) {
+        if !Object::is(&conn.store, &js_store) || !Object::is(&conn.backend, &backend) {
+            return Err(format!(
+                "\"{}\" is already open with a different store or backend",
+                req.db_name
+            )
+            .into());
+        }
+        conn.open_count += 1;
+        return Ok(conn.client_id.clone().into());
+    }
+
+    // backend picks the store explicitly: "js" requires a store to be passed
+    // and "memory" ignores it. Without it we use the store if there is one.
+    let kv: Box<dyn Store> = match backend.as_string().as_deref() {
+        Some("memory") => Box::new(MemStore::new()),
+        Some("js") if js_store.is_undefined() => {
+            return Err("backend \"js\" requires a store".into())
+        }
+        Some("js") => Box::new(JsStore::new(js_store.clone())),
+        Some(other) => return Err(format!("Unknown backend \"{}\"", other).into()),
+        None if !backend.is_undefined() => return Err("backend must be a string".into()),
+        None if !js_store.is_undefined() => Box::new(JsStore::new(js_store.clone())),
+        None => Box::new(MemStore::new()),
     };
 
     let client_id = sync::client_id::init(kv.as_ref(), req.lc.clone())
         .await
-        .map_err(to_debug)?;
+        .map_err(to_debug)?
+        .to_string();
 
     let (sender, receiver) = channel::<Request>(1);
     spawn_local(connection::process(
@@ -139,32 +228,110 @@ async fn do_open(conns: &mut ConnMap, req: &Request) -> Response {
         client_id.clone(),
         req.lc.clone(),
     ));
-    conns.insert(req.db_name.clone(), sender);
+    conns.insert(
+        req.db_name.clone(),
+        Conn {
+            sender,
+            client_id: client_id.clone(),
+            open_count: 1,
+            store: js_store,
+            backend,
+        },
+    );
     Ok(client_id.into())
 }
 
 async fn do_close(conns: &mut ConnMap, req: &Request) -> Response {
-    let tx = match conns.get(&req.db_name[..]) {
+    let conn = match conns.get_mut(&req.db_name[..]) {
         None => return Ok("".into()),
         Some(v) => v,
     };
-    let (tx2, rx2) = channel::<Response>(1);
-    tx.send(Request {
-        lc: req.lc.clone(),
-        db_name: req.db_name.clone(),
-        rpc: Rpc::Close,
-        data: "".into(),
-        response: tx2,
-    })
-    .await;
-    let _ = rx2.recv().await;
-    conns.remove(&req.db_name);
+    conn.open_count -= 1;
+    if conn.open_count == 0 {
+        if let Some(conn) = conns.remove(&req.db_name) {
+            close_conn(conn, &req.db_name, req.lc.clone()).await;
+        }
+    }
+    Ok("".into())
+}
+
+// Closes every open database regardless of how many times it was opened.
+async fn do_close_all(conns: &mut ConnMap, req: &Request) -> Response {
+    for (db_name, conn) in conns.drain() {
+        close_conn(conn, &db_name, req.lc.clone()).await;
+    }
     Ok("".into())
 }
 
+async fn close_conn(conn: Conn, db_name: &str, lc: LogContext) {
+    let (tx, rx) = channel::<Response>(1);
+    conn.sender
+        .send(Request {
+            lc,
+            db_name: db_name.to_string(),
+            rpc: Rpc::Close,
+            data: "".into(),
+            response: tx,
+        })
+        .await;
+    let _ = rx.recv().await;
+}
+
 async fn do_debug(conns: &ConnMap, req: &Request) -> Response {
     match req.data.as_string().as_deref() {
         Some("open_dbs") => Ok(JsValue::from_str(&to_debug(conns.keys()))),
+        // Returns an object mapping each open db to the transactions open on it.
+        Some("open_transactions") => {
+            let result = js_sys::Object::new();
+            for (db_name, conn) in conns.iter() {
+                let (tx, rx) = channel::<Response>(1);
+                conn.sender
+                    .send(Request {
+                        lc: req.lc.clone(),
+                        db_name: db_name.clone(),
+                        rpc: Rpc::Debug,
+                        data: req.data.clone(),
+                        response: tx,
+                    })
+                    .await;
+                let txns = rx.recv().await.map_err(to_debug)??;
+                js_sys::Reflect::set(&result, &JsValue::from_str(db_name), &txns)?;
+            }
+            Ok(result.into())
+        }
         _ => Err("Debug command not defined".into()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_db_name() {
+        use DbNameError::*;
+        let long = "a".repeat(MAX_DB_NAME_LEN + 1);
+        let cases: Vec<(&str, Result<(), DbNameError>)> = vec![
+            ("db", Ok(())),
+            ("my db/1", Ok(())),
+            ("", Err(Empty)),
+            (&long[..MAX_DB_NAME_LEN], Ok(())),
+            (&long, Err(TooLong(MAX_DB_NAME_LEN + 1))),
+            ("a\nb", Err(ControlChar(1))),
+            ("a\u{7f}", Err(ControlChar(1))),
+            ("__db", Err(Reserved)),
+            ("_db", Ok(())),
+        ];
+        for (name, exp) in cases {
+            assert_eq!(
+                exp,
+                validate_db_name(&normalize_db_name(name)),
+                "{:?}",
+                name
+            );
+        }
+        assert_eq!("db", normalize_db_name(" db\n"));
+        assert_eq!(Err(Empty), validate_db_name(&normalize_db_name(" \t ")));
+        assert_eq!("db_name must be non-empty", Empty.to_string());
+    }
+}
@@ -2,6 +2,7 @@
 
 use crate::db::{self, ChangedKeysMap};
 use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct OpenRequest {}
@@ -24,6 +25,12 @@ pub struct OpenTransactionRequest {
     #[serde(rename = "rebaseOpts")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub rebase_opts: Option<RebaseOpts>,
+    // If omitted, the transaction is a write if name is given and a read
+    // otherwise. Read transactions see a snapshot of the default head and do
+    // not take the write lock, so they neither wait for nor hold up writes.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<TransactionMode>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -31,12 +38,18 @@ pub struct RebaseOpts {
     pub basis: String,
     #[serde(rename = "original")]
     pub original_hash: String,
+    // The head of the pull being rebased onto. Defaults to sync's
+    // SYNC_HEAD_NAME.
+    #[serde(default, rename = "syncHeadName")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sync_head_name: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct OpenTransactionResponse {
     #[serde(rename = "transactionId")]
     pub transaction_id: u32,
+    pub mode: TransactionMode,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -50,6 +63,12 @@ pub struct CommitTransactionRequest {
     // diff for the entire pull operation instead.
     #[serde(rename = "generateChangedKeys")]
     pub generate_changed_keys: bool,
+
+    // Asks for the old and new value of each changed key as well, eg to
+    // animate the change. Off by default as it copies every changed value.
+    #[serde(default)]
+    #[serde(rename = "generateChangedValues")]
+    pub generate_changed_values: bool,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -59,6 +78,9 @@ pub struct CommitTransactionResponse {
     pub hash: String,
     #[serde(rename = "changedKeys")]
     pub changed_keys: ChangedKeysMap,
+    #[serde(rename = "changedValues")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changed_values: Vec<db::ValueChange>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -90,10 +112,53 @@ pub struct GetRootResponse {
     pub root: String,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GetChunkRequest {
+    pub hash: String,
+    // How to decode the chunk. Defaults to raw, ie its bytes as stored.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<db::ChunkKind>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetChunkResponse {
+    // None if there is no chunk with the hash.
+    pub chunk: Option<db::InspectedChunk>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct VerifyRequest {
+    // Whether to repair the heads that have corrupt chunks under them.
+    #[serde(default)]
+    pub repair: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VerifyResponse {
+    pub heads: Vec<HeadReport>,
+}
+
+// HeadReport is what verify found under one head. Heads that do not exist
+// are not reported.
+#[derive(Debug, Serialize)]
+pub struct HeadReport {
+    #[serde(rename = "headName")]
+    pub head_name: String,
+    pub corrupt: Vec<db::CorruptChunk>,
+    // Whether the head was repaired. The main head is reset to an empty
+    // snapshot that the next pull fills in again, so callers should pull
+    // after a repair. Sync heads are dropped, which abandons the pull that
+    // was in progress.
+    pub repaired: bool,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct HasRequest {
+    // If omitted the operation runs in its own transaction.
     #[serde(rename = "transactionId")]
-    pub transaction_id: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transaction_id: Option<u32>,
     pub key: String,
 }
 
@@ -104,8 +169,10 @@ pub struct HasResponse {
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct GetRequest {
+    // If omitted the operation runs in its own transaction.
     #[serde(rename = "transactionId")]
-    pub transaction_id: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transaction_id: Option<u32>,
     pub key: String,
 }
 
@@ -214,3 +281,39 @@ pub struct SetLogLevelResponse {}
 pub enum SetLogLevelError {
     UnknownLogLevel(String),
 }
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SetAutoPushRequest {
+    // debounceMs is how long to wait after the last local mutation before
+    // pushing. If it is set the request must also carry the fields of a
+    // TryPushRequest and a pusher. If it is absent auto-push is disabled.
+    #[serde(rename = "debounceMs", default)]
+    pub debounce_ms: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SetAutoPushResponse {}
+
+#[derive(Debug)]
+pub enum SetAutoPushError {
+    InvalidPushRequest(JsValue),
+    InvalidPusher(JsValue),
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum TransactionMode {
+    #[serde(rename = "read")]
+    Read,
+    #[serde(rename = "write")]
+    Write,
+}
+
+// Returned for each open transaction by the open_transactions debug command.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct OpenTransactionInfo {
+    #[serde(rename = "transactionId")]
+    pub transaction_id: u32,
+    #[serde(rename = "ageMs")]
+    pub age_ms: u64,
+    pub mode: TransactionMode,
+}
@@ -2,7 +2,9 @@ use crate::fetch::errors::FetchError;
 use crate::fetch::errors::FetchError::*;
 use crate::fetch::timeout::with_timeout;
 use crate::fetch::tokio_compat;
+use crate::fetch::HttpClient;
 use crate::util::to_debug;
+use async_trait::async_trait;
 use http::Request;
 use std::time::Duration;
 
@@ -29,9 +31,8 @@ impl Client {
 
     // request() makes an HTTP request using a native rust HTTP client, as opposed
     // to using the browser's Fetch API in wasm. It consumes its request input by design.
-    // The response returned will have the status and body set but not the headers,
-    // but only because we haven't writtent that code. Non-200 status code does not
-    // constitute an Err Result.
+    // The response returned will have the status, headers and body set. Non-200
+    // status code does not constitute an Err Result.
     //
     // TODO log req/resp
     pub async fn request(
@@ -64,7 +65,10 @@ impl Client {
             .request(hyper_req)
             .await
             .map_err(|e| RequestFailed(to_debug(e)))?;
-        let http_resp_builder = http::response::Builder::new();
+        let mut http_resp_builder = http::response::Builder::new();
+        for (k, v) in hyper_resp.headers().iter() {
+            http_resp_builder = http_resp_builder.header(k.as_str(), v.as_bytes());
+        }
         let http_resp_bytes = hyper::body::to_bytes(hyper_resp.body_mut())
             .await
             .map_err(|e| ErrorReadingResponseBody(to_debug(e)))?;
@@ -79,6 +83,16 @@ impl Client {
     }
 }
 
+#[async_trait(?Send)]
+impl HttpClient for Client {
+    async fn request(
+        &self,
+        http_req: Request<String>,
+    ) -> Result<http::Response<String>, FetchError> {
+        Client::request(self, http_req).await
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
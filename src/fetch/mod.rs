@@ -8,4 +8,17 @@ pub mod client;
 mod tokio_compat;
 
 pub mod errors;
+pub mod retry;
 mod timeout;
+
+use async_trait::async_trait;
+use errors::FetchError;
+use http::{Request, Response};
+
+// HttpClient makes a single attempt at an HTTP request. A non-200 status is
+// not an Err. client::Client is the native implementation; tests implement it
+// to script responses without a server (see eg sync::test_helpers::MockServer).
+#[async_trait(?Send)]
+pub trait HttpClient {
+    async fn request(&self, http_req: Request<String>) -> Result<Response<String>, FetchError>;
+}
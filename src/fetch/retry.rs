@@ -0,0 +1,316 @@
+use crate::fetch::errors::FetchError;
+use crate::fetch::HttpClient;
+use crate::util::uuid::make_random_numbers;
+use http::{Request, Response, StatusCode};
+use std::future::Future;
+use std::time::Duration;
+
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    // The most attempts made at a request, counting the first. Zero is
+    // treated as one.
+    pub max_attempts: usize,
+    // The delay before the first retry. It doubles with each retry after.
+    pub base_delay_ms: u64,
+    // No delay is longer than this, including one asked for by Retry-After.
+    pub max_delay_ms: u64,
+    // Whether to pick each delay at random from the upper half of its range,
+    // so that clients that failed together do not all retry together.
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay_ms: 500,
+            max_delay_ms: 10_000,
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    // Returns how long to wait before attempt number attempt + 1, given the
+    // Retry-After of the failed attempt's response if it had one.
+    fn delay(&self, attempt: usize, retry_after_ms: Option<u64>) -> Duration {
+        let backoff = self
+            .base_delay_ms
+            .saturating_mul(1u64.checked_shl(attempt as u32 - 1).unwrap_or(u64::MAX));
+        let mut delay_ms = retry_after_ms.unwrap_or(backoff).min(self.max_delay_ms);
+        if self.jitter && retry_after_ms.is_none() {
+            let mut random = [0u8; 4];
+            // Without randomness we just don't jitter.
+            if make_random_numbers(&mut random).is_ok() {
+                let half = delay_ms / 2;
+                delay_ms = half + u64::from(u32::from_le_bytes(random)) % (delay_ms - half + 1);
+            }
+        }
+        Duration::from_millis(delay_ms)
+    }
+}
+
+// Outcome is how a caller of retry classifies the result of an attempt.
+pub enum Outcome {
+    // The attempt succeeded, or failed in a way that retrying will not fix.
+    Final,
+    // The attempt failed in a way that may be transient. The delay before
+    // the next attempt is the given number of milliseconds if the server
+    // asked for one, or the backoff otherwise.
+    Transient(Option<u64>),
+}
+
+// retry calls attempt until classify finds its result Final or
+// policy.max_attempts is reached, waiting with exponential backoff in
+// between, and returns the last result.
+pub async fn retry<T, Fut>(
+    policy: &RetryPolicy,
+    mut attempt: impl FnMut() -> Fut,
+    classify: impl Fn(&T) -> Outcome,
+) -> T
+where
+    Fut: Future<Output = T>,
+{
+    let mut n = 1;
+    loop {
+        let res = attempt().await;
+        let retry_after_ms = match classify(&res) {
+            Outcome::Final => return res,
+            Outcome::Transient(retry_after_ms) => retry_after_ms,
+        };
+        if n >= policy.max_attempts {
+            return res;
+        }
+        async_std::task::sleep(policy.delay(n, retry_after_ms)).await;
+        n += 1;
+    }
+}
+
+// request_with_retry makes http_req with client, retrying with
+// exponential backoff while the request fails in a way that may be
+// transient: a network error or timeout, or a 5xx or 429 response. A
+// Retry-After header of a number of seconds on such a response is used as
+// the delay instead of the backoff. Other responses, including other 4xx,
+// are returned as they are, and so is the last failure once
+// policy.max_attempts is reached.
+pub async fn request_with_retry(
+    client: &dyn HttpClient,
+    http_req: Request<String>,
+    policy: &RetryPolicy,
+) -> Result<Response<String>, FetchError> {
+    retry(
+        policy,
+        || client.request(copy_request(&http_req)),
+        |res| match res {
+            Ok(resp) if retryable_status(resp.status()) => Outcome::Transient(retry_after_ms(resp)),
+            Err(e) if retryable_error(e) => Outcome::Transient(None),
+            _ => Outcome::Final,
+        },
+    )
+    .await
+}
+
+// retryable_status is whether a response with status may succeed if the
+// request is retried.
+pub fn retryable_status(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+// retryable_status_code is retryable_status for a status code reported by a
+// request made elsewhere, eg by a JS puller.
+pub fn retryable_status_code(code: u16) -> bool {
+    matches!(StatusCode::from_u16(code), Ok(status) if retryable_status(status))
+}
+
+fn retryable_error(e: &FetchError) -> bool {
+    use FetchError::*;
+    matches!(
+        e,
+        ErrorReadingResponseBody(_) | FetchFailed(_) | RequestFailed(_) | RequestTimeout(_)
+    )
+}
+
+fn retry_after_ms(resp: &Response<String>) -> Option<u64> {
+    resp.headers()
+        .get(http::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(|secs| secs.saturating_mul(1000))
+}
+
+// Request<String> is not Clone, so each attempt gets a copy.
+fn copy_request(http_req: &Request<String>) -> Request<String> {
+    let mut copy = Request::new(http_req.body().clone());
+    *copy.method_mut() = http_req.method().clone();
+    *copy.uri_mut() = http_req.uri().clone();
+    *copy.version_mut() = http_req.version();
+    *copy.headers_mut() = http_req.headers().clone();
+    copy
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use std::cell::RefCell;
+    use std::time::Instant;
+    use str_macro::str;
+
+    // MockHttpClient replies with each of its responses in turn and records
+    // the requests it was sent.
+    struct MockHttpClient {
+        responses: RefCell<Vec<Result<Response<String>, FetchError>>>,
+        requests: RefCell<Vec<Request<String>>>,
+    }
+
+    impl MockHttpClient {
+        fn new(mut responses: Vec<Result<Response<String>, FetchError>>) -> MockHttpClient {
+            responses.reverse();
+            MockHttpClient {
+                responses: RefCell::new(responses),
+                requests: RefCell::new(vec![]),
+            }
+        }
+
+        fn attempts(&self) -> usize {
+            self.requests.borrow().len()
+        }
+    }
+
+    #[async_trait(?Send)]
+    impl HttpClient for MockHttpClient {
+        async fn request(&self, http_req: Request<String>) -> Result<Response<String>, FetchError> {
+            self.requests.borrow_mut().push(http_req);
+            self.responses
+                .borrow_mut()
+                .pop()
+                .expect("no more scripted responses")
+        }
+    }
+
+    fn status(status: u16) -> Result<Response<String>, FetchError> {
+        Ok(Response::builder().status(status).body(str!("")).unwrap())
+    }
+
+    fn request() -> Request<String> {
+        Request::post("http://example.com/pull")
+            .header("Authorization", "auth")
+            .body(str!("body"))
+            .unwrap()
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay_ms: 10,
+            max_delay_ms: 1000,
+            jitter: false,
+        }
+    }
+
+    #[async_std::test]
+    async fn test_request_with_retry() {
+        // Fails twice then succeeds, waiting 10ms then 20ms in between.
+        let client = MockHttpClient::new(vec![
+            Err(FetchError::RequestFailed(str!("connection reset"))),
+            status(503),
+            status(200),
+        ]);
+        let start = Instant::now();
+        let resp = request_with_retry(&client, request(), &policy())
+            .await
+            .unwrap();
+        let elapsed = start.elapsed();
+        assert_eq!(200, resp.status());
+        assert_eq!(3, client.attempts());
+        assert!(elapsed >= Duration::from_millis(30), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(1000), "{:?}", elapsed);
+        for req in client.requests.borrow().iter() {
+            assert_eq!("POST", req.method());
+            assert_eq!("http://example.com/pull", req.uri());
+            assert_eq!("auth", req.headers()["Authorization"]);
+            assert_eq!("body", req.body());
+        }
+
+        // Gives up after max_attempts with the last failure.
+        let client = MockHttpClient::new(vec![status(500), status(429), status(502)]);
+        let resp = request_with_retry(&client, request(), &policy())
+            .await
+            .unwrap();
+        assert_eq!(502, resp.status());
+        assert_eq!(3, client.attempts());
+
+        // Other 4xx and errors that will not go away return at once.
+        let client = MockHttpClient::new(vec![status(403)]);
+        let resp = request_with_retry(&client, request(), &policy())
+            .await
+            .unwrap();
+        assert_eq!(403, resp.status());
+        assert_eq!(1, client.attempts());
+        let client = MockHttpClient::new(vec![Err(FetchError::InvalidRequestBody(str!("bad")))]);
+        assert!(request_with_retry(&client, request(), &policy())
+            .await
+            .is_err());
+        assert_eq!(1, client.attempts());
+    }
+
+    #[async_std::test]
+    async fn test_retry_after() {
+        let client = MockHttpClient::new(vec![
+            Ok(Response::builder()
+                .status(429)
+                .header("Retry-After", "1")
+                .body(str!(""))
+                .unwrap()),
+            status(200),
+        ]);
+        let policy = RetryPolicy {
+            max_delay_ms: 50,
+            ..policy()
+        };
+        let start = Instant::now();
+        let resp = request_with_retry(&client, request(), &policy)
+            .await
+            .unwrap();
+        let elapsed = start.elapsed();
+        assert_eq!(200, resp.status());
+        // The second asked for is capped at max_delay_ms.
+        assert!(elapsed >= Duration::from_millis(50), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(1000), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn test_retryable_status_code() {
+        for code in &[429, 500, 502, 503] {
+            assert!(retryable_status_code(*code), "{}", code);
+        }
+        for code in &[0, 200, 400, 403, 404, 1000] {
+            assert!(!retryable_status_code(*code), "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_delay() {
+        let p = policy();
+        assert_eq!(Duration::from_millis(10), p.delay(1, None));
+        assert_eq!(Duration::from_millis(20), p.delay(2, None));
+        assert_eq!(Duration::from_millis(40), p.delay(3, None));
+        assert_eq!(Duration::from_millis(1000), p.delay(10, None));
+        assert_eq!(Duration::from_millis(1000), p.delay(100, None));
+        assert_eq!(Duration::from_millis(300), p.delay(1, Some(300)));
+        assert_eq!(Duration::from_millis(1000), p.delay(1, Some(5000)));
+
+        let p = RetryPolicy {
+            jitter: true,
+            ..policy()
+        };
+        for _ in 0..100 {
+            let d = p.delay(3, None);
+            assert!(d >= Duration::from_millis(20) && d <= Duration::from_millis(40));
+        }
+    }
+}
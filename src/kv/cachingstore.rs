@@ -0,0 +1,461 @@
+use crate::kv::{ByteCounts, CommitStats, Read, Result, SavepointId, Store, Write};
+use crate::util::rlog::LogContext;
+use async_trait::async_trait;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+// CachingStore is a view of another store that keeps the values it has most
+// recently read in memory, so that repeated gets of hot keys (eg chunks read
+// over and over while applying a pull) do not each go to the underlying
+// store. The cache holds at most capacity_bytes of keys and values and evicts
+// the least recently used entries beyond that. Only committed values are
+// cached: a write transaction reads keys it has written from the underlying
+// transaction and its commit drops those keys from the cache. Snapshots are
+// not cached. Writes made to the underlying store other than through the view
+// are not seen by the cache, so once wrapped the store should only be written
+// through it.
+pub struct CachingStore<'a> {
+    store: &'a dyn Store,
+    cache: RefCell<Lru>,
+}
+
+impl<'a> CachingStore<'a> {
+    pub fn new(store: &'a dyn Store, capacity_bytes: usize) -> CachingStore<'a> {
+        CachingStore {
+            store,
+            cache: RefCell::new(Lru::new(capacity_bytes)),
+        }
+    }
+
+    // Returns the number of bytes of keys and values cached.
+    pub fn cached_bytes(&self) -> usize {
+        self.cache.borrow().size
+    }
+}
+
+#[async_trait(?Send)]
+impl Store for CachingStore<'_> {
+    async fn read<'a>(&'a self, lc: LogContext) -> Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(CachingRead {
+            read: self.store.read(lc).await?,
+            cache: &self.cache,
+        }))
+    }
+
+    async fn write<'a>(&'a self, lc: LogContext) -> Result<Box<dyn Write + 'a>> {
+        Ok(Box::new(CachingWrite {
+            write: self.store.write(lc).await?,
+            cache: &self.cache,
+            written: RefCell::new(HashSet::new()),
+        }))
+    }
+
+    async fn snapshot<'a>(&'a self) -> Result<Box<dyn Read + 'a>> {
+        self.store.snapshot().await
+    }
+
+    // A cached value is served without opening a transaction.
+    async fn has(&self, key: &str) -> Result<bool> {
+        if self.cache.borrow_mut().get(key).is_some() {
+            return Ok(true);
+        }
+        self.read(LogContext::new()).await?.has(key).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        if let Some(value) = self.cache.borrow_mut().get(key) {
+            return Ok(Some(value));
+        }
+        self.read(LogContext::new()).await?.get(key).await
+    }
+
+    // Marks a cached key as recently used.
+    async fn touch(&self, key: &str) -> Result<bool> {
+        self.has(key).await
+    }
+
+    async fn del_prefix(&self, prefix: &str) -> Result<()> {
+        let res = self.store.del_prefix(prefix).await;
+        self.cache.borrow_mut().remove_prefix(prefix);
+        res
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.store.flush().await
+    }
+
+    async fn close(&self) {}
+}
+
+struct CachingRead<'a> {
+    read: Box<dyn Read + 'a>,
+    cache: &'a RefCell<Lru>,
+}
+
+#[async_trait(?Send)]
+impl Read for CachingRead<'_> {
+    async fn has(&self, key: &str) -> Result<bool> {
+        if self.cache.borrow_mut().get(key).is_some() {
+            return Ok(true);
+        }
+        self.read.has(key).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        if let Some(value) = self.cache.borrow_mut().get(key) {
+            return Ok(Some(value));
+        }
+        let value = self.read.get(key).await?;
+        if let Some(value) = &value {
+            self.cache.borrow_mut().insert(key, value);
+        }
+        Ok(value)
+    }
+
+    async fn content_hash(&self) -> Result<[u8; 32]> {
+        self.read.content_hash().await
+    }
+
+    async fn keys(&self, prefix: &str) -> Result<Vec<String>> {
+        self.read.keys(prefix).await
+    }
+
+    async fn count_prefix(&self, prefix: &str) -> Result<u64> {
+        self.read.count_prefix(prefix).await
+    }
+
+    // Counts are of the underlying transaction, so cache hits read nothing.
+    fn byte_counts(&self) -> Option<ByteCounts> {
+        self.read.byte_counts()
+    }
+}
+
+struct CachingWrite<'a> {
+    write: Box<dyn Write + 'a>,
+    cache: &'a RefCell<Lru>,
+    // The keys put or deleted in this transaction, whose cached values are
+    // stale for it and, once it commits, for everyone.
+    written: RefCell<HashSet<String>>,
+}
+
+#[async_trait(?Send)]
+impl Read for CachingWrite<'_> {
+    async fn has(&self, key: &str) -> Result<bool> {
+        if !self.written.borrow().contains(key) && self.cache.borrow_mut().get(key).is_some() {
+            return Ok(true);
+        }
+        self.write.has(key).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        if self.written.borrow().contains(key) {
+            return self.write.get(key).await;
+        }
+        if let Some(value) = self.cache.borrow_mut().get(key) {
+            return Ok(Some(value));
+        }
+        let value = self.write.get(key).await?;
+        // Unless it is written since, the value is the committed one.
+        if let Some(value) = &value {
+            if !self.written.borrow().contains(key) {
+                self.cache.borrow_mut().insert(key, value);
+            }
+        }
+        Ok(value)
+    }
+
+    async fn content_hash(&self) -> Result<[u8; 32]> {
+        self.write.content_hash().await
+    }
+
+    async fn keys(&self, prefix: &str) -> Result<Vec<String>> {
+        self.write.keys(prefix).await
+    }
+
+    async fn count_prefix(&self, prefix: &str) -> Result<u64> {
+        self.write.count_prefix(prefix).await
+    }
+
+    fn byte_counts(&self) -> Option<ByteCounts> {
+        self.write.byte_counts()
+    }
+}
+
+#[async_trait(?Send)]
+impl Write for CachingWrite<'_> {
+    fn as_read(&self) -> &dyn Read {
+        self
+    }
+
+    async fn put(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.written.borrow_mut().insert(key.to_string());
+        self.write.put(key, value).await
+    }
+
+    async fn del(&self, key: &str) -> Result<()> {
+        self.written.borrow_mut().insert(key.to_string());
+        self.write.del(key).await
+    }
+
+    async fn is_dirty(&self) -> Result<bool> {
+        self.write.is_dirty().await
+    }
+
+    async fn savepoint(&self) -> Result<SavepointId> {
+        self.write.savepoint().await
+    }
+
+    // Keys written since the savepoint stay in written: invalidating a key
+    // that ends up unchanged only costs a cache miss.
+    async fn rollback_to(&self, id: SavepointId) -> Result<()> {
+        self.write.rollback_to(id).await
+    }
+
+    async fn commit(self: Box<Self>) -> Result<()> {
+        self.commit_with_stats().await.map(|_| ())
+    }
+
+    // The written keys are dropped from the cache even if the commit fails,
+    // as it may have gone through in part.
+    async fn commit_with_stats(self: Box<Self>) -> Result<Option<CommitStats>> {
+        let res = self.write.commit_with_stats().await;
+        let mut cache = self.cache.borrow_mut();
+        for key in self.written.into_inner() {
+            cache.remove(&key);
+        }
+        res
+    }
+}
+
+// Lru is a map from keys to values that evicts the least recently used
+// entries once the keys and values in it add up to more than capacity bytes.
+struct Lru {
+    capacity: usize,
+    size: usize,
+    tick: u64,
+    // Each entry's value and the tick it was last used at.
+    entries: HashMap<String, (Vec<u8>, u64)>,
+    // Keys by the tick they were last used at, least recent first.
+    order: BTreeMap<u64, String>,
+}
+
+impl Lru {
+    fn new(capacity: usize) -> Lru {
+        Lru {
+            capacity,
+            size: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn get(&mut self, key: &str) -> Option<Vec<u8>> {
+        let tick = self.next_tick();
+        let (value, used) = self.entries.get_mut(key)?;
+        self.order.remove(used);
+        *used = tick;
+        self.order.insert(tick, key.to_string());
+        Some(value.clone())
+    }
+
+    // Values bigger than the whole cache are not cached.
+    fn insert(&mut self, key: &str, value: &[u8]) {
+        self.remove(key);
+        let size = key.len() + value.len();
+        if size > self.capacity {
+            return;
+        }
+        while self.size + size > self.capacity {
+            let oldest = match self.order.keys().next() {
+                Some(tick) => *tick,
+                None => break,
+            };
+            if let Some(key) = self.order.remove(&oldest) {
+                self.remove(&key);
+            }
+        }
+        let tick = self.next_tick();
+        self.entries.insert(key.to_string(), (value.to_vec(), tick));
+        self.order.insert(tick, key.to_string());
+        self.size += size;
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some((value, used)) = self.entries.remove(key) {
+            self.order.remove(&used);
+            self.size -= key.len() + value.len();
+        }
+    }
+
+    fn remove_prefix(&mut self, prefix: &str) {
+        let keys: Vec<String> = self
+            .entries
+            .keys()
+            .filter(|k| k.starts_with(prefix))
+            .cloned()
+            .collect();
+        for key in keys {
+            self.remove(&key);
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::memstore::MemStore;
+    use std::cell::Cell;
+
+    // CountingStore counts the gets made through its read transactions.
+    struct CountingStore {
+        store: MemStore,
+        gets: Cell<usize>,
+    }
+
+    struct CountingRead<'a> {
+        read: Box<dyn Read + 'a>,
+        gets: &'a Cell<usize>,
+    }
+
+    #[async_trait(?Send)]
+    impl Store for CountingStore {
+        async fn read<'a>(&'a self, lc: LogContext) -> Result<Box<dyn Read + 'a>> {
+            Ok(Box::new(CountingRead {
+                read: self.store.read(lc).await?,
+                gets: &self.gets,
+            }))
+        }
+
+        async fn write<'a>(&'a self, lc: LogContext) -> Result<Box<dyn Write + 'a>> {
+            self.store.write(lc).await
+        }
+
+        async fn close(&self) {}
+    }
+
+    #[async_trait(?Send)]
+    impl Read for CountingRead<'_> {
+        async fn has(&self, key: &str) -> Result<bool> {
+            self.read.has(key).await
+        }
+
+        async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+            self.gets.set(self.gets.get() + 1);
+            self.read.get(key).await
+        }
+    }
+
+    #[async_std::test]
+    async fn test_caching_store() {
+        let counting = CountingStore {
+            store: MemStore::new(),
+            gets: Cell::new(0),
+        };
+        let gets = || counting.gets.get();
+        let cs = CachingStore::new(&counting, 1024);
+
+        // A second get is served from the cache, in or out of a transaction.
+        cs.put("a", b"1").await.unwrap();
+        assert_eq!(Some(b"1".to_vec()), cs.get("a").await.unwrap());
+        assert_eq!(1, gets());
+        assert_eq!(Some(b"1".to_vec()), cs.get("a").await.unwrap());
+        let rt = cs.read(LogContext::new()).await.unwrap();
+        assert_eq!(Some(b"1".to_vec()), rt.get("a").await.unwrap());
+        assert!(rt.has("a").await.unwrap());
+        drop(rt);
+        assert_eq!(1, gets());
+
+        // Missing keys are not cached.
+        assert_eq!(None, cs.get("b").await.unwrap());
+        assert_eq!(None, cs.get("b").await.unwrap());
+        assert_eq!(3, gets());
+
+        // A write transaction sees its own writes and its commit drops them
+        // from the cache.
+        let wt = cs.write(LogContext::new()).await.unwrap();
+        assert_eq!(Some(b"1".to_vec()), wt.get("a").await.unwrap());
+        wt.put("a", b"2").await.unwrap();
+        assert_eq!(Some(b"2".to_vec()), wt.get("a").await.unwrap());
+        wt.commit().await.unwrap();
+        assert_eq!(Some(b"2".to_vec()), cs.get("a").await.unwrap());
+        assert_eq!(4, gets());
+
+        // A rolled back write leaves the cache alone.
+        let wt = cs.write(LogContext::new()).await.unwrap();
+        wt.del("a").await.unwrap();
+        assert!(!wt.has("a").await.unwrap());
+        drop(wt);
+        assert_eq!(Some(b"2".to_vec()), cs.get("a").await.unwrap());
+        assert_eq!(4, gets());
+
+        let wt = cs.write(LogContext::new()).await.unwrap();
+        wt.del("a").await.unwrap();
+        wt.commit().await.unwrap();
+        assert_eq!(None, cs.get("a").await.unwrap());
+        assert_eq!(0, cs.cached_bytes());
+    }
+
+    #[async_std::test]
+    async fn test_caching_store_evicts() {
+        let counting = CountingStore {
+            store: MemStore::new(),
+            gets: Cell::new(0),
+        };
+        let gets = || counting.gets.get();
+        // Room for two entries of five bytes.
+        let cs = CachingStore::new(&counting, 10);
+        for key in &["b", "c", "d"] {
+            cs.put(key, b"vvvv").await.unwrap();
+        }
+        cs.get("b").await.unwrap();
+        cs.get("c").await.unwrap();
+        cs.get("b").await.unwrap();
+        assert_eq!(2, gets());
+        assert_eq!(10, cs.cached_bytes());
+
+        // c is the least recently used.
+        cs.get("d").await.unwrap();
+        assert_eq!(3, gets());
+        cs.get("b").await.unwrap();
+        assert_eq!(3, gets());
+        cs.get("c").await.unwrap();
+        assert_eq!(4, gets());
+        assert_eq!(10, cs.cached_bytes());
+
+        // Values bigger than the cache are not cached.
+        cs.put("big", &[0; 20]).await.unwrap();
+        cs.get("big").await.unwrap();
+        cs.get("big").await.unwrap();
+        assert_eq!(6, gets());
+    }
+
+    #[async_std::test]
+    async fn test_caching_store_count() {
+        use crate::kv::encryptedstore::{EncryptedStore, EncryptedStoreOptions};
+
+        // A store with hashed keys can count its keys but not list them, so
+        // counting only works if it is forwarded rather than listed.
+        let ms = MemStore::new();
+        let es = EncryptedStore::new_with_options(
+            &ms,
+            &[0u8; 32],
+            EncryptedStoreOptions { hash_keys: true },
+        );
+        let cs = CachingStore::new(&es, 1024);
+        cs.put("a", b"1").await.unwrap();
+        cs.put("b", b"2").await.unwrap();
+        let rt = cs.read(LogContext::new()).await.unwrap();
+        assert_eq!(2, rt.count().await.unwrap());
+        drop(rt);
+        let wt = cs.write(LogContext::new()).await.unwrap();
+        wt.put("c", b"3").await.unwrap();
+        assert_eq!(3, wt.count().await.unwrap());
+    }
+}
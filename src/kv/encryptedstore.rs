@@ -0,0 +1,429 @@
+use crate::kv::{
+    check_key, ByteCounts, CommitStats, Read, Result, SavepointId, Store, StoreError, Write,
+};
+use crate::util::rlog::LogContext;
+use crate::util::uuid::make_random_numbers;
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::Aes256Gcm;
+use async_trait::async_trait;
+use data_encoding::HEXLOWER;
+use sha2::{Digest, Sha256};
+
+// The length of the random nonce stored in front of each ciphertext.
+pub const NONCE_BYTES: usize = 12;
+
+// The message the key for hashing keys is derived from, so that the
+// caller's key is not used directly for two purposes.
+const KEY_HASH_CONTEXT: &[u8] = b"replicache encrypted store keys";
+
+#[derive(Clone, Debug, Default)]
+pub struct EncryptedStoreOptions {
+    // Store keys as their hex encoded HMAC-SHA256 rather than in plaintext.
+    // Hashed keys cannot be listed, so keys, scans, count_prefix (with a
+    // non-empty prefix) and del_prefix are not supported. export, import
+    // and migrate, which list keys, fail with NotSupported("keys") too.
+    // clear still works, since it empties the underlying store.
+    pub hash_keys: bool,
+}
+
+// EncryptedStore is a view of another store that encrypts values at rest
+// with AES-256-GCM. Each value is stored as a random nonce followed by its
+// ciphertext, which is bound to the key it is stored under so that values
+// cannot be moved between keys unnoticed. Keys are left in plaintext unless
+// options.hash_keys is set. has and count go straight to the underlying
+// store without decrypting anything.
+pub struct EncryptedStore<'a> {
+    store: &'a dyn Store,
+    crypto: Crypto,
+}
+
+impl<'a> EncryptedStore<'a> {
+    pub fn new(store: &'a dyn Store, key: &[u8; 32]) -> EncryptedStore<'a> {
+        EncryptedStore::new_with_options(store, key, EncryptedStoreOptions::default())
+    }
+
+    pub fn new_with_options(
+        store: &'a dyn Store,
+        key: &[u8; 32],
+        options: EncryptedStoreOptions,
+    ) -> EncryptedStore<'a> {
+        EncryptedStore {
+            store,
+            crypto: Crypto {
+                cipher: Aes256Gcm::new(GenericArray::from_slice(key)),
+                key_hash_key: if options.hash_keys {
+                    Some(hmac_sha256(key, KEY_HASH_CONTEXT))
+                } else {
+                    None
+                },
+            },
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Store for EncryptedStore<'_> {
+    async fn read<'a>(&'a self, lc: LogContext) -> Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(EncryptedRead {
+            read: self.store.read(lc).await?,
+            crypto: &self.crypto,
+        }))
+    }
+
+    async fn write<'a>(&'a self, lc: LogContext) -> Result<Box<dyn Write + 'a>> {
+        Ok(Box::new(EncryptedWrite {
+            write: self.store.write(lc).await?,
+            crypto: &self.crypto,
+        }))
+    }
+
+    async fn snapshot<'a>(&'a self) -> Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(EncryptedRead {
+            read: self.store.snapshot().await?,
+            crypto: &self.crypto,
+        }))
+    }
+
+    async fn del_prefix(&self, prefix: &str) -> Result<()> {
+        self.crypto.check_listable("del_prefix")?;
+        self.store.del_prefix(prefix).await
+    }
+
+    // Every key in the underlying store is ours, so clearing does not need
+    // to list them.
+    async fn clear(&self) -> Result<()> {
+        self.store.clear().await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.store.flush().await
+    }
+
+    async fn close(&self) {}
+}
+
+struct Crypto {
+    cipher: Aes256Gcm,
+    // Set if keys are hashed.
+    key_hash_key: Option<[u8; 32]>,
+}
+
+impl Crypto {
+    // Returns the key that key is stored under in the underlying store.
+    fn stored_key(&self, key: &str) -> String {
+        match &self.key_hash_key {
+            None => key.to_string(),
+            Some(k) => HEXLOWER.encode(&hmac_sha256(k, key.as_bytes())),
+        }
+    }
+
+    fn check_listable(&self, op: &'static str) -> Result<()> {
+        match self.key_hash_key {
+            None => Ok(()),
+            Some(_) => Err(StoreError::NotSupported(op)),
+        }
+    }
+
+    fn seal(&self, stored_key: &str, value: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_BYTES];
+        make_random_numbers(&mut nonce).map_err(|e| format!("Failed to make nonce: {:?}", e))?;
+        let payload = Payload {
+            msg: value,
+            aad: stored_key.as_bytes(),
+        };
+        let ciphertext = self
+            .cipher
+            .encrypt(GenericArray::from_slice(&nonce), payload)
+            .map_err(|_| format!("Failed to encrypt value of {}", stored_key))?;
+        let mut sealed = Vec::with_capacity(NONCE_BYTES + ciphertext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    fn open(&self, stored_key: &str, sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < NONCE_BYTES {
+            return Err(format!("Encrypted value of {} is truncated", stored_key).into());
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_BYTES);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: stored_key.as_bytes(),
+        };
+        self.cipher
+            .decrypt(GenericArray::from_slice(nonce), payload)
+            .map_err(|_| format!("Failed to decrypt value of {}", stored_key).into())
+    }
+
+    async fn get(&self, read: &dyn Read, key: &str) -> Result<Option<Vec<u8>>> {
+        let stored_key = self.stored_key(key);
+        match read.get(&stored_key).await? {
+            None => Ok(None),
+            Some(sealed) => self.open(&stored_key, &sealed).map(Some),
+        }
+    }
+
+    async fn keys(&self, read: &dyn Read, prefix: &str) -> Result<Vec<String>> {
+        self.check_listable("keys")?;
+        read.keys(prefix).await
+    }
+
+    // Counting all keys works whether or not they are hashed.
+    async fn count_prefix(&self, read: &dyn Read, prefix: &str) -> Result<u64> {
+        if prefix.is_empty() {
+            return read.count().await;
+        }
+        self.check_listable("count_prefix")?;
+        read.count_prefix(prefix).await
+    }
+}
+
+struct EncryptedRead<'a> {
+    read: Box<dyn Read + 'a>,
+    crypto: &'a Crypto,
+}
+
+// content_hash is left unsupported: the underlying store's hash covers the
+// ciphertexts, which change with every put of the same value.
+#[async_trait(?Send)]
+impl Read for EncryptedRead<'_> {
+    async fn has(&self, key: &str) -> Result<bool> {
+        self.read.has(&self.crypto.stored_key(key)).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.crypto.get(&*self.read, key).await
+    }
+
+    async fn keys(&self, prefix: &str) -> Result<Vec<String>> {
+        self.crypto.keys(&*self.read, prefix).await
+    }
+
+    async fn count_prefix(&self, prefix: &str) -> Result<u64> {
+        self.crypto.count_prefix(&*self.read, prefix).await
+    }
+
+    // Counts are of the stored bytes, so include nonces and tags.
+    fn byte_counts(&self) -> Option<ByteCounts> {
+        self.read.byte_counts()
+    }
+}
+
+struct EncryptedWrite<'a> {
+    write: Box<dyn Write + 'a>,
+    crypto: &'a Crypto,
+}
+
+#[async_trait(?Send)]
+impl Read for EncryptedWrite<'_> {
+    async fn has(&self, key: &str) -> Result<bool> {
+        self.write.has(&self.crypto.stored_key(key)).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.crypto.get(self.write.as_read(), key).await
+    }
+
+    async fn keys(&self, prefix: &str) -> Result<Vec<String>> {
+        self.crypto.keys(self.write.as_read(), prefix).await
+    }
+
+    async fn count_prefix(&self, prefix: &str) -> Result<u64> {
+        self.crypto.count_prefix(self.write.as_read(), prefix).await
+    }
+
+    fn byte_counts(&self) -> Option<ByteCounts> {
+        self.write.byte_counts()
+    }
+}
+
+#[async_trait(?Send)]
+impl Write for EncryptedWrite<'_> {
+    fn as_read(&self) -> &dyn Read {
+        self
+    }
+
+    async fn put(&self, key: &str, value: &[u8]) -> Result<()> {
+        // A hashed key never looks reserved to the underlying store.
+        check_key(key)?;
+        let stored_key = self.crypto.stored_key(key);
+        let sealed = self.crypto.seal(&stored_key, value)?;
+        self.write.put(&stored_key, &sealed).await
+    }
+
+    async fn del(&self, key: &str) -> Result<()> {
+        self.write.del(&self.crypto.stored_key(key)).await
+    }
+
+    async fn is_dirty(&self) -> Result<bool> {
+        self.write.is_dirty().await
+    }
+
+    async fn savepoint(&self) -> Result<SavepointId> {
+        self.write.savepoint().await
+    }
+
+    async fn rollback_to(&self, id: SavepointId) -> Result<()> {
+        self.write.rollback_to(id).await
+    }
+
+    async fn commit(self: Box<Self>) -> Result<()> {
+        self.write.commit().await
+    }
+
+    // Like byte_counts, bytes_written counts the stored bytes.
+    async fn commit_with_stats(self: Box<Self>) -> Result<Option<CommitStats>> {
+        self.write.commit_with_stats().await
+    }
+}
+
+// HMAC-SHA256 as in RFC 2104.
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    const BLOCK_BYTES: usize = 64;
+    let mut block = [0u8; BLOCK_BYTES];
+    if key.len() > BLOCK_BYTES {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = Sha256::new();
+    inner.input(block.iter().map(|b| b ^ 0x36).collect::<Vec<u8>>());
+    inner.input(message);
+    let mut outer = Sha256::new();
+    outer.input(block.iter().map(|b| b ^ 0x5c).collect::<Vec<u8>>());
+    outer.input(inner.result());
+    let mut mac = [0u8; 32];
+    mac.copy_from_slice(&outer.result());
+    mac
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::memstore::MemStore;
+
+    const KEY: [u8; 32] = [7u8; 32];
+
+    #[test]
+    fn test_hmac_sha256() {
+        // RFC 4231 test cases 2 and 6.
+        assert_eq!(
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            HEXLOWER.encode(&hmac_sha256(b"Jefe", b"what do ya want for nothing?"))
+        );
+        assert_eq!(
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+            HEXLOWER.encode(&hmac_sha256(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            ))
+        );
+    }
+
+    #[async_std::test]
+    async fn test_encrypted_store() {
+        let ms = MemStore::new();
+        let es = EncryptedStore::new(&ms, &KEY);
+
+        es.put("foo", b"bar").await.unwrap();
+        assert_eq!(Some(b"bar".to_vec()), es.get("foo").await.unwrap());
+        assert!(es.has("foo").await.unwrap());
+        assert_eq!(None, es.get("baz").await.unwrap());
+
+        // The stored bytes are a nonce and ciphertext, not the value, and
+        // putting the same value again picks a fresh nonce.
+        let raw = ms.get("foo").await.unwrap().unwrap();
+        assert_ne!(b"bar".to_vec(), raw);
+        assert!(!raw.windows(3).any(|w| w == b"bar"));
+        es.put("foo", b"bar").await.unwrap();
+        assert_ne!(raw, ms.get("foo").await.unwrap().unwrap());
+
+        let wt = es.write(LogContext::new()).await.unwrap();
+        wt.put("a", b"1").await.unwrap();
+        wt.put("b", b"").await.unwrap();
+        wt.del("foo").await.unwrap();
+        assert_eq!(Some(b"1".to_vec()), wt.get("a").await.unwrap());
+        wt.commit().await.unwrap();
+        let rt = es.read(LogContext::new()).await.unwrap();
+        assert_eq!(Some(vec![]), rt.get("b").await.unwrap());
+        assert_eq!(vec!["a", "b"], rt.keys("").await.unwrap());
+        assert_eq!(2, rt.count().await.unwrap());
+        assert_eq!(1, rt.count_prefix("a").await.unwrap());
+        drop(rt);
+
+        // Values do not decrypt under another key or when moved to another
+        // key.
+        let other = EncryptedStore::new(&ms, &[8u8; 32]);
+        assert!(other.get("a").await.is_err());
+        assert!(other.has("a").await.unwrap());
+        let raw = ms.get("a").await.unwrap().unwrap();
+        ms.put("c", &raw).await.unwrap();
+        assert!(es.get("c").await.is_err());
+        ms.put("d", b"short").await.unwrap();
+        assert!(es.get("d").await.is_err());
+    }
+
+    #[async_std::test]
+    async fn test_encrypted_store_hash_keys() {
+        let ms = MemStore::new();
+        let es =
+            EncryptedStore::new_with_options(&ms, &KEY, EncryptedStoreOptions { hash_keys: true });
+
+        es.put("foo", b"bar").await.unwrap();
+        es.put("fob", b"baz").await.unwrap();
+        assert_eq!(Some(b"bar".to_vec()), es.get("foo").await.unwrap());
+        assert!(es.has("foo").await.unwrap());
+        assert!(!ms.has("foo").await.unwrap());
+        let raw_keys = ms
+            .read(LogContext::new())
+            .await
+            .unwrap()
+            .keys("")
+            .await
+            .unwrap();
+        assert_eq!(2, raw_keys.len());
+        assert!(raw_keys.iter().all(|k| k.len() == 64 && !k.contains("fo")));
+
+        let rt = es.read(LogContext::new()).await.unwrap();
+        assert_eq!(2, rt.count().await.unwrap());
+        assert_eq!(Err(StoreError::NotSupported("keys")), rt.keys("").await);
+        assert_eq!(
+            Err(StoreError::NotSupported("count_prefix")),
+            rt.count_prefix("fo").await
+        );
+        drop(rt);
+        assert_eq!(
+            Err(StoreError::NotSupported("del_prefix")),
+            es.del_prefix("fo").await
+        );
+
+        let wt = es.write(LogContext::new()).await.unwrap();
+        wt.del("foo").await.unwrap();
+        wt.commit().await.unwrap();
+        assert!(!es.has("foo").await.unwrap());
+        assert_eq!(
+            1,
+            es.read(LogContext::new())
+                .await
+                .unwrap()
+                .count()
+                .await
+                .unwrap()
+        );
+
+        // import lists the keys it replaces, so it fails and leaves the
+        // store as it was. clear does not need to list them.
+        let blob = MemStore::new().export().await.unwrap();
+        assert_eq!(
+            Err(StoreError::NotSupported("keys")),
+            es.import(&blob).await
+        );
+        assert!(es.has("fob").await.unwrap());
+        es.clear().await.unwrap();
+        assert!(!es.has("fob").await.unwrap());
+        let rt = ms.read(LogContext::new()).await.unwrap();
+        assert_eq!(0, rt.count().await.unwrap());
+    }
+}
@@ -1,6 +1,9 @@
-use crate::kv::{Read, Result, Store, Write};
+use crate::kv::{
+    check_key, content_hash, ByteCounter, ByteCounts, Read, Result, Store, StoreError, Write,
+};
 use crate::util::rlog::LogContext;
 use async_trait::async_trait;
+use std::cell::Cell;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use wasm_bindgen::JsValue;
@@ -20,6 +23,11 @@ extern "C" {
     async fn has(this: &JsRead, key: &str) -> std::result::Result<JsValue, JsValue>;
     #[wasm_bindgen(method, catch)]
     async fn get(this: &JsRead, key: &str) -> std::result::Result<JsValue, JsValue>;
+    // Resolves to an array of the keys visible to the transaction, including
+    // a write's staged puts and dels, that start with prefix. The order does
+    // not matter.
+    #[wasm_bindgen(method, catch)]
+    async fn keys(this: &JsRead, prefix: &str) -> std::result::Result<JsValue, JsValue>;
 
     type JsRelease;
     #[wasm_bindgen(method)]
@@ -44,6 +52,47 @@ impl JsStore {
     }
 }
 
+// A minimal JS store over a Map, staging writes until commit.
+#[cfg(all(test, target_arch = "wasm32"))]
+pub(crate) fn new_test_store() -> JsStore {
+    let new_store = js_sys::Function::new_no_args(
+        r#"
+        const map = new Map();
+        const reader = pending => ({
+            has: async k => pending && pending.has(k)
+                ? pending.get(k) !== undefined
+                : map.has(k),
+            get: async k => pending && pending.has(k) ? pending.get(k) : map.get(k),
+            keys: async prefix => {
+                const keys = new Set(map.keys());
+                for (const [k, v] of pending || []) {
+                    if (v === undefined) keys.delete(k); else keys.add(k);
+                }
+                return [...keys].filter(k => k.startsWith(prefix));
+            },
+            release() {},
+        });
+        return {
+            read: async () => reader(null),
+            write: async () => {
+                const pending = new Map();
+                return Object.assign(reader(pending), {
+                    put: async (k, v) => { pending.set(k, v.slice()); },
+                    del: async k => { pending.set(k, undefined); },
+                    commit: async () => {
+                        for (const [k, v] of pending) {
+                            if (v === undefined) map.delete(k); else map.set(k, v);
+                        }
+                    },
+                });
+            },
+            close: async () => {},
+        };
+        "#,
+    );
+    JsStore::new(new_store.call0(&JsValue::NULL).unwrap())
+}
+
 #[async_trait(?Send)]
 impl Store for JsStore {
     async fn read<'a>(&'a self, _lc: LogContext) -> Result<Box<dyn Read + 'a>> {
@@ -52,10 +101,10 @@ impl Store for JsStore {
         Ok(Box::new(JsReadProxy::new(r)))
     }
 
-    async fn write<'a>(&'a self, _lc: LogContext) -> Result<Box<dyn Write + 'a>> {
+    async fn write<'a>(&'a self, lc: LogContext) -> Result<Box<dyn Write + 'a>> {
         let v = self.write_impl().await?;
         let w = v.unchecked_into::<JsWrite>();
-        Ok(Box::new(JsWriteProxy::new(w)))
+        Ok(Box::new(JsWriteProxy::new(w, lc)))
     }
 
     async fn close(&self) {
@@ -65,11 +114,15 @@ impl Store for JsStore {
 
 struct JsReadProxy {
     js: JsRead,
+    bytes: ByteCounter,
 }
 
 impl JsReadProxy {
     fn new(js: JsRead) -> JsReadProxy {
-        JsReadProxy { js }
+        JsReadProxy {
+            js,
+            bytes: ByteCounter::default(),
+        }
     }
 }
 
@@ -80,7 +133,19 @@ impl Read for JsReadProxy {
     }
 
     async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
-        get(&self.js, key).await
+        get(&self.js, key, &self.bytes).await
+    }
+
+    async fn keys(&self, prefix: &str) -> Result<Vec<String>> {
+        keys(&self.js, prefix).await
+    }
+
+    async fn content_hash(&self) -> Result<[u8; 32]> {
+        js_content_hash(self).await
+    }
+
+    fn byte_counts(&self) -> Option<ByteCounts> {
+        Some(self.bytes.counts())
     }
 }
 
@@ -89,15 +154,37 @@ async fn has(js: &JsRead, key: &str) -> Result<bool> {
     Ok(v == JsValue::TRUE)
 }
 
-async fn get(js: &JsRead, key: &str) -> Result<Option<Vec<u8>>> {
+async fn get(js: &JsRead, key: &str, bytes: &ByteCounter) -> Result<Option<Vec<u8>>> {
     let v: JsValue = js.get(key).await?;
     Ok(if v.is_undefined() {
         None
     } else {
-        Some(v.unchecked_into::<js_sys::Uint8Array>().to_vec())
+        let v = v.unchecked_into::<js_sys::Uint8Array>().to_vec();
+        bytes.read(v.len());
+        Some(v)
     })
 }
 
+async fn keys(js: &JsRead, prefix: &str) -> Result<Vec<String>> {
+    let v: JsValue = js.keys(prefix).await?;
+    let mut keys = js_sys::Array::from(&v)
+        .iter()
+        .map(|k| {
+            k.as_string()
+                .ok_or_else(|| StoreError::Str(format!("keys returned non-string {:?}", k)))
+        })
+        .collect::<Result<Vec<String>>>()?;
+    keys.sort();
+    Ok(keys)
+}
+
+async fn js_content_hash(read: &dyn Read) -> Result<[u8; 32]> {
+    let entries = read.scan_values("").await?;
+    Ok(content_hash(
+        entries.iter().map(|(k, v)| (k.as_str(), v.as_slice())),
+    ))
+}
+
 // We need to implement drop so that we can release the underlying lock on the
 // js side. This also prevents us from directly using the JsValue and we have to
 // wrap it in a Rust proxy.
@@ -109,11 +196,20 @@ impl Drop for JsReadProxy {
 
 struct JsWriteProxy {
     js: JsWrite,
+    // Set by put() and del(); the JS side does not expose its staged writes.
+    dirty: Cell<bool>,
+    bytes: ByteCounter,
+    lc: LogContext,
 }
 
 impl JsWriteProxy {
-    fn new(js: JsWrite) -> JsWriteProxy {
-        JsWriteProxy { js }
+    fn new(js: JsWrite, lc: LogContext) -> JsWriteProxy {
+        JsWriteProxy {
+            js,
+            dirty: Cell::new(false),
+            bytes: ByteCounter::default(),
+            lc,
+        }
     }
 }
 
@@ -124,10 +220,24 @@ impl Read for JsWriteProxy {
     }
 
     async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
-        get(self.js.unchecked_ref::<JsRead>(), key).await
+        get(self.js.unchecked_ref::<JsRead>(), key, &self.bytes).await
+    }
+
+    async fn keys(&self, prefix: &str) -> Result<Vec<String>> {
+        keys(self.js.unchecked_ref::<JsRead>(), prefix).await
+    }
+
+    async fn content_hash(&self) -> Result<[u8; 32]> {
+        js_content_hash(self).await
+    }
+
+    fn byte_counts(&self) -> Option<ByteCounts> {
+        Some(self.bytes.counts())
     }
 }
 
+// If commit() was never called, release() is how the JS side learns that the
+// transaction must be rolled back rather than committed (see kv::Write).
 impl Drop for JsWriteProxy {
     fn drop(&mut self) {
         self.js.unchecked_ref::<JsRelease>().release();
@@ -141,14 +251,72 @@ impl Write for JsWriteProxy {
     }
 
     async fn put(&self, key: &str, value: &[u8]) -> Result<()> {
+        check_key(key)?;
+        self.dirty.set(true);
+        self.bytes.wrote(key.len() + value.len());
         Ok(self.js.put(key, &js_sys::Uint8Array::from(value)).await?)
     }
 
     async fn del(&self, key: &str) -> Result<()> {
+        self.dirty.set(true);
         Ok(self.js.del(key).await?)
     }
 
+    async fn is_dirty(&self) -> Result<bool> {
+        Ok(self.dirty.get())
+    }
+
     async fn commit(self: Box<Self>) -> Result<()> {
+        debug!(self.lc, "Committing {:?}", self.bytes.counts());
         Ok(self.js.commit().await?)
     }
 }
+
+#[cfg(target_arch = "wasm32")]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::memstore::MemStore;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    async fn test_keys() {
+        let store = new_test_store();
+        let wt = store.write(LogContext::new()).await.unwrap();
+        wt.put("b", b"2").await.unwrap();
+        wt.put("a", b"1").await.unwrap();
+        wt.put("c", b"3").await.unwrap();
+        wt.commit().await.unwrap();
+
+        // Staged puts and dels are visible to the write, and keys come back
+        // sorted whatever order the JS side returns them in.
+        let wt = store.write(LogContext::new()).await.unwrap();
+        wt.del("b").await.unwrap();
+        wt.put("ab", b"4").await.unwrap();
+        assert_eq!(vec!["a", "ab", "c"], wt.keys("").await.unwrap());
+        assert_eq!(vec!["a", "ab"], wt.keys("a").await.unwrap());
+        assert_eq!(3, wt.count().await.unwrap());
+        wt.commit().await.unwrap();
+
+        // content_hash matches a MemStore holding the same entries.
+        let ms = MemStore::new();
+        let mw = ms.write(LogContext::new()).await.unwrap();
+        for (k, v) in &[("a", b"1"), ("ab", b"4"), ("c", b"3")] {
+            mw.put(k, *v).await.unwrap();
+        }
+        mw.commit().await.unwrap();
+        let want = ms
+            .read(LogContext::new())
+            .await
+            .unwrap()
+            .content_hash()
+            .await;
+        let rt = store.read(LogContext::new()).await.unwrap();
+        assert_eq!(want.unwrap(), rt.content_hash().await.unwrap());
+        drop(rt);
+
+        store.clear().await.unwrap();
+        let rt = store.read(LogContext::new()).await.unwrap();
+        assert!(rt.keys("").await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,357 @@
+use crate::kv::{ByteCounts, CommitStats, Read, Result, SavepointId, Store, StoreError, Write};
+use crate::util::rlog::LogContext;
+use async_trait::async_trait;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+
+// The prefix of the keys in the log store under which commits' writes are
+// recorded. Each commit logs under a key of its own, numbered in commit
+// order, and deletes only that key once it has committed, so that it cannot
+// clear the entry of a commit that started after it.
+pub const WAL_PREFIX: &str = "wal/";
+
+type Pending = BTreeMap<String, Option<Vec<u8>>>;
+
+// LoggedStore is a view of another store whose commits are write-ahead
+// logged to a separate log store: a write transaction's puts and dels are
+// recorded in the log before they are committed to the store and the entry
+// is deleted once they are. If the backend loses a commit part way (eg an
+// IndexedDB transaction that auto-committed or aborted under us), recover()
+// replays the logged writes on the next start. Replaying is idempotent, so it
+// does not matter whether the commit made it in.
+pub struct LoggedStore<'a> {
+    store: &'a dyn Store,
+    log: &'a dyn Store,
+    // The number of the next commit's log entry, once known. It is found from
+    // the entries already in the log by the first commit.
+    next_seq: Cell<Option<u64>>,
+}
+
+impl<'a> LoggedStore<'a> {
+    pub fn new(store: &'a dyn Store, log: &'a dyn Store) -> LoggedStore<'a> {
+        LoggedStore {
+            store,
+            log,
+            next_seq: Cell::new(None),
+        }
+    }
+
+    // Replays the writes of the commits that did not finish, if there are
+    // any, in the order they were made, and returns whether there were. Call
+    // it before using the store.
+    pub async fn recover(&self) -> Result<bool> {
+        let keys = self
+            .log
+            .read(LogContext::new())
+            .await?
+            .keys(WAL_PREFIX)
+            .await?;
+        if keys.is_empty() {
+            return Ok(false);
+        }
+        let wt = self.store.write(LogContext::new()).await?;
+        for key in &keys {
+            if let Some(data) = self.log.get(key).await? {
+                apply(&*wt, &decode(&data)?).await?;
+            }
+        }
+        wt.commit().await?;
+        let lw = self.log.write(LogContext::new()).await?;
+        for key in &keys {
+            lw.del(key).await?;
+        }
+        lw.commit().await?;
+        Ok(true)
+    }
+}
+
+#[async_trait(?Send)]
+impl Store for LoggedStore<'_> {
+    async fn read<'a>(&'a self, lc: LogContext) -> Result<Box<dyn Read + 'a>> {
+        self.store.read(lc).await
+    }
+
+    async fn write<'a>(&'a self, lc: LogContext) -> Result<Box<dyn Write + 'a>> {
+        Ok(Box::new(LoggedWrite {
+            write: self.store.write(lc).await?,
+            log: self.log,
+            next_seq: &self.next_seq,
+            pending: RefCell::new(BTreeMap::new()),
+            savepoints: RefCell::new(vec![]),
+        }))
+    }
+
+    async fn snapshot<'a>(&'a self) -> Result<Box<dyn Read + 'a>> {
+        self.store.snapshot().await
+    }
+
+    // Deletes through a logged write, so that the deleted keys are recorded
+    // in the log like those of any other commit.
+    async fn del_prefix(&self, prefix: &str) -> Result<()> {
+        let wt = self.write(LogContext::new()).await?;
+        for key in wt.keys(prefix).await? {
+            wt.del(&key).await?;
+        }
+        wt.commit().await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.store.flush().await
+    }
+
+    async fn close(&self) {}
+}
+
+struct LoggedWrite<'a> {
+    write: Box<dyn Write + 'a>,
+    log: &'a dyn Store,
+    next_seq: &'a Cell<Option<u64>>,
+    // A copy of what is staged in write, to be logged at commit.
+    pending: RefCell<Pending>,
+    // A copy of pending as of each of write's savepoints, outermost first.
+    savepoints: RefCell<Vec<(SavepointId, Pending)>>,
+}
+
+#[async_trait(?Send)]
+impl Read for LoggedWrite<'_> {
+    async fn has(&self, key: &str) -> Result<bool> {
+        self.write.has(key).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.write.get(key).await
+    }
+
+    async fn content_hash(&self) -> Result<[u8; 32]> {
+        self.write.content_hash().await
+    }
+
+    async fn keys(&self, prefix: &str) -> Result<Vec<String>> {
+        self.write.keys(prefix).await
+    }
+
+    fn byte_counts(&self) -> Option<ByteCounts> {
+        self.write.byte_counts()
+    }
+}
+
+#[async_trait(?Send)]
+impl Write for LoggedWrite<'_> {
+    fn as_read(&self) -> &dyn Read {
+        self
+    }
+
+    async fn put(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.write.put(key, value).await?;
+        self.pending
+            .borrow_mut()
+            .insert(key.to_string(), Some(value.to_vec()));
+        Ok(())
+    }
+
+    async fn del(&self, key: &str) -> Result<()> {
+        self.write.del(key).await?;
+        self.pending.borrow_mut().insert(key.to_string(), None);
+        Ok(())
+    }
+
+    async fn is_dirty(&self) -> Result<bool> {
+        self.write.is_dirty().await
+    }
+
+    async fn savepoint(&self) -> Result<SavepointId> {
+        let id = self.write.savepoint().await?;
+        let pending = self.pending.borrow().clone();
+        self.savepoints.borrow_mut().push((id, pending));
+        Ok(id)
+    }
+
+    async fn rollback_to(&self, id: SavepointId) -> Result<()> {
+        self.write.rollback_to(id).await?;
+        let mut savepoints = self.savepoints.borrow_mut();
+        match savepoints.iter().position(|(sp, _)| *sp == id) {
+            None => Err(StoreError::UnknownSavepoint),
+            Some(i) => {
+                savepoints.truncate(i + 1);
+                *self.pending.borrow_mut() = savepoints[i].1.clone();
+                Ok(())
+            }
+        }
+    }
+
+    async fn commit(self: Box<Self>) -> Result<()> {
+        self.commit_with_stats().await.map(|_| ())
+    }
+
+    async fn commit_with_stats(self: Box<Self>) -> Result<Option<CommitStats>> {
+        let pending = self.pending.into_inner();
+        if pending.is_empty() {
+            return self.write.commit_with_stats().await;
+        }
+        // The store's write lock is held until the commit below, so entries
+        // are numbered in commit order.
+        let key = wal_key(next_seq(self.log, self.next_seq).await?);
+        self.log.put(&key, &encode(&pending)?).await?;
+        let stats = self.write.commit_with_stats().await?;
+        let lw = self.log.write(LogContext::new()).await?;
+        lw.del(&key).await?;
+        lw.commit().await?;
+        Ok(stats)
+    }
+}
+
+// Zero-padded so that the keys sort in commit order.
+fn wal_key(seq: u64) -> String {
+    format!("{}{:020}", WAL_PREFIX, seq)
+}
+
+async fn next_seq(log: &dyn Store, next: &Cell<Option<u64>>) -> Result<u64> {
+    let seq = match next.get() {
+        Some(seq) => seq,
+        None => log
+            .read(LogContext::new())
+            .await?
+            .keys(WAL_PREFIX)
+            .await?
+            .iter()
+            .filter_map(|key| key[WAL_PREFIX.len()..].parse::<u64>().ok())
+            .max()
+            .map_or(0, |seq| seq + 1),
+    };
+    next.set(Some(seq + 1));
+    Ok(seq)
+}
+
+fn encode(pending: &Pending) -> Result<Vec<u8>> {
+    serde_json::to_vec(pending).map_err(|e| format!("Failed to encode log: {}", e).into())
+}
+
+fn decode(data: &[u8]) -> Result<Pending> {
+    serde_json::from_slice(data).map_err(|e| format!("Failed to decode log: {}", e).into())
+}
+
+async fn apply(wt: &dyn Write, pending: &Pending) -> Result<()> {
+    for (key, value) in pending {
+        match value {
+            Some(v) => wt.put(key, v).await?,
+            None => wt.del(key).await?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::memstore::MemStore;
+
+    async fn wal_keys(log: &MemStore) -> Vec<String> {
+        let rt = log.read(LogContext::new()).await.unwrap();
+        rt.keys(WAL_PREFIX).await.unwrap()
+    }
+
+    #[async_std::test]
+    async fn test_logged_store() {
+        let (ms, log) = (MemStore::new(), MemStore::new());
+        let ls = LoggedStore::new(&ms, &log);
+        assert!(!ls.recover().await.unwrap());
+
+        // A commit lands in the store and leaves the log empty.
+        ms.put("b", b"old").await.unwrap();
+        let wt = ls.write(LogContext::new()).await.unwrap();
+        wt.put("a", b"1").await.unwrap();
+        wt.del("b").await.unwrap();
+        assert_eq!(Some(b"1".to_vec()), wt.get("a").await.unwrap());
+        wt.commit().await.unwrap();
+        assert_eq!(Some(b"1".to_vec()), ms.get("a").await.unwrap());
+        assert!(!ms.has("b").await.unwrap());
+        assert!(wal_keys(&log).await.is_empty());
+
+        // Writes rolled back to a savepoint are dropped from what gets logged.
+        let next_seq = Cell::new(None);
+        let wt = LoggedWrite {
+            write: ms.write(LogContext::new()).await.unwrap(),
+            log: &log,
+            next_seq: &next_seq,
+            pending: RefCell::new(BTreeMap::new()),
+            savepoints: RefCell::new(vec![]),
+        };
+        wt.put("e", b"5").await.unwrap();
+        let sp = wt.savepoint().await.unwrap();
+        wt.put("f", b"6").await.unwrap();
+        wt.rollback_to(sp).await.unwrap();
+        assert_eq!(
+            vec!["e"],
+            wt.pending.borrow().keys().collect::<Vec<&String>>()
+        );
+        drop(wt);
+
+        // A dropped write logs nothing.
+        let wt = ls.write(LogContext::new()).await.unwrap();
+        wt.put("c", b"3").await.unwrap();
+        drop(wt);
+        assert!(wal_keys(&log).await.is_empty());
+
+        // A commit deletes only its own entry, not that of a commit logged
+        // after it (here one that is still in flight).
+        let mut later = Pending::new();
+        later.insert("g".to_string(), Some(b"7".to_vec()));
+        log.put(&wal_key(41), &encode(&later).unwrap())
+            .await
+            .unwrap();
+        ls.put("h", b"8").await.unwrap();
+        assert_eq!(vec![wal_key(41)], wal_keys(&log).await);
+        log.del_prefix(WAL_PREFIX).await.unwrap();
+
+        // Commits that were logged but lost are replayed in order.
+        let mut first = Pending::new();
+        first.insert("a".to_string(), None);
+        first.insert("d".to_string(), Some(b"4".to_vec()));
+        let mut second = Pending::new();
+        second.insert("d".to_string(), Some(b"5".to_vec()));
+        log.put(&wal_key(9), &encode(&first).unwrap())
+            .await
+            .unwrap();
+        log.put(&wal_key(10), &encode(&second).unwrap())
+            .await
+            .unwrap();
+        assert!(ls.recover().await.unwrap());
+        assert!(!ms.has("a").await.unwrap());
+        assert_eq!(Some(b"5".to_vec()), ms.get("d").await.unwrap());
+        assert!(wal_keys(&log).await.is_empty());
+        assert!(!ls.recover().await.unwrap());
+    }
+
+    #[async_std::test]
+    async fn test_del_prefix() {
+        let (ms, log) = (MemStore::new(), MemStore::new());
+        let ls = LoggedStore::new(&ms, &log);
+        for key in &["a/1", "a/2", "b/1"] {
+            ls.put(key, b"v").await.unwrap();
+        }
+
+        // The deletes are logged, so a lost commit deletes them on replay.
+        let wt = LoggedWrite {
+            write: ms.write(LogContext::new()).await.unwrap(),
+            log: &log,
+            next_seq: &ls.next_seq,
+            pending: RefCell::new(BTreeMap::new()),
+            savepoints: RefCell::new(vec![]),
+        };
+        for key in wt.keys("a/").await.unwrap() {
+            wt.del(&key).await.unwrap();
+        }
+        let mut deleted = Pending::new();
+        deleted.insert("a/1".to_string(), None);
+        deleted.insert("a/2".to_string(), None);
+        assert_eq!(deleted, *wt.pending.borrow());
+        drop(wt);
+
+        ls.del_prefix("a/").await.unwrap();
+        assert!(!ms.has("a/1").await.unwrap());
+        assert!(!ms.has("a/2").await.unwrap());
+        assert!(ms.has("b/1").await.unwrap());
+        assert!(wal_keys(&log).await.is_empty());
+    }
+}
@@ -1,23 +1,148 @@
-use crate::kv::{Read, Result, Store, Write};
+use crate::kv::{
+    check_key, content_hash, ByteCounter, ByteCounts, CommitStats, Read, Result, SavepointId,
+    Store, StoreError, Write,
+};
 use crate::util::rlog::LogContext;
-use async_std::sync::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use async_std::sync::{
+    channel, Mutex, Receiver, RwLock, RwLockReadGuard, RwLockWriteGuard, Sender,
+};
 use async_trait::async_trait;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Clone, Debug, Default)]
+pub struct MemStoreOptions {
+    // max_reads caps the number of read transactions that can be open at
+    // once. Reads beyond the cap wait for an open one to be dropped. None
+    // means unlimited and a cap of zero is treated as one.
+    pub max_reads: Option<usize>,
+    // prefer_writes makes new read transactions queue behind a write that is
+    // waiting for the lock, rather than jumping ahead of it. Without it a
+    // steady stream of overlapping reads can starve a writer.
+    pub prefer_writes: bool,
+    // high_water_bytes is the number of bytes buffered in write transactions'
+    // pending maps above which the memory pressure callback (see
+    // MemStore::on_memory_pressure) is called. It is called once each time
+    // the total crosses the mark.
+    pub high_water_bytes: Option<usize>,
+    // max_value_bytes is the largest value put will store. Bigger values are
+    // rejected with StoreError::ValueTooLarge before anything is staged.
+    // None means unlimited.
+    pub max_value_bytes: Option<usize>,
+}
 
 pub struct MemStore {
     map: RwLock<HashMap<String, Vec<u8>>>,
+    read_permits: Option<Permits>,
+    prefer_writes: bool,
+    // Held by a writer while it waits for the map lock when prefer_writes is
+    // set. Readers pass through it before taking the map lock.
+    write_gate: Mutex<()>,
+    pressure: Pressure,
+    max_value_bytes: Option<usize>,
+    // The id to give the next savepoint taken in any of the store's write
+    // transactions. Ids are never reused, so rollback_to can tell a stale id
+    // or one from another transaction from a live savepoint.
+    next_savepoint: AtomicUsize,
+}
+
+// Pressure tracks the bytes buffered by open write transactions.
+#[derive(Default)]
+struct Pressure {
+    pending_bytes: AtomicUsize,
+    high_water_bytes: Option<usize>,
+    callback: Option<Box<dyn Fn(usize)>>,
+}
+
+impl Pressure {
+    fn add(&self, n: usize) {
+        let before = self.pending_bytes.fetch_add(n, Ordering::SeqCst);
+        let after = before + n;
+        if let (Some(mark), Some(callback)) = (self.high_water_bytes, &self.callback) {
+            if before < mark && after >= mark {
+                callback(after);
+            }
+        }
+    }
+
+    fn sub(&self, n: usize) {
+        self.pending_bytes.fetch_sub(n, Ordering::SeqCst);
+    }
 }
 
 impl MemStore {
     pub fn new() -> MemStore {
+        MemStore::new_with_options(MemStoreOptions::default())
+    }
+
+    pub fn new_with_options(opts: MemStoreOptions) -> MemStore {
         MemStore {
             map: RwLock::new(HashMap::new()),
+            read_permits: opts.max_reads.map(Permits::new),
+            prefer_writes: opts.prefer_writes,
+            write_gate: Mutex::new(()),
+            pressure: Pressure {
+                high_water_bytes: opts.high_water_bytes,
+                ..Default::default()
+            },
+            max_value_bytes: opts.max_value_bytes,
+            next_savepoint: AtomicUsize::new(0),
         }
     }
 
+    // Registers f to be called with the number of buffered bytes when they
+    // reach MemStoreOptions::high_water_bytes, so that the app can flush or
+    // evict.
+    pub fn on_memory_pressure(&mut self, f: impl Fn(usize) + 'static) {
+        self.pressure.callback = Some(Box::new(f));
+    }
+
+    // Returns the number of bytes currently buffered by write transactions.
+    pub fn pending_bytes(&self) -> usize {
+        self.pressure.pending_bytes.load(Ordering::SeqCst)
+    }
+
     pub async fn new_async() -> Box<dyn Store> {
         Box::new(MemStore::new())
     }
+
+    // Returns a copy of every entry, eg to set a test's store back to a known
+    // state with restore or to compare against after the test runs. It waits
+    // for an open write transaction to finish. It is not called snapshot so
+    // as not to shadow Store::snapshot.
+    pub async fn capture(&self) -> MemSnapshot {
+        let map = self.map.read().await;
+        MemSnapshot {
+            entries: map.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+        }
+    }
+
+    // Replaces every entry with those of snap in one step. It takes the
+    // write lock like a write transaction does, so it waits for open
+    // transactions and none sees it part way.
+    pub async fn restore(&self, snap: MemSnapshot) {
+        let mut map = self.lock_for_write().await;
+        *map = snap.entries.into_iter().collect();
+    }
+
+    async fn lock_for_write(&self) -> RwLockWriteGuard<'_, HashMap<String, Vec<u8>>> {
+        let gate = if self.prefer_writes {
+            Some(self.write_gate.lock().await)
+        } else {
+            None
+        };
+        let guard = self.map.write().await;
+        drop(gate);
+        guard
+    }
+}
+
+// MemSnapshot is an owned copy of a MemStore's entries made by
+// MemStore::capture. Entries are sorted so that snapshots compare and print
+// deterministically.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MemSnapshot {
+    pub entries: BTreeMap<String, Vec<u8>>,
 }
 
 impl Default for MemStore {
@@ -29,25 +154,162 @@ impl Default for MemStore {
 #[async_trait(?Send)]
 impl Store for MemStore {
     async fn read<'a>(&'a self, _: LogContext) -> Result<Box<dyn Read + 'a>> {
+        let permit = match &self.read_permits {
+            None => None,
+            Some(permits) => Some(permits.acquire().await?),
+        };
+        if self.prefer_writes {
+            drop(self.write_gate.lock().await);
+        }
         let guard = self.map.read().await;
-        Ok(Box::new(ReadTransaction::new(guard)))
+        Ok(Box::new(ReadTransaction::new(guard, permit)))
     }
 
-    async fn write<'a>(&'a self, _: LogContext) -> Result<Box<dyn Write + 'a>> {
-        let guard = self.map.write().await;
-        Ok(Box::new(WriteTransaction::new(guard)))
+    async fn write<'a>(&'a self, lc: LogContext) -> Result<Box<dyn Write + 'a>> {
+        Ok(Box::new(WriteTransaction::new(
+            self.lock_for_write().await,
+            &self.pressure,
+            self.max_value_bytes,
+            &self.next_savepoint,
+            lc,
+        )))
+    }
+
+    // Copies the map so that the snapshot holds no lock and writers can carry
+    // on while it is open. The copy costs time and memory in the size of the
+    // store.
+    async fn snapshot<'a>(&'a self) -> Result<Box<dyn Read + 'a>> {
+        let map = self.map.read().await.clone();
+        Ok(Box::new(Snapshot {
+            map,
+            bytes: ByteCounter::default(),
+        }))
+    }
+
+    // Writes directly into the map under the write lock so that entries are
+    // not also staged in a transaction's pending map. The window does not
+    // apply as no put is ever outstanding. Entries go into the map as they are
+    // read; if one is rejected, the values the earlier ones replaced are put
+    // back, so that a rejected import writes none of them.
+    async fn bulk_put_with_window(
+        &self,
+        entries: &mut dyn Iterator<Item = (String, Vec<u8>)>,
+        _window: usize,
+    ) -> Result<()> {
+        let mut map = self.lock_for_write().await;
+        let mut replaced = vec![];
+        for (key, value) in entries {
+            if let Err(e) =
+                check_key(&key).and_then(|_| check_value_size(&value, self.max_value_bytes))
+            {
+                for (key, old) in replaced.into_iter().rev() {
+                    match old {
+                        Some(old) => map.insert(key, old),
+                        None => map.remove(&key),
+                    };
+                }
+                return Err(e);
+            }
+            let old = map.insert(key.clone(), value);
+            replaced.push((key, old));
+        }
+        Ok(())
+    }
+
+    // Like bulk_put_with_window, writes directly into the map under the
+    // write lock.
+    async fn put_many(&self, entries: &[(String, Vec<u8>)]) -> Result<()> {
+        for (key, value) in entries.iter() {
+            check_key(key)?;
+            check_value_size(value, self.max_value_bytes)?;
+        }
+        let mut map = self.lock_for_write().await;
+        for (key, value) in entries {
+            map.insert(key.clone(), value.clone());
+        }
+        Ok(())
+    }
+
+    // Deletes under the write lock in one pass, rather than staging a del per
+    // key in a transaction.
+    async fn del_prefix(&self, prefix: &str) -> Result<()> {
+        self.lock_for_write()
+            .await
+            .retain(|k, _| !k.starts_with(prefix));
+        Ok(())
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.lock_for_write().await.clear();
+        Ok(())
     }
 
     async fn close(&self) {}
 }
 
+fn check_value_size(value: &[u8], limit: Option<usize>) -> Result<()> {
+    match limit {
+        Some(limit) if value.len() > limit => Err(StoreError::ValueTooLarge {
+            size: value.len(),
+            limit,
+        }),
+        _ => Ok(()),
+    }
+}
+
+// Permits is a counting semaphore built on a bounded channel that starts out
+// full: acquiring takes a token out and dropping the Permit puts it back.
+struct Permits {
+    tx: Sender<()>,
+    rx: Receiver<()>,
+}
+
+impl Permits {
+    fn new(n: usize) -> Permits {
+        let (tx, rx) = channel(n.max(1));
+        for _ in 0..n.max(1) {
+            // Cannot fail: the channel has exactly this much capacity.
+            let _ = tx.try_send(());
+        }
+        Permits { tx, rx }
+    }
+
+    async fn acquire(&self) -> Result<Permit<'_>> {
+        self.rx
+            .recv()
+            .await
+            .map_err(|e| format!("Failed to acquire read permit: {}", e))?;
+        Ok(Permit { tx: &self.tx })
+    }
+}
+
+struct Permit<'a> {
+    tx: &'a Sender<()>,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        // Cannot fail: we took this token out of the channel.
+        let _ = self.tx.try_send(());
+    }
+}
+
 struct ReadTransaction<'a> {
     map: RwLockReadGuard<'a, HashMap<String, Vec<u8>>>,
+    _permit: Option<Permit<'a>>,
+    bytes: ByteCounter,
 }
 
-impl ReadTransaction<'_> {
-    fn new(map: RwLockReadGuard<'_, HashMap<String, Vec<u8>>>) -> ReadTransaction {
-        ReadTransaction { map }
+impl<'a> ReadTransaction<'a> {
+    fn new(
+        map: RwLockReadGuard<'a, HashMap<String, Vec<u8>>>,
+        permit: Option<Permit<'a>>,
+    ) -> ReadTransaction<'a> {
+        ReadTransaction {
+            map,
+            _permit: permit,
+            bytes: ByteCounter::default(),
+        }
     }
 }
 
@@ -60,23 +322,146 @@ impl Read for ReadTransaction<'_> {
     async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
         match self.map.get(key) {
             None => Ok(None),
-            Some(v) => Ok(Some(v.to_vec())),
+            Some(v) => {
+                self.bytes.read(v.len());
+                Ok(Some(v.to_vec()))
+            }
         }
     }
+
+    async fn content_hash(&self) -> Result<[u8; 32]> {
+        Ok(content_hash(
+            self.map.iter().map(|(k, v)| (k.as_str(), v.as_slice())),
+        ))
+    }
+
+    async fn keys(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut keys: Vec<String> = self
+            .map
+            .keys()
+            .filter(|k| k.starts_with(prefix))
+            .cloned()
+            .collect();
+        keys.sort();
+        Ok(keys)
+    }
+
+    async fn count(&self) -> Result<u64> {
+        Ok(self.map.len() as u64)
+    }
+
+    async fn count_prefix(&self, prefix: &str) -> Result<u64> {
+        Ok(self.map.keys().filter(|k| k.starts_with(prefix)).count() as u64)
+    }
+
+    fn byte_counts(&self) -> Option<ByteCounts> {
+        Some(self.bytes.counts())
+    }
+}
+
+struct Snapshot {
+    map: HashMap<String, Vec<u8>>,
+    bytes: ByteCounter,
+}
+
+#[async_trait(?Send)]
+impl Read for Snapshot {
+    async fn has(&self, key: &str) -> Result<bool> {
+        Ok(self.map.contains_key(key))
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let value = self.map.get(key).cloned();
+        self.bytes.read(value.as_ref().map_or(0, Vec::len));
+        Ok(value)
+    }
+
+    async fn content_hash(&self) -> Result<[u8; 32]> {
+        Ok(content_hash(
+            self.map.iter().map(|(k, v)| (k.as_str(), v.as_slice())),
+        ))
+    }
+
+    async fn keys(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut keys: Vec<String> = self
+            .map
+            .keys()
+            .filter(|k| k.starts_with(prefix))
+            .cloned()
+            .collect();
+        keys.sort();
+        Ok(keys)
+    }
+
+    async fn count(&self) -> Result<u64> {
+        Ok(self.map.len() as u64)
+    }
+
+    async fn count_prefix(&self, prefix: &str) -> Result<u64> {
+        Ok(self.map.keys().filter(|k| k.starts_with(prefix)).count() as u64)
+    }
+
+    fn byte_counts(&self) -> Option<ByteCounts> {
+        Some(self.bytes.counts())
+    }
 }
 
+type Pending = HashMap<String, Option<Vec<u8>>>;
+
 struct WriteTransaction<'a> {
     map: RwLockWriteGuard<'a, HashMap<String, Vec<u8>>>,
-    pending: Mutex<HashMap<String, Option<Vec<u8>>>>,
+    pending: Mutex<Pending>,
+    pending_bytes: AtomicUsize,
+    // A copy of pending as of each savepoint, outermost first.
+    savepoints: Mutex<Vec<(SavepointId, Pending)>>,
+    pressure: &'a Pressure,
+    max_value_bytes: Option<usize>,
+    next_savepoint: &'a AtomicUsize,
+    bytes: ByteCounter,
+    lc: LogContext,
 }
 
-impl WriteTransaction<'_> {
-    fn new(map: RwLockWriteGuard<'_, HashMap<String, Vec<u8>>>) -> WriteTransaction {
+impl<'a> WriteTransaction<'a> {
+    fn new(
+        map: RwLockWriteGuard<'a, HashMap<String, Vec<u8>>>,
+        pressure: &'a Pressure,
+        max_value_bytes: Option<usize>,
+        next_savepoint: &'a AtomicUsize,
+        lc: LogContext,
+    ) -> WriteTransaction<'a> {
         WriteTransaction {
             map,
             pending: Mutex::new(HashMap::new()),
+            pending_bytes: AtomicUsize::new(0),
+            savepoints: Mutex::new(vec![]),
+            pressure,
+            max_value_bytes,
+            next_savepoint,
+            bytes: ByteCounter::default(),
+            lc,
         }
     }
+
+    async fn set_pending(&self, key: &str, value: Option<Vec<u8>>) {
+        let size = |v: &Option<Vec<u8>>| key.len() + v.as_ref().map_or(0, Vec::len);
+        let added = size(&value);
+        let removed = self
+            .pending
+            .lock()
+            .await
+            .insert(key.into(), value)
+            .map_or(0, |old| size(&old));
+        self.pending_bytes.fetch_add(added, Ordering::SeqCst);
+        self.pending_bytes.fetch_sub(removed, Ordering::SeqCst);
+        self.pressure.sub(removed);
+        self.pressure.add(added);
+    }
+}
+
+impl Drop for WriteTransaction<'_> {
+    fn drop(&mut self) {
+        self.pressure.sub(*self.pending_bytes.get_mut());
+    }
 }
 
 #[async_trait(?Send)]
@@ -90,11 +475,43 @@ impl Read for WriteTransaction<'_> {
     }
 
     async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
-        match self.pending.lock().await.get(key) {
-            Some(Some(v)) => Ok(Some(v.to_vec())),
-            Some(None) => Ok(None),
-            None => Ok(self.map.get(key).map(|v| v.to_vec())),
-        }
+        let value = match self.pending.lock().await.get(key) {
+            Some(Some(v)) => Some(v.to_vec()),
+            Some(None) => None,
+            None => self.map.get(key).map(|v| v.to_vec()),
+        };
+        self.bytes.read(value.as_ref().map_or(0, Vec::len));
+        Ok(value)
+    }
+
+    async fn content_hash(&self) -> Result<[u8; 32]> {
+        let pending = self.pending.lock().await;
+        let committed = self
+            .map
+            .iter()
+            .filter(|(k, _)| !pending.contains_key(*k))
+            .map(|(k, v)| (k.as_str(), v.as_slice()));
+        let staged = pending
+            .iter()
+            .filter_map(|(k, v)| v.as_ref().map(|v| (k.as_str(), v.as_slice())));
+        Ok(content_hash(committed.chain(staged)))
+    }
+
+    async fn keys(&self, prefix: &str) -> Result<Vec<String>> {
+        let pending = self.pending.lock().await;
+        let committed = self.map.keys().filter(|k| !pending.contains_key(*k));
+        let staged = pending.iter().filter(|(_, v)| v.is_some()).map(|(k, _)| k);
+        let mut keys: Vec<String> = committed
+            .chain(staged)
+            .filter(|k| k.starts_with(prefix))
+            .cloned()
+            .collect();
+        keys.sort();
+        Ok(keys)
+    }
+
+    fn byte_counts(&self) -> Option<ByteCounts> {
+        Some(self.bytes.counts())
     }
 }
 
@@ -105,38 +522,326 @@ impl Write for WriteTransaction<'_> {
     }
 
     async fn put(&self, key: &str, value: &[u8]) -> Result<()> {
-        self.pending
-            .lock()
-            .await
-            .insert(key.into(), Some(value.to_vec()));
+        check_key(key)?;
+        check_value_size(value, self.max_value_bytes)?;
+        self.bytes.wrote(key.len() + value.len());
+        self.set_pending(key, Some(value.to_vec())).await;
         Ok(())
     }
 
     async fn del(&self, key: &str) -> Result<()> {
-        self.pending.lock().await.insert(key.into(), None);
+        self.set_pending(key, None).await;
+        Ok(())
+    }
+
+    async fn is_dirty(&self) -> Result<bool> {
+        Ok(!self.pending.lock().await.is_empty())
+    }
+
+    async fn savepoint(&self) -> Result<SavepointId> {
+        let id = SavepointId(self.next_savepoint.fetch_add(1, Ordering::SeqCst));
+        let mut savepoints = self.savepoints.lock().await;
+        savepoints.push((id, self.pending.lock().await.clone()));
+        Ok(id)
+    }
+
+    async fn rollback_to(&self, id: SavepointId) -> Result<()> {
+        let mut savepoints = self.savepoints.lock().await;
+        let i = match savepoints.iter().position(|(sp, _)| *sp == id) {
+            None => return Err(StoreError::UnknownSavepoint),
+            Some(i) => i,
+        };
+        savepoints.truncate(i + 1);
+        let restored = savepoints[i].1.clone();
+        let size = |(k, v): (&String, &Option<Vec<u8>>)| k.len() + v.as_ref().map_or(0, Vec::len);
+        let added: usize = restored.iter().map(size).sum();
+        let removed = self.pending_bytes.swap(added, Ordering::SeqCst);
+        *self.pending.lock().await = restored;
+        self.pressure.sub(removed);
+        self.pressure.add(added);
         Ok(())
     }
 
-    async fn commit(mut self: Box<Self>) -> Result<()> {
+    async fn commit(self: Box<Self>) -> Result<()> {
+        self.commit_with_stats().await.map(|_| ())
+    }
+
+    async fn commit_with_stats(mut self: Box<Self>) -> Result<Option<CommitStats>> {
+        debug!(self.lc, "Committing {:?}", self.bytes.counts());
         let pending = self.pending.lock().await;
-        for item in pending.iter() {
-            match item.1 {
-                Some(v) => self.map.insert(item.0.clone(), v.clone()),
-                None => self.map.remove(item.0),
+        let mut stats = CommitStats::default();
+        for (key, value) in in_key_order(&pending) {
+            match value {
+                Some(v) => {
+                    stats.keys_written += 1;
+                    stats.bytes_written += key.len() + v.len();
+                    self.map.insert(key.clone(), v.clone());
+                }
+                None => {
+                    stats.keys_deleted += 1;
+                    self.map.remove(key);
+                }
             };
         }
-        Ok(())
+        Ok(Some(stats))
     }
 }
 
+// Returns the staged writes sorted by key, so that commit applies them in the
+// same order whatever order they were staged in.
+fn in_key_order(pending: &Pending) -> Vec<(&String, &Option<Vec<u8>>)> {
+    let mut writes: Vec<_> = pending.iter().collect();
+    writes.sort_unstable_by(|a, b| a.0.cmp(b.0));
+    writes
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::kv::trait_tests;
 
+    #[test]
+    fn test_in_key_order() {
+        let keys = ["b", "a/1", "c", "a", "\u{e9}", "B"];
+        let mut pending = Pending::new();
+        for (i, key) in keys.iter().enumerate() {
+            let value = if i % 2 == 0 {
+                Some(vec![i as u8])
+            } else {
+                None
+            };
+            pending.insert(key.to_string(), value);
+        }
+        let got: Vec<&str> = in_key_order(&pending)
+            .into_iter()
+            .map(|(k, _)| k.as_str())
+            .collect();
+        assert_eq!(vec!["B", "a", "a/1", "b", "c", "\u{e9}"], got);
+        // Each key keeps its own write.
+        for (key, value) in in_key_order(&pending) {
+            assert_eq!(pending.get(key).unwrap(), value);
+        }
+    }
+
     #[async_std::test]
     async fn test_memstore() {
         trait_tests::run_all(&MemStore::new_async).await;
     }
+
+    #[async_std::test]
+    async fn test_check_isolation() {
+        use std::time::Duration;
+
+        let dur = Duration::from_millis(20);
+        assert_eq!(
+            Ok(()),
+            trait_tests::check_isolation(&MemStore::new(), dur).await
+        );
+        let ms = MemStore::new_with_options(MemStoreOptions {
+            max_reads: Some(1),
+            ..Default::default()
+        });
+        assert_eq!(
+            Err(trait_tests::IsolationError::SecondReadBlocked),
+            trait_tests::check_isolation(&ms, dur).await
+        );
+    }
+
+    #[async_std::test]
+    async fn test_max_reads() {
+        use async_std::future::timeout;
+        use std::time::Duration;
+
+        let ms = MemStore::new_with_options(MemStoreOptions {
+            max_reads: Some(2),
+            ..Default::default()
+        });
+        let dur = Duration::from_millis(50);
+        let r1 = ms.read(LogContext::new()).await.unwrap();
+        let r2 = ms.read(LogContext::new()).await.unwrap();
+        assert!(timeout(dur, ms.read(LogContext::new())).await.is_err());
+        drop(r1);
+        let r3 = timeout(dur, ms.read(LogContext::new())).await.unwrap();
+        assert!(timeout(dur, ms.read(LogContext::new())).await.is_err());
+        drop(r2);
+        drop(r3.unwrap());
+
+        // Writes are unaffected by the cap once reads are done.
+        ms.put("foo", b"bar").await.unwrap();
+        assert!(ms.has("foo").await.unwrap());
+    }
+
+    #[async_std::test]
+    async fn test_prefer_writes() {
+        use async_std::future::timeout;
+        use std::time::Duration;
+
+        async fn test(prefer_writes: bool) {
+            let ms = MemStore::new_with_options(MemStoreOptions {
+                prefer_writes,
+                ..Default::default()
+            });
+            let dur = Duration::from_millis(50);
+            let r1 = ms.read(LogContext::new()).await.unwrap();
+
+            // Start a write, which has to wait for r1.
+            let mut w = ms.write(LogContext::new());
+            assert!(timeout(dur, &mut w).await.is_err());
+
+            // A new read only gets in ahead of the waiting write by default.
+            let r2 = timeout(dur, ms.read(LogContext::new())).await;
+            assert_eq!(!prefer_writes, r2.is_ok());
+            drop(r2);
+
+            drop(r1);
+            let w = timeout(dur, w).await.unwrap().unwrap();
+            w.put("foo", b"bar").await.unwrap();
+            w.commit().await.unwrap();
+            assert!(ms.has("foo").await.unwrap());
+        }
+
+        test(false).await;
+        test(true).await;
+
+        // Writes made without a transaction wait at the gate too, so new
+        // reads queue behind them.
+        async fn gated(ms: &MemStore, op: impl std::future::Future<Output = Result<()>>) {
+            let dur = Duration::from_millis(50);
+            let r1 = ms.read(LogContext::new()).await.unwrap();
+            let mut op = Box::pin(op);
+            assert!(timeout(dur, &mut op).await.is_err());
+            assert!(timeout(dur, ms.read(LogContext::new())).await.is_err());
+            drop(r1);
+            timeout(dur, op).await.unwrap().unwrap();
+        }
+        let ms = MemStore::new_with_options(MemStoreOptions {
+            prefer_writes: true,
+            ..Default::default()
+        });
+        let entries = vec![("a/1".to_string(), b"1".to_vec())];
+        gated(&ms, ms.put_many(&entries)).await;
+        let mut iter = entries.clone().into_iter();
+        gated(&ms, ms.bulk_put_with_window(&mut iter, 1)).await;
+        gated(&ms, ms.del_prefix("a/")).await;
+        gated(&ms, ms.clear()).await;
+    }
+
+    #[async_std::test]
+    async fn test_memory_pressure() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let mut ms = MemStore::new_with_options(MemStoreOptions {
+            high_water_bytes: Some(10),
+            ..Default::default()
+        });
+        let calls = Rc::new(RefCell::new(vec![]));
+        let calls2 = calls.clone();
+        ms.on_memory_pressure(move |n| calls2.borrow_mut().push(n));
+
+        let w = ms.write(LogContext::new()).await.unwrap();
+        w.put("a", b"1234").await.unwrap();
+        assert_eq!(5, ms.pending_bytes());
+        assert!(calls.borrow().is_empty());
+        // Replacing a pending value only counts the difference.
+        w.put("a", b"12").await.unwrap();
+        assert_eq!(3, ms.pending_bytes());
+        w.put("b", b"12345678").await.unwrap();
+        assert_eq!(vec![12], *calls.borrow());
+        w.del("c").await.unwrap();
+        assert_eq!(vec![12], *calls.borrow());
+        w.commit().await.unwrap();
+        assert_eq!(0, ms.pending_bytes());
+
+        // Dropping a write releases its bytes too, and crossing the mark
+        // again calls back again.
+        let w = ms.write(LogContext::new()).await.unwrap();
+        w.put("d", b"1234567890").await.unwrap();
+        assert_eq!(vec![12, 11], *calls.borrow());
+        drop(w);
+        assert_eq!(0, ms.pending_bytes());
+        assert!(ms.has("b").await.unwrap());
+        assert!(!ms.has("d").await.unwrap());
+    }
+
+    #[async_std::test]
+    async fn test_max_value_bytes() {
+        let ms = MemStore::new_with_options(MemStoreOptions {
+            max_value_bytes: Some(4),
+            ..Default::default()
+        });
+        ms.put("a", b"1234").await.unwrap();
+        let too_large = Err(StoreError::ValueTooLarge { size: 5, limit: 4 });
+        assert_eq!(too_large, ms.put("a", b"12345").await);
+        assert_eq!(Some(b"1234".to_vec()), ms.get("a").await.unwrap());
+
+        // Nothing is staged for a rejected put.
+        let w = ms.write(LogContext::new()).await.unwrap();
+        assert_eq!(too_large, w.put("b", b"12345").await);
+        assert!(!w.is_dirty().await.unwrap());
+        drop(w);
+
+        // A bulk put with any value too large writes none of them.
+        let entries = vec![
+            ("c".to_string(), b"1".to_vec()),
+            ("d".to_string(), b"12345".to_vec()),
+        ];
+        assert_eq!(
+            too_large,
+            ms.bulk_put(&mut entries.clone().into_iter()).await
+        );
+        assert!(!ms.has("c").await.unwrap());
+        assert_eq!(too_large, ms.put_many(&entries).await);
+        assert!(!ms.has("c").await.unwrap());
+    }
+
+    #[async_std::test]
+    async fn test_snapshot() {
+        let ms = MemStore::new();
+        ms.put("a", b"1").await.unwrap();
+        let snap = ms.snapshot().await.unwrap();
+
+        // Writes go ahead while the snapshot is held but are not seen by it.
+        ms.put("a", b"2").await.unwrap();
+        ms.put("b", b"3").await.unwrap();
+        assert_eq!(Some(b"1".to_vec()), snap.get("a").await.unwrap());
+        assert_eq!(vec!["a"], snap.keys("").await.unwrap());
+        assert_eq!(Some(b"2".to_vec()), ms.get("a").await.unwrap());
+    }
+
+    #[async_std::test]
+    async fn test_capture_restore() {
+        use async_std::future::timeout;
+        use std::time::Duration;
+
+        let ms = MemStore::new();
+        ms.put("a", b"1").await.unwrap();
+        ms.put("b", b"2").await.unwrap();
+        let saved = ms.capture().await;
+        assert_eq!(
+            vec![
+                ("a".to_string(), b"1".to_vec()),
+                ("b".to_string(), b"2".to_vec())
+            ],
+            saved.entries.clone().into_iter().collect::<Vec<_>>()
+        );
+
+        ms.put("a", b"changed").await.unwrap();
+        ms.put("c", b"3").await.unwrap();
+        assert_ne!(saved, ms.capture().await);
+
+        // Restoring waits for an open transaction.
+        let dur = Duration::from_millis(20);
+        let w = ms.write(LogContext::new()).await.unwrap();
+        assert!(timeout(dur, ms.restore(saved.clone())).await.is_err());
+        drop(w);
+        ms.restore(saved.clone()).await;
+        assert_eq!(saved, ms.capture().await);
+        assert!(!ms.has("c").await.unwrap());
+        assert_eq!(Some(b"1".to_vec()), ms.get("a").await.unwrap());
+
+        ms.restore(MemSnapshot::default()).await;
+        assert!(!ms.has("a").await.unwrap());
+    }
 }
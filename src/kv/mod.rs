@@ -1,19 +1,81 @@
+pub mod cachingstore;
+pub mod encryptedstore;
 pub mod jsstore;
+pub mod loggedstore;
 pub mod memstore;
+pub mod prefixedstore;
+pub mod readonlystore;
+pub mod retrystore;
+pub mod slowlogstore;
+pub mod tracingstore;
 
 use crate::util::{rlog::LogContext, to_debug};
+use async_std::task;
 use async_trait::async_trait;
+use data_encoding::{BASE64, HEXLOWER};
+use futures::future::try_join_all;
+use futures::stream::{self, FuturesOrdered, FuturesUnordered, LocalBoxStream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryInto;
 use std::fmt;
-use wasm_bindgen::JsValue;
+use wasm_bindgen::{JsCast, JsValue};
 
-#[derive(Debug, PartialEq)]
+// StoreError holds only extracted strings rather than eg raw JsValues, so that
+// it can be cloned and handed to each of several tasks awaiting the same
+// operation.
+#[derive(Clone, Debug, PartialEq)]
 pub enum StoreError {
+    // The store was opened read-only and cannot be written to.
+    ReadOnly,
+    // The backend's transaction ended before the operation ran, eg an
+    // IndexedDB transaction that auto-committed because the caller awaited
+    // something else in between. The transaction cannot be used again and the
+    // caller should start a new one.
+    TransactionClosed,
+    // The backend aborted the transaction, eg IndexedDB on a failed request
+    // or a constraint violation. Nothing it staged was committed.
+    TransactionAborted,
+    // A put's value is bigger than the store allows (see eg
+    // MemStoreOptions::max_value_bytes). Nothing was written.
+    ValueTooLarge { size: usize, limit: usize },
+    // The backend is out of space. Retrying will not help until the user
+    // frees some up.
+    QuotaExceeded,
+    // The store does not implement the named operation.
+    NotSupported(&'static str),
+    // rollback_to was passed a savepoint that was rolled back past or that
+    // belongs to another transaction.
+    UnknownSavepoint,
+    // A text key starts with BINARY_KEY_PREFIX but is not the encoding of a
+    // binary key (see check_key). Nothing was written.
+    ReservedKey(String),
     Str(String),
 }
 
 impl fmt::Display for StoreError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            StoreError::ReadOnly => write!(f, "store is read-only"),
+            StoreError::TransactionClosed => write!(f, "transaction is closed"),
+            StoreError::TransactionAborted => write!(f, "transaction was aborted"),
+            StoreError::ValueTooLarge { size, limit } => write!(
+                f,
+                "value of {} bytes is larger than the limit of {} bytes",
+                size, limit
+            ),
+            StoreError::QuotaExceeded => write!(f, "storage quota exceeded"),
+            StoreError::NotSupported(op) => write!(f, "{} is not supported by this store", op),
+            StoreError::UnknownSavepoint => write!(f, "unknown savepoint"),
+            StoreError::ReservedKey(key) => {
+                write!(
+                    f,
+                    "key {:?} starts with the reserved binary key prefix",
+                    key
+                )
+            }
             StoreError::Str(s) => write!(f, "{}", s),
         }
     }
@@ -25,8 +87,29 @@ impl From<String> for StoreError {
     }
 }
 
+impl From<&str> for StoreError {
+    fn from(err: &str) -> StoreError {
+        StoreError::Str(err.to_string())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<std::io::Error> for StoreError {
+    fn from(err: std::io::Error) -> StoreError {
+        StoreError::Str(err.to_string())
+    }
+}
+
 impl From<JsValue> for StoreError {
     fn from(err: JsValue) -> StoreError {
+        if let Some(e) = err.dyn_ref::<web_sys::DomException>() {
+            match e.name().as_str() {
+                "TransactionInactiveError" => return StoreError::TransactionClosed,
+                "AbortError" => return StoreError::TransactionAborted,
+                "QuotaExceededError" => return StoreError::QuotaExceeded,
+                _ => (),
+            }
+        }
         // TODO(nate): Pick out a useful subset of this value.
         StoreError::Str(to_debug(err))
     }
@@ -34,11 +117,218 @@ impl From<JsValue> for StoreError {
 
 type Result<T> = std::result::Result<T, StoreError>;
 
+// The key under which Store::migrate records the version it last ran. It is
+// under "sys/", with the other metadata, so that it cannot collide with app
+// data.
+pub const MIGRATION_VERSION_KEY: &str = "sys/migration_version";
+// The most keys Store::migrate rewrites in one write transaction.
+pub const MIGRATE_BATCH_SIZE: usize = 1000;
+
+// ByteCounts is the number of bytes a transaction has read and written so
+// far: read counts the values returned by get and written the keys and values
+// passed to put.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ByteCounts {
+    pub read: usize,
+    pub written: usize,
+}
+
+// CommitStats is what a write transaction's commit wrote: the keys it put,
+// the keys it deleted (whether or not they existed) and the bytes of the keys
+// and values it put. Each key is counted once however many times it was
+// written within the transaction.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CommitStats {
+    pub keys_written: usize,
+    pub keys_deleted: usize,
+    pub bytes_written: usize,
+}
+
+// SavepointId identifies a savepoint taken with Write::savepoint. It is only
+// meaningful to the transaction that returned it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SavepointId(usize);
+
+// ByteCounter accumulates a transaction's ByteCounts.
+#[derive(Debug, Default)]
+pub struct ByteCounter {
+    read: Cell<usize>,
+    written: Cell<usize>,
+}
+
+impl ByteCounter {
+    pub fn read(&self, n: usize) {
+        self.read.set(self.read.get() + n);
+    }
+
+    pub fn wrote(&self, n: usize) {
+        self.written.set(self.written.get() + n);
+    }
+
+    pub fn counts(&self) -> ByteCounts {
+        ByteCounts {
+            read: self.read.get(),
+            written: self.written.get(),
+        }
+    }
+}
+
+// OpenWrite holds the write transaction that a view of another store wraps,
+// until the view commits it. Taking it out to commit lets the view's Drop tell
+// a commit from a rollback.
+pub struct OpenWrite<'a>(Option<Box<dyn Write + 'a>>);
+
+impl<'a> OpenWrite<'a> {
+    pub fn new(write: Box<dyn Write + 'a>) -> OpenWrite<'a> {
+        OpenWrite(Some(write))
+    }
+
+    pub fn get(&self) -> &(dyn Write + 'a) {
+        self.0.as_deref().expect("write used after commit")
+    }
+
+    pub fn take(&mut self) -> Box<dyn Write + 'a> {
+        self.0.take().expect("write used after commit")
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.0.is_some()
+    }
+}
+
+// The number of puts Store::bulk_put has outstanding at a time.
+pub const BULK_PUT_WINDOW: usize = 256;
+// The number of gets Read::scan_values has outstanding at a time.
+pub const SCAN_VALUES_WINDOW: usize = 64;
+
+// ScanOptions selects the entries Read::scan returns.
+#[derive(Clone, Debug, Default)]
+pub struct ScanOptions {
+    // Only keys that start with prefix.
+    pub prefix: Option<String>,
+    // Only keys at or after start_key.
+    pub start_key: Option<String>,
+    // At most limit entries.
+    pub limit: Option<usize>,
+}
+
+// MigrateFn maps a key and its value to the new value, or None to delete it.
+pub type MigrateFn = dyn Fn(&str, Vec<u8>) -> Option<Vec<u8>>;
+
+// The most entries Store::import_stream puts in one write transaction.
+pub const IMPORT_BATCH_SIZE: usize = 1000;
+
+// ImportSummary counts what Store::import_stream has done so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ImportSummary {
+    pub imported: usize,
+    // The number of batches committed.
+    pub batches: usize,
+    // Whether the import stopped because it was cancelled rather than
+    // because it ran out of entries.
+    pub cancelled: bool,
+}
+
+// ImportProgressFn is called with the totals so far after each batch.
+pub type ImportProgressFn<'a> = dyn Fn(&ImportSummary) + 'a;
+
+// CancelToken lets a caller stop a long running operation (eg
+// Store::import_stream) from elsewhere, such as a progress callback or
+// another task. The operation stops the next time it checks the token.
+#[derive(Debug, Default)]
+pub struct CancelToken {
+    cancelled: Cell<bool>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.set(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.get()
+    }
+}
+
+// Identifies blobs made by Store::export. The version changes whenever the
+// layout does; import only loads blobs of the current version.
+pub const EXPORT_FORMAT: &str = "replicache-kv";
+pub const EXPORT_VERSION: u32 = 1;
+
+// Keys starting with this are reserved for binary keys that are not valid
+// UTF-8 (see encode_binary_key). U+FFFF is a noncharacter, so text keys should
+// not start with it anyway, and stores reject those that do (see check_key).
+pub const BINARY_KEY_PREFIX: &str = "\u{FFFF}";
+
+// Returns the string key under which the binary key is stored. A key that is
+// valid UTF-8 (embedded nulls included) and does not start with
+// BINARY_KEY_PREFIX is stored as is, so get_bytes(b"a") and get("a") name the
+// same entry. Any other key is stored as BINARY_KEY_PREFIX followed by its
+// bytes in lowercase hex, which keeps such keys in byte order among
+// themselves. They sort after text keys made of Basic Multilingual Plane
+// characters but before those with a supplementary-plane character (UTF-8
+// lead byte F0 or above) at the same position.
+pub fn encode_binary_key(key: &[u8]) -> String {
+    match std::str::from_utf8(key) {
+        Ok(s) if !s.starts_with(BINARY_KEY_PREFIX) => s.to_string(),
+        _ => {
+            let mut s = BINARY_KEY_PREFIX.to_string();
+            s.push_str(&HEXLOWER.encode(key));
+            s
+        }
+    }
+}
+
+// Reverses encode_binary_key, eg for keys listed by Read::keys.
+pub fn decode_binary_key(key: &str) -> Result<Vec<u8>> {
+    match key.strip_prefix(BINARY_KEY_PREFIX) {
+        None => Ok(key.as_bytes().to_vec()),
+        Some(hex) => HEXLOWER
+            .decode(hex.as_bytes())
+            .map_err(|e| format!("Invalid binary key {:?}: {}", key, e).into()),
+    }
+}
+
+// Returns an error unless key may be written as is. A key starting with
+// BINARY_KEY_PREFIX must be exactly what encode_binary_key makes of some
+// binary key, so that a text key can never alias a binary one. Stores check
+// every key they are asked to write.
+pub fn check_key(key: &str) -> Result<()> {
+    if !key.starts_with(BINARY_KEY_PREFIX) {
+        return Ok(());
+    }
+    match decode_binary_key(key) {
+        Ok(bytes) if encode_binary_key(&bytes) == key => Ok(()),
+        _ => Err(StoreError::ReservedKey(key.to_string())),
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct Export {
+    format: String,
+    version: u32,
+    // Values are base64 encoded.
+    entries: BTreeMap<String, String>,
+}
+
 #[async_trait(?Send)]
 pub trait Store {
     async fn read<'a>(&'a self, lc: LogContext) -> Result<Box<dyn Read + 'a>>;
     async fn write<'a>(&'a self, lc: LogContext) -> Result<Box<dyn Write + 'a>>;
 
+    // snapshot returns a consistent view of the store that can be held across
+    // many reads: writes committed after it is taken are not visible through
+    // it. A read transaction is already such a view, hence the default, but it
+    // keeps writers out for as long as it is held. Stores that can pin a view
+    // without blocking writers should override it.
+    async fn snapshot<'a>(&'a self) -> Result<Box<dyn Read + 'a>> {
+        self.read(LogContext::new()).await
+    }
+
     async fn put(&self, key: &str, value: &[u8]) -> Result<()> {
         let lc = LogContext::new();
         let wt = self.write(lc).await?;
@@ -46,16 +336,334 @@ pub trait Store {
         Ok(wt.commit().await?)
     }
 
+    // put_if_changed writes value to key unless key already holds exactly
+    // value, and returns whether it wrote. The check and the put happen in
+    // the same write transaction, which is dropped rather than committed when
+    // nothing changed, so the backend sees no write at all.
+    async fn put_if_changed(&self, key: &str, value: &[u8]) -> Result<bool> {
+        let lc = LogContext::new();
+        let wt = self.write(lc).await?;
+        if wt.get(key).await?.as_deref() == Some(value) {
+            return Ok(false);
+        }
+        wt.put(key, value).await?;
+        wt.commit().await?;
+        Ok(true)
+    }
+
     async fn has(&self, key: &str) -> Result<bool> {
         let lc = LogContext::new();
         Ok(self.read(lc).await?.has(key).await?)
     }
 
+    // touch marks key as recently used, for stores that evict the least
+    // recently used entries (eg CachingStore's cache), without reading its
+    // value. Returns whether the key exists. The default only checks for the
+    // key.
+    async fn touch(&self, key: &str) -> Result<bool> {
+        self.has(key).await
+    }
+
     async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
         let lc = LogContext::new();
         Ok(self.read(lc).await?.get(key).await?)
     }
 
+    // get_or_insert_with returns the value of key, first setting it to f() if
+    // it is not present. The read and the insert happen in one write
+    // transaction so concurrent callers cannot both miss and insert.
+    async fn get_or_insert_with(&self, key: &str, f: &dyn Fn() -> Vec<u8>) -> Result<Vec<u8>> {
+        let lc = LogContext::new();
+        let wt = self.write(lc).await?;
+        if let Some(value) = wt.get(key).await? {
+            return Ok(value);
+        }
+        let value = f();
+        wt.put(key, &value).await?;
+        wt.commit().await?;
+        Ok(value)
+    }
+
+    // swap exchanges the values of keys a and b in a single write
+    // transaction. If only one of them is present, its value moves to the
+    // other key and it is deleted; if neither is, nothing is written.
+    async fn swap(&self, a: &str, b: &str) -> Result<()> {
+        let lc = LogContext::new();
+        let wt = self.write(lc).await?;
+        let (va, vb) = (wt.get(a).await?, wt.get(b).await?);
+        if va == vb {
+            return Ok(());
+        }
+        for (key, value) in &[(a, vb), (b, va)] {
+            match value {
+                Some(v) => wt.put(key, v).await?,
+                None => wt.del(key).await?,
+            }
+        }
+        Ok(wt.commit().await?)
+    }
+
+    // put_ordered writes pairs in slice order in a single write transaction.
+    // Each put is awaited before the next is issued, so within the
+    // transaction later entries observe earlier ones, and if a key appears
+    // more than once the last value wins.
+    async fn put_ordered(&self, pairs: &[(&str, &[u8])]) -> Result<()> {
+        let lc = LogContext::new();
+        let wt = self.write(lc).await?;
+        for (key, value) in pairs {
+            wt.put(key, value).await?;
+        }
+        Ok(wt.commit().await?)
+    }
+
+    // put_many writes entries in a single write transaction, rather than the
+    // transaction per entry that calling put for each would open. All of the
+    // puts are issued at once and awaited together before the commit, so
+    // stores whose puts are round trips (eg IndexedDB) overlap them. If a key
+    // appears more than once which value wins is unspecified; use
+    // put_ordered for that.
+    async fn put_many(&self, entries: &[(String, Vec<u8>)]) -> Result<()> {
+        let lc = LogContext::new();
+        let wt = self.write(lc).await?;
+        let wt_ref = &wt;
+        try_join_all(
+            entries
+                .iter()
+                .map(|(key, value)| async move { wt_ref.put(key, value).await }),
+        )
+        .await?;
+        wt.commit().await
+    }
+
+    // bulk_put writes all of entries in a single write transaction. It is
+    // intended for large imports (eg the initial sync) where staging every
+    // entry before commit is too expensive; backends that can should override
+    // bulk_put_with_window to write entries as they arrive rather than
+    // buffering them all.
+    async fn bulk_put(&self, entries: &mut dyn Iterator<Item = (String, Vec<u8>)>) -> Result<()> {
+        self.bulk_put_with_window(entries, BULK_PUT_WINDOW).await
+    }
+
+    // bulk_put_with_window is bulk_put with at most window puts outstanding
+    // at a time: once window puts are issued, the next is issued only when one
+    // of them completes. A window of zero is treated as one. Puts of
+    // different keys may complete out of order, but a put is not issued while
+    // an earlier put of the same key is outstanding, so if a key appears more
+    // than once in entries its last value wins.
+    async fn bulk_put_with_window(
+        &self,
+        entries: &mut dyn Iterator<Item = (String, Vec<u8>)>,
+        window: usize,
+    ) -> Result<()> {
+        let lc = LogContext::new();
+        let wt = self.write(lc).await?;
+        {
+            let wt = &wt;
+            let mut in_flight = FuturesUnordered::new();
+            let mut in_flight_keys = HashSet::new();
+            // An entry whose key is still being put, held back until that put
+            // completes.
+            let mut held: Option<(String, Vec<u8>)> = None;
+            let mut entries = entries.fuse();
+            loop {
+                while in_flight.len() < window.max(1) {
+                    match held.take().or_else(|| entries.next()) {
+                        None => break,
+                        Some((key, value)) if in_flight_keys.contains(&key) => {
+                            held = Some((key, value));
+                            break;
+                        }
+                        Some((key, value)) => {
+                            in_flight_keys.insert(key.clone());
+                            in_flight.push(async move { wt.put(&key, &value).await.map(|_| key) })
+                        }
+                    }
+                }
+                match in_flight.next().await {
+                    None => break,
+                    Some(res) => in_flight_keys.remove(&res?),
+                };
+            }
+        }
+        Ok(wt.commit().await?)
+    }
+
+    // del_prefix deletes every key that starts with prefix in a single write
+    // transaction. Keys are matched by byte prefix, so "a/" leaves "ab/" and
+    // "a" alone. Deleting a prefix needs to enumerate keys, which the basic
+    // get/put interface cannot, so the default is for stores that cannot.
+    async fn del_prefix(&self, _prefix: &str) -> Result<()> {
+        Err(StoreError::NotSupported("del_prefix"))
+    }
+
+    // clear deletes every key in a single write transaction. The default
+    // lists the keys and deletes them one by one; stores that can empty
+    // themselves in one step should override it.
+    async fn clear(&self) -> Result<()> {
+        let wt = self.write(LogContext::new()).await?;
+        for key in wt.keys("").await? {
+            wt.del(&key).await?;
+        }
+        wt.commit().await
+    }
+
+    // migrate rewrites every entry with f and returns the number of entries
+    // it changed.
+    // It runs only if the version recorded under MIGRATION_VERSION_KEY is
+    // below version, and records version once done. Entries are rewritten in
+    // write transactions of at most MIGRATE_BATCH_SIZE keys to bound memory,
+    // so if migrate fails part way some batches are already committed and
+    // the next call applies f to them again: f must leave already migrated
+    // values as they are.
+    async fn migrate(&self, version: u64, f: &MigrateFn) -> Result<usize> {
+        let lc = LogContext::new();
+        let current = match self.get(MIGRATION_VERSION_KEY).await? {
+            None => 0,
+            Some(v) => u64::from_le_bytes(
+                v.as_slice()
+                    .try_into()
+                    .map_err(|_| "invalid migration version")?,
+            ),
+        };
+        if current >= version {
+            return Ok(0);
+        }
+
+        let keys = self.read(lc.clone()).await?.keys("").await?;
+        let mut migrated = 0;
+        for batch in keys.chunks(MIGRATE_BATCH_SIZE) {
+            let wt = self.write(lc.clone()).await?;
+            for key in batch.iter().filter(|k| *k != MIGRATION_VERSION_KEY) {
+                // The key may have been deleted since it was listed.
+                let old = match wt.get(key).await? {
+                    None => continue,
+                    Some(v) => v,
+                };
+                match f(key, old.clone()) {
+                    Some(new) if new == old => continue,
+                    Some(new) => wt.put(key, &new).await?,
+                    None => wt.del(key).await?,
+                }
+                migrated += 1;
+            }
+            wt.commit().await?;
+        }
+        self.put(MIGRATION_VERSION_KEY, &version.to_le_bytes())
+            .await?;
+        Ok(migrated)
+    }
+
+    // export serializes every entry into a blob that import can load into
+    // this or another store. Entries are read from a single read
+    // transaction, so the blob is a consistent snapshot.
+    async fn export(&self) -> Result<Vec<u8>> {
+        let rt = self.read(LogContext::new()).await?;
+        let mut entries = BTreeMap::new();
+        for key in rt.keys("").await? {
+            // The key may have been deleted since it was listed.
+            if let Some(v) = rt.get(&key).await? {
+                entries.insert(key, BASE64.encode(&v));
+            }
+        }
+        serde_json::to_vec(&Export {
+            format: EXPORT_FORMAT.to_string(),
+            version: EXPORT_VERSION,
+            entries,
+        })
+        .map_err(|e| format!("Failed to encode export: {}", e).into())
+    }
+
+    // import replaces the contents of the store with those of a blob made by
+    // export. The blob is checked in full before anything is written and
+    // the old entries are deleted and the new ones put in one write
+    // transaction, so on error the store is left as it was.
+    async fn import(&self, blob: &[u8]) -> Result<()> {
+        let export: Export =
+            serde_json::from_slice(blob).map_err(|e| format!("Failed to decode export: {}", e))?;
+        if export.format != EXPORT_FORMAT {
+            return Err(format!("Unknown export format {:?}", export.format).into());
+        }
+        if export.version != EXPORT_VERSION {
+            return Err(format!("Unsupported export version {}", export.version).into());
+        }
+        let mut entries = Vec::with_capacity(export.entries.len());
+        for (key, value) in export.entries {
+            let value = BASE64
+                .decode(value.as_bytes())
+                .map_err(|e| format!("Invalid value for {:?}: {}", key, e))?;
+            entries.push((key, value));
+        }
+
+        let wt = self.write(LogContext::new()).await?;
+        for key in wt.keys("").await? {
+            wt.del(&key).await?;
+        }
+        for (key, value) in entries.iter() {
+            wt.put(key, value).await?;
+        }
+        wt.commit().await
+    }
+
+    // import_stream puts entries in batches of IMPORT_BATCH_SIZE, each
+    // committed in its own write transaction with bulk_put, so that a large
+    // import neither buffers everything nor holds the write lock throughout.
+    // on_progress is called after each batch, and the import then yields so
+    // that other tasks (eg the UI, or whatever sets cancel) get to run
+    // between batches. cancel is checked before each batch, so a cancelled
+    // import leaves the batches already committed in
+    // place and entries positioned after them: calling import_stream again
+    // with the same entries resumes it. An error also leaves earlier batches
+    // in place.
+    async fn import_stream(
+        &self,
+        entries: &mut dyn Iterator<Item = (String, Vec<u8>)>,
+        on_progress: &ImportProgressFn<'_>,
+        cancel: &CancelToken,
+    ) -> Result<ImportSummary> {
+        self.import_stream_with_batch_size(entries, IMPORT_BATCH_SIZE, on_progress, cancel)
+            .await
+    }
+
+    // import_stream_with_batch_size is import_stream with batches of at most
+    // batch_size entries. A batch size of zero is treated as one.
+    async fn import_stream_with_batch_size(
+        &self,
+        entries: &mut dyn Iterator<Item = (String, Vec<u8>)>,
+        batch_size: usize,
+        on_progress: &ImportProgressFn<'_>,
+        cancel: &CancelToken,
+    ) -> Result<ImportSummary> {
+        let mut summary = ImportSummary::default();
+        loop {
+            if cancel.is_cancelled() {
+                summary.cancelled = true;
+                return Ok(summary);
+            }
+            let batch: Vec<_> = entries.take(batch_size.max(1)).collect();
+            if batch.is_empty() {
+                return Ok(summary);
+            }
+            let n = batch.len();
+            self.bulk_put(&mut batch.into_iter()).await?;
+            summary.imported += n;
+            summary.batches += 1;
+            on_progress(&summary);
+            task::yield_now().await;
+        }
+    }
+
+    // flush is a durability barrier: once it returns, every write committed
+    // before the call has reached the store's durable backend. Stores that
+    // are durable as soon as a commit completes (eg IndexedDB, which commits
+    // on oncomplete) have nothing to do, hence the default. Stores that
+    // buffer committed writes must write them back here.
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    // close releases the store's backend. Views of another store (eg
+    // PrefixedStore) only borrow it, so closing a view leaves the underlying
+    // store open for its owner to close.
     async fn close(&self);
 }
 
@@ -63,8 +671,145 @@ pub trait Store {
 pub trait Read {
     async fn has(&self, key: &str) -> Result<bool>;
     async fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    // has and get for binary keys, stored under encode_binary_key(key).
+    async fn has_bytes(&self, key: &[u8]) -> Result<bool> {
+        self.has(&encode_binary_key(key)).await
+    }
+
+    async fn get_bytes(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get(&encode_binary_key(key)).await
+    }
+
+    // Returns a hash of all the key/value pairs visible to this transaction
+    // that depends only on the contents, not on insertion order or backend.
+    // Implementations that can enumerate their entries should compute it with
+    // content_hash(). The default is for stores that cannot.
+    async fn content_hash(&self) -> Result<[u8; 32]> {
+        Err(StoreError::NotSupported("content_hash"))
+    }
+
+    // Returns the keys visible to this transaction that start with prefix,
+    // in sorted order. The default is for stores that cannot enumerate their
+    // keys.
+    async fn keys(&self, _prefix: &str) -> Result<Vec<String>> {
+        Err(StoreError::NotSupported("keys"))
+    }
+
+    // Returns the number of keys visible to this transaction.
+    async fn count(&self) -> Result<u64> {
+        self.count_prefix("").await
+    }
+
+    // Returns the number of keys visible to this transaction that start with
+    // prefix. The default lists them with keys; stores that can count without
+    // listing should override it.
+    async fn count_prefix(&self, prefix: &str) -> Result<u64> {
+        Ok(self.keys(prefix).await?.len() as u64)
+    }
+
+    // Returns the entries visible to this transaction whose keys start with
+    // prefix, in key order. Values are fetched with up to
+    // SCAN_VALUES_WINDOW gets outstanding, so stores whose gets are round
+    // trips (eg IndexedDB) overlap them.
+    async fn scan_values(&self, prefix: &str) -> Result<Vec<(String, Vec<u8>)>> {
+        self.scan_values_with_window(prefix, SCAN_VALUES_WINDOW)
+            .await
+    }
+
+    // scan_values with at most window gets outstanding. Results come back in
+    // key order whatever order the gets complete in.
+    async fn scan_values_with_window(
+        &self,
+        prefix: &str,
+        window: usize,
+    ) -> Result<Vec<(String, Vec<u8>)>> {
+        let mut keys = self.keys(prefix).await?.into_iter();
+        let mut in_flight = FuturesOrdered::new();
+        let mut entries = Vec::with_capacity(keys.len());
+        loop {
+            while in_flight.len() < window.max(1) {
+                match keys.next() {
+                    None => break,
+                    Some(key) => in_flight.push(async move {
+                        let value = self.get(&key).await?;
+                        Ok::<_, StoreError>((key, value))
+                    }),
+                }
+            }
+            match in_flight.next().await {
+                None => break,
+                Some(res) => {
+                    if let (key, Some(value)) = res? {
+                        entries.push((key, value));
+                    }
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    // Returns a stream of the entries visible to this transaction that opts
+    // selects, in key order. Unlike scan_values, values are fetched one at a
+    // time as the stream is polled, so a caller can stop part way without
+    // reading the rest. Keys are listed up front with keys, so a write
+    // transaction's scan sees its own pending writes.
+    async fn scan<'a>(
+        &'a self,
+        opts: ScanOptions,
+    ) -> Result<LocalBoxStream<'a, Result<(String, Vec<u8>)>>> {
+        let keys = self.keys(opts.prefix.as_deref().unwrap_or("")).await?;
+        let start = match &opts.start_key {
+            None => 0,
+            Some(start_key) => match keys.binary_search(start_key) {
+                Ok(i) | Err(i) => i,
+            },
+        };
+        let keys = keys
+            .into_iter()
+            .skip(start)
+            .take(opts.limit.unwrap_or(usize::MAX));
+        Ok(stream::iter(keys)
+            .filter_map(move |key| async move {
+                match self.get(&key).await {
+                    Ok(Some(value)) => Some(Ok((key, value))),
+                    Ok(None) => None,
+                    Err(e) => Some(Err(e)),
+                }
+            })
+            .boxed_local())
+    }
+
+    // Returns the bytes this transaction has read and written so far, or None
+    // if the store does not count them.
+    fn byte_counts(&self) -> Option<ByteCounts> {
+        None
+    }
+}
+
+// content_hash XORs together a hash of each entry, so the result does not
+// depend on the order of entries. Keys must be unique. Lengths are hashed in
+// so that eg ("ab", "c") and ("a", "bc") hash differently.
+pub fn content_hash<'a>(entries: impl Iterator<Item = (&'a str, &'a [u8])>) -> [u8; 32] {
+    let mut sum = [0u8; 32];
+    for (key, value) in entries {
+        let mut hasher = Sha512::new();
+        hasher.input((key.len() as u64).to_le_bytes());
+        hasher.input(key.as_bytes());
+        hasher.input(value);
+        let entry = hasher.result();
+        for (s, e) in sum.iter_mut().zip(entry.iter()) {
+            *s ^= e;
+        }
+    }
+    sum
 }
 
+// A Write holds the store's write lock until it is committed or dropped.
+// Dropping a Write without calling commit() rolls it back: implementations
+// must discard everything staged by put() and del() and must not apply any
+// part of it, even if the underlying storage would otherwise auto-commit.
+// Callers that bail out early can therefore just drop the transaction.
 #[async_trait(?Send)]
 pub trait Write: Read {
     fn as_read(&self) -> &dyn Read;
@@ -72,13 +817,56 @@ pub trait Write: Read {
     async fn put(&self, key: &str, value: &[u8]) -> Result<()>;
     async fn del(&self, key: &str) -> Result<()>;
 
+    // put and del for binary keys, stored under encode_binary_key(key).
+    async fn put_bytes(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.put(&encode_binary_key(key), value).await
+    }
+
+    async fn del_bytes(&self, key: &[u8]) -> Result<()> {
+        self.del(&encode_binary_key(key)).await
+    }
+
+    // Returns true if put() or del() has been called on this transaction, ie
+    // if committing it would write anything. It does not check whether a
+    // put changed a value (see Store::put_if_changed for that).
+    async fn is_dirty(&self) -> Result<bool>;
+
+    // savepoint marks the writes staged so far, so that rollback_to can
+    // later discard the ones staged after it while keeping those before.
+    // Savepoints nest: rolling back to one discards the writes and the
+    // savepoints taken since, but leaves it in place to be rolled back to
+    // again. The default is for stores that write through rather than stage
+    // their writes.
+    async fn savepoint(&self) -> Result<SavepointId> {
+        Err(StoreError::NotSupported("savepoint"))
+    }
+
+    async fn rollback_to(&self, _id: SavepointId) -> Result<()> {
+        Err(StoreError::NotSupported("rollback_to"))
+    }
+
     async fn commit(self: Box<Self>) -> Result<()>;
+
+    // commit_with_stats commits like commit and reports what the commit
+    // wrote, or None if the store does not count it.
+    async fn commit_with_stats(self: Box<Self>) -> Result<Option<CommitStats>> {
+        self.commit().await?;
+        Ok(None)
+    }
 }
 
 pub mod trait_tests {
-    use super::Store;
+    use super::{
+        decode_binary_key, encode_binary_key, ByteCounts, CancelToken, CommitStats, ImportSummary,
+        ScanOptions, Store, StoreError, MIGRATION_VERSION_KEY,
+    };
     use crate::util::rlog::LogContext;
+    use async_std::future::timeout;
+    use futures::stream::{StreamExt, TryStreamExt};
+    use std::cell::RefCell;
+    use std::fmt;
     use std::future::Future;
+    use std::time::Duration;
 
     pub async fn run_all<F, T>(new_store: F)
     where
@@ -97,6 +885,56 @@ pub mod trait_tests {
         write_transaction(&mut *s).await;
         s = new_store().await;
         isolation(&mut *s).await;
+        s = new_store().await;
+        bulk_put(&mut *s).await;
+        s = new_store().await;
+        bulk_put_duplicate_keys(&mut *s).await;
+        s = new_store().await;
+        get_or_insert_with(&mut *s).await;
+        s = new_store().await;
+        put_ordered(&mut *s).await;
+        s = new_store().await;
+        content_hash(&mut *s).await;
+        s = new_store().await;
+        flush(&mut *s).await;
+        s = new_store().await;
+        put_if_changed(&mut *s).await;
+        s = new_store().await;
+        is_dirty(&mut *s).await;
+        s = new_store().await;
+        del_prefix(&mut *s).await;
+        s = new_store().await;
+        keys(&mut *s).await;
+        s = new_store().await;
+        migrate(&mut *s).await;
+        s = new_store().await;
+        snapshot(&mut *s).await;
+        s = new_store().await;
+        byte_counts(&mut *s).await;
+        s = new_store().await;
+        swap(&mut *s).await;
+        s = new_store().await;
+        export_import(&mut *s).await;
+        s = new_store().await;
+        scan_values(&mut *s).await;
+        s = new_store().await;
+        touch(&mut *s).await;
+        s = new_store().await;
+        import_stream(&mut *s).await;
+        s = new_store().await;
+        scan(&mut *s).await;
+        s = new_store().await;
+        clear(&mut *s).await;
+        s = new_store().await;
+        count(&mut *s).await;
+        s = new_store().await;
+        put_many(&mut *s).await;
+        s = new_store().await;
+        binary_keys(&mut *s).await;
+        s = new_store().await;
+        commit_stats(&mut *s).await;
+        s = new_store().await;
+        savepoints(&mut *s).await;
     }
 
     pub async fn store(store: &mut dyn Store) {
@@ -150,6 +988,7 @@ pub mod trait_tests {
         let wt = store.write(LogContext::new()).await.unwrap();
         wt.del("k1").await.unwrap();
         assert!(!wt.has("k1").await.unwrap());
+        assert_eq!(None, wt.get("k1").await.unwrap());
         wt.commit().await.unwrap();
         assert!(!store.has("k1").await.unwrap());
 
@@ -169,6 +1008,15 @@ pub mod trait_tests {
         wt.commit().await.unwrap();
         assert_eq!(Some(b"final".to_vec()), store.get("k2").await.unwrap());
 
+        // Test del of a committed key then put then commit.
+        let wt = store.write(LogContext::new()).await.unwrap();
+        wt.del("k2").await.unwrap();
+        assert_eq!(None, wt.get("k2").await.unwrap());
+        wt.put("k2", b"after del").await.unwrap();
+        assert_eq!(Some(b"after del".to_vec()), wt.get("k2").await.unwrap());
+        wt.commit().await.unwrap();
+        assert_eq!(Some(b"after del".to_vec()), store.get("k2").await.unwrap());
+
         // Test as_read.
         let wt = store.write(LogContext::new()).await.unwrap();
         wt.put("k2", b"new value").await.unwrap();
@@ -178,57 +1026,805 @@ pub mod trait_tests {
     }
 
     pub async fn isolation(store: &mut dyn Store) {
-        use async_std::future::timeout;
-        use std::time::Duration;
-
         // We don't get line numbers in stack traces in wasm so we use an error message
         // which is logged to console to identify the issue. AFAICT this does nothing
         // when running regular tests, but that's ok because we get useful stack traces
         // in that case.
-        fn spew(msg: &str) {
-            error!("", "{}", msg);
+        if let Err(e) = check_isolation(store, Duration::from_millis(200)).await {
+            error!("", "{}", e);
+            panic!("{}", e);
         }
+        let r = store.read(LogContext::new()).await.unwrap();
+        assert!(!r.has("foo").await.unwrap());
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub enum IsolationError {
+        SecondReadBlocked,
+        TwoReadsAllowedWrite,
+        OneReadAllowedWrite,
+        WriteAllowedWrite,
+        WriteAllowedRead,
+        DroppedWriteBlockedRead,
+        Storage(StoreError),
+    }
+
+    impl fmt::Display for IsolationError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            use IsolationError::*;
+            match self {
+                SecondReadBlocked => write!(f, "should be able to open second read"),
+                TwoReadsAllowedWrite => write!(f, "2 open read tx should have prevented new write"),
+                OneReadAllowedWrite => write!(f, "1 open read tx should have prevented new write"),
+                WriteAllowedWrite => write!(f, "1 open write tx should have prevented new write"),
+                WriteAllowedRead => write!(f, "1 open write tx should have prevented new read"),
+                DroppedWriteBlockedRead => write!(f, "dropped write tx should allow new read"),
+                Storage(e) => write!(f, "store error: {}", e),
+            }
+        }
+    }
+
+    impl From<StoreError> for IsolationError {
+        fn from(err: StoreError) -> IsolationError {
+            IsolationError::Storage(err)
+        }
+    }
+
+    // check_isolation checks the read/write isolation invariants every Store
+    // must uphold and reports the first one that does not hold. A transaction
+    // is considered blocked if it cannot be opened within dur, so slow
+    // backends may need a larger value.
+    pub async fn check_isolation(store: &dyn Store, dur: Duration) -> Result<(), IsolationError> {
+        use IsolationError::*;
 
         // Assert there can be multiple concurrent read txs...
-        let r1 = store.read(LogContext::new()).await.unwrap();
-        let r2 = store
-            .read(LogContext::new())
+        let r1 = store.read(LogContext::new()).await?;
+        let r2 = timeout(dur, store.read(LogContext::new()))
             .await
-            .expect("should be able to open second read");
+            .map_err(|_| SecondReadBlocked)??;
         // and that while outstanding they prevent write txs...
-        let dur = Duration::from_millis(200);
-        let w = store.write(LogContext::new());
-        if timeout(dur, w).await.is_ok() {
-            spew("2 open read tx should have prevented new write");
-            panic!();
+        if timeout(dur, store.write(LogContext::new())).await.is_ok() {
+            return Err(TwoReadsAllowedWrite);
         }
         // until both the reads are done...
         drop(r1);
-        let w = store.write(LogContext::new());
-        if timeout(dur, w).await.is_ok() {
-            spew("1 open read tx should have prevented new write");
-            panic!();
+        if timeout(dur, store.write(LogContext::new())).await.is_ok() {
+            return Err(OneReadAllowedWrite);
         }
         drop(r2);
-        let w = store.write(LogContext::new()).await.unwrap();
+        let w = store.write(LogContext::new()).await?;
 
         // At this point we have a write tx outstanding. Assert that
         // we cannot open another write transaction.
-        let w2 = store.write(LogContext::new());
-        if timeout(dur, w2).await.is_ok() {
-            spew("1 open write tx should have prevented new write");
-            panic!();
+        if timeout(dur, store.write(LogContext::new())).await.is_ok() {
+            return Err(WriteAllowedWrite);
         }
 
         // The write tx is still outstanding, ensure we cannot open
         // a read tx until it is finished.
-        let r = store.read(LogContext::new());
-        if timeout(dur, r).await.is_ok() {
-            spew("1 open write tx should have prevented new read");
-            panic!();
+        if timeout(dur, store.read(LogContext::new())).await.is_ok() {
+            return Err(WriteAllowedRead);
         }
         drop(w);
-        let r = store.read(LogContext::new()).await.unwrap();
-        assert!(!r.has("foo").await.unwrap());
+        timeout(dur, store.read(LogContext::new()))
+            .await
+            .map_err(|_| DroppedWriteBlockedRead)??;
+        Ok(())
+    }
+
+    pub async fn bulk_put(store: &mut dyn Store) {
+        store.put("k0", b"old").await.unwrap();
+
+        let mut entries = (0..100).map(|i| (format!("k{}", i), format!("v{}", i).into_bytes()));
+        store.bulk_put(&mut entries).await.unwrap();
+        for i in 0..100 {
+            assert_eq!(
+                Some(format!("v{}", i).into_bytes()),
+                store.get(&format!("k{}", i)).await.unwrap()
+            );
+        }
+
+        // An empty import is fine and leaves the store alone.
+        store.bulk_put(&mut std::iter::empty()).await.unwrap();
+        assert_eq!(Some(b"v0".to_vec()), store.get("k0").await.unwrap());
+    }
+
+    pub async fn bulk_put_duplicate_keys(store: &mut dyn Store) {
+        // The last value for a key wins, even with several puts outstanding.
+        let mut entries = (0..20).map(|i| (format!("k{}", i % 3), vec![i]));
+        store.bulk_put_with_window(&mut entries, 4).await.unwrap();
+        for (key, last) in &[("k0", 18), ("k1", 19), ("k2", 17)] {
+            assert_eq!(Some(vec![*last]), store.get(key).await.unwrap());
+        }
+    }
+
+    pub async fn get_or_insert_with(store: &mut dyn Store) {
+        use std::cell::Cell;
+
+        let calls = Cell::new(0);
+        let f = || {
+            calls.set(calls.get() + 1);
+            b"default".to_vec()
+        };
+        assert_eq!(
+            b"default".to_vec(),
+            store.get_or_insert_with("k1", &f).await.unwrap()
+        );
+        assert_eq!(Some(b"default".to_vec()), store.get("k1").await.unwrap());
+        assert_eq!(1, calls.get());
+
+        // Present values are returned without calling f.
+        assert_eq!(
+            b"default".to_vec(),
+            store.get_or_insert_with("k1", &f).await.unwrap()
+        );
+        store.put("k2", b"v2").await.unwrap();
+        assert_eq!(
+            b"v2".to_vec(),
+            store.get_or_insert_with("k2", &f).await.unwrap()
+        );
+        assert_eq!(1, calls.get());
+    }
+
+    pub async fn content_hash(store: &mut dyn Store) {
+        async fn hash(store: &dyn Store) -> [u8; 32] {
+            let r = store.read(LogContext::new()).await.unwrap();
+            r.content_hash().await.unwrap()
+        }
+
+        let empty = hash(store).await;
+        assert_eq!([0u8; 32], empty);
+
+        store.put("a", b"1").await.unwrap();
+        store.put("b", b"2").await.unwrap();
+        let ab = hash(store).await;
+        assert_ne!(empty, ab);
+
+        // Same contents in a different order hash the same.
+        let wt = store.write(LogContext::new()).await.unwrap();
+        wt.del("a").await.unwrap();
+        wt.del("b").await.unwrap();
+        assert_eq!(empty, wt.content_hash().await.unwrap());
+        wt.put("b", b"2").await.unwrap();
+        wt.put("a", b"1").await.unwrap();
+        assert_eq!(ab, wt.content_hash().await.unwrap());
+        wt.commit().await.unwrap();
+        assert_eq!(ab, hash(store).await);
+
+        // Moving bytes between key and value changes the hash.
+        let wt = store.write(LogContext::new()).await.unwrap();
+        wt.put("xy", b"z").await.unwrap();
+        let h1 = wt.content_hash().await.unwrap();
+        wt.del("xy").await.unwrap();
+        wt.put("x", b"yz").await.unwrap();
+        assert_ne!(h1, wt.content_hash().await.unwrap());
+    }
+
+    pub async fn put_many(store: &mut dyn Store) {
+        store.put("k0000", b"old").await.unwrap();
+        let entries: Vec<(String, Vec<u8>)> = (0..1000)
+            .map(|i| (format!("k{:04}", i), format!("v{}", i).into_bytes()))
+            .collect();
+        store.put_many(&entries).await.unwrap();
+        for i in (0..1000).step_by(97).chain(vec![999]) {
+            assert_eq!(
+                Some(format!("v{}", i).into_bytes()),
+                store.get(&format!("k{:04}", i)).await.unwrap()
+            );
+        }
+        let rt = store.read(LogContext::new()).await.unwrap();
+        assert_eq!(1000, rt.keys("k").await.unwrap().len());
+        drop(rt);
+
+        store.put_many(&[]).await.unwrap();
+    }
+
+    pub async fn binary_keys(store: &mut dyn Store) {
+        let keys: &[&[u8]] = &[b"a\0b", b"\xff\xfe", b"\xc3\xa9", b"\0", b"\xef\xbf\xbfx"];
+        let wt = store.write(LogContext::new()).await.unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            assert!(!wt.has_bytes(key).await.unwrap());
+            wt.put_bytes(key, &[i as u8]).await.unwrap();
+        }
+        wt.commit().await.unwrap();
+
+        let rt = store.read(LogContext::new()).await.unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            assert!(rt.has_bytes(key).await.unwrap());
+            assert_eq!(Some(vec![i as u8]), rt.get_bytes(key).await.unwrap());
+        }
+        // Keys that are UTF-8 are the same as the string keys.
+        assert_eq!(Some(vec![0]), rt.get("a\0b").await.unwrap());
+        assert_eq!(Some(vec![2]), rt.get("é").await.unwrap());
+        let mut listed = rt
+            .keys("")
+            .await
+            .unwrap()
+            .iter()
+            .map(|k| decode_binary_key(k).unwrap())
+            .collect::<Vec<_>>();
+        listed.sort();
+        let mut want: Vec<Vec<u8>> = keys.iter().map(|k| k.to_vec()).collect();
+        want.sort();
+        assert_eq!(want, listed);
+        drop(rt);
+
+        let wt = store.write(LogContext::new()).await.unwrap();
+        wt.del_bytes(b"\xff\xfe").await.unwrap();
+        wt.commit().await.unwrap();
+        assert!(!store.has(&encode_binary_key(b"\xff\xfe")).await.unwrap());
+        assert!(store.has("\0").await.unwrap());
+
+        // Text keys cannot use the reserved prefix unless they are exactly
+        // the encoding of a binary key.
+        for key in &["\u{FFFF}", "\u{FFFF}zz", "\u{FFFF}61", "\u{FFFF}FF"] {
+            assert_eq!(
+                Err(StoreError::ReservedKey(key.to_string())),
+                store.put(key, b"x").await
+            );
+            assert!(!store.has(key).await.unwrap());
+        }
+        store.put(&encode_binary_key(b"\xff"), b"x").await.unwrap();
+        let rt = store.read(LogContext::new()).await.unwrap();
+        assert_eq!(Some(b"x".to_vec()), rt.get_bytes(b"\xff").await.unwrap());
+        drop(rt);
+
+        // A bulk put with a reserved key writes none of the entries.
+        let mut entries = vec![
+            ("k".to_string(), b"v".to_vec()),
+            ("\u{FFFF}".to_string(), b"v".to_vec()),
+        ]
+        .into_iter();
+        assert!(store.bulk_put(&mut entries).await.is_err());
+        assert!(!store.has("k").await.unwrap());
+    }
+
+    pub async fn put_ordered(store: &mut dyn Store) {
+        store.put("index", b"old").await.unwrap();
+        store
+            .put_ordered(&[("data", b"chunk"), ("index", b"data"), ("data", b"chunk2")])
+            .await
+            .unwrap();
+        assert_eq!(Some(b"chunk2".to_vec()), store.get("data").await.unwrap());
+        assert_eq!(Some(b"data".to_vec()), store.get("index").await.unwrap());
+
+        store.put_ordered(&[]).await.unwrap();
+        assert_eq!(Some(b"data".to_vec()), store.get("index").await.unwrap());
+    }
+
+    pub async fn flush(store: &mut dyn Store) {
+        // Flushing with nothing written is fine.
+        store.flush().await.unwrap();
+
+        store.put("foo", b"bar").await.unwrap();
+        store.flush().await.unwrap();
+        assert_eq!(Some(b"bar".to_vec()), store.get("foo").await.unwrap());
+
+        // Flushing again is a no-op.
+        store.flush().await.unwrap();
+        assert_eq!(Some(b"bar".to_vec()), store.get("foo").await.unwrap());
+    }
+
+    pub async fn put_if_changed(store: &mut dyn Store) {
+        assert!(store.put_if_changed("foo", b"bar").await.unwrap());
+        assert_eq!(Some(b"bar".to_vec()), store.get("foo").await.unwrap());
+        assert!(!store.put_if_changed("foo", b"bar").await.unwrap());
+        assert_eq!(Some(b"bar".to_vec()), store.get("foo").await.unwrap());
+        assert!(store.put_if_changed("foo", b"baz").await.unwrap());
+        assert_eq!(Some(b"baz".to_vec()), store.get("foo").await.unwrap());
+
+        // An empty value is still a change from no value.
+        assert!(store.put_if_changed("empty", b"").await.unwrap());
+        assert!(!store.put_if_changed("empty", b"").await.unwrap());
+        assert!(store.has("empty").await.unwrap());
+    }
+
+    pub async fn is_dirty(store: &mut dyn Store) {
+        let wt = store.write(LogContext::new()).await.unwrap();
+        assert!(!wt.is_dirty().await.unwrap());
+        wt.get("foo").await.unwrap();
+        assert!(!wt.is_dirty().await.unwrap());
+        wt.put("foo", b"bar").await.unwrap();
+        assert!(wt.is_dirty().await.unwrap());
+        wt.commit().await.unwrap();
+
+        // A del counts even if the key is absent.
+        let wt = store.write(LogContext::new()).await.unwrap();
+        wt.del("nope").await.unwrap();
+        assert!(wt.is_dirty().await.unwrap());
+    }
+
+    pub async fn del_prefix(store: &mut dyn Store) {
+        for key in &["a", "a/", "a/1", "a/2/x", "ab/1", "b/1"] {
+            store.put(key, b"v").await.unwrap();
+        }
+        store.del_prefix("a/").await.unwrap();
+        for key in &["a/", "a/1", "a/2/x"] {
+            assert!(!store.has(key).await.unwrap(), "{}", key);
+        }
+        // Neighbouring keys and prefixes are untouched.
+        for key in &["a", "ab/1", "b/1"] {
+            assert!(store.has(key).await.unwrap(), "{}", key);
+        }
+
+        // Deleting a prefix that matches nothing is fine.
+        store.del_prefix("c/").await.unwrap();
+        assert!(store.has("b/1").await.unwrap());
+    }
+
+    pub async fn clear(store: &mut dyn Store) {
+        store.clear().await.unwrap();
+        for key in &["a", "b", "c/d"] {
+            store.put(key, b"x").await.unwrap();
+        }
+        store.clear().await.unwrap();
+        let rt = store.read(LogContext::new()).await.unwrap();
+        assert_eq!(Vec::<String>::new(), rt.keys("").await.unwrap());
+        assert!(!rt.has("a").await.unwrap());
+        drop(rt);
+
+        // The store is still usable.
+        store.put("a", b"y").await.unwrap();
+        assert_eq!(Some(b"y".to_vec()), store.get("a").await.unwrap());
+    }
+
+    pub async fn count(store: &mut dyn Store) {
+        for key in &["a/1", "a/2", "b/1"] {
+            store.put(key, b"x").await.unwrap();
+        }
+        let rt = store.read(LogContext::new()).await.unwrap();
+        assert_eq!(3, rt.count().await.unwrap());
+        assert_eq!(2, rt.count_prefix("a/").await.unwrap());
+        assert_eq!(0, rt.count_prefix("c/").await.unwrap());
+        drop(rt);
+
+        // Pending puts and dels are counted.
+        let wt = store.write(LogContext::new()).await.unwrap();
+        wt.put("a/3", b"x").await.unwrap();
+        wt.put("a/1", b"y").await.unwrap();
+        wt.del("b/1").await.unwrap();
+        wt.del("nope").await.unwrap();
+        assert_eq!(3, wt.count().await.unwrap());
+        assert_eq!(3, wt.count_prefix("a/").await.unwrap());
+        assert_eq!(0, wt.count_prefix("b/").await.unwrap());
+        wt.commit().await.unwrap();
+
+        let snapshot = store.snapshot().await.unwrap();
+        assert_eq!(3, snapshot.count().await.unwrap());
+        assert_eq!(0, snapshot.count_prefix("b/").await.unwrap());
+    }
+
+    pub async fn keys(store: &mut dyn Store) {
+        for key in &["b", "a/2", "a/1", "c"] {
+            store.put(key, b"v").await.unwrap();
+        }
+        let rt = store.read(LogContext::new()).await.unwrap();
+        assert_eq!(vec!["a/1", "a/2", "b", "c"], rt.keys("").await.unwrap());
+        assert_eq!(vec!["a/1", "a/2"], rt.keys("a/").await.unwrap());
+        assert!(rt.keys("d").await.unwrap().is_empty());
+        drop(rt);
+
+        // A write transaction sees its own puts and dels.
+        let wt = store.write(LogContext::new()).await.unwrap();
+        wt.put("a/0", b"v").await.unwrap();
+        wt.del("a/2").await.unwrap();
+        assert_eq!(vec!["a/0", "a/1"], wt.keys("a/").await.unwrap());
+    }
+
+    pub async fn migrate(store: &mut dyn Store) {
+        store.put("a", b"1").await.unwrap();
+        store.put("b", b"2").await.unwrap();
+        store.put("c", b"3").await.unwrap();
+        let f = |key: &str, value: Vec<u8>| match key {
+            "a" => Some(b"10".to_vec()),
+            "b" => None,
+            _ => Some(value),
+        };
+
+        // Only a and b changed.
+        assert_eq!(2, store.migrate(1, &f).await.unwrap());
+        assert_eq!(Some(b"10".to_vec()), store.get("a").await.unwrap());
+        assert!(!store.has("b").await.unwrap());
+        assert_eq!(Some(b"3".to_vec()), store.get("c").await.unwrap());
+
+        // Each version runs once.
+        store.put("b", b"2").await.unwrap();
+        assert_eq!(0, store.migrate(1, &f).await.unwrap());
+        assert!(store.has("b").await.unwrap());
+        assert_eq!(1, store.migrate(2, &f).await.unwrap());
+        assert!(!store.has("b").await.unwrap());
+        assert_eq!(
+            Some(2u64.to_le_bytes().to_vec()),
+            store.get(MIGRATION_VERSION_KEY).await.unwrap()
+        );
+        assert!(MIGRATION_VERSION_KEY.starts_with("sys/"));
+    }
+
+    pub async fn snapshot(store: &mut dyn Store) {
+        store.put("a", b"1").await.unwrap();
+        store.put("b", b"2").await.unwrap();
+        let snap = store.snapshot().await.unwrap();
+        assert_eq!(Some(b"1".to_vec()), snap.get("a").await.unwrap());
+        assert_eq!(Some(b"2".to_vec()), snap.get("b").await.unwrap());
+        assert!(!snap.has("c").await.unwrap());
+        drop(snap);
+
+        // Once dropped, the store can be written again.
+        store.put("c", b"3").await.unwrap();
+        assert!(store.snapshot().await.unwrap().has("c").await.unwrap());
+    }
+
+    pub async fn swap(store: &mut dyn Store) {
+        store.put("a", b"1").await.unwrap();
+        store.put("b", b"2").await.unwrap();
+        store.swap("a", "b").await.unwrap();
+        assert_eq!(Some(b"2".to_vec()), store.get("a").await.unwrap());
+        assert_eq!(Some(b"1".to_vec()), store.get("b").await.unwrap());
+
+        // An absent key takes the other's place as absent.
+        store.swap("a", "c").await.unwrap();
+        assert!(!store.has("a").await.unwrap());
+        assert_eq!(Some(b"2".to_vec()), store.get("c").await.unwrap());
+
+        store.swap("b", "b").await.unwrap();
+        assert_eq!(Some(b"1".to_vec()), store.get("b").await.unwrap());
+        store.swap("x", "y").await.unwrap();
+        assert!(!store.has("x").await.unwrap());
+        assert!(!store.has("y").await.unwrap());
+    }
+
+    pub async fn touch(store: &mut dyn Store) {
+        assert!(!store.touch("foo").await.unwrap());
+        store.put("foo", b"bar").await.unwrap();
+        assert!(store.touch("foo").await.unwrap());
+        assert_eq!(Some(b"bar".to_vec()), store.get("foo").await.unwrap());
+    }
+
+    pub async fn import_stream(store: &mut dyn Store) {
+        let entries = || (0..10u8).map(|i| (format!("k{}", i), vec![i]));
+        let mut iter = entries();
+        let cancel = CancelToken::new();
+        let progress = RefCell::new(vec![]);
+        let on_progress = |summary: &ImportSummary| {
+            progress.borrow_mut().push(summary.imported);
+            if summary.batches == 2 {
+                cancel.cancel();
+            }
+        };
+
+        // Cancelling leaves the committed batches in place.
+        let summary = store
+            .import_stream_with_batch_size(&mut iter, 3, &on_progress, &cancel)
+            .await
+            .unwrap();
+        assert_eq!(
+            ImportSummary {
+                imported: 6,
+                batches: 2,
+                cancelled: true,
+            },
+            summary
+        );
+        assert_eq!(vec![3, 6], *progress.borrow());
+        assert_eq!(Some(vec![5]), store.get("k5").await.unwrap());
+        assert!(!store.has("k6").await.unwrap());
+
+        // Importing the rest resumes where it stopped.
+        let summary = store
+            .import_stream_with_batch_size(&mut iter, 3, &|_| {}, &CancelToken::new())
+            .await
+            .unwrap();
+        assert_eq!(
+            ImportSummary {
+                imported: 4,
+                batches: 2,
+                cancelled: false,
+            },
+            summary
+        );
+        for (key, value) in entries() {
+            assert_eq!(Some(value), store.get(&key).await.unwrap());
+        }
+
+        // A token cancelled up front imports nothing.
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let summary = store
+            .import_stream(&mut entries(), &|_| panic!("no batches"), &cancel)
+            .await
+            .unwrap();
+        assert_eq!(0, summary.imported);
+        assert!(summary.cancelled);
+
+        // The import yields between batches, so a concurrent task can cancel
+        // it after the first.
+        let (cancel, mut iter) = (CancelToken::new(), entries());
+        let (summary, _) = futures::join!(
+            store.import_stream_with_batch_size(&mut iter, 3, &|_| {}, &cancel),
+            async { cancel.cancel() },
+        );
+        assert_eq!(
+            ImportSummary {
+                imported: 3,
+                batches: 1,
+                cancelled: true,
+            },
+            summary.unwrap()
+        );
+    }
+
+    pub async fn scan(store: &mut dyn Store) {
+        for key in &["a", "b/1", "b/2", "b/3", "c"] {
+            store.put(key, key.as_bytes()).await.unwrap();
+        }
+        let wt = store.write(LogContext::new()).await.unwrap();
+        wt.put("b/0", b"new").await.unwrap();
+        wt.put("b/2", b"changed").await.unwrap();
+        wt.del("b/3").await.unwrap();
+
+        let scan = |opts| {
+            let wt = &wt;
+            async move {
+                wt.scan(opts)
+                    .await
+                    .unwrap()
+                    .try_collect::<Vec<_>>()
+                    .await
+                    .unwrap()
+            }
+        };
+        let entry = |k: &str, v: &[u8]| (k.to_string(), v.to_vec());
+
+        // Pending writes are merged over the committed entries.
+        let prefix = || Some("b/".to_string());
+        assert_eq!(
+            vec![
+                entry("b/0", b"new"),
+                entry("b/1", b"b/1"),
+                entry("b/2", b"changed"),
+            ],
+            scan(ScanOptions {
+                prefix: prefix(),
+                ..Default::default()
+            })
+            .await
+        );
+        assert_eq!(
+            vec![entry("b/1", b"b/1"), entry("b/2", b"changed")],
+            scan(ScanOptions {
+                prefix: prefix(),
+                start_key: Some("b/05".to_string()),
+                limit: None,
+            })
+            .await
+        );
+        assert_eq!(
+            vec![entry("a", b"a"), entry("b/0", b"new")],
+            scan(ScanOptions {
+                limit: Some(2),
+                ..Default::default()
+            })
+            .await
+        );
+        assert_eq!(
+            vec![entry("c", b"c")],
+            scan(ScanOptions {
+                start_key: Some("b/3".to_string()),
+                ..Default::default()
+            })
+            .await
+        );
+
+        // Once committed the writes are seen by reads.
+        wt.commit().await.unwrap();
+        let rt = store.read(LogContext::new()).await.unwrap();
+        let keys: Vec<_> = rt
+            .scan(ScanOptions::default())
+            .await
+            .unwrap()
+            .map(|r| r.unwrap().0)
+            .collect()
+            .await;
+        assert_eq!(vec!["a", "b/0", "b/1", "b/2", "c"], keys);
+    }
+
+    pub async fn scan_values(store: &mut dyn Store) {
+        for i in (0..20u8).rev() {
+            store.put(&format!("k{:02}", i), &[i]).await.unwrap();
+        }
+        store.put("other", b"x").await.unwrap();
+        let want: Vec<(String, Vec<u8>)> =
+            (0..20u8).map(|i| (format!("k{:02}", i), vec![i])).collect();
+
+        let rt = store.read(LogContext::new()).await.unwrap();
+        assert_eq!(want, rt.scan_values("k").await.unwrap());
+        for window in &[0, 1, 3, 100] {
+            assert_eq!(
+                want,
+                rt.scan_values_with_window("k", *window).await.unwrap()
+            );
+        }
+        assert!(rt.scan_values("none").await.unwrap().is_empty());
+    }
+
+    pub async fn export_import(store: &mut dyn Store) {
+        store.put("a", b"1").await.unwrap();
+        store.put("b", &[0, 255]).await.unwrap();
+        let blob = store.export().await.unwrap();
+
+        // Import replaces whatever is there.
+        store.put("a", b"changed").await.unwrap();
+        store.put("c", b"3").await.unwrap();
+        store.import(&blob).await.unwrap();
+        assert_eq!(Some(b"1".to_vec()), store.get("a").await.unwrap());
+        assert_eq!(Some(vec![0, 255]), store.get("b").await.unwrap());
+        assert!(!store.has("c").await.unwrap());
+        assert_eq!(blob, store.export().await.unwrap());
+
+        // Bad blobs are rejected and leave the store alone.
+        let other_version = String::from_utf8(blob.clone())
+            .unwrap()
+            .replace("\"version\":1", "\"version\":2");
+        for bad in &[
+            other_version.as_bytes(),
+            b"{\"format\":\"other\",\"version\":1,\"entries\":{}}",
+            b"{\"format\":\"replicache-kv\",\"version\":1,\"entries\":{\"a\":\"!\"}}",
+            b"not json",
+        ] {
+            assert!(store.import(bad).await.is_err());
+        }
+        assert_eq!(blob, store.export().await.unwrap());
+    }
+
+    pub async fn commit_stats(store: &mut dyn Store) {
+        store.put("a", b"1").await.unwrap();
+        store.put("b", b"2").await.unwrap();
+
+        let wt = store.write(LogContext::new()).await.unwrap();
+        wt.put("a", b"first").await.unwrap();
+        wt.put("a", b"second").await.unwrap();
+        wt.put("c", b"3").await.unwrap();
+        wt.del("b").await.unwrap();
+        wt.put("d", b"gone").await.unwrap();
+        wt.del("d").await.unwrap();
+        assert_eq!(
+            Some(CommitStats {
+                keys_written: 2,
+                keys_deleted: 2,
+                bytes_written: 9,
+            }),
+            wt.commit_with_stats().await.unwrap()
+        );
+        assert_eq!(Some(b"second".to_vec()), store.get("a").await.unwrap());
+        assert!(!store.has("b").await.unwrap());
+
+        let wt = store.write(LogContext::new()).await.unwrap();
+        assert_eq!(
+            Some(CommitStats::default()),
+            wt.commit_with_stats().await.unwrap()
+        );
+    }
+
+    pub async fn savepoints(store: &mut dyn Store) {
+        store.put("a", b"committed").await.unwrap();
+
+        let wt = store.write(LogContext::new()).await.unwrap();
+        wt.put("b", b"kept").await.unwrap();
+        let outer = wt.savepoint().await.unwrap();
+        wt.put("a", b"outer").await.unwrap();
+        wt.del("b").await.unwrap();
+        let inner = wt.savepoint().await.unwrap();
+        wt.put("c", b"inner").await.unwrap();
+
+        // Rolling back to the inner savepoint keeps the outer's writes.
+        wt.rollback_to(inner).await.unwrap();
+        assert!(!wt.has("c").await.unwrap());
+        assert_eq!(Some(b"outer".to_vec()), wt.get("a").await.unwrap());
+        assert!(!wt.has("b").await.unwrap());
+
+        // Rolling back to the outer one discards the inner one too, and
+        // leaves the outer one to be rolled back to again.
+        wt.put("c", b"inner again").await.unwrap();
+        wt.rollback_to(outer).await.unwrap();
+        assert_eq!(
+            Err(StoreError::UnknownSavepoint),
+            wt.rollback_to(inner).await
+        );
+        // A savepoint taken in the inner one's place does not revive it.
+        let replacement = wt.savepoint().await.unwrap();
+        assert_ne!(inner, replacement);
+        assert_eq!(
+            Err(StoreError::UnknownSavepoint),
+            wt.rollback_to(inner).await
+        );
+        assert_eq!(Some(b"committed".to_vec()), wt.get("a").await.unwrap());
+        assert_eq!(Some(b"kept".to_vec()), wt.get("b").await.unwrap());
+        assert!(!wt.has("c").await.unwrap());
+        wt.put("d", b"after").await.unwrap();
+        wt.rollback_to(outer).await.unwrap();
+        assert!(!wt.has("d").await.unwrap());
+        wt.put("d", b"after").await.unwrap();
+        wt.commit().await.unwrap();
+
+        assert_eq!(Some(b"committed".to_vec()), store.get("a").await.unwrap());
+        assert_eq!(Some(b"kept".to_vec()), store.get("b").await.unwrap());
+        assert!(!store.has("c").await.unwrap());
+        assert_eq!(Some(b"after".to_vec()), store.get("d").await.unwrap());
+
+        // Rolling back everything leaves nothing to commit.
+        let wt = store.write(LogContext::new()).await.unwrap();
+        let start = wt.savepoint().await.unwrap();
+        wt.del("a").await.unwrap();
+        wt.rollback_to(start).await.unwrap();
+        assert!(!wt.is_dirty().await.unwrap());
+        wt.commit().await.unwrap();
+        assert!(store.has("a").await.unwrap());
+
+        // Savepoints from another transaction are unknown, even where that
+        // transaction took as many savepoints as this one has.
+        let wt = store.write(LogContext::new()).await.unwrap();
+        wt.savepoint().await.unwrap();
+        wt.savepoint().await.unwrap();
+        for id in &[outer, start] {
+            assert_eq!(Err(StoreError::UnknownSavepoint), wt.rollback_to(*id).await);
+        }
+        wt.commit().await.unwrap();
+    }
+
+    pub async fn byte_counts(store: &mut dyn Store) {
+        store.put("foo", b"bar").await.unwrap();
+
+        let rt = store.read(LogContext::new()).await.unwrap();
+        assert_eq!(Some(ByteCounts::default()), rt.byte_counts());
+        rt.get("foo").await.unwrap();
+        rt.get("missing").await.unwrap();
+        rt.has("foo").await.unwrap();
+        assert_eq!(
+            Some(ByteCounts {
+                read: 3,
+                written: 0
+            }),
+            rt.byte_counts()
+        );
+        drop(rt);
+
+        let wt = store.write(LogContext::new()).await.unwrap();
+        wt.put("k", b"value").await.unwrap();
+        wt.get("k").await.unwrap();
+        wt.del("foo").await.unwrap();
+        assert_eq!(
+            Some(ByteCounts {
+                read: 5,
+                written: 6
+            }),
+            wt.byte_counts()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_error_clone() {
+        for err in &[
+            StoreError::ReadOnly,
+            StoreError::TransactionClosed,
+            StoreError::Str("foo".to_string()),
+        ] {
+            assert_eq!(*err, err.clone());
+        }
+    }
+
+    #[test]
+    fn test_store_error_from() {
+        assert_eq!(StoreError::Str("foo".to_string()), "foo".into());
+        assert_eq!(StoreError::Str("foo".to_string()), "foo".to_string().into());
+        #[cfg(not(target_arch = "wasm32"))]
+        assert_eq!(
+            StoreError::Str("entity not found".to_string()),
+            std::io::Error::from(std::io::ErrorKind::NotFound).into()
+        );
     }
 }
@@ -0,0 +1,235 @@
+use crate::kv::{check_key, ByteCounts, CommitStats, Read, Result, SavepointId, Store, Write};
+use crate::util::rlog::LogContext;
+use async_trait::async_trait;
+
+// PrefixedStore is a view of another store that prepends a fixed prefix to
+// every key, so that several subsystems can share one store without their
+// keys colliding. Callers use short logical keys and only ever see keys
+// within their own prefix. Prefixes should not be prefixes of each other
+// (eg end them with a separator like "sub/") or one view can see the
+// other's keys.
+pub struct PrefixedStore<'a> {
+    store: &'a dyn Store,
+    prefix: String,
+}
+
+impl<'a> PrefixedStore<'a> {
+    pub fn new(store: &'a dyn Store, prefix: &str) -> PrefixedStore<'a> {
+        PrefixedStore {
+            store,
+            prefix: prefix.to_string(),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Store for PrefixedStore<'_> {
+    async fn read<'a>(&'a self, lc: LogContext) -> Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(PrefixedRead {
+            read: self.store.read(lc).await?,
+            prefix: &self.prefix,
+        }))
+    }
+
+    async fn write<'a>(&'a self, lc: LogContext) -> Result<Box<dyn Write + 'a>> {
+        Ok(Box::new(PrefixedWrite {
+            write: self.store.write(lc).await?,
+            prefix: &self.prefix,
+        }))
+    }
+
+    async fn snapshot<'a>(&'a self) -> Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(PrefixedRead {
+            read: self.store.snapshot().await?,
+            prefix: &self.prefix,
+        }))
+    }
+
+    async fn del_prefix(&self, prefix: &str) -> Result<()> {
+        self.store.del_prefix(&prefixed(&self.prefix, prefix)).await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.store.flush().await
+    }
+
+    async fn close(&self) {}
+}
+
+struct PrefixedRead<'a> {
+    read: Box<dyn Read + 'a>,
+    prefix: &'a str,
+}
+
+// content_hash is left unsupported: the underlying store's hash covers every
+// key, not just those within the prefix.
+#[async_trait(?Send)]
+impl Read for PrefixedRead<'_> {
+    async fn has(&self, key: &str) -> Result<bool> {
+        self.read.has(&prefixed(self.prefix, key)).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.read.get(&prefixed(self.prefix, key)).await
+    }
+
+    async fn keys(&self, prefix: &str) -> Result<Vec<String>> {
+        let keys = self.read.keys(&prefixed(self.prefix, prefix)).await?;
+        Ok(strip(self.prefix, keys))
+    }
+
+    // Counts are of the underlying keys, so include the prefix.
+    fn byte_counts(&self) -> Option<ByteCounts> {
+        self.read.byte_counts()
+    }
+}
+
+struct PrefixedWrite<'a> {
+    write: Box<dyn Write + 'a>,
+    prefix: &'a str,
+}
+
+#[async_trait(?Send)]
+impl Read for PrefixedWrite<'_> {
+    async fn has(&self, key: &str) -> Result<bool> {
+        self.write.has(&prefixed(self.prefix, key)).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.write.get(&prefixed(self.prefix, key)).await
+    }
+
+    async fn keys(&self, prefix: &str) -> Result<Vec<String>> {
+        let keys = self.write.keys(&prefixed(self.prefix, prefix)).await?;
+        Ok(strip(self.prefix, keys))
+    }
+
+    fn byte_counts(&self) -> Option<ByteCounts> {
+        self.write.byte_counts()
+    }
+}
+
+#[async_trait(?Send)]
+impl Write for PrefixedWrite<'_> {
+    fn as_read(&self) -> &dyn Read {
+        self
+    }
+
+    async fn put(&self, key: &str, value: &[u8]) -> Result<()> {
+        // The backend only sees the prefixed key, which never looks reserved.
+        check_key(key)?;
+        self.write.put(&prefixed(self.prefix, key), value).await
+    }
+
+    async fn del(&self, key: &str) -> Result<()> {
+        self.write.del(&prefixed(self.prefix, key)).await
+    }
+
+    async fn is_dirty(&self) -> Result<bool> {
+        self.write.is_dirty().await
+    }
+
+    async fn savepoint(&self) -> Result<SavepointId> {
+        self.write.savepoint().await
+    }
+
+    async fn rollback_to(&self, id: SavepointId) -> Result<()> {
+        self.write.rollback_to(id).await
+    }
+
+    async fn commit(self: Box<Self>) -> Result<()> {
+        self.write.commit().await
+    }
+
+    // Like byte_counts, bytes_written includes the prefix.
+    async fn commit_with_stats(self: Box<Self>) -> Result<Option<CommitStats>> {
+        self.write.commit_with_stats().await
+    }
+}
+
+fn prefixed(prefix: &str, key: &str) -> String {
+    let mut s = String::with_capacity(prefix.len() + key.len());
+    s.push_str(prefix);
+    s.push_str(key);
+    s
+}
+
+fn strip(prefix: &str, keys: Vec<String>) -> Vec<String> {
+    keys.into_iter()
+        .map(|k| k[prefix.len()..].to_string())
+        .collect()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::memstore::MemStore;
+    use crate::kv::StoreError;
+    use str_macro::str;
+
+    #[async_std::test]
+    async fn test_prefixed_store() {
+        let ms = MemStore::new();
+        let a = PrefixedStore::new(&ms, "a/");
+        let b = PrefixedStore::new(&ms, "b/");
+
+        a.put("foo", b"1").await.unwrap();
+        b.put("foo", b"2").await.unwrap();
+        assert_eq!(Some(b"1".to_vec()), a.get("foo").await.unwrap());
+        assert_eq!(Some(b"2".to_vec()), b.get("foo").await.unwrap());
+        assert_eq!(Some(b"1".to_vec()), ms.get("a/foo").await.unwrap());
+        assert!(!ms.has("foo").await.unwrap());
+
+        let wt = a.write(LogContext::new()).await.unwrap();
+        assert!(!wt.is_dirty().await.unwrap());
+        wt.del("foo").await.unwrap();
+        assert!(wt.is_dirty().await.unwrap());
+        assert!(!wt.has("foo").await.unwrap());
+        wt.commit().await.unwrap();
+        assert!(!a.has("foo").await.unwrap());
+        assert!(b.has("foo").await.unwrap());
+
+        a.put("bar", b"1").await.unwrap();
+        let rt = a.read(LogContext::new()).await.unwrap();
+        assert_eq!(vec!["bar"], rt.keys("").await.unwrap());
+        assert_eq!(
+            Err(StoreError::NotSupported("content_hash")),
+            rt.content_hash().await
+        );
+        drop(rt);
+
+        // Deleting everything in a view leaves the other view alone.
+        a.put("bar", b"1").await.unwrap();
+        a.del_prefix("").await.unwrap();
+        assert!(!a.has("bar").await.unwrap());
+        assert!(b.has("foo").await.unwrap());
+
+        // bulk_put goes through the default windowed puts.
+        let mut entries = (0..10).map(|i| (format!("k{}", i), vec![i]));
+        a.bulk_put_with_window(&mut entries, 3).await.unwrap();
+        assert_eq!(
+            10,
+            ms.read(LogContext::new())
+                .await
+                .unwrap()
+                .keys("a/k")
+                .await
+                .unwrap()
+                .len()
+        );
+        assert_eq!(Some(vec![9]), a.get("k9").await.unwrap());
+
+        // Where a key repeats, its last value wins.
+        let mut entries = (0..10).map(|i| (format!("k{}", i % 2), vec![i]));
+        a.bulk_put_with_window(&mut entries, 3).await.unwrap();
+        assert_eq!(Some(vec![8]), a.get("k0").await.unwrap());
+        assert_eq!(Some(vec![9]), a.get("k1").await.unwrap());
+
+        // Keys are checked before they are prefixed.
+        assert_eq!(
+            Err(StoreError::ReservedKey(str!("\u{FFFF}"))),
+            a.put("\u{FFFF}", b"x").await
+        );
+    }
+}
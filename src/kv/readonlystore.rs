@@ -0,0 +1,92 @@
+use crate::kv::{Read, Result, Store, StoreError, Write};
+use crate::util::rlog::LogContext;
+use async_trait::async_trait;
+
+// ReadOnlyStore is a view of another store that only ever opens read
+// transactions. It never takes the underlying store's write lock, so any
+// number of read-only views (eg background workers that only read) can be
+// open alongside the store's writer without contending with it or with each
+// other beyond what the store's reads already do. write() fails with
+// StoreError::ReadOnly.
+pub struct ReadOnlyStore<'a> {
+    store: &'a dyn Store,
+}
+
+impl<'a> ReadOnlyStore<'a> {
+    pub fn new(store: &'a dyn Store) -> ReadOnlyStore<'a> {
+        ReadOnlyStore { store }
+    }
+}
+
+#[async_trait(?Send)]
+impl Store for ReadOnlyStore<'_> {
+    async fn read<'a>(&'a self, lc: LogContext) -> Result<Box<dyn Read + 'a>> {
+        self.store.read(lc).await
+    }
+
+    async fn write<'a>(&'a self, _: LogContext) -> Result<Box<dyn Write + 'a>> {
+        Err(StoreError::ReadOnly)
+    }
+
+    async fn snapshot<'a>(&'a self) -> Result<Box<dyn Read + 'a>> {
+        self.store.snapshot().await
+    }
+
+    async fn del_prefix(&self, _: &str) -> Result<()> {
+        Err(StoreError::ReadOnly)
+    }
+
+    // There is never anything of ours to flush, and flushing the
+    // underlying store is up to its owner.
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn close(&self) {}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::memstore::MemStore;
+
+    #[async_std::test]
+    async fn test_read_only_store() {
+        let ms = MemStore::new();
+        ms.put("foo", b"bar").await.unwrap();
+
+        let ro1 = ReadOnlyStore::new(&ms);
+        let ro2 = ReadOnlyStore::new(&ms);
+        assert_eq!(Some(b"bar".to_vec()), ro1.get("foo").await.unwrap());
+        assert!(!ro1.has("baz").await.unwrap());
+
+        // Read transactions from several views can be open at once.
+        let r1 = ro1.read(LogContext::new()).await.unwrap();
+        let r2 = ro2.read(LogContext::new()).await.unwrap();
+        assert!(r1.has("foo").await.unwrap());
+        assert!(r2.has("foo").await.unwrap());
+        drop(r1);
+        drop(r2);
+
+        assert_eq!(
+            StoreError::ReadOnly,
+            ro1.write(LogContext::new()).await.err().unwrap()
+        );
+        assert_eq!(
+            StoreError::ReadOnly,
+            ro1.put("baz", b"qux").await.unwrap_err()
+        );
+        let mut entries = vec![("baz".to_string(), b"qux".to_vec())].into_iter();
+        assert_eq!(
+            StoreError::ReadOnly,
+            ro1.bulk_put(&mut entries).await.unwrap_err()
+        );
+
+        // Writes through the underlying store are visible to the views.
+        ms.put("baz", b"qux").await.unwrap();
+        assert_eq!(Some(b"qux".to_vec()), ro2.get("baz").await.unwrap());
+        ro1.close().await;
+        assert!(ms.has("foo").await.unwrap());
+    }
+}
@@ -0,0 +1,198 @@
+use crate::kv::{Read, Result, Store, StoreError, Write};
+use crate::util::rlog::LogContext;
+use async_trait::async_trait;
+use std::future::Future;
+
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    // The most attempts made at an operation, counting the first. Zero is
+    // treated as one.
+    pub max_attempts: usize,
+    // Whether an error is transient and the operation worth retrying.
+    pub retryable: fn(&StoreError) -> bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            retryable: |e| *e == StoreError::TransactionClosed,
+        }
+    }
+}
+
+// RetryStore is a view of another store that retries operations that fail
+// with an error its policy deems transient, opening a fresh transaction for
+// each attempt. Opening transactions and the one-shot operations that are
+// idempotent (get, has, put, put_if_changed, get_or_insert_with,
+// put_ordered and del_prefix) are retried as a whole. swap is not
+// idempotent and bulk_put consumes its entries, so those and anything done
+// within a transaction the caller holds are not: only the opening of their
+// transaction is retried.
+pub struct RetryStore<'a> {
+    store: &'a dyn Store,
+    policy: RetryPolicy,
+}
+
+impl<'a> RetryStore<'a> {
+    pub fn new(store: &'a dyn Store) -> RetryStore<'a> {
+        RetryStore::new_with_policy(store, RetryPolicy::default())
+    }
+
+    pub fn new_with_policy(store: &'a dyn Store, policy: RetryPolicy) -> RetryStore<'a> {
+        RetryStore { store, policy }
+    }
+
+    async fn retry<T, F, Fut>(&self, f: F) -> Result<T>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match f().await {
+                Err(e) if attempt < self.policy.max_attempts && (self.policy.retryable)(&e) => {
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Store for RetryStore<'_> {
+    async fn read<'a>(&'a self, lc: LogContext) -> Result<Box<dyn Read + 'a>> {
+        self.retry(|| self.store.read(lc.clone())).await
+    }
+
+    async fn write<'a>(&'a self, lc: LogContext) -> Result<Box<dyn Write + 'a>> {
+        self.retry(|| self.store.write(lc.clone())).await
+    }
+
+    async fn snapshot<'a>(&'a self) -> Result<Box<dyn Read + 'a>> {
+        self.retry(|| self.store.snapshot()).await
+    }
+
+    async fn put(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.retry(|| self.store.put(key, value)).await
+    }
+
+    async fn put_if_changed(&self, key: &str, value: &[u8]) -> Result<bool> {
+        self.retry(|| self.store.put_if_changed(key, value)).await
+    }
+
+    async fn has(&self, key: &str) -> Result<bool> {
+        self.retry(|| self.store.has(key)).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.retry(|| self.store.get(key)).await
+    }
+
+    async fn get_or_insert_with(&self, key: &str, f: &dyn Fn() -> Vec<u8>) -> Result<Vec<u8>> {
+        self.retry(|| self.store.get_or_insert_with(key, f)).await
+    }
+
+    async fn put_ordered(&self, pairs: &[(&str, &[u8])]) -> Result<()> {
+        self.retry(|| self.store.put_ordered(pairs)).await
+    }
+
+    async fn del_prefix(&self, prefix: &str) -> Result<()> {
+        self.retry(|| self.store.del_prefix(prefix)).await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.retry(|| self.store.flush()).await
+    }
+
+    async fn close(&self) {}
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::memstore::MemStore;
+    use std::cell::Cell;
+
+    // FlakyStore fails the next `failures` transactions it is asked to open
+    // with `error`.
+    struct FlakyStore {
+        store: MemStore,
+        failures: Cell<usize>,
+        error: StoreError,
+        attempts: Cell<usize>,
+    }
+
+    impl FlakyStore {
+        fn new(failures: usize, error: StoreError) -> FlakyStore {
+            FlakyStore {
+                store: MemStore::new(),
+                failures: Cell::new(failures),
+                error,
+                attempts: Cell::new(0),
+            }
+        }
+
+        fn attempt(&self) -> Result<()> {
+            self.attempts.set(self.attempts.get() + 1);
+            if self.failures.get() == 0 {
+                return Ok(());
+            }
+            self.failures.set(self.failures.get() - 1);
+            Err(self.error.clone())
+        }
+    }
+
+    #[async_trait(?Send)]
+    impl Store for FlakyStore {
+        async fn read<'a>(&'a self, lc: LogContext) -> Result<Box<dyn Read + 'a>> {
+            self.attempt()?;
+            self.store.read(lc).await
+        }
+
+        async fn write<'a>(&'a self, lc: LogContext) -> Result<Box<dyn Write + 'a>> {
+            self.attempt()?;
+            self.store.write(lc).await
+        }
+
+        async fn close(&self) {}
+    }
+
+    #[async_std::test]
+    async fn test_retry_store() {
+        // Transient errors are retried up to the limit.
+        let fs = FlakyStore::new(2, StoreError::TransactionClosed);
+        let rs = RetryStore::new(&fs);
+        rs.put("foo", b"bar").await.unwrap();
+        assert_eq!(3, fs.attempts.get());
+        assert_eq!(Some(b"bar".to_vec()), rs.get("foo").await.unwrap());
+
+        let fs = FlakyStore::new(3, StoreError::TransactionClosed);
+        let rs = RetryStore::new(&fs);
+        assert_eq!(
+            Err(StoreError::TransactionClosed),
+            rs.has("foo").await.map(|_| ())
+        );
+        assert_eq!(3, fs.attempts.get());
+
+        // Other errors are not.
+        let fs = FlakyStore::new(1, StoreError::Str("boom".to_string()));
+        let rs = RetryStore::new(&fs);
+        assert!(rs.write(LogContext::new()).await.is_err());
+        assert_eq!(1, fs.attempts.get());
+
+        // Unless the policy says so.
+        let fs = FlakyStore::new(4, StoreError::Str("boom".to_string()));
+        let rs = RetryStore::new_with_policy(
+            &fs,
+            RetryPolicy {
+                max_attempts: 5,
+                retryable: |_| true,
+            },
+        );
+        assert!(rs.read(LogContext::new()).await.is_ok());
+        assert_eq!(5, fs.attempts.get());
+    }
+}
@@ -0,0 +1,321 @@
+use crate::kv::{ByteCounts, CommitStats, OpenWrite, Read, Result, SavepointId, Store, Write};
+use crate::util::clock::Clock;
+use crate::util::rlog::LogContext;
+use async_trait::async_trait;
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::rc::Rc;
+
+// SlowTransaction describes a transaction that was open for longer than a
+// SlowLogStore's threshold.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SlowTransaction {
+    // "read" or "write".
+    pub mode: &'static str,
+    // How the transaction ended: "ended" for reads, "committed", "failed" or
+    // "rolled back" for writes.
+    pub outcome: &'static str,
+    pub elapsed_ms: u64,
+    // The number of distinct keys the transaction read or wrote.
+    pub keys: usize,
+}
+
+type SlowFn = dyn Fn(&SlowTransaction);
+
+// SlowLogStore is a view of another store that logs each transaction that
+// stays open for longer than threshold_ms, timed from when it is opened to
+// when it is committed, rolled back or (for reads) dropped. Time is read
+// from clock so that tests can control it. Logging is at info level as we
+// have no warn by design.
+pub struct SlowLogStore<'a> {
+    store: &'a dyn Store,
+    clock: &'a dyn Clock,
+    threshold_ms: u64,
+    on_slow: Option<Rc<SlowFn>>,
+}
+
+impl<'a> SlowLogStore<'a> {
+    pub fn new(store: &'a dyn Store, clock: &'a dyn Clock, threshold_ms: u64) -> SlowLogStore<'a> {
+        SlowLogStore {
+            store,
+            clock,
+            threshold_ms,
+            on_slow: None,
+        }
+    }
+
+    // Registers f to be called with each slow transaction, besides logging
+    // it, eg to report it to a metrics backend.
+    pub fn on_slow(&mut self, f: impl Fn(&SlowTransaction) + 'static) {
+        self.on_slow = Some(Rc::new(f));
+    }
+
+    fn timing(&self, mode: &'static str, lc: LogContext) -> Timing<'a> {
+        Timing {
+            clock: self.clock,
+            threshold_ms: self.threshold_ms,
+            on_slow: self.on_slow.clone(),
+            start_ms: self.clock.now_ms(),
+            mode,
+            keys: RefCell::new(HashSet::new()),
+            lc,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Store for SlowLogStore<'_> {
+    async fn read<'a>(&'a self, lc: LogContext) -> Result<Box<dyn Read + 'a>> {
+        let timing = self.timing("read", lc.clone());
+        Ok(Box::new(SlowLogRead {
+            read: self.store.read(lc).await?,
+            timing,
+        }))
+    }
+
+    async fn write<'a>(&'a self, lc: LogContext) -> Result<Box<dyn Write + 'a>> {
+        // Waiting for the write lock counts towards the time.
+        let timing = self.timing("write", lc.clone());
+        Ok(Box::new(SlowLogWrite {
+            write: OpenWrite::new(self.store.write(lc).await?),
+            timing,
+            outcome: Cell::new("rolled back"),
+        }))
+    }
+
+    async fn snapshot<'a>(&'a self) -> Result<Box<dyn Read + 'a>> {
+        self.store.snapshot().await
+    }
+
+    async fn del_prefix(&self, prefix: &str) -> Result<()> {
+        self.store.del_prefix(prefix).await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.store.flush().await
+    }
+
+    async fn close(&self) {}
+}
+
+struct Timing<'a> {
+    clock: &'a dyn Clock,
+    threshold_ms: u64,
+    on_slow: Option<Rc<SlowFn>>,
+    start_ms: u64,
+    mode: &'static str,
+    keys: RefCell<HashSet<String>>,
+    lc: LogContext,
+}
+
+impl Timing<'_> {
+    fn touch(&self, key: &str) {
+        let mut keys = self.keys.borrow_mut();
+        if !keys.contains(key) {
+            keys.insert(key.to_string());
+        }
+    }
+
+    fn finish(&self, outcome: &'static str) {
+        let elapsed_ms = self.clock.now_ms().saturating_sub(self.start_ms);
+        if elapsed_ms <= self.threshold_ms {
+            return;
+        }
+        let slow = SlowTransaction {
+            mode: self.mode,
+            outcome,
+            elapsed_ms,
+            keys: self.keys.borrow().len(),
+        };
+        info!(
+            self.lc,
+            "Slow {} transaction {} after {}ms touching {} keys",
+            slow.mode,
+            slow.outcome,
+            slow.elapsed_ms,
+            slow.keys
+        );
+        if let Some(f) = &self.on_slow {
+            f(&slow);
+        }
+    }
+}
+
+struct SlowLogRead<'a> {
+    read: Box<dyn Read + 'a>,
+    timing: Timing<'a>,
+}
+
+impl Drop for SlowLogRead<'_> {
+    fn drop(&mut self) {
+        self.timing.finish("ended");
+    }
+}
+
+#[async_trait(?Send)]
+impl Read for SlowLogRead<'_> {
+    async fn has(&self, key: &str) -> Result<bool> {
+        self.timing.touch(key);
+        self.read.has(key).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.timing.touch(key);
+        self.read.get(key).await
+    }
+
+    async fn content_hash(&self) -> Result<[u8; 32]> {
+        self.read.content_hash().await
+    }
+
+    async fn keys(&self, prefix: &str) -> Result<Vec<String>> {
+        self.read.keys(prefix).await
+    }
+
+    fn byte_counts(&self) -> Option<ByteCounts> {
+        self.read.byte_counts()
+    }
+}
+
+struct SlowLogWrite<'a> {
+    write: OpenWrite<'a>,
+    timing: Timing<'a>,
+    outcome: Cell<&'static str>,
+}
+
+impl Drop for SlowLogWrite<'_> {
+    fn drop(&mut self) {
+        self.timing.finish(self.outcome.get());
+    }
+}
+
+#[async_trait(?Send)]
+impl Read for SlowLogWrite<'_> {
+    async fn has(&self, key: &str) -> Result<bool> {
+        self.timing.touch(key);
+        self.write.get().has(key).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.timing.touch(key);
+        self.write.get().get(key).await
+    }
+
+    async fn content_hash(&self) -> Result<[u8; 32]> {
+        self.write.get().content_hash().await
+    }
+
+    async fn keys(&self, prefix: &str) -> Result<Vec<String>> {
+        self.write.get().keys(prefix).await
+    }
+
+    fn byte_counts(&self) -> Option<ByteCounts> {
+        self.write.get().byte_counts()
+    }
+}
+
+#[async_trait(?Send)]
+impl Write for SlowLogWrite<'_> {
+    fn as_read(&self) -> &dyn Read {
+        self
+    }
+
+    async fn put(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.timing.touch(key);
+        self.write.get().put(key, value).await
+    }
+
+    async fn del(&self, key: &str) -> Result<()> {
+        self.timing.touch(key);
+        self.write.get().del(key).await
+    }
+
+    async fn is_dirty(&self) -> Result<bool> {
+        self.write.get().is_dirty().await
+    }
+
+    async fn savepoint(&self) -> Result<SavepointId> {
+        self.write.get().savepoint().await
+    }
+
+    async fn rollback_to(&self, id: SavepointId) -> Result<()> {
+        self.write.get().rollback_to(id).await
+    }
+
+    async fn commit(self: Box<Self>) -> Result<()> {
+        self.commit_with_stats().await.map(|_| ())
+    }
+
+    // The time includes the commit itself.
+    async fn commit_with_stats(mut self: Box<Self>) -> Result<Option<CommitStats>> {
+        let write = self.write.take();
+        let res = write.commit_with_stats().await;
+        self.outcome
+            .set(if res.is_ok() { "committed" } else { "failed" });
+        res
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::memstore::MemStore;
+    use crate::util::clock::MockClock;
+
+    #[async_std::test]
+    async fn test_slow_log_store() {
+        let ms = MemStore::new();
+        let clock = MockClock::new(1000);
+        let mut s = SlowLogStore::new(&ms, &clock, 100);
+        let slow = Rc::new(RefCell::new(vec![]));
+        let slow2 = slow.clone();
+        s.on_slow(move |t| slow2.borrow_mut().push(t.clone()));
+
+        // Fast transactions are not reported.
+        s.put("a", b"1").await.unwrap();
+        assert!(slow.borrow().is_empty());
+
+        let w = s.write(LogContext::new()).await.unwrap();
+        w.put("a", b"2").await.unwrap();
+        w.put("b", b"2").await.unwrap();
+        w.get("a").await.unwrap();
+        clock.advance(101);
+        w.commit().await.unwrap();
+        let r = s.read(LogContext::new()).await.unwrap();
+        assert_eq!(Some(b"2".to_vec()), r.get("b").await.unwrap());
+        clock.advance(500);
+        drop(r);
+        let w = s.write(LogContext::new()).await.unwrap();
+        clock.advance(200);
+        drop(w);
+
+        assert_eq!(
+            vec![
+                SlowTransaction {
+                    mode: "write",
+                    outcome: "committed",
+                    elapsed_ms: 101,
+                    keys: 2,
+                },
+                SlowTransaction {
+                    mode: "read",
+                    outcome: "ended",
+                    elapsed_ms: 500,
+                    keys: 1,
+                },
+                SlowTransaction {
+                    mode: "write",
+                    outcome: "rolled back",
+                    elapsed_ms: 200,
+                    keys: 0,
+                },
+            ],
+            *slow.borrow()
+        );
+
+        s.put("p/1", b"1").await.unwrap();
+        s.del_prefix("p/").await.unwrap();
+        assert!(!ms.has("p/1").await.unwrap());
+    }
+}
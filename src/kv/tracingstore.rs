@@ -0,0 +1,329 @@
+use crate::kv::{ByteCounts, CommitStats, OpenWrite, Read, Result, SavepointId, Store, Write};
+use crate::util::rlog;
+use crate::util::rlog::LogContext;
+use async_trait::async_trait;
+use std::fmt;
+use std::future::Future;
+use std::rc::Rc;
+
+type TraceFn = dyn Fn(&str);
+
+// TracingStore is a view of another store that logs each get, has, put and
+// del with its key, the length of the value and how long it took, and each
+// commit and rollback of a write transaction, eg to see which chunks a pull
+// reads and writes. Lines are logged at debug level and start with label, so
+// that several traced stores can be told apart. Times are read from
+// rlog::Timer and shown to the microsecond, as most operations take well
+// under a millisecond. Not to be confused with LoggedStore, which logs
+// writes to another store for recovery.
+pub struct TracingStore<'a> {
+    store: &'a dyn Store,
+    label: String,
+    on_trace: Option<Rc<TraceFn>>,
+}
+
+impl<'a> TracingStore<'a> {
+    pub fn new(store: &'a dyn Store, label: &str) -> TracingStore<'a> {
+        TracingStore {
+            store,
+            label: label.to_string(),
+            on_trace: None,
+        }
+    }
+
+    // Registers f to be called with each line, besides logging it, eg to
+    // collect a trace of a test.
+    pub fn on_trace(&mut self, f: impl Fn(&str) + 'static) {
+        self.on_trace = Some(Rc::new(f));
+    }
+
+    fn tracer(&self, lc: LogContext) -> Tracer<'_> {
+        Tracer {
+            label: &self.label,
+            on_trace: self.on_trace.clone(),
+            lc,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Store for TracingStore<'_> {
+    async fn read<'a>(&'a self, lc: LogContext) -> Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(TracingRead {
+            read: self.store.read(lc.clone()).await?,
+            tracer: self.tracer(lc),
+        }))
+    }
+
+    async fn write<'a>(&'a self, lc: LogContext) -> Result<Box<dyn Write + 'a>> {
+        Ok(Box::new(TracingWrite {
+            write: OpenWrite::new(self.store.write(lc.clone()).await?),
+            tracer: self.tracer(lc),
+        }))
+    }
+
+    async fn snapshot<'a>(&'a self) -> Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(TracingRead {
+            read: self.store.snapshot().await?,
+            tracer: self.tracer(LogContext::new()),
+        }))
+    }
+
+    async fn del_prefix(&self, prefix: &str) -> Result<()> {
+        self.tracer(LogContext::new())
+            .timed(
+                format_args!("del_prefix {}", prefix),
+                self.store.del_prefix(prefix),
+                |_| String::new(),
+            )
+            .await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.store.flush().await
+    }
+
+    async fn close(&self) {}
+}
+
+// Elapsed formats a duration in microseconds as milliseconds, eg "0.012ms".
+struct Elapsed(u64);
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{:03}ms", self.0 / 1000, self.0 % 1000)
+    }
+}
+
+struct Tracer<'a> {
+    label: &'a str,
+    on_trace: Option<Rc<TraceFn>>,
+    lc: LogContext,
+}
+
+impl Tracer<'_> {
+    fn trace(&self, args: fmt::Arguments<'_>) {
+        let line = format!("{} {}", self.label, args);
+        debug!(self.lc, "{}", line);
+        if let Some(f) = &self.on_trace {
+            f(&line);
+        }
+    }
+
+    // Runs op and traces it as op_name with how long it took. outcome
+    // describes what a successful op did, eg " -> 5 bytes".
+    async fn timed<T>(
+        &self,
+        op_name: fmt::Arguments<'_>,
+        op: impl Future<Output = Result<T>>,
+        outcome: impl FnOnce(&T) -> String,
+    ) -> Result<T> {
+        let timer = rlog::Timer::new();
+        let res = op.await;
+        let elapsed = Elapsed(timer.elapsed_us());
+        match &res {
+            Ok(v) => self.trace(format_args!("{}{} in {}", op_name, outcome(v), elapsed)),
+            Err(e) => self.trace(format_args!("{} failed in {}: {}", op_name, elapsed, e)),
+        }
+        res
+    }
+
+    async fn has(&self, read: &dyn Read, key: &str) -> Result<bool> {
+        self.timed(format_args!("has {}", key), read.has(key), |has| {
+            format!(" -> {}", has)
+        })
+        .await
+    }
+
+    async fn get(&self, read: &dyn Read, key: &str) -> Result<Option<Vec<u8>>> {
+        self.timed(format_args!("get {}", key), read.get(key), |v| match v {
+            Some(v) => format!(" -> {} bytes", v.len()),
+            None => " -> missing".to_string(),
+        })
+        .await
+    }
+}
+
+struct TracingRead<'a> {
+    read: Box<dyn Read + 'a>,
+    tracer: Tracer<'a>,
+}
+
+#[async_trait(?Send)]
+impl Read for TracingRead<'_> {
+    async fn has(&self, key: &str) -> Result<bool> {
+        self.tracer.has(&*self.read, key).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.tracer.get(&*self.read, key).await
+    }
+
+    async fn content_hash(&self) -> Result<[u8; 32]> {
+        self.read.content_hash().await
+    }
+
+    async fn keys(&self, prefix: &str) -> Result<Vec<String>> {
+        self.read.keys(prefix).await
+    }
+
+    fn byte_counts(&self) -> Option<ByteCounts> {
+        self.read.byte_counts()
+    }
+}
+
+struct TracingWrite<'a> {
+    write: OpenWrite<'a>,
+    tracer: Tracer<'a>,
+}
+
+impl Drop for TracingWrite<'_> {
+    fn drop(&mut self) {
+        if self.write.is_open() {
+            self.tracer.trace(format_args!("rollback"));
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Read for TracingWrite<'_> {
+    async fn has(&self, key: &str) -> Result<bool> {
+        self.tracer.has(self.write.get().as_read(), key).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.tracer.get(self.write.get().as_read(), key).await
+    }
+
+    async fn content_hash(&self) -> Result<[u8; 32]> {
+        self.write.get().content_hash().await
+    }
+
+    async fn keys(&self, prefix: &str) -> Result<Vec<String>> {
+        self.write.get().keys(prefix).await
+    }
+
+    fn byte_counts(&self) -> Option<ByteCounts> {
+        self.write.get().byte_counts()
+    }
+}
+
+#[async_trait(?Send)]
+impl Write for TracingWrite<'_> {
+    fn as_read(&self) -> &dyn Read {
+        self
+    }
+
+    async fn put(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.tracer
+            .timed(
+                format_args!("put {}", key),
+                self.write.get().put(key, value),
+                |_| format!(" <- {} bytes", value.len()),
+            )
+            .await
+    }
+
+    async fn del(&self, key: &str) -> Result<()> {
+        self.tracer
+            .timed(
+                format_args!("del {}", key),
+                self.write.get().del(key),
+                |_| String::new(),
+            )
+            .await
+    }
+
+    async fn is_dirty(&self) -> Result<bool> {
+        self.write.get().is_dirty().await
+    }
+
+    async fn savepoint(&self) -> Result<SavepointId> {
+        self.write.get().savepoint().await
+    }
+
+    async fn rollback_to(&self, id: SavepointId) -> Result<()> {
+        self.write.get().rollback_to(id).await
+    }
+
+    async fn commit(self: Box<Self>) -> Result<()> {
+        self.commit_with_stats().await.map(|_| ())
+    }
+
+    async fn commit_with_stats(mut self: Box<Self>) -> Result<Option<CommitStats>> {
+        let write = self.write.take();
+        self.tracer
+            .timed(format_args!("commit"), write.commit_with_stats(), |_| {
+                String::new()
+            })
+            .await
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::memstore::MemStore;
+    use std::cell::RefCell;
+
+    #[test]
+    fn test_elapsed() {
+        assert_eq!("0.000ms", Elapsed(0).to_string());
+        assert_eq!("0.012ms", Elapsed(12).to_string());
+        assert_eq!("1234.567ms", Elapsed(1_234_567).to_string());
+    }
+
+    // Strips the time from a traced line, checking it is there.
+    fn untimed(line: &str) -> &str {
+        match line.rfind(" in ") {
+            None => line,
+            Some(i) => {
+                let t = &line[i + " in ".len()..];
+                assert!(t.ends_with("ms"), "{}", line);
+                assert!(t[..t.len() - 2].parse::<f64>().is_ok(), "{}", line);
+                &line[..i]
+            }
+        }
+    }
+
+    #[async_std::test]
+    async fn test_tracing_store() {
+        let ms = MemStore::new();
+        let mut ts = TracingStore::new(&ms, "chunks");
+        let lines = Rc::new(RefCell::new(vec![]));
+        let lines2 = lines.clone();
+        ts.on_trace(move |line| lines2.borrow_mut().push(line.to_string()));
+
+        ts.put("a", b"hello").await.unwrap();
+        assert_eq!(Some(b"hello".to_vec()), ts.get("a").await.unwrap());
+
+        let wt = ts.write(LogContext::new()).await.unwrap();
+        assert!(!wt.has("b").await.unwrap());
+        wt.del("a").await.unwrap();
+        drop(wt);
+        let rt = ts.read(LogContext::new()).await.unwrap();
+        assert_eq!(None, rt.get("b").await.unwrap());
+        drop(rt);
+        ts.del_prefix("a").await.unwrap();
+        assert!(!ms.has("a").await.unwrap());
+
+        assert_eq!(
+            vec![
+                "chunks put a <- 5 bytes",
+                "chunks commit",
+                "chunks get a -> 5 bytes",
+                "chunks has b -> false",
+                "chunks del a",
+                "chunks rollback",
+                "chunks get b -> missing",
+                "chunks del_prefix a",
+            ],
+            lines
+                .borrow()
+                .iter()
+                .map(|l| untimed(l))
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use super::leaf_generated::leaf::{self, LeafEntry};
 use super::Entry;
 use crate::dag::Chunk;
+use crate::util::flatbuf;
 use flatbuffers::FlatBufferBuilder;
 use std::cmp::Ordering;
 
@@ -24,6 +25,7 @@ impl Leaf {
 
     pub fn load(chunk: Chunk) -> Result<Leaf, LoadError> {
         // Validate at load-time so we can assume data is valid thereafter.
+        Leaf::check_bounds(chunk.data()).map_err(LoadError::Corrupt)?;
         let root = leaf::get_root_as_leaf(chunk.data());
         let entries = root
             .entries()
@@ -50,6 +52,24 @@ impl Leaf {
         Ok(Leaf { chunk })
     }
 
+    // Checks every offset the accessors follow is within the buffer.
+    fn check_bounds(buf: &[u8]) -> flatbuf::Result<()> {
+        let root = flatbuf::root(buf)?;
+        let entries = match flatbuf::field(buf, root, leaf::Leaf::VT_ENTRIES)? {
+            None => return Ok(()),
+            Some(entries) => entries,
+        };
+        for i in 0..flatbuf::vector(buf, entries, 4)? {
+            let entry = flatbuf::vector_table(buf, entries, i)?;
+            for &voffset in &[LeafEntry::VT_KEY, LeafEntry::VT_VAL] {
+                if let Some(v) = flatbuf::field(buf, entry, voffset)? {
+                    flatbuf::vector(buf, v, 1)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub fn new<'a>(entries: impl Iterator<Item = Entry<'a>>) -> Leaf {
         let mut builder = FlatBufferBuilder::default();
         let entries = entries
@@ -165,6 +185,7 @@ impl<'a> From<leaf::LeafEntry<'a>> for Entry<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use str_macro::str;
 
     #[test]
     fn try_from() {
@@ -281,6 +302,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn load_truncated() {
+        let k = vec![0, 1, 2];
+        let leaf = Leaf::new(vec![Entry { key: &k, val: &k }].into_iter());
+        let data = leaf.chunk.data();
+        // Loading a truncated chunk must fail rather than panic.
+        for n in 0..data.len() {
+            let _ = Leaf::load(Chunk::read(str!("h"), data[..n].to_vec(), None));
+        }
+        assert_eq!(
+            Err(LoadError::Corrupt("offset out of bounds")),
+            Leaf::load(Chunk::read(str!("h"), vec![1, 2, 3], None))
+        );
+    }
+
     fn make_leaf(kv: Option<Vec<Option<Vec<u8>>>>) -> Chunk {
         let mut builder = FlatBufferBuilder::default();
         let mut entries: Option<
@@ -4,8 +4,8 @@ use super::leaf;
 use super::leaf::Leaf;
 use super::Entry;
 use crate::dag;
-use crate::dag::Read;
 use crate::dag::Write;
+use crate::dag::{Chunk, Read};
 use std::collections::BTreeMap;
 use std::iter::{Iterator, Peekable};
 use std::{cmp::Ordering, string::FromUtf8Error};
@@ -59,6 +59,10 @@ impl Map {
     pub async fn load(hash: &str, read: &Read<'_>) -> Result<Map, LoadError> {
         let chunk = read.get_chunk(hash).await?;
         let chunk = chunk.ok_or(LoadError::UnknownHash)?;
+        Map::from_chunk(chunk)
+    }
+
+    pub fn from_chunk(chunk: Chunk) -> Result<Map, LoadError> {
         let base = Leaf::load(chunk)?;
         Ok(Map {
             base: base.into(),
@@ -91,7 +95,8 @@ impl Map {
         self.base_get(key)
     }
 
-    fn base_get(&self, key: &[u8]) -> Option<&[u8]> {
+    // Returns the flushed value for key, ignoring any pending change.
+    pub fn base_get(&self, key: &[u8]) -> Option<&[u8]> {
         match &self.base {
             None => None,
             Some(leaf) => match leaf.binary_search(key) {
@@ -117,7 +122,7 @@ impl Map {
         }
     }
 
-    pub async fn flush(&mut self, write: &mut Write<'_>) -> Result<Hash, FlushError> {
+    pub async fn flush(&mut self, write: &Write<'_>) -> Result<Hash, FlushError> {
         // TODO: Consider locking during this
         let new_base = Leaf::new(self.iter());
         write.put_chunk(new_base.chunk()).await?;
@@ -126,36 +131,45 @@ impl Map {
         Ok(self.base.as_ref().unwrap().chunk().hash().into())
     }
 
+    // Returns each pending key whose value differs from its flushed one, along
+    // with the flushed and pending values (None where the key is absent).
+    pub fn pending_changes(&self) -> impl Iterator<Item = (&[u8], Option<&[u8]>, Option<&[u8]>)> {
+        self.pending.iter().filter_map(move |(key, new)| {
+            let old = self.base_get(key);
+            let new = new.as_deref();
+            if old == new {
+                None
+            } else {
+                Some((key.as_slice(), old, new))
+            }
+        })
+    }
+
     // Returns the diff between the pending entries and the already flushed entries.
     pub fn pending_changed_keys(&self) -> Result<Vec<String>, FromUtf8Error> {
-        let mut keys = Vec::with_capacity(self.pending.len());
-        for (key, pending_val) in self.pending.iter() {
-            match pending_val {
-                Some(pending_val) => match self.base_get(key) {
-                    Some(base_val) => {
-                        if pending_val != base_val {
-                            keys.push(String::from_utf8(key.clone())?);
-                        }
-                    }
-                    None => {
-                        keys.push(String::from_utf8(key.clone())?);
-                    }
-                },
-                None => {
-                    if self.base_has(key) {
-                        keys.push(String::from_utf8(key.clone())?);
-                    }
-                }
-            }
-        }
-        Ok(keys)
+        self.pending_changes()
+            .map(|(key, _, _)| String::from_utf8(key.to_vec()))
+            .collect()
     }
 
     /// Returns the keys that are different between two maps.
     pub fn changed_keys<'a>(a: &'a Self, b: &'a Self) -> Result<Vec<String>, FromUtf8Error> {
+        Map::changes(a, b)
+            .into_iter()
+            .map(|(key, _, _)| String::from_utf8(key.to_vec()))
+            .collect()
+    }
+
+    // Returns each key whose value differs between a and b, in key order,
+    // with its value in a and in b (None where the key is absent).
+    #[allow(clippy::type_complexity)]
+    pub fn changes<'a>(
+        a: &'a Self,
+        b: &'a Self,
+    ) -> Vec<(&'a [u8], Option<&'a [u8]>, Option<&'a [u8]>)> {
         let mut it_a = a.iter();
         let mut it_b = b.iter();
-        let mut keys = vec![];
+        let mut changes = vec![];
 
         let mut a = it_a.next();
         let mut b = it_b.next();
@@ -163,36 +177,36 @@ impl Map {
             match (a, b) {
                 (None, None) => break,
                 (None, Some(b_entry)) => {
-                    keys.push(String::from_utf8(b_entry.key.to_vec())?);
+                    changes.push((b_entry.key, None, Some(b_entry.val)));
                     b = it_b.next();
                 }
                 (Some(a_entry), None) => {
-                    keys.push(String::from_utf8(a_entry.key.to_vec())?);
+                    changes.push((a_entry.key, Some(a_entry.val), None));
                     a = it_a.next();
                 }
                 (Some(a_entry), Some(b_entry)) => {
                     let ord = a_entry.key.cmp(b_entry.key);
                     match ord {
                         Ordering::Less => {
-                            keys.push(String::from_utf8(a_entry.key.to_vec())?);
+                            changes.push((a_entry.key, Some(a_entry.val), None));
                             a = it_a.next();
                         }
                         Ordering::Equal => {
                             if a_entry.val != b_entry.val {
-                                keys.push(String::from_utf8(a_entry.key.to_vec())?);
+                                changes.push((a_entry.key, Some(a_entry.val), Some(b_entry.val)));
                             }
                             a = it_a.next();
                             b = it_b.next();
                         }
                         Ordering::Greater => {
-                            keys.push(String::from_utf8(b_entry.key.to_vec())?);
+                            changes.push((b_entry.key, None, Some(b_entry.val)));
                             b = it_b.next();
                         }
                     };
                 }
             }
         }
-        Ok(keys)
+        changes
     }
 }
 
@@ -545,8 +559,8 @@ mod tests {
 
             let kv = MemStore::new();
             let store = Store::new(Box::new(kv));
-            let mut write = store.write(LogContext::new()).await.unwrap();
-            let hash = map.flush(&mut write).await.unwrap();
+            let write = store.write(LogContext::new()).await.unwrap();
+            let hash = map.flush(&write).await.unwrap();
 
             // Original map should still have same data.
             test(&map, &expected);
@@ -719,5 +733,16 @@ mod tests {
 
         map.put(b"b".to_vec(), b"2".to_vec());
         assert_eq!(map.pending_changed_keys().unwrap(), vec![str!("b")]);
+
+        map.del(b"a".to_vec());
+        map.put(b"e".to_vec(), b"e".to_vec());
+        assert_eq!(
+            vec![
+                (&b"a"[..], Some(&b"a"[..]), None),
+                (&b"b"[..], Some(&b"b"[..]), Some(&b"2"[..])),
+                (&b"e"[..], None, Some(&b"e"[..])),
+            ],
+            map.pending_changes().collect::<Vec<_>>()
+        );
     }
 }
@@ -0,0 +1,155 @@
+use super::push::{push, Pusher};
+use super::{request_id, HttpRequestInfo, TryPushError, TryPushRequest};
+use crate::dag;
+use crate::util::rlog::LogContext;
+use async_std::sync::RwLock;
+use async_std::task;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+pub struct AutoPushConfig {
+    // How long to wait after the last local mutation before pushing.
+    pub debounce: Duration,
+    pub pusher: Box<dyn Pusher>,
+    pub req: TryPushRequest,
+}
+
+// AutoPush pushes pending mutations once local mutations settle. Each call to
+// mutated() schedules a push after the debounce interval and cancels the one
+// scheduled by the previous call, so a burst of mutations results in a single
+// push of all of them.
+#[derive(Default)]
+pub struct AutoPush {
+    // Bumped by every mutation and reconfiguration. A scheduled push only
+    // goes ahead if the generation has not moved on while it waited.
+    generation: AtomicU64,
+    config: RwLock<Option<AutoPushConfig>>,
+}
+
+impl AutoPush {
+    pub fn new() -> AutoPush {
+        AutoPush::default()
+    }
+
+    // Enables auto-push with config, or disables it if config is None. Any
+    // push already scheduled is cancelled.
+    pub async fn configure(&self, config: Option<AutoPushConfig>) {
+        let mut guard = self.config.write().await;
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        *guard = config;
+    }
+
+    // Called after each local mutation. Waits out the debounce interval and
+    // then pushes, unless auto-push is disabled or another mutation arrived in
+    // the meantime, in which case it returns None and leaves the push to the
+    // later call.
+    pub async fn mutated(
+        &self,
+        store: &dag::Store,
+        client_id: &str,
+        lc: LogContext,
+    ) -> Result<Option<HttpRequestInfo>, TryPushError> {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let debounce = match &*self.config.read().await {
+            None => return Ok(None),
+            Some(config) => config.debounce,
+        };
+        task::sleep(debounce).await;
+
+        let config = self.config.read().await;
+        let config = match &*config {
+            Some(config) if self.generation.load(Ordering::SeqCst) == generation => config,
+            _ => {
+                debug!(lc, "Auto-push superseded");
+                return Ok(None);
+            }
+        };
+        let request_id = request_id::new(client_id);
+        lc.add_context("request_id", &request_id);
+        push(
+            &request_id,
+            store,
+            lc,
+            client_id.to_string(),
+            &*config.pusher,
+            config.req.clone(),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::push::{PushError, PushRequest};
+    use super::*;
+    use crate::db::test_helpers::*;
+    use crate::kv::memstore::MemStore;
+    use async_trait::async_trait;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use str_macro::str;
+
+    struct CountingPusher {
+        pushed: Rc<RefCell<Vec<usize>>>,
+    }
+
+    #[async_trait(?Send)]
+    impl Pusher for CountingPusher {
+        async fn push(
+            &self,
+            push_req: &PushRequest,
+            _: &str,
+            _: &str,
+            _: &str,
+            _: &[(String, String)],
+        ) -> Result<HttpRequestInfo, PushError> {
+            self.pushed.borrow_mut().push(push_req.mutations.len());
+            Ok(HttpRequestInfo {
+                http_status_code: 200,
+                error_message: str!(""),
+            })
+        }
+    }
+
+    #[async_std::test]
+    async fn test_auto_push() {
+        let store = dag::Store::new(Box::new(MemStore::new()));
+        let mut chain: Chain = vec![];
+        add_genesis(&mut chain, &store).await;
+        add_local(&mut chain, &store).await;
+        add_local(&mut chain, &store).await;
+
+        let auto_push = AutoPush::new();
+        let mutated = || auto_push.mutated(&store, "client_id", LogContext::new());
+
+        // Disabled by default.
+        assert_eq!(None, mutated().await.unwrap());
+
+        let pushed = Rc::new(RefCell::new(vec![]));
+        auto_push
+            .configure(Some(AutoPushConfig {
+                debounce: Duration::from_millis(20),
+                pusher: Box::new(CountingPusher {
+                    pushed: pushed.clone(),
+                }),
+                req: TryPushRequest {
+                    push_url: str!("push_url"),
+                    push_auth: str!("push_auth"),
+                    ..Default::default()
+                },
+            }))
+            .await;
+
+        // A burst of mutations results in one push, made by the last call.
+        let (r1, r2, r3) = futures::join!(mutated(), mutated(), mutated());
+        assert!(r1.unwrap().is_none());
+        assert!(r2.unwrap().is_none());
+        assert_eq!(200, r3.unwrap().unwrap().http_status_code);
+        assert_eq!(vec![2], *pushed.borrow());
+
+        // Reconfiguring cancels a scheduled push.
+        let (r, _) = futures::join!(mutated(), auto_push.configure(None));
+        assert!(r.unwrap().is_none());
+        assert_eq!(vec![2], *pushed.borrow());
+    }
+}
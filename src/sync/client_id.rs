@@ -1,22 +1,22 @@
 use crate::util::rlog::LogContext;
-use crate::util::uuid::uuid;
+use crate::util::uuid::Uuid;
 use crate::{
     kv::{Store, StoreError},
     util::uuid::UuidError,
 };
 
-pub async fn init(s: &dyn Store, lc: LogContext) -> Result<String, InitClientIdError> {
+pub async fn init(s: &dyn Store, lc: LogContext) -> Result<Uuid, InitClientIdError> {
     use InitClientIdError::*;
 
     const CID_KEY: &str = "sys/cid";
     let cid = s.get(CID_KEY).await.map_err(GetErr)?;
     if let Some(cid) = cid {
         let s = String::from_utf8(cid).map_err(InvalidUtf8)?;
-        return Ok(s);
+        return Uuid::parse(&s).map_err(InvalidClientId);
     }
     let wt = s.write(lc).await.map_err(OpenErr)?;
-    let uuid = uuid().map_err(UuidErr)?;
-    wt.put(CID_KEY, uuid.as_bytes())
+    let uuid = Uuid::new().map_err(UuidErr)?;
+    wt.put(CID_KEY, uuid.as_str().as_bytes())
         .await
         .map_err(PutClientIdErr)?;
     wt.commit().await.map_err(CommitErr)?;
@@ -27,6 +27,7 @@ pub async fn init(s: &dyn Store, lc: LogContext) -> Result<String, InitClientIdE
 pub enum InitClientIdError {
     CommitErr(StoreError),
     GetErr(StoreError),
+    InvalidClientId(UuidError),
     InvalidUtf8(std::string::FromUtf8Error),
     OpenErr(StoreError),
     PutClientIdErr(StoreError),
@@ -47,5 +48,13 @@ mod tests {
         let ms = Box::new(MemStore::new());
         let cid3 = init(ms.as_ref(), LogContext::new()).await.unwrap();
         assert_ne!(cid1, cid3);
+
+        // A stored id that is not a UUID is an error rather than used.
+        let ms = Box::new(MemStore::new());
+        ms.put("sys/cid", b"not a uuid").await.unwrap();
+        assert!(matches!(
+            init(ms.as_ref(), LogContext::new()).await,
+            Err(InitClientIdError::InvalidClientId(_))
+        ));
     }
 }
@@ -0,0 +1,206 @@
+use super::{HttpRequestInfo, PullError, PullRequest, PullResponse, Puller};
+use async_trait::async_trait;
+use futures::future::{FutureExt, LocalBoxFuture, Shared};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+type PullResult = Result<(Option<PullResponse>, HttpRequestInfo), Rc<PullError>>;
+
+// PullCoalescer lets concurrent pulls share one in-flight request. Pulls are
+// keyed by url, auth, extra headers and request body, which holds the cookie
+// and last mutation id, so only pulls that would get the same response are
+// merged, and a caller never gets a response fetched with someone else's
+// credentials, whether they are in auth or in an extra header.
+#[derive(Default)]
+pub struct PullCoalescer {
+    in_flight: RefCell<HashMap<String, Shared<LocalBoxFuture<'static, PullResult>>>>,
+}
+
+impl PullCoalescer {
+    pub fn new() -> PullCoalescer {
+        PullCoalescer::default()
+    }
+
+    // Returns a Puller that pulls through puller unless the same pull is
+    // already in flight, in which case it waits for that pull's result.
+    pub fn puller(&self, puller: Rc<dyn Puller>) -> CoalescingPuller<'_> {
+        CoalescingPuller {
+            coalescer: self,
+            puller,
+        }
+    }
+}
+
+pub struct CoalescingPuller<'a> {
+    coalescer: &'a PullCoalescer,
+    puller: Rc<dyn Puller>,
+}
+
+#[async_trait(?Send)]
+impl Puller for CoalescingPuller<'_> {
+    // Errors from a shared pull come back as PullError::Shared since every
+    // caller gets the same one.
+    async fn pull(
+        &self,
+        pull_req: &PullRequest,
+        url: &str,
+        auth: &str,
+        request_id: &str,
+        extra_headers: &[(String, String)],
+    ) -> Result<(Option<PullResponse>, HttpRequestInfo), PullError> {
+        let key = serde_json::to_string(&(url, auth, extra_headers, pull_req))
+            .map_err(PullError::SerializeRequestError)?;
+        let shared = self
+            .coalescer
+            .in_flight
+            .borrow_mut()
+            .entry(key.clone())
+            .or_insert_with(|| {
+                let puller = self.puller.clone();
+                let (pull_req, url, auth, request_id, extra_headers) = (
+                    pull_req.clone(),
+                    url.to_string(),
+                    auth.to_string(),
+                    request_id.to_string(),
+                    extra_headers.to_vec(),
+                );
+                async move {
+                    puller
+                        .pull(&pull_req, &url, &auth, &request_id, &extra_headers)
+                        .await
+                        .map_err(Rc::new)
+                }
+                .boxed_local()
+                .shared()
+            })
+            .clone();
+        let res = shared.await;
+
+        // The first caller to see the result retires the pull so that later
+        // pulls make a fresh request.
+        let mut in_flight = self.coalescer.in_flight.borrow_mut();
+        if matches!(in_flight.get(&key), Some(s) if s.peek().is_some()) {
+            in_flight.remove(&key);
+        }
+        res.map_err(PullError::Shared)
+    }
+
+    async fn ping(
+        &self,
+        url: &str,
+        auth: &str,
+        request_id: &str,
+        extra_headers: &[(String, String)],
+    ) -> Result<Option<HttpRequestInfo>, PullError> {
+        self.puller.ping(url, auth, request_id, extra_headers).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::time::Duration;
+    use str_macro::str;
+
+    // Counts pulls, each of which takes a little while and fails if fail is
+    // set.
+    struct CountingPuller {
+        pulls: Rc<Cell<u32>>,
+        fail: bool,
+    }
+
+    #[async_trait(?Send)]
+    impl Puller for CountingPuller {
+        async fn pull(
+            &self,
+            _: &PullRequest,
+            _: &str,
+            _: &str,
+            _: &str,
+            _: &[(String, String)],
+        ) -> Result<(Option<PullResponse>, HttpRequestInfo), PullError> {
+            self.pulls.set(self.pulls.get() + 1);
+            async_std::task::sleep(Duration::from_millis(10)).await;
+            if self.fail {
+                return Err(PullError::InvalidResponse(
+                    serde_json::from_str::<()>("").unwrap_err(),
+                ));
+            }
+            Ok((
+                None,
+                HttpRequestInfo {
+                    http_status_code: 200,
+                    error_message: str!(""),
+                },
+            ))
+        }
+    }
+
+    #[async_std::test]
+    async fn test_coalesce_pulls() {
+        let coalescer = PullCoalescer::new();
+        let pulls = Rc::new(Cell::new(0));
+        let puller = |fail| {
+            coalescer.puller(Rc::new(CountingPuller {
+                pulls: pulls.clone(),
+                fail,
+            }))
+        };
+        let (p1, p2, p3) = (puller(false), puller(false), puller(false));
+        let req = |cookie: &str| PullRequest {
+            cookie: serde_json::json!(cookie),
+            ..Default::default()
+        };
+        let (a, b) = (req("a"), req("b"));
+
+        // Concurrent pulls of the same state share a request; a different
+        // cookie gets its own.
+        let (r1, r2, r3) = futures::join!(
+            p1.pull(&a, "url", "auth", "1", &[]),
+            p2.pull(&a, "url", "auth", "2", &[]),
+            p3.pull(&b, "url", "auth", "3", &[]),
+        );
+        assert_eq!(2, pulls.get());
+        assert_eq!(r1.unwrap().1, r2.unwrap().1);
+        assert_eq!(200, r3.unwrap().1.http_status_code);
+
+        // Pulls with different auth do not share a request.
+        let (r1, r2) = futures::join!(
+            p1.pull(&a, "url", "auth", "7", &[]),
+            p2.pull(&a, "url", "other auth", "8", &[]),
+        );
+        assert_eq!(4, pulls.get());
+        r1.unwrap();
+        r2.unwrap();
+
+        // Nor do pulls with different extra headers, which can carry
+        // credentials too. The same extra headers share one.
+        let tenant = |t: &str| vec![(str!("X-Tenant"), t.to_string())];
+        let (ta, tb) = (tenant("a"), tenant("b"));
+        let (r1, r2, r3) = futures::join!(
+            p1.pull(&a, "url", "auth", "9", &ta),
+            p2.pull(&a, "url", "auth", "10", &ta),
+            p3.pull(&a, "url", "auth", "11", &tb),
+        );
+        assert_eq!(6, pulls.get());
+        r1.unwrap();
+        r2.unwrap();
+        r3.unwrap();
+
+        // Once done, the same pull goes to the server again.
+        p1.pull(&a, "url", "auth", "4", &[]).await.unwrap();
+        assert_eq!(7, pulls.get());
+
+        // Every caller sees the error.
+        let (f1, f2) = (puller(true), puller(true));
+        let (r1, r2) = futures::join!(
+            f1.pull(&a, "url", "auth", "5", &[]),
+            f2.pull(&a, "url", "auth", "6", &[]),
+        );
+        assert_eq!(8, pulls.get());
+        assert!(matches!(r1, Err(PullError::Shared(_))));
+        assert!(matches!(r2, Err(PullError::Shared(_))));
+    }
+}
@@ -0,0 +1,284 @@
+use crate::dag;
+use crate::db::{self, Commit};
+use crate::prolly;
+use crate::util::rlog::LogContext;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::string::FromUtf8Error;
+
+// MutationConflict describes a key that a local mutation being rebased onto
+// the sync head wrote, and that the pull also changed. Values are JSON text,
+// None meaning the key is absent.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct MutationConflict {
+    pub key: String,
+    // The value the local mutation wrote.
+    pub local: Option<String>,
+    // The value on the sync head, ie what the mutation is being rebased onto.
+    pub server: Option<String>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "op")]
+pub enum WriteOp {
+    #[serde(rename = "put")]
+    Put { key: String, value: String },
+    #[serde(rename = "del")]
+    Del { key: String },
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub enum Resolution {
+    // Keep what the local mutation wrote.
+    #[serde(rename = "keepLocal")]
+    KeepLocal,
+    // Undo the local mutation's write to the key.
+    #[serde(rename = "keepServer")]
+    KeepServer,
+    // Undo the local mutation's write to the key and apply these instead.
+    #[serde(rename = "custom")]
+    Custom(Vec<WriteOp>),
+}
+
+// The default resolution: the rebased mutation is re-applied as is, so the
+// last writer wins.
+pub fn keep_local(_: &MutationConflict) -> Resolution {
+    Resolution::KeepLocal
+}
+
+// Calls on_conflict for each key that both the rebased mutation in write and
+// the pull onto sync_head_name changed, and applies the resolution to write
+// before it is committed. Keys the pull did not touch are never reported.
+pub async fn resolve_conflicts(
+    write: &mut db::Write<'_>,
+    sync_head_name: &str,
+    on_conflict: &dyn Fn(&MutationConflict) -> Resolution,
+    lc: LogContext,
+) -> Result<(), ResolveConflictsError> {
+    use ResolveConflictsError::*;
+
+    if !write.is_rebase() {
+        return Ok(());
+    }
+    let local_keys = write.pending_changed_keys().map_err(InvalidUtf8)?;
+    if local_keys.is_empty() {
+        return Ok(());
+    }
+
+    let server_keys = {
+        let dag_read = write.dag_read();
+        let main_head = dag_read
+            .get_head(db::DEFAULT_HEAD_NAME)
+            .await
+            .map_err(GetHeadError)?
+            .ok_or(MissingMainHead)?;
+        let sync_head = dag_read
+            .get_head(sync_head_name)
+            .await
+            .map_err(GetHeadError)?
+            .ok_or(MissingSyncHead)?;
+        let main_snapshot = Commit::base_snapshot(&main_head, &dag_read)
+            .await
+            .map_err(NoBaseSnapshot)?;
+        let sync_snapshot = Commit::base_snapshot(&sync_head, &dag_read)
+            .await
+            .map_err(NoBaseSnapshot)?;
+        let main_map = prolly::Map::load(main_snapshot.value_hash(), &dag_read)
+            .await
+            .map_err(LoadMapError)?;
+        let sync_map = prolly::Map::load(sync_snapshot.value_hash(), &dag_read)
+            .await
+            .map_err(LoadMapError)?;
+        prolly::Map::changed_keys(&main_map, &sync_map)
+            .map_err(InvalidUtf8)?
+            .into_iter()
+            .collect::<HashSet<_>>()
+    };
+
+    let value = |v: Option<&[u8]>| v.map(|v| String::from_utf8(v.to_vec())).transpose();
+    let mut resolutions = Vec::new();
+    for key in local_keys.into_iter().filter(|k| server_keys.contains(k)) {
+        let conflict = MutationConflict {
+            local: value(write.get(key.as_bytes())).map_err(InvalidUtf8)?,
+            server: value(write.basis_get(key.as_bytes())).map_err(InvalidUtf8)?,
+            key,
+        };
+        let resolution = on_conflict(&conflict);
+        debug!(
+            lc,
+            "Resolved conflict on {} with {:?}", conflict.key, resolution
+        );
+        resolutions.push((conflict, resolution));
+    }
+
+    for (conflict, resolution) in resolutions {
+        let ops = match resolution {
+            Resolution::KeepLocal => continue,
+            Resolution::KeepServer => vec![],
+            Resolution::Custom(ops) => ops,
+        };
+        let key = conflict.key.into_bytes();
+        match conflict.server {
+            Some(v) => write
+                .put(lc.clone(), key, v.into_bytes())
+                .await
+                .map_err(PutError)?,
+            None => write.del(lc.clone(), key).await.map_err(DelError)?,
+        };
+        for op in ops {
+            match op {
+                WriteOp::Put { key, value } => write
+                    .put(lc.clone(), key.into_bytes(), value.into_bytes())
+                    .await
+                    .map_err(PutError)?,
+                WriteOp::Del { key } => write
+                    .del(lc.clone(), key.into_bytes())
+                    .await
+                    .map_err(DelError)?,
+            };
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum ResolveConflictsError {
+    DelError(db::DelError),
+    GetHeadError(dag::Error),
+    InvalidUtf8(FromUtf8Error),
+    LoadMapError(prolly::LoadError),
+    MissingMainHead,
+    MissingSyncHead,
+    NoBaseSnapshot(db::BaseSnapshotError),
+    PutError(db::PutError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::SYNC_HEAD_NAME;
+    use super::*;
+    use crate::db::test_helpers::*;
+    use crate::db::Whence;
+    use crate::kv::memstore::MemStore;
+    use std::cell::RefCell;
+    use str_macro::str;
+
+    #[async_std::test]
+    async fn test_resolve_conflicts() {
+        struct Case<'a> {
+            name: &'a str,
+            resolution: Resolution,
+            exp_local: Option<&'a str>,
+        }
+        let cases = vec![
+            Case {
+                name: "keep local",
+                resolution: Resolution::KeepLocal,
+                exp_local: Some("\"1\""),
+            },
+            Case {
+                name: "keep server",
+                resolution: Resolution::KeepServer,
+                exp_local: Some("\"server\""),
+            },
+            Case {
+                name: "custom put",
+                resolution: Resolution::Custom(vec![WriteOp::Put {
+                    key: str!("local"),
+                    value: str!("\"merged\""),
+                }]),
+                exp_local: Some("\"merged\""),
+            },
+            Case {
+                name: "custom del",
+                resolution: Resolution::Custom(vec![WriteOp::Del { key: str!("local") }]),
+                exp_local: None,
+            },
+        ];
+
+        for c in cases {
+            let store = dag::Store::new(Box::new(MemStore::new()));
+            let mut chain: Chain = vec![];
+            add_genesis(&mut chain, &store).await;
+            // Writes "local" => "\"1\"".
+            add_local(&mut chain, &store).await;
+
+            // The pull changes "local" and "server" but not "mine".
+            let mut w = db::Write::new_snapshot(
+                Whence::Hash(chain[0].chunk().hash().to_string()),
+                0,
+                serde_json::json!("cookie"),
+                store.write(LogContext::new()).await.unwrap(),
+                db::read_indexes(&chain[0]),
+            )
+            .await
+            .unwrap();
+            for key in &["local", "server"] {
+                w.put(
+                    LogContext::new(),
+                    key.as_bytes().to_vec(),
+                    b"\"server\"".to_vec(),
+                )
+                .await
+                .unwrap();
+            }
+            w.commit(SYNC_HEAD_NAME).await.unwrap();
+
+            let mut w = db::Write::new_local(
+                Whence::Head(str!(SYNC_HEAD_NAME)),
+                str!("mutator_name_1"),
+                serde_json::json!([1]).to_string(),
+                Some(chain[1].chunk().hash().to_string()),
+                store.write(LogContext::new()).await.unwrap(),
+            )
+            .await
+            .unwrap();
+            for key in &["local", "mine"] {
+                w.put(
+                    LogContext::new(),
+                    key.as_bytes().to_vec(),
+                    b"\"1\"".to_vec(),
+                )
+                .await
+                .unwrap();
+            }
+
+            let conflicts = RefCell::new(vec![]);
+            let resolution = RefCell::new(Some(c.resolution));
+            resolve_conflicts(
+                &mut w,
+                SYNC_HEAD_NAME,
+                &|conflict| {
+                    conflicts.borrow_mut().push(MutationConflict {
+                        key: conflict.key.clone(),
+                        local: conflict.local.clone(),
+                        server: conflict.server.clone(),
+                    });
+                    resolution.borrow_mut().take().unwrap()
+                },
+                LogContext::new(),
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(
+                vec![MutationConflict {
+                    key: str!("local"),
+                    local: Some(str!("\"1\"")),
+                    server: Some(str!("\"server\"")),
+                }],
+                conflicts.into_inner(),
+                "{}",
+                c.name
+            );
+            assert_eq!(
+                c.exp_local.map(str::as_bytes),
+                w.get(b"local"),
+                "{}",
+                c.name
+            );
+            assert_eq!(Some(&b"\"1\""[..]), w.get(b"mine"), "{}", c.name);
+            assert_eq!(Some(&b"\"server\""[..]), w.get(b"server"), "{}", c.name);
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use super::request_headers;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use wasm_bindgen::prelude::*;
@@ -19,25 +20,17 @@ pub async fn call_js_request<Body, Response, Error>(
     body: Body,
     auth: &str,
     request_id: &str,
+    extra_headers: &[(String, String)],
 ) -> Result<Response, Error>
 where
     Body: Serialize,
     Response: DeserializeOwned,
     Error: From<JsValue> + From<serde_wasm_bindgen::Error>,
 {
-    #[derive(Serialize)]
-    struct Headers<'a> {
-        #[serde(rename = "Content-type")]
-        content_type: &'a str,
-        #[serde(rename = "Authorization")]
-        authorization: &'a str,
-        #[serde(rename = "X-Replicache-RequestID")]
-        request_id: &'a str,
-    }
-
     #[derive(Serialize)]
     struct Init<'a> {
-        headers: Headers<'a>,
+        // As [name, value] pairs, which Request takes as well as an object.
+        headers: Vec<(&'a str, &'a str)>,
         body: &'a str,
         method: &'a str,
     }
@@ -45,11 +38,7 @@ where
     // We control body
     let body = serde_json::to_string(&body).unwrap();
     let init = Init {
-        headers: Headers {
-            content_type: "application/json",
-            authorization: auth,
-            request_id,
-        },
+        headers: request_headers(Some("application/json"), auth, request_id, extra_headers),
         body: &body,
         method: "POST",
     };
@@ -0,0 +1,54 @@
+use crate::dag;
+use crate::kv::StoreError;
+use crate::util::clock::Clock;
+use std::convert::TryInto;
+
+// The kv key under which the time of the last successful sync is kept.
+const LAST_SYNC_KEY: &str = "sys/lastsync";
+
+// Records clock's current time as the time of the last successful sync.
+pub async fn record_sync_time(store: &dag::Store, clock: &dyn Clock) -> Result<(), SyncTimeError> {
+    store
+        .kv()
+        .put(LAST_SYNC_KEY, &clock.now_ms().to_le_bytes())
+        .await
+        .map_err(SyncTimeError::StoreError)
+}
+
+// Returns the time of the last successful pull or push, in milliseconds since
+// the Unix epoch, or None if there has not been one.
+pub async fn last_sync_time(store: &dag::Store) -> Result<Option<u64>, SyncTimeError> {
+    use SyncTimeError::*;
+    match store.kv().get(LAST_SYNC_KEY).await.map_err(StoreError)? {
+        None => Ok(None),
+        Some(v) => Ok(Some(u64::from_le_bytes(
+            v.as_slice().try_into().map_err(|_| InvalidTime)?,
+        ))),
+    }
+}
+
+#[derive(Debug)]
+pub enum SyncTimeError {
+    InvalidTime,
+    StoreError(StoreError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::memstore::MemStore;
+    use crate::util::clock::MockClock;
+
+    #[async_std::test]
+    async fn test_last_sync_time() {
+        let store = dag::Store::new(Box::new(MemStore::new()));
+        let clock = MockClock::new(1000);
+        assert_eq!(None, last_sync_time(&store).await.unwrap());
+
+        record_sync_time(&store, &clock).await.unwrap();
+        assert_eq!(Some(1000), last_sync_time(&store).await.unwrap());
+        clock.advance(500);
+        record_sync_time(&store, &clock).await.unwrap();
+        assert_eq!(Some(1500), last_sync_time(&store).await.unwrap());
+    }
+}
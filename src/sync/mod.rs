@@ -1,16 +1,180 @@
 #![allow(clippy::redundant_pattern_matching)] // For derive(Deserialize).
 
+mod auto_push;
 pub mod client_id;
+mod coalesce;
+mod conflict;
 mod js_request;
+mod last_sync;
+mod mutation_state;
 mod patch;
 mod pull;
 mod push;
 pub mod request_id;
-#[cfg(test)]
+#[cfg(any(test, feature = "test-helpers"))]
 pub mod test_helpers;
 mod types;
+pub use auto_push::*;
+pub use coalesce::*;
+pub use conflict::*;
+pub use last_sync::*;
+pub use mutation_state::*;
 pub use pull::*;
 pub use push::*;
 pub use types::*;
 
 pub const SYNC_HEAD_NAME: &str = "sync";
+// Points at the marker that lets an interrupted begin_pull resume applying
+// its patch rather than starting over (see pull::PullProgress).
+pub const PULL_PROGRESS_HEAD_NAME: &str = "pull-progress";
+// Sent with every pull and push as the X-Replicache-Client-Version header so
+// that the server can tell which client build is connecting, eg to only send
+// newer patch formats to clients that understand them.
+pub const CLIENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Returns the headers to send with a pull, push or ping: ours, then
+// extra_headers from the request. An extra header replaces one of ours with
+// the same name, compared ignoring case as HTTP does, except Content-type,
+// which is always ours since we make the body.
+pub fn request_headers<'a>(
+    content_type: Option<&'a str>,
+    auth: &'a str,
+    request_id: &'a str,
+    extra_headers: &'a [(String, String)],
+) -> Vec<(&'a str, &'a str)> {
+    let is_content_type = |name: &str| name.eq_ignore_ascii_case("Content-type");
+    let is_extra = |name: &str| {
+        extra_headers
+            .iter()
+            .any(|(n, _)| n.eq_ignore_ascii_case(name))
+    };
+    let mut headers: Vec<(&str, &str)> = content_type
+        .map(|ct| ("Content-type", ct))
+        .into_iter()
+        .chain(
+            vec![
+                ("Authorization", auth),
+                ("X-Replicache-RequestID", request_id),
+                ("X-Replicache-Client-Version", CLIENT_VERSION),
+            ]
+            .into_iter()
+            .filter(|(name, _)| !is_extra(name)),
+        )
+        .collect();
+    headers.extend(
+        extra_headers
+            .iter()
+            .filter(|(name, _)| !is_content_type(name))
+            .map(|(name, value)| (name.as_str(), value.as_str())),
+    );
+    headers
+}
+
+// Each sync channel pulls onto its own head so that independent channels
+// against one db do not clobber each other. Requests name the channel's head,
+// or leave it out to use SYNC_HEAD_NAME.
+pub fn sync_head_name(name: Option<&str>) -> Result<&str, SyncHeadNameError> {
+    use SyncHeadNameError::*;
+    let name = name.unwrap_or(SYNC_HEAD_NAME);
+    if name.is_empty() {
+        return Err(Empty);
+    }
+    if name.contains('/') {
+        return Err(InvalidChar('/'));
+    }
+    if name == crate::db::DEFAULT_HEAD_NAME || name.starts_with(PULL_PROGRESS_HEAD_NAME) {
+        return Err(Reserved(name.to_string()));
+    }
+    Ok(name)
+}
+
+// The head that an interrupted pull onto sync_head_name resumes from.
+pub fn pull_progress_head_name(sync_head_name: &str) -> String {
+    if sync_head_name == SYNC_HEAD_NAME {
+        PULL_PROGRESS_HEAD_NAME.to_string()
+    } else {
+        format!("{}-{}", PULL_PROGRESS_HEAD_NAME, sync_head_name)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SyncHeadNameError {
+    Empty,
+    InvalidChar(char),
+    Reserved(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_headers() {
+        let h = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            pairs
+                .iter()
+                .map(|(n, v)| (n.to_string(), v.to_string()))
+                .collect()
+        };
+        assert_eq!(
+            vec![
+                ("Content-type", "application/json"),
+                ("Authorization", "auth"),
+                ("X-Replicache-RequestID", "rid"),
+                ("X-Replicache-Client-Version", CLIENT_VERSION),
+            ],
+            request_headers(Some("application/json"), "auth", "rid", &[])
+        );
+        let extra = h(&[
+            ("X-Tenant", "t1"),
+            ("authorization", "Bearer token"),
+            ("CONTENT-TYPE", "text/plain"),
+            ("X-Tenant", "t2"),
+        ]);
+        assert_eq!(
+            vec![
+                ("Content-type", "application/json"),
+                ("X-Replicache-RequestID", "rid"),
+                ("X-Replicache-Client-Version", CLIENT_VERSION),
+                ("X-Tenant", "t1"),
+                ("authorization", "Bearer token"),
+                ("X-Tenant", "t2"),
+            ],
+            request_headers(Some("application/json"), "auth", "rid", &extra)
+        );
+        // Without a body there is no Content-type to send, ours or theirs.
+        assert_eq!(
+            vec![
+                ("X-Replicache-RequestID", "rid"),
+                ("X-Replicache-Client-Version", CLIENT_VERSION),
+                ("X-Tenant", "t1"),
+                ("authorization", "Bearer token"),
+                ("X-Tenant", "t2"),
+            ],
+            request_headers(None, "auth", "rid", &extra)
+        );
+    }
+
+    #[test]
+    fn test_sync_head_name() {
+        use SyncHeadNameError::*;
+        assert_eq!(Ok(SYNC_HEAD_NAME), sync_head_name(None));
+        assert_eq!(Ok("org"), sync_head_name(Some("org")));
+        assert_eq!(Err(Empty), sync_head_name(Some("")));
+        assert_eq!(Err(InvalidChar('/')), sync_head_name(Some("a/b")));
+        assert_eq!(
+            Err(Reserved(str_macro::str!("main"))),
+            sync_head_name(Some("main"))
+        );
+        assert_eq!(
+            Err(Reserved(str_macro::str!("pull-progress-org"))),
+            sync_head_name(Some("pull-progress-org"))
+        );
+
+        assert_eq!(
+            PULL_PROGRESS_HEAD_NAME,
+            pull_progress_head_name(SYNC_HEAD_NAME)
+        );
+        assert_eq!("pull-progress-org", pull_progress_head_name("org"));
+    }
+}
@@ -0,0 +1,111 @@
+use super::{last_sync_time, SyncTimeError};
+use crate::dag;
+use crate::db::{self, Commit};
+use crate::util::rlog::LogContext;
+use serde::{Deserialize, Serialize};
+
+// MutationState describes how far the local mutations on the main chain are
+// ahead of the server. The difference between the two ids is the number of
+// mutations that have not been acknowledged by a pull yet.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct MutationState {
+    // The mutation id of the newest commit on the main chain.
+    #[serde(rename = "lastMutationID")]
+    pub last_mutation_id: u64,
+    // The last mutation id the server has acknowledged, ie the one recorded
+    // in the base snapshot of the main chain.
+    #[serde(rename = "lastServerAck")]
+    pub last_server_ack: u64,
+    // When the last pull or push succeeded, in milliseconds since the Unix
+    // epoch, or None if none has.
+    #[serde(rename = "lastSyncTime")]
+    pub last_sync_time: Option<u64>,
+}
+
+pub async fn mutation_state(
+    store: &dag::Store,
+    lc: LogContext,
+) -> Result<MutationState, MutationStateError> {
+    use MutationStateError::*;
+
+    let (last_mutation_id, last_server_ack) = {
+        let dag_read = store.read(lc).await.map_err(ReadError)?;
+        let dag_read = dag_read.read();
+        let main_head_hash = dag_read
+            .get_head(db::DEFAULT_HEAD_NAME)
+            .await
+            .map_err(GetHeadError)?
+            .ok_or(NoMainHead)?;
+        let main_head = Commit::from_hash(&main_head_hash, &dag_read)
+            .await
+            .map_err(LoadHeadError)?;
+        let base_snapshot = Commit::base_snapshot(&main_head_hash, &dag_read)
+            .await
+            .map_err(NoBaseSnapshot)?;
+        (main_head.mutation_id(), base_snapshot.mutation_id())
+    };
+    // The sync time lives outside the dag, so it is read once the dag read
+    // has been released.
+    Ok(MutationState {
+        last_mutation_id,
+        last_server_ack,
+        last_sync_time: last_sync_time(store).await.map_err(SyncTimeError)?,
+    })
+}
+
+#[derive(Debug)]
+pub enum MutationStateError {
+    GetHeadError(dag::Error),
+    LoadHeadError(db::FromHashError),
+    NoBaseSnapshot(db::BaseSnapshotError),
+    NoMainHead,
+    ReadError(dag::Error),
+    SyncTimeError(SyncTimeError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_helpers::*;
+    use crate::kv::memstore::MemStore;
+    use crate::sync::record_sync_time;
+    use crate::util::clock::MockClock;
+
+    #[async_std::test]
+    async fn test_mutation_state() {
+        let store = dag::Store::new(Box::new(MemStore::new()));
+        let mut chain: Chain = vec![];
+        add_genesis(&mut chain, &store).await;
+        assert_eq!(
+            MutationState {
+                last_mutation_id: 0,
+                last_server_ack: 0,
+                last_sync_time: None,
+            },
+            mutation_state(&store, LogContext::new()).await.unwrap()
+        );
+
+        add_local(&mut chain, &store).await;
+        add_local(&mut chain, &store).await;
+        assert_eq!(
+            MutationState {
+                last_mutation_id: 2,
+                last_server_ack: 0,
+                last_sync_time: None,
+            },
+            mutation_state(&store, LogContext::new()).await.unwrap()
+        );
+
+        add_snapshot(&mut chain, &store, None).await;
+        add_local(&mut chain, &store).await;
+        let state = mutation_state(&store, LogContext::new()).await.unwrap();
+        assert_eq!(chain[3].mutation_id(), state.last_server_ack);
+        assert_eq!(state.last_server_ack + 1, state.last_mutation_id);
+
+        record_sync_time(&store, &MockClock::new(1234))
+            .await
+            .unwrap();
+        let state = mutation_state(&store, LogContext::new()).await.unwrap();
+        assert_eq!(Some(1234), state.last_sync_time);
+    }
+}
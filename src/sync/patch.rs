@@ -1,9 +1,10 @@
 use crate::db;
 use crate::util::rlog;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
-#[derive(Deserialize)]
-#[cfg_attr(test, derive(Clone, Debug, PartialEq))]
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
 #[serde(tag = "op")]
 pub enum Operation {
     #[serde(rename = "put")]
@@ -24,8 +25,10 @@ pub async fn apply(db_write: &mut db::Write<'_>, patch: &[Operation]) -> Result<
             Operation::Put { key, value } => {
                 let key = key.as_bytes().to_vec();
                 let value = serde_json::to_vec(value).map_err(InvalidValue)?;
+                // Servers often resend values the client already has, so
+                // skip those rather than churn the indexes.
                 db_write
-                    .put(rlog::LogContext::new(), key, value)
+                    .put_if_changed(rlog::LogContext::new(), key, value)
                     .await
                     .map_err(PutError)?;
             }
@@ -45,6 +48,49 @@ pub async fn apply(db_write: &mut db::Write<'_>, patch: &[Operation]) -> Result<
     Ok(())
 }
 
+// PatchSummary is the net effect of a patch, computed without applying it.
+// Keys are sorted and each appears at most once, in puts or dels according
+// to the last operation on it.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct PatchSummary {
+    // clear is true if applying the patch clears the existing map (before
+    // the puts and dels below).
+    pub clear: bool,
+    pub puts: Vec<String>,
+    pub dels: Vec<String>,
+}
+
+pub fn summarize(patch: &[Operation]) -> PatchSummary {
+    let mut clear = false;
+    let mut last_op: BTreeMap<&str, bool> = BTreeMap::new();
+    for op in patch.iter() {
+        match op {
+            Operation::Put { key, .. } => {
+                last_op.insert(key, true);
+            }
+            Operation::Del { key } => {
+                last_op.insert(key, false);
+            }
+            Operation::Clear => {
+                clear = true;
+                last_op.clear();
+            }
+        }
+    }
+    let mut summary = PatchSummary {
+        clear,
+        ..Default::default()
+    };
+    for (key, is_put) in last_op {
+        if is_put {
+            summary.puts.push(key.to_string());
+        } else {
+            summary.dels.push(key.to_string());
+        }
+    }
+    summary
+}
+
 #[derive(Debug)]
 pub enum PatchError {
     ClearError(db::ClearError),
@@ -257,4 +303,36 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_summarize() {
+        let put = |key: &str| Operation::Put {
+            key: key.to_string(),
+            value: json!(1),
+        };
+        let del = |key: &str| Operation::Del {
+            key: key.to_string(),
+        };
+        let summary = |clear: bool, puts: Vec<&str>, dels: Vec<&str>| PatchSummary {
+            clear,
+            puts: puts.into_iter().map(String::from).collect(),
+            dels: dels.into_iter().map(String::from).collect(),
+        };
+
+        assert_eq!(summary(false, vec![], vec![]), summarize(&[]));
+        assert_eq!(
+            summary(false, vec!["a", "b"], vec!["c"]),
+            summarize(&[put("b"), del("c"), put("a")])
+        );
+        // The last op on a key wins.
+        assert_eq!(
+            summary(false, vec!["b"], vec!["a"]),
+            summarize(&[put("a"), del("b"), del("a"), put("b")])
+        );
+        // Ops before a clear are subsumed by it.
+        assert_eq!(
+            summary(true, vec!["c"], vec![]),
+            summarize(&[put("a"), del("b"), Operation::Clear, put("c")])
+        );
+    }
 }
@@ -3,12 +3,14 @@
 use super::js_request::call_js_request;
 use super::patch;
 use super::types::*;
-use super::SYNC_HEAD_NAME;
+use super::{pull_progress_head_name, sync_head_name};
 use crate::dag;
 use crate::db::{Commit, MetaTyped, Whence, DEFAULT_HEAD_NAME};
 #[cfg(not(target_arch = "wasm32"))]
 use crate::fetch;
 use crate::fetch::errors::FetchError;
+use crate::fetch::retry::{self, Outcome, RetryPolicy};
+use crate::hash::Hash;
 use crate::prolly;
 use crate::util::rlog;
 use crate::util::rlog::LogContext;
@@ -22,6 +24,8 @@ use log::log_enabled;
 use serde::{Deserialize, Serialize};
 use std::default::Default;
 use std::fmt::Debug;
+use std::rc::Rc;
+use std::time::Duration;
 use std::{collections::HashMap, string::FromUtf8Error};
 use str_macro::str;
 use wasm_bindgen::prelude::*;
@@ -42,12 +46,72 @@ pub async fn begin_pull(
 ) -> Result<BeginTryPullResponse, BeginTryPullError> {
     use BeginTryPullError::*;
 
+    let sync_head_name = self::sync_head_name(begin_pull_req.sync_head_name.as_deref())
+        .map_err(InvalidSyncHeadName)?
+        .to_string();
+    let progress_head_name = pull_progress_head_name(&sync_head_name);
+    let resp = pull_and_apply(
+        client_id,
+        begin_pull_req,
+        puller,
+        request_id,
+        store,
+        lc.clone(),
+        &sync_head_name,
+        &progress_head_name,
+    )
+    .await?;
+
+    // Once there is a response the progress head is done with: either the
+    // patch applied or there was nothing to apply. Clearing it lets its
+    // checkpoints be collected. A pull that fails to apply discards it too
+    // (see pull_and_apply), so only a begin_pull that never returns (eg the
+    // tab closed) or that fails before it has a patch (eg the fetch fails)
+    // leaves it for the next one to resume from.
+    clear_pull_progress(store, lc, &progress_head_name)
+        .await
+        .map_err(SavePullProgressError)?;
+    Ok(resp)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn pull_and_apply(
+    client_id: String,
+    begin_pull_req: BeginTryPullRequest,
+    puller: &dyn Puller,
+    request_id: String,
+    store: &dag::Store,
+    lc: LogContext,
+    sync_head_name: &str,
+    progress_head_name: &str,
+) -> Result<BeginTryPullResponse, BeginTryPullError> {
+    use BeginTryPullError::*;
+
     let BeginTryPullRequest {
         pull_url,
         pull_auth,
         schema_version,
+        dry_run,
+        index_flush_concurrency,
+        sync_head_name: _,
+        ping_timeout_ms,
+        timeout_ms,
+        extra_headers,
     } = begin_pull_req;
 
+    if let Some(ms) = ping_timeout_ms {
+        ping(
+            puller,
+            &pull_url,
+            &pull_auth,
+            &request_id,
+            &extra_headers,
+            ms,
+            lc.clone(),
+        )
+        .await?;
+    }
+
     let dag_read = store.read(lc.clone()).await.map_err(ReadError)?;
     let main_head_hash = dag_read
         .read()
@@ -73,10 +137,20 @@ pub async fn begin_pull(
     };
     debug!(lc, "Starting pull...");
     let pull_timer = rlog::Timer::new();
-    let (pull_resp, http_request_info) = puller
-        .pull(&pull_req, &pull_url, &pull_auth, &request_id)
-        .await
-        .map_err(PullFailed)?;
+    let pull = puller.pull(
+        &pull_req,
+        &pull_url,
+        &pull_auth,
+        &request_id,
+        &extra_headers,
+    );
+    let (pull_resp, http_request_info) = match timeout_ms {
+        None => pull.await,
+        Some(ms) => async_std::future::timeout(Duration::from_millis(ms), pull)
+            .await
+            .map_err(|_| PullTimeout(ms))?,
+    }
+    .map_err(PullFailed)?;
 
     debug!(
         lc.clone(),
@@ -90,35 +164,107 @@ pub async fn begin_pull(
     );
 
     // If Puller did not get a pull response we still want to return the HTTP
-    // request info to the JS SDK.
+    // request info to the JS SDK. The request body carries the cookie, so a
+    // server can answer 304 Not Modified when it has nothing newer.
     if pull_resp.is_none() {
+        let not_modified =
+            http_request_info.http_status_code == u16::from(http::StatusCode::NOT_MODIFIED);
         return Ok(BeginTryPullResponse {
             http_request_info,
             sync_head: str!(""),
             request_id,
+            patch_summary: None,
+            not_modified,
         });
     }
 
     let pull_resp = pull_resp.unwrap();
 
+    if dry_run {
+        return Ok(BeginTryPullResponse {
+            http_request_info,
+            sync_head: str!(""),
+            request_id,
+            patch_summary: Some(patch::summarize(&pull_resp.patch)),
+            not_modified: false,
+        });
+    }
+
+    // If there is no patch and the lmid and cookie don't change, it's a nop,
+    // which is checked before taking the write lock so that polling an
+    // unchanged server does not contend with local writes. Otherwise, we will
+    // write a new commit, including for the case of just a cookie change.
+    if pull_resp.patch.is_empty()
+        && pull_resp.last_mutation_id == base_last_mutation_id
+        && pull_resp.cookie == base_cookie
+    {
+        return Ok(BeginTryPullResponse {
+            http_request_info,
+            sync_head: str!(""),
+            request_id,
+            patch_summary: None,
+            not_modified: true,
+        });
+    }
+
+    let commit_hash = match apply_pull(
+        store,
+        lc.clone(),
+        &base_snapshot,
+        base_last_mutation_id,
+        &pull_resp,
+        index_flush_concurrency,
+        sync_head_name,
+        progress_head_name,
+    )
+    .await
+    {
+        Ok(commit_hash) => commit_hash,
+        // Another sync owns the progress head, so it is not ours to discard.
+        Err(OverlappingSyncsJSLogInfo) => return Err(OverlappingSyncsJSLogInfo),
+        Err(e) => {
+            // Discard what this pull staged so that its chunks can be
+            // collected, rather than leave a partial apply lying around.
+            if let Err(clear_err) = clear_pull_progress(store, lc.clone(), progress_head_name).await
+            {
+                error!(lc, "Failed to discard pull progress: {:?}", clear_err);
+            }
+            return Err(e);
+        }
+    };
+
+    Ok(BeginTryPullResponse {
+        http_request_info: HttpRequestInfo {
+            http_status_code: http::StatusCode::OK.into(),
+            error_message: str!(""),
+        },
+        sync_head: commit_hash,
+        request_id,
+        patch_summary: None,
+        not_modified: false,
+    })
+}
+
+// Applies pull_resp on top of base_snapshot and commits the result to
+// sync_head_name, returning the commit's hash.
+#[allow(clippy::too_many_arguments)]
+async fn apply_pull(
+    store: &dag::Store,
+    lc: LogContext,
+    base_snapshot: &Commit,
+    base_last_mutation_id: u64,
+    pull_resp: &PullResponse,
+    index_flush_concurrency: Option<usize>,
+    sync_head_name: &str,
+    progress_head_name: &str,
+) -> Result<String, BeginTryPullError> {
+    use BeginTryPullError::*;
+
     // It is possible that another sync completed while we were pulling. Ensure
     // that is not the case by re-checking the base snapshot.
     let dag_write = store.write(lc.clone()).await.map_err(LockError)?;
     let dag_read = dag_write.read();
-    let main_head_post_pull = dag_read
-        .get_head(DEFAULT_HEAD_NAME)
-        .await
-        .map_err(GetHeadError)?;
-    if main_head_post_pull.is_none() {
-        return Err(MainHeadDisappeared);
-    }
-    let base_snapshot_post_pull =
-        Commit::base_snapshot(main_head_post_pull.as_ref().unwrap(), &dag_read)
-            .await
-            .map_err(NoBaseSnapshot)?;
-    if base_snapshot.chunk().hash() != base_snapshot_post_pull.chunk().hash() {
-        return Err(OverlappingSyncsJSLogInfo);
-    }
+    let main_head_post_pull = check_base_snapshot(&dag_read, base_snapshot.chunk().hash()).await?;
 
     // If other entities (eg, other clients) are modifying the client view
     // the client view can change but the last_mutation_id stays the same.
@@ -130,74 +276,242 @@ pub async fn begin_pull(
         )));
     }
 
-    // If there is no patch and the lmid and cookie don't change, it's a nop.
-    // Otherwise, we will write a new commit, including for the case of just
-    // a cookie change.
-    if pull_resp.patch.is_empty()
-        && pull_resp.last_mutation_id == base_last_mutation_id
-        && pull_resp.cookie == base_cookie
-    {
-        let sync_head = str!("");
-        return Ok(BeginTryPullResponse {
-            http_request_info,
-            sync_head,
-            request_id,
-        });
-    }
-
-    // We are going to need to rebuild the indexes. We want to take the definitions from
-    // the last commit on the chain that will not be rebased. We do this here before creating
-    // the new snapshot while we still have the dag_read borrowed.
-    let chain = Commit::chain(main_head_post_pull.as_ref().unwrap(), &dag_read)
+    // If an earlier begin_pull for this same response was interrupted part
+    // way through applying the patch, pick up from its last checkpoint.
+    let patch_hash =
+        Hash::of(&serde_json::to_vec(&pull_resp.patch).map_err(SerializePatchError)?).to_string();
+    let progress = load_pull_progress(&dag_read, progress_head_name)
         .await
-        .map_err(InternalGetChainError)?;
-    let index_records: Vec<db::IndexRecord> = chain
-        .iter()
-        .find(|c| c.mutation_id() <= pull_resp.last_mutation_id)
-        .ok_or(InternalInvalidChainError)?
-        .indexes();
-    drop(dag_read);
+        .map_err(GetHeadError)?
+        .filter(|p| {
+            p.base_snapshot == base_snapshot.chunk().hash()
+                && p.last_mutation_id == pull_resp.last_mutation_id
+                && p.cookie == pull_resp.cookie
+                && p.patch_hash == patch_hash
+                && p.applied <= pull_resp.patch.len()
+        });
 
-    let mut db_write = db::Write::new_snapshot(
-        Whence::Hash(base_snapshot.chunk().hash().to_string()),
-        pull_resp.last_mutation_id,
-        pull_resp.cookie.clone(),
-        dag_write,
-        HashMap::new(), // Note: created with no indexes
-    )
-    .await
-    .map_err(ReadCommitError)?;
-
-    // Rebuild the indexes
-    // TODO would be so nice to have a way to re-use old indexes, which are likely
-    //      only a small diff from what we want.
-    for m in index_records.iter() {
-        let def = &m.definition;
-        db_write
-            .create_index(
-                lc.clone(),
-                def.name.clone(),
-                &def.key_prefix,
-                &def.json_pointer,
+    let (mut db_write, mut applied) = match progress {
+        Some(progress) => {
+            debug!(
+                lc,
+                "Resuming pull at op {} of {}",
+                progress.applied,
+                pull_resp.patch.len()
+            );
+            let db_write = db::Write::resume_snapshot(Whence::Hash(progress.checkpoint), dag_write)
+                .await
+                .map_err(ResumeError)?;
+            (db_write, progress.applied)
+        }
+        None => {
+            // We are going to need to rebuild the indexes. We want to take the definitions from
+            // the last commit on the chain that will not be rebased. We do this here before creating
+            // the new snapshot while we still have the dag_read borrowed.
+            let chain = Commit::chain(&main_head_post_pull, &dag_read)
+                .await
+                .map_err(InternalGetChainError)?;
+            let index_records: Vec<db::IndexRecord> = chain
+                .iter()
+                .find(|c| c.mutation_id() <= pull_resp.last_mutation_id)
+                .ok_or(InternalInvalidChainError)?
+                .indexes();
+            drop(dag_read);
+
+            let mut db_write = db::Write::new_snapshot(
+                Whence::Hash(base_snapshot.chunk().hash().to_string()),
+                pull_resp.last_mutation_id,
+                pull_resp.cookie.clone(),
+                dag_write,
+                HashMap::new(), // Note: created with no indexes
             )
             .await
-            .map_err(InternalRebuildIndexError)?;
+            .map_err(ReadCommitError)?;
+
+            // Rebuild the indexes
+            // TODO would be so nice to have a way to re-use old indexes, which are likely
+            //      only a small diff from what we want.
+            for m in index_records.iter() {
+                let def = &m.definition;
+                db_write
+                    .create_index(
+                        lc.clone(),
+                        def.name.clone(),
+                        &def.key_prefix,
+                        &def.json_pointer,
+                    )
+                    .await
+                    .map_err(InternalRebuildIndexError)?;
+            }
+            (db_write, 0)
+        }
+    };
+
+    let index_flush_concurrency =
+        index_flush_concurrency.unwrap_or(db::DEFAULT_INDEX_FLUSH_CONCURRENCY);
+    db_write.set_index_flush_concurrency(index_flush_concurrency);
+
+    // Apply the patch in batches, checkpointing after each one but the last
+    // so that an interrupted pull does not have to start over. Checkpoints
+    // are staged on the progress head, never the sync head: the sync head
+    // only moves, in the single commit after the loop, once the whole patch
+    // has applied, so a pull that fails part way is never visible, and its
+    // progress head is discarded.
+    //
+    // Committing a checkpoint releases the dag write lock until the next
+    // batch takes it again. That is safe because the checkpoint is reachable
+    // from the progress head so it cannot be collected, and local mutations
+    // committed to main in between do not touch the snapshot being built;
+    // they are rebased onto it by maybe_end_try_pull. What could interfere is
+    // another sync, so on taking the lock back we check that main is still
+    // based on base_snapshot and that the progress head is still ours, and
+    // give up with OverlappingSyncsJSLogInfo if not.
+    loop {
+        let end = pull_resp.patch.len().min(applied + PULL_CHECKPOINT_OPS);
+        patch::apply(&mut db_write, &pull_resp.patch[applied..end])
+            .await
+            .map_err(PatchFailed)?;
+        applied = end;
+        if applied == pull_resp.patch.len() {
+            break;
+        }
+        let checkpoint = db_write
+            .commit(progress_head_name)
+            .await
+            .map_err(CommitError)?;
+        let marker = save_pull_progress(
+            store,
+            lc.clone(),
+            progress_head_name,
+            &PullProgress {
+                base_snapshot: base_snapshot.chunk().hash().to_string(),
+                last_mutation_id: pull_resp.last_mutation_id,
+                cookie: pull_resp.cookie.clone(),
+                patch_hash: patch_hash.clone(),
+                applied,
+                checkpoint: checkpoint.clone(),
+            },
+        )
+        .await
+        .map_err(SavePullProgressError)?;
+        let dag_write = store.write(lc.clone()).await.map_err(LockError)?;
+        {
+            let dag_read = dag_write.read();
+            check_base_snapshot(&dag_read, base_snapshot.chunk().hash()).await?;
+            let progress_head = dag_read
+                .get_head(progress_head_name)
+                .await
+                .map_err(GetHeadError)?;
+            if progress_head.as_deref() != Some(marker.as_str()) {
+                return Err(OverlappingSyncsJSLogInfo);
+            }
+        }
+        db_write = db::Write::resume_snapshot(Whence::Hash(checkpoint), dag_write)
+            .await
+            .map_err(ResumeError)?;
+        db_write.set_index_flush_concurrency(index_flush_concurrency);
     }
 
-    patch::apply(&mut db_write, &pull_resp.patch)
+    let commit_hash = db_write.commit(sync_head_name).await.map_err(CommitError)?;
+    Ok(commit_hash)
+}
+
+// Returns main's head, failing with OverlappingSyncsJSLogInfo unless it is
+// still based on base_snapshot, ie unless another sync completed meanwhile.
+async fn check_base_snapshot(
+    dag_read: &dag::Read<'_>,
+    base_snapshot: &str,
+) -> Result<String, BeginTryPullError> {
+    use BeginTryPullError::*;
+    let main_head = dag_read
+        .get_head(DEFAULT_HEAD_NAME)
+        .await
+        .map_err(GetHeadError)?
+        .ok_or(MainHeadDisappeared)?;
+    let main_base_snapshot = Commit::base_snapshot(&main_head, dag_read)
         .await
-        .map_err(PatchFailed)?;
+        .map_err(NoBaseSnapshot)?;
+    if main_base_snapshot.chunk().hash() != base_snapshot {
+        return Err(OverlappingSyncsJSLogInfo);
+    }
+    Ok(main_head)
+}
 
-    let commit_hash = db_write.commit(SYNC_HEAD_NAME).await.map_err(CommitError)?;
+// Number of patch operations begin_pull applies between checkpoints.
+const PULL_CHECKPOINT_OPS: usize = 1000;
+
+// PullProgress records how far into a pull response's patch an earlier
+// begin_pull got. It is stored as a chunk under the sync head's pull progress
+// head (see pull_progress_head_name) that
+// references checkpoint, a snapshot commit holding the result of applying the
+// first `applied` operations on top of base_snapshot. The patch is assumed to
+// be idempotent, so re-applying ops that made it in after the checkpoint is
+// harmless.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+struct PullProgress {
+    base_snapshot: String,
+    last_mutation_id: u64,
+    cookie: serde_json::Value,
+    patch_hash: String,
+    applied: usize,
+    checkpoint: String,
+}
 
-    Ok(BeginTryPullResponse {
-        http_request_info: HttpRequestInfo {
-            http_status_code: http::StatusCode::OK.into(),
-            error_message: str!(""),
-        },
-        sync_head: commit_hash,
-        request_id,
-    })
+// A marker that is missing its checkpoint or does not parse is ignored and the
+// pull starts over.
+async fn load_pull_progress(
+    dag_read: &dag::Read<'_>,
+    head_name: &str,
+) -> Result<Option<PullProgress>, dag::Error> {
+    let hash = match dag_read.get_head(head_name).await? {
+        None => return Ok(None),
+        Some(hash) => hash,
+    };
+    let progress = match dag_read.get_chunk(&hash).await? {
+        None => return Ok(None),
+        Some(chunk) => serde_json::from_slice::<PullProgress>(chunk.data()).ok(),
+    };
+    match progress {
+        Some(p) if dag_read.has_chunk(&p.checkpoint).await? => Ok(Some(p)),
+        _ => Ok(None),
+    }
+}
+
+// Returns the hash of the marker, which head_name now points at.
+async fn save_pull_progress(
+    store: &dag::Store,
+    lc: LogContext,
+    head_name: &str,
+    progress: &PullProgress,
+) -> Result<String, dag::Error> {
+    let data = serde_json::to_vec(progress)
+        .map_err(|e| dag::Error::CorruptStore(format!("invalid pull progress: {}", e)))?;
+    let chunk = dag::Chunk::new((data, 0), &[&progress.checkpoint]);
+    let dag_write = store.write(lc).await?;
+    dag_write.put_chunk(&chunk).await?;
+    dag_write.set_head(head_name, Some(chunk.hash())).await?;
+    dag_write.commit().await?;
+    Ok(chunk.hash().to_string())
+}
+
+// Takes the write lock only if there is something to clear, so that a pull
+// that had nothing to apply does not contend with local writes.
+async fn clear_pull_progress(
+    store: &dag::Store,
+    lc: LogContext,
+    head_name: &str,
+) -> Result<(), dag::Error> {
+    let dag_read = store.read(lc.clone()).await?;
+    if dag_read.read().get_head(head_name).await?.is_none() {
+        return Ok(());
+    }
+    drop(dag_read);
+    let dag_write = store.write(lc).await?;
+    if dag_write.read().get_head(head_name).await?.is_none() {
+        return Ok(());
+    }
+    dag_write.set_head(head_name, None).await?;
+    dag_write.commit().await
 }
 
 pub async fn maybe_end_try_pull(
@@ -207,6 +521,9 @@ pub async fn maybe_end_try_pull(
 ) -> Result<MaybeEndTryPullResponse, MaybeEndTryPullError> {
     use MaybeEndTryPullError::*;
 
+    let sync_head_name = sync_head_name(maybe_end_pull_req.sync_head_name.as_deref())
+        .map_err(InvalidSyncHeadName)?;
+
     // Ensure sync head is what the caller thinks it is.
     let dag_write = store
         .write(lc.clone())
@@ -214,7 +531,7 @@ pub async fn maybe_end_try_pull(
         .map_err(OpenWriteTxWriteError)?;
     let dag_read = dag_write.read();
     let sync_head_hash = dag_read
-        .get_head(SYNC_HEAD_NAME)
+        .get_head(sync_head_name)
         .await
         .map_err(GetSyncHeadError)?
         .ok_or(MissingSyncHead)?;
@@ -315,7 +632,7 @@ pub async fn maybe_end_try_pull(
         .await
         .map_err(WriteDefaultHeadError)?;
     dag_write
-        .set_head(SYNC_HEAD_NAME, None)
+        .set_head(sync_head_name, None)
         .await
         .map_err(WriteSyncHeadError)?;
     dag_write.commit().await.map_err(CommitError)?;
@@ -403,8 +720,7 @@ async fn add_changed_keys_for_indexes<'a>(
     Ok(())
 }
 
-#[derive(Debug, Default, PartialEq, Serialize)]
-#[cfg_attr(test, derive(Clone))]
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
 pub struct PullRequest {
     #[serde(rename = "clientID")]
     pub client_id: String,
@@ -421,8 +737,8 @@ pub struct PullRequest {
     pub schema_version: String,
 }
 
-#[derive(Deserialize)]
-#[cfg_attr(test, derive(Clone, Debug, PartialEq))]
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
 pub struct PullResponse {
     #[serde(default)]
     pub cookie: serde_json::Value,
@@ -431,6 +747,38 @@ pub struct PullResponse {
     pub patch: Vec<patch::Operation>,
 }
 
+// Fails with Unreachable unless the endpoint answers a ping within
+// timeout_ms. Any response short of a server error counts as an answer: a
+// pull endpoint need not support the ping's method to be up.
+async fn ping(
+    puller: &dyn Puller,
+    url: &str,
+    auth: &str,
+    request_id: &str,
+    extra_headers: &[(String, String)],
+    timeout_ms: u64,
+    lc: LogContext,
+) -> Result<(), BeginTryPullError> {
+    use BeginTryPullError::Unreachable;
+    let timeout = Duration::from_millis(timeout_ms);
+    let res =
+        async_std::future::timeout(timeout, puller.ping(url, auth, request_id, extra_headers))
+            .await
+            .map_err(|_| Unreachable(format!("ping timed out after {}ms", timeout_ms)))?
+            .map_err(|e| Unreachable(format!("ping failed: {:?}", e)))?;
+    match res {
+        Some(info) if info.http_status_code >= 500 => Err(Unreachable(format!(
+            "ping returned {}",
+            info.http_status_code
+        ))),
+        Some(info) => {
+            debug!(lc, "Ping returned {}", info.http_status_code);
+            Ok(())
+        }
+        None => Ok(()),
+    }
+}
+
 // We define this trait so we can provide a fake implementation for testing.
 #[async_trait(?Send)]
 pub trait Puller {
@@ -440,18 +788,43 @@ pub trait Puller {
         url: &str,
         auth: &str,
         request_id: &str,
+        extra_headers: &[(String, String)],
     ) -> Result<(Option<PullResponse>, HttpRequestInfo), PullError>;
+
+    // ping makes a cheap request (eg a HEAD) to url to check that the
+    // endpoint is up before a pull is started. The default returns None,
+    // meaning the puller cannot ping and the pull should just go ahead.
+    async fn ping(
+        &self,
+        _url: &str,
+        _auth: &str,
+        _request_id: &str,
+        _extra_headers: &[(String, String)],
+    ) -> Result<Option<HttpRequestInfo>, PullError> {
+        Ok(None)
+    }
 }
 
 #[cfg(not(target_arch = "wasm32"))]
 pub struct FetchPuller<'a> {
-    fetch_client: &'a fetch::client::Client,
+    fetch_client: &'a dyn fetch::HttpClient,
+    retry_policy: RetryPolicy,
 }
 
 #[cfg(not(target_arch = "wasm32"))]
 impl FetchPuller<'_> {
-    pub fn new(fetch_client: &fetch::client::Client) -> FetchPuller {
-        FetchPuller { fetch_client }
+    pub fn new(fetch_client: &dyn fetch::HttpClient) -> FetchPuller<'_> {
+        FetchPuller::new_with_retry_policy(fetch_client, RetryPolicy::default())
+    }
+
+    pub fn new_with_retry_policy(
+        fetch_client: &dyn fetch::HttpClient,
+        retry_policy: RetryPolicy,
+    ) -> FetchPuller<'_> {
+        FetchPuller {
+            fetch_client,
+            retry_policy,
+        }
     }
 }
 
@@ -461,21 +834,22 @@ impl Puller for FetchPuller<'_> {
     // A failed HTTP response (non 200) is not an error. In that case we get
     // `None` for the `PullResponse`. We get errors for a few non HTTP related
     // reasons such as if we fail to create a Request object, the call to fetch
-    // fails or the response is not the expected JSON format.
+    // fails or the response is not the expected JSON format. Network errors
+    // and 5xx and 429 responses are retried first (see request_with_retry).
     async fn pull(
         &self,
         pull_req: &PullRequest,
         url: &str,
         auth: &str,
         request_id: &str,
+        extra_headers: &[(String, String)],
     ) -> Result<(Option<PullResponse>, HttpRequestInfo), PullError> {
         use PullError::*;
-        let http_req = new_pull_http_request(pull_req, url, auth, request_id)?;
-        let http_resp: http::Response<String> = self
-            .fetch_client
-            .request(http_req)
-            .await
-            .map_err(FetchFailed)?;
+        let http_req = new_pull_http_request(pull_req, url, auth, request_id, extra_headers)?;
+        let http_resp: http::Response<String> =
+            retry::request_with_retry(self.fetch_client, http_req, &self.retry_policy)
+                .await
+                .map_err(FetchFailed)?;
         let ok = http_resp.status() == http::StatusCode::OK;
         let http_request_info = HttpRequestInfo {
             http_status_code: http_resp.status().into(),
@@ -492,6 +866,32 @@ impl Puller for FetchPuller<'_> {
         };
         Ok((pull_response, http_request_info))
     }
+
+    async fn ping(
+        &self,
+        url: &str,
+        auth: &str,
+        request_id: &str,
+        extra_headers: &[(String, String)],
+    ) -> Result<Option<HttpRequestInfo>, PullError> {
+        use PullError::*;
+        let mut builder = http::request::Builder::new().method("HEAD").uri(url);
+        for (name, value) in super::request_headers(None, auth, request_id, extra_headers) {
+            builder = builder.header(name, value);
+        }
+        let http_req = builder.body(str!("")).map_err(InvalidRequest)?;
+        // Not retried: the ping is there to find out whether the endpoint is
+        // up, so a failure should be reported rather than waited out.
+        let http_resp = self
+            .fetch_client
+            .request(http_req)
+            .await
+            .map_err(FetchFailed)?;
+        Ok(Some(HttpRequestInfo {
+            http_status_code: http_resp.status().into(),
+            error_message: str!(""),
+        }))
+    }
 }
 
 // Pulled into a helper fn because we use it integration tests.
@@ -501,18 +901,17 @@ pub fn new_pull_http_request(
     url: &str,
     auth: &str,
     request_id: &str,
+    extra_headers: &[(String, String)],
 ) -> Result<http::Request<String>, PullError> {
     use PullError::*;
     let body = serde_json::to_string(pull_req).map_err(SerializeRequestError)?;
-    let builder = http::request::Builder::new();
-    let http_req = builder
-        .method("POST")
-        .uri(url)
-        .header("Content-type", "application/json")
-        .header("Authorization", auth)
-        .header("X-Replicache-RequestID", request_id)
-        .body(body)
-        .map_err(InvalidRequest)?;
+    let mut builder = http::request::Builder::new().method("POST").uri(url);
+    for (name, value) in
+        super::request_headers(Some("application/json"), auth, request_id, extra_headers)
+    {
+        builder = builder.header(name, value);
+    }
+    let http_req = builder.body(body).map_err(InvalidRequest)?;
     Ok(http_req)
 }
 
@@ -524,6 +923,8 @@ pub enum PullError {
     InvalidResponse(serde_json::error::Error),
     InvalidResponseJson(serde_wasm_bindgen::Error),
     SerializeRequestError(serde_json::error::Error),
+    // The error of a pull shared by several callers (see PullCoalescer).
+    Shared(Rc<PullError>),
     JsError(JsValue),
 }
 
@@ -541,6 +942,7 @@ impl From<serde_wasm_bindgen::Error> for PullError {
 
 pub struct JsPuller {
     puller: js_sys::Function,
+    retry_policy: RetryPolicy,
 }
 
 impl JsPuller {
@@ -549,6 +951,7 @@ impl JsPuller {
         let js_puller_func = js_puller_value.dyn_into()?;
         Ok(JsPuller {
             puller: js_puller_func,
+            retry_policy: RetryPolicy::default(),
         })
     }
 }
@@ -569,6 +972,7 @@ impl Puller for JsPuller {
         url: &str,
         auth: &str,
         request_id: &str,
+        extra_headers: &[(String, String)],
     ) -> Result<(Option<PullResponse>, HttpRequestInfo), PullError> {
         let PullRequest {
             client_id,
@@ -608,9 +1012,31 @@ impl Puller for JsPuller {
             #[serde(rename = "httpRequestInfo")]
             http_request_info: HttpRequestInfo,
         }
-        let res =
-            call_js_request::<Body, Result, PullError>(&self.puller, url, body, auth, request_id)
-                .await?;
+        // The JS puller reports the status it got rather than the response,
+        // so there is no Retry-After to honor. A fetch that fails outright
+        // rejects, which comes back as a JsError.
+        let body = &body;
+        let res = retry::retry(
+            &self.retry_policy,
+            || {
+                call_js_request::<_, Result, PullError>(
+                    &self.puller,
+                    url,
+                    body,
+                    auth,
+                    request_id,
+                    extra_headers,
+                )
+            },
+            |res| match res {
+                Ok(r) if retry::retryable_status_code(r.http_request_info.http_status_code) => {
+                    Outcome::Transient(None)
+                }
+                Err(PullError::JsError(_)) => Outcome::Transient(None),
+                _ => Outcome::Final,
+            },
+        )
+        .await?;
         Ok((res.response, res.http_request_info))
     }
 }
@@ -726,6 +1152,19 @@ mod tests {
                         req.header("X-Replicache-RequestID").unwrap().as_str(),
                         request_id
                     );
+                    assert_eq!(
+                        req.header("X-Replicache-Client-Version").unwrap().as_str(),
+                        CLIENT_VERSION
+                    );
+                    // Extra headers are sent, but cannot override Content-Type.
+                    assert_eq!(req.header("X-Tenant").unwrap().as_str(), "tenant");
+                    let content_types: Vec<_> = req
+                        .header("Content-Type")
+                        .unwrap()
+                        .iter()
+                        .map(|v| v.as_str())
+                        .collect();
+                    assert_eq!(vec!["application/json"], content_types);
                     assert_eq!(req.body_string().await?, *EXP_BODY);
                     Ok(Response::builder(status).body(Body::from_string(body.to_string())))
                 });
@@ -742,6 +1181,10 @@ mod tests {
                     &format!("http://{}{}", addr, path),
                     pull_auth,
                     request_id,
+                    &[
+                        (str!("X-Tenant"), str!("tenant")),
+                        (str!("Content-Type"), str!("text/plain")),
+                    ],
                 )
                 .await;
 
@@ -769,6 +1212,77 @@ mod tests {
         }
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
+    #[async_std::test]
+    async fn test_fetch_puller_retries() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let mut app = tide::new();
+        let server_hits = hits.clone();
+        app.at("/pull").post(move |mut req: tide::Request<()>| {
+            let hits = server_hits.clone();
+            async move {
+                req.body_string().await?;
+                // Unavailable the first time, then up.
+                Ok(match hits.fetch_add(1, Ordering::SeqCst) {
+                    0 => Response::builder(503).body(Body::from_string(str!("down"))),
+                    _ => Response::builder(200).body(Body::from_string(str!(
+                        r#"{"cookie": "1", "lastMutationID": 2, "patch": []}"#
+                    ))),
+                })
+            }
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = async_std::task::spawn_local(app.listen(listener));
+
+        let client = fetch::client::Client::new();
+        let puller = FetchPuller::new_with_retry_policy(
+            &client,
+            RetryPolicy {
+                max_attempts: 2,
+                base_delay_ms: 1,
+                max_delay_ms: 1,
+                jitter: false,
+            },
+        );
+        let pull_req = PullRequest {
+            client_id: str!("client_id"),
+            cookie: json!("cookie"),
+            last_mutation_id: 1,
+            pull_version: PULL_VERSION,
+            schema_version: str!(""),
+        };
+        let url = format!("http://{}/pull", addr);
+        let (resp, info) = puller
+            .pull(&pull_req, &url, "auth", "request_id", &[])
+            .await
+            .unwrap();
+        assert_eq!(2, hits.load(Ordering::SeqCst));
+        assert_eq!(200, info.http_status_code);
+        assert_eq!(2, resp.unwrap().last_mutation_id);
+
+        // Once max_attempts is used up the last failure is returned.
+        hits.store(0, Ordering::SeqCst);
+        let puller = FetchPuller::new_with_retry_policy(
+            &client,
+            RetryPolicy {
+                max_attempts: 1,
+                ..Default::default()
+            },
+        );
+        let (resp, info) = puller
+            .pull(&pull_req, &url, "auth", "request_id", &[])
+            .await
+            .unwrap();
+        assert_eq!(1, hits.load(Ordering::SeqCst));
+        assert_eq!(503, info.http_status_code);
+        assert_eq!(None, resp);
+        handle.cancel().await;
+    }
+
     macro_rules! map(
         () => (
             ::std::collections::HashMap::new()
@@ -869,6 +1383,8 @@ mod tests {
                     http_request_info: good_http_request_info.clone(),
                     sync_head: str!(""),
                     request_id: request_id.clone(),
+                    patch_summary: None,
+                    not_modified: false,
                 }),
             },
             Case {
@@ -888,6 +1404,8 @@ mod tests {
                     http_request_info: good_http_request_info.clone(),
                     sync_head: str!(""),
                     request_id: request_id.clone(),
+                    patch_summary: None,
+                    not_modified: false,
                 }),
             },
             Case {
@@ -907,6 +1425,8 @@ mod tests {
                     http_request_info: good_http_request_info.clone(),
                     sync_head: str!(""),
                     request_id: request_id.clone(),
+                    patch_summary: None,
+                    not_modified: false,
                 }),
             },
             Case {
@@ -923,6 +1443,8 @@ mod tests {
                     http_request_info: good_http_request_info.clone(),
                     sync_head: str!(""),
                     request_id: request_id.clone(),
+                    patch_summary: None,
+                    not_modified: false,
                 }),
             },
             Case {
@@ -942,6 +1464,8 @@ mod tests {
                     http_request_info: good_http_request_info.clone(),
                     sync_head: str!(""),
                     request_id: request_id.clone(),
+                    patch_summary: None,
+                    not_modified: false,
                 }),
             },
             // The patch, last_mutation_id, and cookie determine whether we write a new
@@ -960,6 +1484,8 @@ mod tests {
                     http_request_info: good_http_request_info.clone(),
                     sync_head: str!(""),
                     request_id: request_id.clone(),
+                    patch_summary: None,
+                    not_modified: true,
                 }),
             },
             Case {
@@ -980,6 +1506,8 @@ mod tests {
                     http_request_info: good_http_request_info.clone(),
                     sync_head: str!(""),
                     request_id: request_id.clone(),
+                    patch_summary: None,
+                    not_modified: false,
                 }),
             },
             Case {
@@ -1001,6 +1529,8 @@ mod tests {
                     http_request_info: good_http_request_info.clone(),
                     sync_head: str!(""),
                     request_id: request_id.clone(),
+                    patch_summary: None,
+                    not_modified: false,
                 }),
             },
             Case {
@@ -1022,6 +1552,8 @@ mod tests {
                     http_request_info: good_http_request_info.clone(),
                     sync_head: str!(""),
                     request_id: request_id.clone(),
+                    patch_summary: None,
+                    not_modified: false,
                 }),
             },
             Case {
@@ -1041,6 +1573,8 @@ mod tests {
                     http_request_info: good_http_request_info.clone(),
                     sync_head: str!(""),
                     request_id: request_id.clone(),
+                    patch_summary: None,
+                    not_modified: false,
                 }),
             },
             Case {
@@ -1060,6 +1594,8 @@ mod tests {
                     http_request_info: good_http_request_info.clone(),
                     sync_head: str!(""),
                     request_id: request_id.clone(),
+                    patch_summary: None,
+                    not_modified: false,
                 }),
             },
             Case {
@@ -1079,6 +1615,8 @@ mod tests {
                     http_request_info: good_http_request_info.clone(),
                     sync_head: str!(""),
                     request_id: request_id.clone(),
+                    patch_summary: None,
+                    not_modified: false,
                 }),
             },
             Case {
@@ -1097,6 +1635,8 @@ mod tests {
                     http_request_info: good_http_request_info.clone(),
                     sync_head: str!(""),
                     request_id: request_id.clone(),
+                    patch_summary: None,
+                    not_modified: false,
                 }),
             },
             Case {
@@ -1123,6 +1663,24 @@ mod tests {
             },
                     sync_head: str!(""),
                     request_id: request_id.clone(),
+                    patch_summary: None,
+                    not_modified: false,
+                }),
+            },
+            Case {
+                name: "pull 304s -> beginpull succeeds w/no synchead",
+                num_pending_mutations: 0,
+                pull_result: Err(str!("NotModified(304)")),
+                exp_new_sync_head: None,
+                exp_begin_try_pull_result: Ok(BeginTryPullResponse {
+                    http_request_info: HttpRequestInfo {
+                        error_message: str!(""),
+                        http_status_code: 304,
+                    },
+                    sync_head: str!(""),
+                    request_id: request_id.clone(),
+                    patch_summary: None,
+                    not_modified: true,
                 }),
             },
         ];
@@ -1195,6 +1753,7 @@ mod tests {
                 pull_url: pull_url.clone(),
                 pull_auth: pull_auth.clone(),
                 schema_version: schema_version.clone(),
+                ..Default::default()
             };
 
             let result = begin_pull(
@@ -1318,6 +1877,12 @@ mod tests {
                     result.request_id,
                     c.exp_begin_try_pull_result.as_ref().unwrap().request_id
                 );
+                assert_eq!(
+                    result.not_modified,
+                    c.exp_begin_try_pull_result.as_ref().unwrap().not_modified,
+                    "{}",
+                    c.name
+                );
             } else {
                 // use to_debug since some errors cannot be made PartialEq
                 assert_eq!(
@@ -1328,6 +1893,549 @@ mod tests {
         }
     }
 
+    #[async_std::test]
+    async fn test_begin_try_pull_dry_run() {
+        let store = dag::Store::new(Box::new(MemStore::new()));
+        let mut chain: Chain = vec![];
+        add_genesis(&mut chain, &store).await;
+        add_snapshot(&mut chain, &store, Some(vec![("foo", "\"bar\"")])).await;
+        let (base_last_mutation_id, base_cookie) = Commit::snapshot_meta_parts(&chain[1]).unwrap();
+        let main_head = chain.last().unwrap().chunk().hash().to_string();
+
+        let exp_pull_req = PullRequest {
+            client_id: str!("client_id"),
+            cookie: base_cookie,
+            last_mutation_id: base_last_mutation_id,
+            pull_version: PULL_VERSION,
+            schema_version: str!(""),
+        };
+        let fake_puller = FakePuller {
+            exp_pull_req: &exp_pull_req,
+            exp_pull_url: "pull_url",
+            exp_pull_auth: "pull_auth",
+            exp_request_id: "request_id",
+            resp: Some(PullResponse {
+                cookie: json!("new_cookie"),
+                last_mutation_id: base_last_mutation_id + 1,
+                patch: vec![
+                    Operation::Put {
+                        key: str!("new"),
+                        value: json!("value"),
+                    },
+                    Operation::Del { key: str!("foo") },
+                ],
+            }),
+            err: None,
+        };
+
+        let result = begin_pull(
+            str!("client_id"),
+            BeginTryPullRequest {
+                pull_url: str!("pull_url"),
+                pull_auth: str!("pull_auth"),
+                dry_run: true,
+                ..Default::default()
+            },
+            &fake_puller,
+            str!("request_id"),
+            &store,
+            LogContext::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!("", result.sync_head);
+        assert_eq!(
+            Some(patch::PatchSummary {
+                clear: false,
+                puts: vec![str!("new")],
+                dels: vec![str!("foo")],
+            }),
+            result.patch_summary
+        );
+
+        // Nothing was written.
+        let owned_read = store.read(LogContext::new()).await.unwrap();
+        let read = owned_read.read();
+        assert_eq!(None, read.get_head(SYNC_HEAD_NAME).await.unwrap());
+        assert_eq!(
+            Some(main_head),
+            read.get_head(DEFAULT_HEAD_NAME).await.unwrap()
+        );
+    }
+
+    #[async_std::test]
+    async fn test_begin_try_pull_resume() {
+        async fn test(patch_changed: bool) {
+            let store = dag::Store::new(Box::new(MemStore::new()));
+            let mut chain: Chain = vec![];
+            add_genesis(&mut chain, &store).await;
+            add_snapshot(&mut chain, &store, None).await;
+            let base_snapshot = chain[1].chunk().hash().to_string();
+            let (base_last_mutation_id, base_cookie) =
+                Commit::snapshot_meta_parts(&chain[1]).unwrap();
+
+            let patch: Vec<Operation> = (0..PULL_CHECKPOINT_OPS + 5)
+                .map(|i| Operation::Put {
+                    key: format!("k{}", i),
+                    value: json!(i),
+                })
+                .collect();
+            let cookie = json!("new_cookie");
+            let last_mutation_id = base_last_mutation_id + 1;
+
+            // Simulate a pull that was interrupted after its first checkpoint.
+            // The checkpoint also has a key the patch does not so we can tell
+            // whether it was resumed from.
+            let dag_write = store.write(LogContext::new()).await.unwrap();
+            let mut db_write = db::Write::new_snapshot(
+                Whence::Hash(base_snapshot.clone()),
+                last_mutation_id,
+                cookie.clone(),
+                dag_write,
+                HashMap::new(),
+            )
+            .await
+            .unwrap();
+            patch::apply(&mut db_write, &patch[..PULL_CHECKPOINT_OPS])
+                .await
+                .unwrap();
+            db_write
+                .put(LogContext::new(), b"checkpoint".to_vec(), b"true".to_vec())
+                .await
+                .unwrap();
+            let checkpoint = db_write.commit(PULL_PROGRESS_HEAD_NAME).await.unwrap();
+            save_pull_progress(
+                &store,
+                LogContext::new(),
+                PULL_PROGRESS_HEAD_NAME,
+                &PullProgress {
+                    base_snapshot: base_snapshot.clone(),
+                    last_mutation_id,
+                    cookie: cookie.clone(),
+                    patch_hash: Hash::of(&serde_json::to_vec(&patch).unwrap()).to_string(),
+                    applied: PULL_CHECKPOINT_OPS,
+                    checkpoint,
+                },
+            )
+            .await
+            .unwrap();
+
+            let mut resp_patch = patch.clone();
+            if patch_changed {
+                resp_patch.push(Operation::Del { key: str!("k0") });
+            }
+            let exp_pull_req = PullRequest {
+                client_id: str!("client_id"),
+                cookie: base_cookie,
+                last_mutation_id: base_last_mutation_id,
+                pull_version: PULL_VERSION,
+                schema_version: str!(""),
+            };
+            let fake_puller = FakePuller {
+                exp_pull_req: &exp_pull_req,
+                exp_pull_url: "pull_url",
+                exp_pull_auth: "pull_auth",
+                exp_request_id: "request_id",
+                resp: Some(PullResponse {
+                    cookie,
+                    last_mutation_id,
+                    patch: resp_patch,
+                }),
+                err: None,
+            };
+            let result = begin_pull(
+                str!("client_id"),
+                BeginTryPullRequest {
+                    pull_url: str!("pull_url"),
+                    pull_auth: str!("pull_auth"),
+                    ..Default::default()
+                },
+                &fake_puller,
+                str!("request_id"),
+                &store,
+                LogContext::new(),
+            )
+            .await
+            .unwrap();
+
+            let dag_read = store.read(LogContext::new()).await.unwrap();
+            assert_eq!(
+                None,
+                dag_read
+                    .read()
+                    .get_head(PULL_PROGRESS_HEAD_NAME)
+                    .await
+                    .unwrap()
+            );
+            let sync_head = Commit::from_hash(&result.sync_head, &dag_read.read())
+                .await
+                .unwrap();
+            assert_eq!(Some(base_snapshot.as_str()), sync_head.meta().basis_hash());
+            drop(sync_head);
+            let read = db::OwnedRead::from_whence(Whence::Hash(result.sync_head), dag_read)
+                .await
+                .unwrap();
+            let read = read.as_read();
+            assert_eq!(!patch_changed, read.has(b"checkpoint"));
+            assert_eq!(!patch_changed, read.has(b"k0"));
+            let last_key = format!("k{}", PULL_CHECKPOINT_OPS + 4);
+            assert!(read.has(last_key.as_bytes()));
+        }
+
+        test(false).await;
+        test(true).await;
+    }
+
+    #[async_std::test]
+    async fn test_begin_pull_clears_progress() {
+        use crate::sync::test_helpers::{MockPullResponse, MockServer};
+
+        let store = dag::Store::new(Box::new(MemStore::new()));
+        let mut chain: Chain = vec![];
+        add_genesis(&mut chain, &store).await;
+        add_snapshot(&mut chain, &store, None).await;
+        let base_snapshot = chain[1].chunk().hash().to_string();
+        let (last_mutation_id, cookie) = Commit::snapshot_meta_parts(&chain[1]).unwrap();
+
+        // A pull that is not modified, one that changes nothing and a dry
+        // run each clear progress left by an earlier, interrupted pull.
+        let server = MockServer::new();
+        server.add_pull_response(MockPullResponse::Status(304, str!("")));
+        server.add_pull_response(MockPullResponse::Ok(PullResponse {
+            cookie: cookie.clone(),
+            last_mutation_id,
+            patch: vec![],
+        }));
+        server.add_pull_response(MockPullResponse::Ok(PullResponse {
+            cookie: json!("new_cookie"),
+            last_mutation_id,
+            patch: vec![],
+        }));
+        for dry_run in &[false, false, true] {
+            save_pull_progress(
+                &store,
+                LogContext::new(),
+                PULL_PROGRESS_HEAD_NAME,
+                &PullProgress {
+                    base_snapshot: base_snapshot.clone(),
+                    last_mutation_id,
+                    cookie: json!("stale_cookie"),
+                    patch_hash: str!(""),
+                    applied: 0,
+                    checkpoint: base_snapshot.clone(),
+                },
+            )
+            .await
+            .unwrap();
+            let result = begin_pull(
+                str!("client_id"),
+                BeginTryPullRequest {
+                    pull_url: str!("pull_url"),
+                    pull_auth: str!("pull_auth"),
+                    dry_run: *dry_run,
+                    ..Default::default()
+                },
+                &server,
+                str!("request_id"),
+                &store,
+                LogContext::new(),
+            )
+            .await
+            .unwrap();
+            assert_eq!("", result.sync_head);
+            let dag_read = store.read(LogContext::new()).await.unwrap();
+            assert_eq!(
+                None,
+                dag_read
+                    .read()
+                    .get_head(PULL_PROGRESS_HEAD_NAME)
+                    .await
+                    .unwrap()
+            );
+        }
+        assert_eq!(3, server.pulls().len());
+    }
+
+    #[async_std::test]
+    async fn test_begin_pull_discards_progress_on_failure() {
+        use crate::kv::memstore::MemStoreOptions;
+        use crate::sync::test_helpers::{MockPullResponse, MockServer};
+
+        // The store takes the first checkpoint but not the sync head commit,
+        // whose map has a value bigger than max_value_bytes.
+        let store = dag::Store::new(Box::new(MemStore::new_with_options(MemStoreOptions {
+            max_value_bytes: Some(200_000),
+            ..Default::default()
+        })));
+        let mut chain: Chain = vec![];
+        add_genesis(&mut chain, &store).await;
+        add_snapshot(&mut chain, &store, None).await;
+        let (last_mutation_id, _) = Commit::snapshot_meta_parts(&chain[1]).unwrap();
+
+        let mut patch: Vec<Operation> = (0..PULL_CHECKPOINT_OPS)
+            .map(|i| Operation::Put {
+                key: format!("k{}", i),
+                value: json!(i),
+            })
+            .collect();
+        patch.push(Operation::Put {
+            key: str!("big"),
+            value: json!("x".repeat(300_000)),
+        });
+        let server = MockServer::new();
+        server.add_pull_response(MockPullResponse::Ok(PullResponse {
+            cookie: json!("new_cookie"),
+            last_mutation_id,
+            patch,
+        }));
+        let res = begin_pull(
+            str!("client_id"),
+            BeginTryPullRequest {
+                pull_url: str!("pull_url"),
+                pull_auth: str!("pull_auth"),
+                ..Default::default()
+            },
+            &server,
+            str!("request_id"),
+            &store,
+            LogContext::new(),
+        )
+        .await;
+        assert!(matches!(res, Err(BeginTryPullError::CommitError(_))));
+
+        // Neither the sync head nor the staged checkpoint is left behind.
+        let dag_read = store.read(LogContext::new()).await.unwrap();
+        let read = dag_read.read();
+        assert_eq!(None, read.get_head(SYNC_HEAD_NAME).await.unwrap());
+        assert_eq!(None, read.get_head(PULL_PROGRESS_HEAD_NAME).await.unwrap());
+    }
+
+    #[async_std::test]
+    async fn test_begin_pull_ping() {
+        // PingPuller answers pings as told and fails every pull with a 404
+        // so a test can tell whether the pull was attempted.
+        enum Ping {
+            Hang,
+            Fail,
+            Status(u16),
+            Unsupported,
+        }
+        struct PingPuller(Ping);
+
+        #[async_trait(?Send)]
+        impl Puller for PingPuller {
+            async fn pull(
+                &self,
+                _: &PullRequest,
+                _: &str,
+                _: &str,
+                _: &str,
+                _: &[(String, String)],
+            ) -> Result<(Option<PullResponse>, HttpRequestInfo), PullError> {
+                Ok((
+                    None,
+                    HttpRequestInfo {
+                        http_status_code: 404,
+                        error_message: str!(""),
+                    },
+                ))
+            }
+
+            async fn ping(
+                &self,
+                _: &str,
+                _: &str,
+                _: &str,
+                _: &[(String, String)],
+            ) -> Result<Option<HttpRequestInfo>, PullError> {
+                match self.0 {
+                    Ping::Hang => {
+                        async_std::task::sleep(Duration::from_secs(10)).await;
+                        unreachable!();
+                    }
+                    Ping::Fail => Err(PullError::InvalidResponse(
+                        serde_json::from_str::<()>("").unwrap_err(),
+                    )),
+                    Ping::Status(http_status_code) => Ok(Some(HttpRequestInfo {
+                        http_status_code,
+                        error_message: str!(""),
+                    })),
+                    Ping::Unsupported => Ok(None),
+                }
+            }
+        }
+
+        let store = dag::Store::new(Box::new(MemStore::new()));
+        let mut chain: Chain = vec![];
+        add_genesis(&mut chain, &store).await;
+        add_snapshot(&mut chain, &store, None).await;
+
+        let begin = |ping: Ping, ping_timeout_ms: Option<u64>| {
+            let store = &store;
+            async move {
+                begin_pull(
+                    str!("client_id"),
+                    BeginTryPullRequest {
+                        pull_url: str!("pull_url"),
+                        pull_auth: str!("pull_auth"),
+                        ping_timeout_ms,
+                        ..Default::default()
+                    },
+                    &PingPuller(ping),
+                    str!("request_id"),
+                    store,
+                    LogContext::new(),
+                )
+                .await
+            }
+        };
+        let pulled = |r: Result<BeginTryPullResponse, BeginTryPullError>| {
+            r.unwrap().http_request_info.http_status_code == 404
+        };
+        let unreachable = |r: Result<BeginTryPullResponse, BeginTryPullError>| {
+            matches!(r, Err(BeginTryPullError::Unreachable(_)))
+        };
+
+        // Without a timeout the ping is skipped.
+        assert!(pulled(begin(Ping::Hang, None).await));
+        assert!(unreachable(begin(Ping::Hang, Some(10)).await));
+        assert!(unreachable(begin(Ping::Fail, Some(1000)).await));
+        assert!(unreachable(begin(Ping::Status(503), Some(1000)).await));
+        // Endpoints that only take POST still count as up.
+        assert!(pulled(begin(Ping::Status(405), Some(1000)).await));
+        assert!(pulled(begin(Ping::Unsupported, Some(1000)).await));
+    }
+
+    #[async_std::test]
+    async fn test_begin_pull_timeout() {
+        use std::time::Instant;
+
+        // Never answers.
+        struct HangingPuller;
+
+        #[async_trait(?Send)]
+        impl Puller for HangingPuller {
+            async fn pull(
+                &self,
+                _: &PullRequest,
+                _: &str,
+                _: &str,
+                _: &str,
+                _: &[(String, String)],
+            ) -> Result<(Option<PullResponse>, HttpRequestInfo), PullError> {
+                futures::future::pending().await
+            }
+        }
+
+        let store = dag::Store::new(Box::new(MemStore::new()));
+        let mut chain: Chain = vec![];
+        add_genesis(&mut chain, &store).await;
+        add_snapshot(&mut chain, &store, None).await;
+
+        let start = Instant::now();
+        let res = begin_pull(
+            str!("client_id"),
+            BeginTryPullRequest {
+                pull_url: str!("pull_url"),
+                pull_auth: str!("pull_auth"),
+                timeout_ms: Some(30),
+                ..Default::default()
+            },
+            &HangingPuller,
+            str!("request_id"),
+            &store,
+            LogContext::new(),
+        )
+        .await;
+        let elapsed = start.elapsed();
+        assert!(matches!(res, Err(BeginTryPullError::PullTimeout(30))));
+        assert!(elapsed >= Duration::from_millis(30), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+    }
+
+    #[async_std::test]
+    async fn test_mock_server() {
+        use crate::sync::test_helpers::{MockPullResponse, MockServer};
+
+        let store = dag::Store::new(Box::new(MemStore::new()));
+        let mut chain: Chain = vec![];
+        add_genesis(&mut chain, &store).await;
+        add_snapshot(&mut chain, &store, None).await;
+        add_local(&mut chain, &store).await;
+        let server = MockServer::new();
+
+        // Pushes are recorded as sent.
+        push(
+            "push_request_id",
+            &store,
+            LogContext::new(),
+            str!("client_id"),
+            &server,
+            TryPushRequest {
+                push_url: str!("push_url"),
+                push_auth: str!("push_auth"),
+                extra_headers: vec![(str!("X-Tenant"), str!("tenant"))],
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let pushes = server.pushes();
+        assert_eq!(1, pushes.len());
+        assert_eq!("push_url", pushes[0].url);
+        assert_eq!(
+            vec![(str!("X-Tenant"), str!("tenant"))],
+            pushes[0].extra_headers
+        );
+        let push_req: PushRequest = serde_json::from_str(&pushes[0].body).unwrap();
+        assert_eq!(1, push_req.mutations.len());
+        assert_eq!(chain[2].mutation_id(), push_req.mutations[0].id);
+
+        // Pulls get the scripted responses in order, then a 500.
+        server.add_pull_response(MockPullResponse::Ok(PullResponse {
+            cookie: json!("new_cookie"),
+            last_mutation_id: chain[2].mutation_id(),
+            patch: vec![Operation::Put {
+                key: str!("pulled"),
+                value: json!("value"),
+            }],
+        }));
+        server.add_pull_response(MockPullResponse::Status(403, str!("forbidden")));
+        let begin = || {
+            begin_pull(
+                str!("client_id"),
+                BeginTryPullRequest {
+                    pull_url: str!("pull_url"),
+                    pull_auth: str!("pull_auth"),
+                    extra_headers: vec![(str!("X-Tenant"), str!("tenant"))],
+                    ..Default::default()
+                },
+                &server,
+                str!("pull_request_id"),
+                &store,
+                LogContext::new(),
+            )
+        };
+        let resp = begin().await.unwrap();
+        assert!(!resp.sync_head.is_empty());
+        let resp = begin().await.unwrap();
+        assert_eq!(403, resp.http_request_info.http_status_code);
+        assert_eq!("forbidden", resp.http_request_info.error_message);
+        let resp = begin().await.unwrap();
+        assert_eq!(500, resp.http_request_info.http_status_code);
+
+        let pulls = server.pulls();
+        assert_eq!(3, pulls.len());
+        assert_eq!("pull_auth", pulls[0].auth);
+        assert_eq!("pull_request_id", pulls[0].request_id);
+        assert_eq!(
+            vec![(str!("X-Tenant"), str!("tenant"))],
+            pulls[0].extra_headers
+        );
+        let pull_req: serde_json::Value = serde_json::from_str(&pulls[0].body).unwrap();
+        assert_eq!(json!(chain[1].mutation_id()), pull_req["lastMutationID"]);
+    }
+
     pub struct FakePuller<'a> {
         exp_pull_req: &'a PullRequest,
         exp_pull_url: &'a str,
@@ -1353,6 +2461,7 @@ mod tests {
             url: &str,
             auth: &str,
             request_id: &str,
+            _extra_headers: &[(String, String)],
         ) -> Result<(Option<PullResponse>, HttpRequestInfo), PullError> {
             assert_eq!(self.exp_pull_req, pull_req);
             assert_eq!(self.exp_pull_url, url);
@@ -1365,6 +2474,10 @@ mod tests {
                         http_status_code: http::StatusCode::INTERNAL_SERVER_ERROR.into(),
                         error_message: str!("Fetch not OK"),
                     },
+                    "NotModified(304)" => HttpRequestInfo {
+                        http_status_code: http::StatusCode::NOT_MODIFIED.into(),
+                        error_message: str!(""),
+                    },
                     _ => panic!("not implemented"),
                 },
                 None => HttpRequestInfo {
@@ -1494,6 +2607,7 @@ mod tests {
             let req = MaybeEndTryPullRequest {
                 request_id: str!("request_id"),
                 sync_head: sync_head.clone(),
+                sync_head_name: None,
             };
             let result = maybe_end_try_pull(&store, LogContext::new(), req).await;
 
@@ -1552,6 +2666,98 @@ mod tests {
         }
     }
 
+    #[async_std::test]
+    async fn test_sync_head_name() {
+        let store = dag::Store::new(Box::new(MemStore::new()));
+        let mut chain: Chain = vec![];
+        add_genesis(&mut chain, &store).await;
+        add_snapshot(&mut chain, &store, None).await;
+        let (base_last_mutation_id, base_cookie) = Commit::snapshot_meta_parts(&chain[1]).unwrap();
+
+        let exp_pull_req = PullRequest {
+            client_id: str!("client_id"),
+            cookie: base_cookie,
+            last_mutation_id: base_last_mutation_id,
+            pull_version: PULL_VERSION,
+            schema_version: str!(""),
+        };
+        let fake_puller = FakePuller {
+            exp_pull_req: &exp_pull_req,
+            exp_pull_url: "pull_url",
+            exp_pull_auth: "pull_auth",
+            exp_request_id: "request_id",
+            resp: Some(PullResponse {
+                cookie: json!("new_cookie"),
+                last_mutation_id: base_last_mutation_id,
+                patch: vec![Operation::Put {
+                    key: str!("org"),
+                    value: json!(true),
+                }],
+            }),
+            err: None,
+        };
+        let begin_req = |sync_head_name: Option<&str>| BeginTryPullRequest {
+            pull_url: str!("pull_url"),
+            pull_auth: str!("pull_auth"),
+            sync_head_name: sync_head_name.map(str::to_string),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            begin_pull(
+                str!("client_id"),
+                begin_req(Some(DEFAULT_HEAD_NAME)),
+                &fake_puller,
+                str!("request_id"),
+                &store,
+                LogContext::new(),
+            )
+            .await,
+            Err(BeginTryPullError::InvalidSyncHeadName(_))
+        ));
+
+        let result = begin_pull(
+            str!("client_id"),
+            begin_req(Some("org")),
+            &fake_puller,
+            str!("request_id"),
+            &store,
+            LogContext::new(),
+        )
+        .await
+        .unwrap();
+        {
+            let owned_read = store.read(LogContext::new()).await.unwrap();
+            let read = owned_read.read();
+            assert_eq!(
+                Some(&result.sync_head),
+                read.get_head("org").await.unwrap().as_ref()
+            );
+            assert_eq!(None, read.get_head(SYNC_HEAD_NAME).await.unwrap());
+        }
+
+        // Ending the pull on the default head does not see it.
+        let end_req = |sync_head_name: Option<&str>| MaybeEndTryPullRequest {
+            request_id: str!("request_id"),
+            sync_head: result.sync_head.clone(),
+            sync_head_name: sync_head_name.map(str::to_string),
+        };
+        assert!(matches!(
+            maybe_end_try_pull(&store, LogContext::new(), end_req(None)).await,
+            Err(MaybeEndTryPullError::MissingSyncHead)
+        ));
+        maybe_end_try_pull(&store, LogContext::new(), end_req(Some("org")))
+            .await
+            .unwrap();
+        let owned_read = store.read(LogContext::new()).await.unwrap();
+        let read = owned_read.read();
+        assert_eq!(None, read.get_head("org").await.unwrap());
+        assert_eq!(
+            Some(result.sync_head),
+            read.get_head(DEFAULT_HEAD_NAME).await.unwrap()
+        );
+    }
+
     #[async_std::test]
     async fn test_changed_keys() {
         struct IndexDef<'a> {
@@ -1620,6 +2826,7 @@ mod tests {
                 pull_url: pull_url.clone(),
                 pull_auth: pull_auth.clone(),
                 schema_version: schema_version.clone(),
+                ..Default::default()
             };
 
             let pull_result = begin_pull(
@@ -1636,6 +2843,7 @@ mod tests {
             let req = MaybeEndTryPullRequest {
                 request_id: request_id.clone(),
                 sync_head: pull_result.sync_head.clone(),
+                sync_head_name: None,
             };
             let result = maybe_end_try_pull(&store, LogContext::new(), req)
                 .await
@@ -3,10 +3,12 @@ use super::{HttpRequestInfo, TryPushError, TryPushRequest};
 #[cfg(not(target_arch = "wasm32"))]
 use crate::fetch;
 use crate::fetch::errors::FetchError;
+use crate::fetch::retry::{self, Outcome, RetryPolicy};
 use crate::util::rlog;
 use crate::{dag, db, util::rlog::LogContext};
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 #[cfg(not(target_arch = "wasm32"))]
 use str_macro::str;
 use wasm_bindgen::{JsCast, JsValue};
@@ -58,18 +60,30 @@ pub trait Pusher {
         push_url: &str,
         push_auth: &str,
         request_id: &str,
+        extra_headers: &[(String, String)],
     ) -> Result<HttpRequestInfo, PushError>;
 }
 
 #[cfg(not(target_arch = "wasm32"))]
 pub struct FetchPusher<'a> {
-    fetch_client: &'a fetch::client::Client,
+    fetch_client: &'a dyn fetch::HttpClient,
+    retry_policy: RetryPolicy,
 }
 
 #[cfg(not(target_arch = "wasm32"))]
 impl FetchPusher<'_> {
-    pub fn new(fetch_client: &fetch::client::Client) -> FetchPusher {
-        FetchPusher { fetch_client }
+    pub fn new(fetch_client: &dyn fetch::HttpClient) -> FetchPusher<'_> {
+        FetchPusher::new_with_retry_policy(fetch_client, RetryPolicy::default())
+    }
+
+    pub fn new_with_retry_policy(
+        fetch_client: &dyn fetch::HttpClient,
+        retry_policy: RetryPolicy,
+    ) -> FetchPusher<'_> {
+        FetchPusher {
+            fetch_client,
+            retry_policy,
+        }
     }
 }
 
@@ -83,21 +97,23 @@ impl Pusher for FetchPusher<'_> {
     // A failed HTTP response (non 200) is not an error. In that case we get
     // `None` for the `PushResponse`. We get errors for a few non HTTP related
     // reasons such as if we fail to create a Request object, the call to fetch
-    // fails or the response is not the expected JSON format.
+    // fails or the response is not the expected JSON format. Network errors
+    // and 5xx and 429 responses are retried first (see request_with_retry).
     async fn push(
         &self,
         push_req: &PushRequest,
         push_url: &str,
         push_auth: &str,
         request_id: &str,
+        extra_headers: &[(String, String)],
     ) -> Result<HttpRequestInfo, PushError> {
         use PushError::*;
-        let http_req = new_push_http_request(push_req, push_url, push_auth, request_id)?;
-        let http_resp: http::Response<String> = self
-            .fetch_client
-            .request(http_req)
-            .await
-            .map_err(FetchFailed)?;
+        let http_req =
+            new_push_http_request(push_req, push_url, push_auth, request_id, extra_headers)?;
+        let http_resp: http::Response<String> =
+            retry::request_with_retry(self.fetch_client, http_req, &self.retry_policy)
+                .await
+                .map_err(FetchFailed)?;
         let ok = http_resp.status() == http::StatusCode::OK;
         let http_request_info = HttpRequestInfo {
             http_status_code: http_resp.status().into(),
@@ -113,13 +129,17 @@ impl Pusher for FetchPusher<'_> {
 
 pub struct JsPusher {
     pusher: js_sys::Function,
+    retry_policy: RetryPolicy,
 }
 
 impl JsPusher {
     pub fn new(v: JsValue) -> Result<JsPusher, JsValue> {
         let js_val = js_sys::Reflect::get(&v, &JsValue::from_str("pusher"))?;
         let js_func = js_val.dyn_into()?;
-        Ok(JsPusher { pusher: js_func })
+        Ok(JsPusher {
+            pusher: js_func,
+            retry_policy: RetryPolicy::default(),
+        })
     }
 }
 
@@ -131,6 +151,7 @@ impl Pusher for JsPusher {
         url: &str,
         auth: &str,
         request_id: &str,
+        extra_headers: &[(String, String)],
     ) -> Result<HttpRequestInfo, PushError> {
         let PushRequest {
             client_id,
@@ -159,12 +180,28 @@ impl Pusher for JsPusher {
             schema_version,
         };
 
-        let res = call_js_request::<Body, HttpRequestInfo, PushError>(
-            &self.pusher,
-            url,
-            body,
-            auth,
-            request_id,
+        // As for JsPuller, there is no Retry-After to honor and a fetch that
+        // fails outright comes back as a JsError.
+        let body = &body;
+        let res = retry::retry(
+            &self.retry_policy,
+            || {
+                call_js_request::<_, HttpRequestInfo, PushError>(
+                    &self.pusher,
+                    url,
+                    body,
+                    auth,
+                    request_id,
+                    extra_headers,
+                )
+            },
+            |res| match res {
+                Ok(info) if retry::retryable_status_code(info.http_status_code) => {
+                    Outcome::Transient(None)
+                }
+                Err(PushError::JsError(_)) => Outcome::Transient(None),
+                _ => Outcome::Final,
+            },
         )
         .await?;
         Ok(res)
@@ -191,18 +228,20 @@ fn new_push_http_request(
     push_url: &str,
     push_auth: &str,
     request_id: &str,
+    extra_headers: &[(String, String)],
 ) -> Result<http::Request<String>, PushError> {
     use PushError::*;
     let body = serde_json::to_string(push_req).map_err(SerializePushError)?;
-    let builder = http::request::Builder::new();
-    let http_req = builder
-        .method("POST")
-        .uri(push_url)
-        .header("Content-type", "application/json")
-        .header("Authorization", push_auth)
-        .header("X-Replicache-RequestID", request_id)
-        .body(body)
-        .map_err(InvalidRequest)?;
+    let mut builder = http::request::Builder::new().method("POST").uri(push_url);
+    for (name, value) in super::request_headers(
+        Some("application/json"),
+        push_auth,
+        request_id,
+        extra_headers,
+    ) {
+        builder = builder.header(name, value);
+    }
+    let http_req = builder.body(body).map_err(InvalidRequest)?;
     Ok(http_req)
 }
 
@@ -255,30 +294,51 @@ pub async fn push(
     // want tail first (in mutation id order).
     pending.reverse();
 
+    // Windows are sent one at a time and only once the server has accepted
+    // the previous one, so that it sees the mutations in order and a failure
+    // stops the push rather than leaving a gap.
+    let window_size = match req.window_size {
+        Some(n) if n > 0 => n,
+        _ => pending.len().max(1),
+    };
     let mut http_request_info: Option<HttpRequestInfo> = None;
-    if !pending.is_empty() {
+    for (i, window) in pending.chunks(window_size).enumerate() {
         let mut push_mutations: Vec<Mutation> = Vec::new();
-        for commit in pending.iter() {
+        for commit in window.iter() {
             match commit.meta().typed() {
                 db::MetaTyped::Local(lm) => push_mutations.push(lm.into()),
                 _ => return Err(InternalNonLocalPendingCommit),
             }
         }
         let push_req = PushRequest {
-            client_id,
+            client_id: client_id.clone(),
             mutations: push_mutations,
             push_version: PUSH_VERSION,
-            schema_version: req.schema_version,
+            schema_version: req.schema_version.clone(),
         };
-        debug!(lc, "Starting push...");
+        debug!(lc, "Starting push of window {}...", i);
         let push_timer = rlog::Timer::new();
-        let req_info = pusher
-            .push(&push_req, &req.push_url, &req.push_auth, request_id)
-            .await
-            .map_err(PushFailed)?;
-        http_request_info = Some(req_info);
-
+        let push = pusher.push(
+            &push_req,
+            &req.push_url,
+            &req.push_auth,
+            request_id,
+            &req.extra_headers,
+        );
+        let req_info = match req.timeout_ms {
+            None => push.await,
+            Some(ms) => async_std::future::timeout(Duration::from_millis(ms), push)
+                .await
+                .map_err(|_| PushTimeout(ms))?,
+        }
+        .map_err(PushFailed)?;
         debug!(lc, "...Push complete in {}ms", push_timer.elapsed_ms());
+
+        let ok = req_info.http_status_code == u16::from(http::StatusCode::OK);
+        http_request_info = Some(req_info);
+        if !ok {
+            break;
+        }
     }
 
     Ok(http_request_info)
@@ -379,6 +439,19 @@ mod tests {
                         req.header("X-Replicache-RequestID").unwrap().as_str(),
                         request_id
                     );
+                    assert_eq!(
+                        req.header("X-Replicache-Client-Version").unwrap().as_str(),
+                        CLIENT_VERSION
+                    );
+                    // Extra headers are sent, but cannot override Content-Type.
+                    assert_eq!(req.header("X-Tenant").unwrap().as_str(), "tenant");
+                    let content_types: Vec<_> = req
+                        .header("Content-Type")
+                        .unwrap()
+                        .iter()
+                        .map(|v| v.as_str())
+                        .collect();
+                    assert_eq!(vec!["application/json"], content_types);
                     assert_eq!(req.body_string().await?, *EXP_BODY);
                     Ok(Response::builder(status).body(Body::from_string(body.to_string())))
                 });
@@ -395,6 +468,10 @@ mod tests {
                     &format!("http://{}{}", addr, path),
                     batch_push_auth,
                     request_id,
+                    &[
+                        (str!("X-Tenant"), str!("tenant")),
+                        (str!("Content-Type"), str!("text/plain")),
+                    ],
                 )
                 .await;
 
@@ -412,6 +489,61 @@ mod tests {
         }
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
+    #[async_std::test]
+    async fn test_fetch_pusher_retries() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let mut app = tide::new();
+        let server_hits = hits.clone();
+        app.at("/push").post(move |mut req: tide::Request<()>| {
+            let hits = server_hits.clone();
+            async move {
+                req.body_string().await?;
+                // Rate limited the first time, then accepted.
+                Ok(match hits.fetch_add(1, Ordering::SeqCst) {
+                    0 => Response::builder(429).body(Body::from_string(str!("slow down"))),
+                    _ => Response::builder(200).body(Body::from_string(str!("{}"))),
+                })
+            }
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = async_std::task::spawn_local(app.listen(listener));
+
+        let client = fetch::client::Client::new();
+        let pusher = FetchPusher::new_with_retry_policy(
+            &client,
+            RetryPolicy {
+                max_attempts: 2,
+                base_delay_ms: 1,
+                max_delay_ms: 1,
+                jitter: false,
+            },
+        );
+        let push_req = PushRequest {
+            client_id: str!("client_id"),
+            mutations: vec![],
+            push_version: PUSH_VERSION,
+            schema_version: str!(""),
+        };
+        let info = pusher
+            .push(
+                &push_req,
+                &format!("http://{}/push", addr),
+                "auth",
+                "request_id",
+                &[],
+            )
+            .await
+            .unwrap();
+        assert_eq!(2, hits.load(Ordering::SeqCst));
+        assert_eq!(200, info.http_status_code);
+        handle.cancel().await;
+    }
+
     pub struct FakePusher<'a> {
         exp_push: bool,
         exp_push_req: Option<&'a push::PushRequest>,
@@ -430,6 +562,7 @@ mod tests {
             push_url: &str,
             push_auth: &str,
             request_id: &str,
+            _extra_headers: &[(String, String)],
         ) -> Result<HttpRequestInfo, push::PushError> {
             assert!(self.exp_push);
 
@@ -651,6 +784,7 @@ mod tests {
                     push_url: push_url.clone(),
                     push_auth: push_auth.clone(),
                     schema_version: push_schema_version.clone(),
+                    ..Default::default()
                 },
             )
             .await
@@ -659,4 +793,132 @@ mod tests {
             assert_eq!(batch_push_info, c.exp_batch_push_info, "name: {}", c.name);
         }
     }
+
+    #[async_std::test]
+    async fn test_try_push_windows() {
+        use std::cell::RefCell;
+
+        // Records the mutation ids of each push and fails the push numbered
+        // fail_at.
+        struct WindowPusher {
+            pushed: RefCell<Vec<Vec<u64>>>,
+            fail_at: Option<usize>,
+        }
+
+        #[async_trait(?Send)]
+        impl push::Pusher for WindowPusher {
+            async fn push(
+                &self,
+                push_req: &push::PushRequest,
+                _push_url: &str,
+                _push_auth: &str,
+                _request_id: &str,
+                _extra_headers: &[(String, String)],
+            ) -> Result<HttpRequestInfo, push::PushError> {
+                let mut pushed = self.pushed.borrow_mut();
+                pushed.push(push_req.mutations.iter().map(|m| m.id).collect());
+                let status = match self.fail_at {
+                    Some(i) if i + 1 == pushed.len() => 500,
+                    _ => 200,
+                };
+                Ok(HttpRequestInfo {
+                    http_status_code: status,
+                    error_message: str!(""),
+                })
+            }
+        }
+
+        let store = dag::Store::new(Box::new(MemStore::new()));
+        let mut chain: Chain = vec![];
+        add_genesis(&mut chain, &store).await;
+        for _ in 0..5 {
+            add_local(&mut chain, &store).await;
+        }
+
+        let push = |window_size: Option<usize>, fail_at: Option<usize>| {
+            let store = &store;
+            async move {
+                let pusher = WindowPusher {
+                    pushed: RefCell::new(vec![]),
+                    fail_at,
+                };
+                let info = super::push(
+                    "request_id",
+                    store,
+                    LogContext::new(),
+                    str!("client_id"),
+                    &pusher,
+                    TryPushRequest {
+                        push_url: str!("push_url"),
+                        push_auth: str!("push_auth"),
+                        window_size,
+                        ..Default::default()
+                    },
+                )
+                .await
+                .unwrap()
+                .unwrap();
+                (info.http_status_code, pusher.pushed.into_inner())
+            }
+        };
+
+        assert_eq!((200, vec![vec![1, 2, 3, 4, 5]]), push(None, None).await);
+        assert_eq!((200, vec![vec![1, 2, 3, 4, 5]]), push(Some(0), None).await);
+        assert_eq!(
+            (200, vec![vec![1, 2], vec![3, 4], vec![5]]),
+            push(Some(2), None).await
+        );
+        // A window the server rejects ends the push.
+        assert_eq!(
+            (500, vec![vec![1, 2], vec![3, 4]]),
+            push(Some(2), Some(1)).await
+        );
+    }
+
+    #[async_std::test]
+    async fn test_push_timeout() {
+        use std::time::Instant;
+
+        // Never answers.
+        struct HangingPusher;
+
+        #[async_trait(?Send)]
+        impl push::Pusher for HangingPusher {
+            async fn push(
+                &self,
+                _: &push::PushRequest,
+                _: &str,
+                _: &str,
+                _: &str,
+                _: &[(String, String)],
+            ) -> Result<HttpRequestInfo, push::PushError> {
+                futures::future::pending().await
+            }
+        }
+
+        let store = dag::Store::new(Box::new(MemStore::new()));
+        let mut chain: Chain = vec![];
+        add_genesis(&mut chain, &store).await;
+        add_local(&mut chain, &store).await;
+
+        let start = Instant::now();
+        let res = super::push(
+            "request_id",
+            &store,
+            LogContext::new(),
+            str!("client_id"),
+            &HangingPusher,
+            TryPushRequest {
+                push_url: str!("push_url"),
+                push_auth: str!("push_auth"),
+                timeout_ms: Some(30),
+                ..Default::default()
+            },
+        )
+        .await;
+        let elapsed = start.elapsed();
+        assert!(matches!(res, Err(TryPushError::PushTimeout(30))));
+        assert!(elapsed >= Duration::from_millis(30), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+    }
 }
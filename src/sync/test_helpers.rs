@@ -3,7 +3,15 @@ use crate::dag;
 use crate::db;
 use crate::db::test_helpers::*;
 use crate::db::{Commit, Whence};
+use crate::fetch::errors::FetchError;
+use crate::fetch::HttpClient;
+use crate::kv;
 use crate::util::rlog;
+use crate::util::rlog::LogContext;
+use async_trait::async_trait;
+use http::{Request, Response};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
 use str_macro::str;
 
 // See db::test_helpers for add_local, add_snapshot, etc. We can't put add_local_rebase
@@ -57,3 +65,334 @@ pub async fn add_sync_snapshot<'a>(
 
     sync_chain
 }
+
+// MockServer is an in-process stand-in for the pull and push endpoints, so
+// that the pull, apply and push loop can be tested without fetch. Pulls are
+// answered from a script of responses, in order, and every pull and push is
+// recorded so that tests can assert on exactly what the client sent.
+//
+// It can be used directly as the Puller and Pusher, or as the HttpClient of
+// a FetchPuller and FetchPusher (at PULL_URL and PUSH_URL) to also cover the
+// HTTP requests they build.
+#[derive(Default)]
+pub struct MockServer {
+    pull_script: RefCell<VecDeque<MockPullResponse>>,
+    push_status: Cell<Option<u16>>,
+    pulls: RefCell<Vec<MockRequest>>,
+    pushes: RefCell<Vec<MockRequest>>,
+}
+
+pub enum MockPullResponse {
+    Ok(PullResponse),
+    // An HTTP error with the given status and body.
+    Status(u16, String),
+}
+
+// A request as it would have been sent over HTTP. body is the JSON body.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MockRequest {
+    pub url: String,
+    pub auth: String,
+    pub request_id: String,
+    pub extra_headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl MockServer {
+    pub const PULL_URL: &'static str = "http://mock-server/pull";
+    pub const PUSH_URL: &'static str = "http://mock-server/push";
+
+    pub fn new() -> MockServer {
+        MockServer::default()
+    }
+
+    // Queues a response for a later pull. Pulls beyond the end of the script
+    // get a 500.
+    pub fn add_pull_response(&self, response: MockPullResponse) {
+        self.pull_script.borrow_mut().push_back(response);
+    }
+
+    // Sets the status pushes are answered with, 200 by default.
+    pub fn set_push_status(&self, status: u16) {
+        self.push_status.set(Some(status));
+    }
+
+    pub fn pulls(&self) -> Vec<MockRequest> {
+        self.pulls.borrow().clone()
+    }
+
+    pub fn pushes(&self) -> Vec<MockRequest> {
+        self.pushes.borrow().clone()
+    }
+
+    // Pops the next scripted pull response as a status and either a response
+    // or an error message.
+    fn next_pull(&self) -> (u16, Result<PullResponse, String>) {
+        match self.pull_script.borrow_mut().pop_front() {
+            Some(MockPullResponse::Ok(resp)) => (200, Ok(resp)),
+            Some(MockPullResponse::Status(status, body)) => (status, Err(body)),
+            None => (500, Err(str!("no scripted pull response"))),
+        }
+    }
+
+    fn push_result(&self) -> (u16, String) {
+        match self.push_status.get().unwrap_or(200) {
+            200 => (200, str!("")),
+            status => (status, str!("push failed")),
+        }
+    }
+
+    fn record(
+        requests: &RefCell<Vec<MockRequest>>,
+        url: &str,
+        auth: &str,
+        request_id: &str,
+        extra_headers: &[(String, String)],
+        body: String,
+    ) {
+        requests.borrow_mut().push(MockRequest {
+            url: url.to_string(),
+            auth: auth.to_string(),
+            request_id: request_id.to_string(),
+            extra_headers: extra_headers.to_vec(),
+            body,
+        });
+    }
+}
+
+fn http_request_info(status: u16, error_message: String) -> HttpRequestInfo {
+    HttpRequestInfo {
+        http_status_code: status,
+        error_message,
+    }
+}
+
+#[async_trait(?Send)]
+impl Puller for MockServer {
+    async fn pull(
+        &self,
+        pull_req: &PullRequest,
+        url: &str,
+        auth: &str,
+        request_id: &str,
+        extra_headers: &[(String, String)],
+    ) -> Result<(Option<PullResponse>, HttpRequestInfo), PullError> {
+        let body = serde_json::to_string(pull_req).map_err(PullError::SerializeRequestError)?;
+        MockServer::record(&self.pulls, url, auth, request_id, extra_headers, body);
+        Ok(match self.next_pull() {
+            (status, Ok(resp)) => (Some(resp), http_request_info(status, str!(""))),
+            (status, Err(body)) => (None, http_request_info(status, body)),
+        })
+    }
+}
+
+#[async_trait(?Send)]
+impl Pusher for MockServer {
+    async fn push(
+        &self,
+        push_req: &PushRequest,
+        url: &str,
+        auth: &str,
+        request_id: &str,
+        extra_headers: &[(String, String)],
+    ) -> Result<HttpRequestInfo, PushError> {
+        let body = serde_json::to_string(push_req).map_err(PushError::SerializePushError)?;
+        MockServer::record(&self.pushes, url, auth, request_id, extra_headers, body);
+        let (status, error_message) = self.push_result();
+        Ok(http_request_info(status, error_message))
+    }
+}
+
+// Requests to PULL_URL and PUSH_URL are recorded and answered as pull and
+// push are. Any HEAD (a ping) gets a 200 and anything else a 404. Recorded
+// header names are lower case, as http keeps them, and extra_headers holds
+// every header but the ones request_headers always sends.
+#[async_trait(?Send)]
+impl HttpClient for MockServer {
+    async fn request(&self, http_req: Request<String>) -> Result<Response<String>, FetchError> {
+        let url = http_req.uri().to_string();
+        let requests = match url.as_str() {
+            _ if http_req.method() == http::Method::HEAD => {
+                return Ok(http_response(200, str!("")))
+            }
+            MockServer::PULL_URL => &self.pulls,
+            MockServer::PUSH_URL => &self.pushes,
+            _ => return Ok(http_response(404, str!("not found"))),
+        };
+        let header = |name| match http_req.headers().get(name) {
+            Some(value) => value.to_str().unwrap_or("").to_string(),
+            None => str!(""),
+        };
+        let extra_headers: Vec<(String, String)> = http_req
+            .headers()
+            .iter()
+            .filter(|(name, _)| {
+                !matches!(
+                    name.as_str(),
+                    "content-type"
+                        | "authorization"
+                        | "x-replicache-requestid"
+                        | "x-replicache-client-version"
+                )
+            })
+            .map(|(name, value)| (name.to_string(), value.to_str().unwrap_or("").to_string()))
+            .collect();
+        MockServer::record(
+            requests,
+            &url,
+            &header("authorization"),
+            &header("x-replicache-requestid"),
+            &extra_headers,
+            http_req.body().clone(),
+        );
+        Ok(if url == MockServer::PULL_URL {
+            match self.next_pull() {
+                (status, Ok(resp)) => http_response(status, serde_json::to_string(&resp).unwrap()),
+                (status, Err(body)) => http_response(status, body),
+            }
+        } else {
+            let (status, body) = self.push_result();
+            http_response(status, body)
+        })
+    }
+}
+
+fn http_response(status: u16, body: String) -> Response<String> {
+    Response::builder().status(status).body(body).unwrap()
+}
+
+// MockClient is a client over a kv::Store for tests outside the crate, which
+// cannot reach dag or db. It commits local mutations and pushes and pulls
+// them through any Pusher and Puller, eg a MockServer. As in the rest of the
+// test helpers, errors panic.
+pub struct MockClient {
+    client_id: String,
+    store: dag::Store,
+}
+
+impl MockClient {
+    pub async fn new(client_id: &str, kv: Box<dyn kv::Store>) -> MockClient {
+        let store = dag::Store::new(kv);
+        let has_main = store
+            .read(LogContext::new())
+            .await
+            .unwrap()
+            .read()
+            .get_head(db::DEFAULT_HEAD_NAME)
+            .await
+            .unwrap()
+            .is_some();
+        if !has_main {
+            db::init_db(
+                store.write(LogContext::new()).await.unwrap(),
+                db::DEFAULT_HEAD_NAME,
+            )
+            .await
+            .unwrap();
+        }
+        MockClient {
+            client_id: client_id.to_string(),
+            store,
+        }
+    }
+
+    // Commits a local mutation named mutator_name that puts each of entries
+    // and returns its mutation id.
+    pub async fn mutate(
+        &self,
+        mutator_name: &str,
+        args: serde_json::Value,
+        entries: &[(&str, serde_json::Value)],
+    ) -> u64 {
+        let mut w = db::Write::new_local(
+            Whence::Head(str!(db::DEFAULT_HEAD_NAME)),
+            mutator_name.to_string(),
+            args.to_string(),
+            None,
+            self.store.write(LogContext::new()).await.unwrap(),
+        )
+        .await
+        .unwrap();
+        for (key, value) in entries {
+            w.put(
+                LogContext::new(),
+                key.as_bytes().to_vec(),
+                value.to_string().into_bytes(),
+            )
+            .await
+            .unwrap();
+        }
+        let hash = w.commit(db::DEFAULT_HEAD_NAME).await.unwrap();
+        let dag_read = self.store.read(LogContext::new()).await.unwrap();
+        Commit::from_hash(&hash, &dag_read.read())
+            .await
+            .unwrap()
+            .mutation_id()
+    }
+
+    // Returns the value of key at the main head.
+    pub async fn get(&self, key: &str) -> Option<serde_json::Value> {
+        let dag_read = self.store.read(LogContext::new()).await.unwrap();
+        let read = db::OwnedRead::from_whence(Whence::Head(str!(db::DEFAULT_HEAD_NAME)), dag_read)
+            .await
+            .unwrap();
+        let value = read
+            .as_read()
+            .get(key.as_bytes())
+            .map(|value| serde_json::from_slice(value).unwrap());
+        value
+    }
+
+    pub async fn push(&self, pusher: &dyn Pusher, req: TryPushRequest) -> Option<HttpRequestInfo> {
+        push(
+            &request_id::new(&self.client_id),
+            &self.store,
+            LogContext::new(),
+            self.client_id.clone(),
+            pusher,
+            req,
+        )
+        .await
+        .unwrap()
+    }
+
+    // Pulls and, if the pull landed, moves the main head to it. Local
+    // mutations the server has not confirmed yet would have to be replayed
+    // with their mutators, which MockClient does not have, so push them
+    // first.
+    pub async fn pull(
+        &self,
+        puller: &dyn Puller,
+        req: BeginTryPullRequest,
+    ) -> BeginTryPullResponse {
+        let sync_head_name = req.sync_head_name.clone();
+        let resp = begin_pull(
+            self.client_id.clone(),
+            req,
+            puller,
+            request_id::new(&self.client_id),
+            &self.store,
+            LogContext::new(),
+        )
+        .await
+        .unwrap();
+        if !resp.sync_head.is_empty() {
+            let end = maybe_end_try_pull(
+                &self.store,
+                LogContext::new(),
+                MaybeEndTryPullRequest {
+                    request_id: resp.request_id.clone(),
+                    sync_head: resp.sync_head.clone(),
+                    sync_head_name,
+                },
+            )
+            .await
+            .unwrap();
+            assert!(
+                end.replay_mutations.is_empty(),
+                "MockClient cannot replay mutations"
+            );
+        }
+        resp
+    }
+}
@@ -1,4 +1,4 @@
-use super::{patch, ChangedKeysError, PullError, PushError};
+use super::{patch, ChangedKeysError, PullError, PushError, SyncHeadNameError};
 use crate::{
     dag,
     db::{self, ChangedKeysMap},
@@ -7,8 +7,8 @@ use crate::{
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::JsValue;
 
-#[derive(Serialize, Deserialize)]
-#[cfg_attr(test, derive(Clone, Debug, PartialEq))]
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
 pub struct HttpRequestInfo {
     #[serde(rename = "httpStatusCode")]
     pub http_status_code: u16,
@@ -22,6 +22,9 @@ pub struct MaybeEndTryPullRequest {
     pub request_id: String,
     #[serde(rename = "syncHead")]
     pub sync_head: String,
+    // The head the pull being ended landed on. Defaults to SYNC_HEAD_NAME.
+    #[serde(default, rename = "syncHeadName")]
+    pub sync_head_name: Option<String>,
 }
 
 // If replay_mutations is empty then there are no pending mutations to replay
@@ -52,7 +55,7 @@ pub struct ReplayMutation {
     pub original: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct BeginTryPullRequest {
     #[serde(rename = "pullURL")]
     pub pull_url: String,
@@ -60,6 +63,33 @@ pub struct BeginTryPullRequest {
     pub pull_auth: String,
     #[serde(rename = "schemaVersion")]
     pub schema_version: String,
+    // dry_run fetches and summarizes the patch (see patchSummary in the
+    // response) without writing anything or advancing the cookie.
+    #[serde(default, rename = "dryRun")]
+    pub dry_run: bool,
+    // How many index maps to flush at once when committing the pulled
+    // snapshot. The value map is flushed alongside them regardless. Defaults
+    // to db::DEFAULT_INDEX_FLUSH_CONCURRENCY.
+    #[serde(default, rename = "indexFlushConcurrency")]
+    pub index_flush_concurrency: Option<usize>,
+    // The head to pull onto. Defaults to SYNC_HEAD_NAME.
+    #[serde(default, rename = "syncHeadName")]
+    pub sync_head_name: Option<String>,
+    // If set, the pull endpoint is pinged first (see Puller::ping) and the
+    // pull fails with Unreachable unless it answers within this many
+    // milliseconds.
+    #[serde(default, rename = "pingTimeoutMs")]
+    pub ping_timeout_ms: Option<u64>,
+    // If set, the pull fails with PullTimeout unless the puller answers
+    // within this many milliseconds. Without it a hung connection is only
+    // cut short by the puller's own timeout, if it has one.
+    #[serde(default, rename = "timeoutMs")]
+    pub timeout_ms: Option<u64>,
+    // Headers to send with the ping and pull besides our own, as [name,
+    // value] pairs, eg a tenant id an auth proxy needs. One with the name of
+    // one of ours replaces it, except for Content-type.
+    #[serde(default, rename = "extraHeaders")]
+    pub extra_headers: Vec<(String, String)>,
 }
 
 #[derive(Serialize)]
@@ -71,9 +101,18 @@ pub struct BeginTryPullResponse {
     pub sync_head: String,
     #[serde(rename = "requestID")]
     pub request_id: String,
+    // Only present for dry run pulls that got a pull response.
+    #[serde(rename = "patchSummary")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub patch_summary: Option<patch::PatchSummary>,
+    // Set when the server reported that nothing changed since the base
+    // snapshot, either with a 304 or with an empty patch and the same cookie
+    // and last mutation id. Nothing is written in that case.
+    #[serde(default, rename = "notModified")]
+    pub not_modified: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct TryPushRequest {
     #[serde(rename = "pushURL")]
     pub push_url: String,
@@ -81,6 +120,22 @@ pub struct TryPushRequest {
     pub push_auth: String,
     #[serde(rename = "schemaVersion")]
     pub schema_version: String,
+    // window_size caps the number of mutations sent per push request. A
+    // large backlog is sent as consecutive windows, each waiting for the
+    // server to accept the previous one, so a client catching up after a long
+    // time offline does not build one huge request. None sends everything at
+    // once.
+    #[serde(default)]
+    #[serde(rename = "windowSize")]
+    pub window_size: Option<usize>,
+    // If set, the push fails with PushTimeout unless the pusher answers each
+    // request within this many milliseconds.
+    #[serde(default, rename = "timeoutMs")]
+    pub timeout_ms: Option<u64>,
+    // Headers to send with each push request besides our own, as for
+    // BeginTryPullRequest::extra_headers.
+    #[serde(default, rename = "extraHeaders")]
+    pub extra_headers: Vec<(String, String)>,
 }
 
 #[derive(Serialize)]
@@ -98,6 +153,8 @@ pub enum TryPushError {
     InternalNonLocalPendingCommit,
     InvalidPusher(JsValue),
     PushFailed(PushError),
+    // The pusher did not answer within TryPushRequest::timeout_ms.
+    PushTimeout(u64),
     ReadError(dag::Error),
 }
 
@@ -112,15 +169,22 @@ pub enum BeginTryPullError {
     InternalRebuildIndexError(db::CreateIndexError),
     InvalidBaseSnapshotCookie(serde_json::error::Error),
     InvalidPuller(JsValue),
+    InvalidSyncHeadName(SyncHeadNameError),
     LockError(dag::Error),
     MainHeadDisappeared,
     NoBaseSnapshot(db::BaseSnapshotError),
     OverlappingSyncsJSLogInfo, // "JSLogInfo" is a signal to bindings to not log this alarmingly.
     PatchFailed(patch::PatchError),
     PullFailed(PullError),
+    // The puller did not answer within BeginTryPullRequest::timeout_ms.
+    PullTimeout(u64),
     ReadCommitError(db::ReadCommitError),
     ReadError(dag::Error),
+    ResumeError(db::ResumeSnapshotError),
+    SavePullProgressError(dag::Error),
+    SerializePatchError(serde_json::error::Error),
     TimeTravelProhibited(String),
+    Unreachable(String),
 }
 
 #[derive(Debug)]
@@ -131,6 +195,7 @@ pub enum MaybeEndTryPullError {
     GetSyncHeadError(dag::Error),
     InternalArgsUtf8Error(std::string::FromUtf8Error),
     InternalProgrammerError(String),
+    InvalidSyncHeadName(SyncHeadNameError),
     InvalidUtf8(std::string::FromUtf8Error),
     LoadHeadError(prolly::LoadError),
     LoadSyncHeadError(db::FromHashError),
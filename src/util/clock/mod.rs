@@ -0,0 +1,88 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Clock is the source of wall-clock time for anything that records or
+// compares timestamps (eg expiry times or time-ordered ids). Code that needs
+// the time should take a &dyn Clock rather than reading it directly, so that
+// tests can substitute a MockClock and advance time deterministically.
+pub trait Clock {
+    // Milliseconds since the Unix epoch.
+    fn now_ms(&self) -> u64;
+}
+
+// SystemClock reads the real wall clock: Date.now() in the browser and
+// SystemTime elsewhere.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[cfg(target_arch = "wasm32")]
+    fn now_ms(&self) -> u64 {
+        js_sys::Date::now() as u64
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn now_ms(&self) -> u64 {
+        use std::time::{SystemTime, UNIX_EPOCH};
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            // The clock is set before 1970. Pretend it's 1970.
+            .unwrap_or(0)
+    }
+}
+
+// MockClock only moves when told to.
+#[derive(Debug, Default)]
+pub struct MockClock {
+    now_ms: AtomicU64,
+}
+
+impl MockClock {
+    pub fn new(now_ms: u64) -> MockClock {
+        MockClock {
+            now_ms: AtomicU64::new(now_ms),
+        }
+    }
+
+    pub fn set(&self, now_ms: u64) {
+        self.now_ms.store(now_ms, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, ms: u64) {
+        self.now_ms.fetch_add(ms, Ordering::SeqCst);
+    }
+}
+
+impl Clock for MockClock {
+    fn now_ms(&self) -> u64 {
+        self.now_ms.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock() {
+        let clock = MockClock::new(1000);
+        assert_eq!(1000, clock.now_ms());
+        clock.advance(5);
+        assert_eq!(1005, clock.now_ms());
+        clock.set(42);
+        assert_eq!(42, clock.now_ms());
+
+        let clock: &dyn Clock = &clock;
+        assert_eq!(42, clock.now_ms());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_system_clock() {
+        // 2020-01-01T00:00:00Z.
+        let then = 1_577_836_800_000;
+        let first = SystemClock.now_ms();
+        assert!(first > then);
+        assert!(SystemClock.now_ms() >= first);
+    }
+}
@@ -0,0 +1,157 @@
+// Bounds checks for flatbuffers. The flatbuffers crate we use has no
+// verifier, so following an offset past the end of a truncated or garbage
+// buffer panics. Readers of untrusted chunks use these to check the parts of
+// the buffer they are about to follow, so that a damaged chunk is reported as
+// corrupt rather than taking down the whole process.
+
+pub type Result<T> = std::result::Result<T, &'static str>;
+
+fn read_u16(buf: &[u8], pos: usize) -> Result<u16> {
+    let end = pos.checked_add(2).ok_or("offset overflow")?;
+    let bytes = buf.get(pos..end).ok_or("offset out of bounds")?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(buf: &[u8], pos: usize) -> Result<u32> {
+    let end = pos.checked_add(4).ok_or("offset overflow")?;
+    let bytes = buf.get(pos..end).ok_or("offset out of bounds")?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+// Follows the uoffset stored at pos.
+fn follow(buf: &[u8], pos: usize) -> Result<usize> {
+    let offset = read_u32(buf, pos)? as usize;
+    pos.checked_add(offset).ok_or("offset overflow")
+}
+
+// Returns the position of the vtable of the table at pos.
+fn vtable(buf: &[u8], pos: usize) -> Result<usize> {
+    let soffset = read_u32(buf, pos)? as i32 as i64;
+    let vt = pos as i64 - soffset;
+    if vt < 0 || vt as usize >= buf.len() {
+        return Err("vtable out of bounds");
+    }
+    Ok(vt as usize)
+}
+
+// Checks the table at pos and its vtable are within the buffer.
+pub fn table(buf: &[u8], pos: usize) -> Result<()> {
+    let vt = vtable(buf, pos)?;
+    let vt_len = read_u16(buf, vt)? as usize;
+    if vt_len < 4 || vt + vt_len > buf.len() {
+        return Err("invalid vtable");
+    }
+    let table_len = read_u16(buf, vt + 2)? as usize;
+    if pos + table_len > buf.len() {
+        return Err("table out of bounds");
+    }
+    Ok(())
+}
+
+// Checks the root table and returns its position.
+pub fn root(buf: &[u8]) -> Result<usize> {
+    let pos = follow(buf, 0)?;
+    table(buf, pos)?;
+    Ok(pos)
+}
+
+// Returns the position of the field of the table at pos, or None if the
+// field is absent.
+fn field_pos(buf: &[u8], pos: usize, voffset: flatbuffers::VOffsetT) -> Result<Option<usize>> {
+    let voffset = voffset as u16 as usize;
+    let vt = vtable(buf, pos)?;
+    let vt_len = read_u16(buf, vt)? as usize;
+    if voffset + 2 > vt_len {
+        return Ok(None);
+    }
+    match read_u16(buf, vt + voffset)? {
+        0 => Ok(None),
+        offset => Ok(Some(pos + offset as usize)),
+    }
+}
+
+// Returns the position an offset field (a table, vector or string) of the
+// table at pos points to, or None if the field is absent. voffset is the
+// generated VT_ constant for the field. The table must have been checked.
+pub fn field(buf: &[u8], pos: usize, voffset: flatbuffers::VOffsetT) -> Result<Option<usize>> {
+    match field_pos(buf, pos, voffset)? {
+        None => Ok(None),
+        Some(field) => follow(buf, field).map(Some),
+    }
+}
+
+// Checks the scalar field of the table at pos, which is size bytes, is within
+// the buffer if present. The table must have been checked.
+pub fn scalar(buf: &[u8], pos: usize, voffset: flatbuffers::VOffsetT, size: usize) -> Result<()> {
+    let field = match field_pos(buf, pos, voffset)? {
+        None => return Ok(()),
+        Some(field) => field,
+    };
+    match field.checked_add(size) {
+        Some(end) if end <= buf.len() => Ok(()),
+        _ => Err("scalar out of bounds"),
+    }
+}
+
+// Checks the vector at pos, whose elements are elem_size bytes, is within the
+// buffer and returns its length.
+pub fn vector(buf: &[u8], pos: usize, elem_size: usize) -> Result<usize> {
+    let len = read_u32(buf, pos)? as usize;
+    let end = len
+        .checked_mul(elem_size)
+        .and_then(|n| n.checked_add(pos + 4))
+        .ok_or("vector overflow")?;
+    if end > buf.len() {
+        return Err("vector out of bounds");
+    }
+    Ok(len)
+}
+
+// Checks the string at pos is within the buffer and is valid UTF-8.
+pub fn string(buf: &[u8], pos: usize) -> Result<()> {
+    let len = vector(buf, pos, 1)?;
+    std::str::from_utf8(&buf[pos + 4..pos + 4 + len]).map_err(|_| "invalid utf-8")?;
+    Ok(())
+}
+
+// Returns the position of the table at index i of the vector of tables at
+// pos, checking it. The vector must have been checked.
+pub fn vector_table(buf: &[u8], pos: usize, i: usize) -> Result<usize> {
+    let table_pos = follow(buf, pos + 4 + i * 4)?;
+    table(buf, table_pos)?;
+    Ok(table_pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_root() {
+        assert_eq!(Err("offset out of bounds"), root(&[1, 2, 3]));
+        assert_eq!(Err("offset out of bounds"), root(&[200, 0, 0, 0]));
+        // Root at 4, whose vtable would be at 4 - 100.
+        assert_eq!(
+            Err("vtable out of bounds"),
+            root(&[4, 0, 0, 0, 100, 0, 0, 0])
+        );
+    }
+
+    #[test]
+    fn test_vector() {
+        let buf = [2, 0, 0, 0, 1, 2, 3, 4];
+        assert_eq!(Ok(2), vector(&buf, 0, 2));
+        assert_eq!(Err("vector out of bounds"), vector(&buf, 0, 3));
+        assert_eq!(
+            Err("vector overflow"),
+            vector(&[255, 255, 255, 255], 0, usize::MAX)
+        );
+    }
+
+    #[test]
+    fn test_string() {
+        assert_eq!(Ok(()), string(&[2, 0, 0, 0, b'h', b'i'], 0));
+        assert_eq!(Err("vector out of bounds"), string(&[3, 0, 0, 0, b'h'], 0));
+        assert_eq!(Err("invalid utf-8"), string(&[1, 0, 0, 0, 0xff], 0));
+    }
+}
@@ -1,5 +1,8 @@
 #[macro_use]
 pub mod rlog;
+pub mod clock;
+pub mod flatbuf;
+pub mod sortkey;
 mod to_debug;
 pub mod uuid;
 pub mod wasm;
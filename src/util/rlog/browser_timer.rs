@@ -13,13 +13,16 @@ impl Default for Timer {
 
 impl Timer {
     pub fn new() -> Timer {
-        // Consider using Date.now() since we do not use the fractions anyway
         Timer {
             start_ms: performance_now(),
         }
     }
 
-    pub fn elapsed_ms(self) -> u64 {
+    pub fn elapsed_ms(&self) -> u64 {
         (performance_now() - self.start_ms) as u64
     }
+
+    pub fn elapsed_us(&self) -> u64 {
+        ((performance_now() - self.start_ms) * 1000.0) as u64
+    }
 }
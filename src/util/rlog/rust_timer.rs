@@ -17,9 +17,13 @@ impl Timer {
         }
     }
 
-    pub fn elapsed_ms(self) -> u64 {
+    pub fn elapsed_ms(&self) -> u64 {
         self.start.elapsed().as_millis() as u64
     }
+
+    pub fn elapsed_us(&self) -> u64 {
+        self.start.elapsed().as_micros() as u64
+    }
 }
 
 #[cfg(test)]
@@ -32,5 +36,6 @@ mod tests {
         let ten_ms = std::time::Duration::from_millis(10);
         std::thread::sleep(ten_ms);
         assert!(timer.elapsed_ms() > 0);
+        assert!(timer.elapsed_us() >= 10_000);
     }
 }
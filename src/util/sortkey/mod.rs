@@ -0,0 +1,59 @@
+// Keys sort as strings (by byte), both in the kv stores and in the map, so
+// "10" sorts before "2". Apps that key by number or timestamp and want range
+// scans in numeric order should encode the number with encode_u64_key, which
+// produces fixed width keys whose string order is their numeric order. The
+// encoded number can be used as the whole key or as a suffix after a fixed
+// prefix (eg "event/" + encode_u64_key(ts)).
+
+// The length of every key encode_u64_key returns.
+pub const U64_KEY_LEN: usize = 16;
+
+// Returns n as 16 lowercase hex digits.
+pub fn encode_u64_key(n: u64) -> String {
+    format!("{:016x}", n)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DecodeKeyError {
+    InvalidLength(usize),
+    InvalidDigit(char),
+}
+
+// Returns the number encode_u64_key encoded as key.
+pub fn decode_u64_key(key: &str) -> Result<u64, DecodeKeyError> {
+    use DecodeKeyError::*;
+    if key.len() != U64_KEY_LEN {
+        return Err(InvalidLength(key.len()));
+    }
+    key.chars().try_fold(0, |n, c| match c {
+        '0'..='9' | 'a'..='f' => Ok(n << 4 | c.to_digit(16).unwrap() as u64),
+        _ => Err(InvalidDigit(c)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_u64_key() {
+        let nums = [0, 1, 2, 9, 10, 15, 16, 255, 1 << 32, u64::MAX - 1, u64::MAX];
+        let keys: Vec<String> = nums.iter().map(|n| encode_u64_key(*n)).collect();
+        assert_eq!("0000000000000010", keys[6]);
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+        for (n, key) in nums.iter().zip(keys.iter()) {
+            assert_eq!(U64_KEY_LEN, key.len());
+            assert_eq!(Ok(*n), decode_u64_key(key));
+        }
+
+        assert_eq!(Err(DecodeKeyError::InvalidLength(2)), decode_u64_key("10"));
+        assert_eq!(
+            Err(DecodeKeyError::InvalidDigit('g')),
+            decode_u64_key("000000000000000g")
+        );
+        assert_eq!(
+            Err(DecodeKeyError::InvalidDigit('A')),
+            decode_u64_key("000000000000000A")
+        );
+    }
+}
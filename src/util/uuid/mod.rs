@@ -1,4 +1,6 @@
+use crate::util::clock::{Clock, SystemClock};
 use std::char;
+use std::fmt;
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen]
@@ -9,27 +11,85 @@ extern "C" {
 
 #[derive(Debug)]
 pub enum UuidError {
+    InvalidFormat(String),
     NoCryptoGetRandomValues(JsValue),
 }
 
+// Uuid is a string that is known to be a well-formed v4 or v7 UUID, as made
+// by uuid and uuidv7.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Uuid(String);
+
+impl Uuid {
+    // Returns a new random (v4) UUID.
+    pub fn new() -> Result<Uuid, UuidError> {
+        uuid().map(Uuid)
+    }
+
+    // Checks that s has the layout of the UUIDs we make: hex digits grouped
+    // 8-4-4-4-12, a version of 4 or 7 and a variant of 8, 9, a or b. Hex
+    // digits may be either case.
+    pub fn parse(s: &str) -> Result<Uuid, UuidError> {
+        let bytes = s.as_bytes();
+        let valid = bytes.len() == UUID_V4_FORMAT.len()
+            && UUID_V4_FORMAT
+                .iter()
+                .zip(bytes)
+                .all(|(kind, b)| match kind {
+                    UuidElements::Random09AF => b.is_ascii_hexdigit(),
+                    UuidElements::Random89AB => b"89abAB".contains(b),
+                    UuidElements::Version => *b == b'4' || *b == b'7',
+                    UuidElements::Hyphen => *b == b'-',
+                });
+        if !valid {
+            return Err(UuidError::InvalidFormat(s.to_string()));
+        }
+        Ok(Uuid(s.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 pub fn uuid() -> Result<String, UuidError> {
     let mut numbers = [0u8; 36];
     make_random_numbers(&mut numbers)?;
     Ok(uuid_from_numbers(&numbers))
 }
 
+// uuidv7 returns a version 7 UUID: the current Unix time in milliseconds in
+// the first 48 bits followed by random bits, so that ids made later sort
+// after ones made earlier (within the same millisecond they are in random
+// order).
+pub fn uuidv7() -> Result<String, UuidError> {
+    uuidv7_with_clock(&SystemClock)
+}
+
+// uuidv7_with_clock is uuidv7 with the time read from clock.
+pub fn uuidv7_with_clock(clock: &dyn Clock) -> Result<String, UuidError> {
+    let mut numbers = [0u8; 36];
+    make_random_numbers(&mut numbers)?;
+    Ok(uuidv7_from_numbers(clock.now_ms(), &numbers))
+}
+
 #[cfg(target_arch = "wasm32")]
 pub fn make_random_numbers(numbers: &mut [u8]) -> Result<(), UuidError> {
     get_random_values(numbers).map_err(UuidError::NoCryptoGetRandomValues)
 }
 
+// thread_rng is already cached per thread, so all there is to save is
+// filling the slice in one call rather than a byte at a time.
 #[cfg(not(target_arch = "wasm32"))]
 pub fn make_random_numbers(numbers: &mut [u8]) -> Result<(), UuidError> {
     use rand::Rng;
-    let mut rng = rand::thread_rng();
-    for v in numbers.iter_mut() {
-        *v = rng.gen();
-    }
+    rand::thread_rng().fill(numbers);
     Ok(())
 }
 
@@ -82,6 +142,31 @@ const UUID_V4_FORMAT: [UuidElements; 36] = [
 const ERROR_MAKE_CHAR: &str = "Error in making char";
 
 pub fn uuid_from_numbers(random_numbers: &[u8; 36]) -> String {
+    uuid_from_numbers_with_version(random_numbers, '4')
+}
+
+// The first 48 bits of a v7 UUID are its timestamp, in the first 12 hex
+// digits of the format (which are all Random09AF).
+const UUID_V7_TIMESTAMP_DIGITS: usize = 12;
+
+pub fn uuidv7_from_numbers(now_ms: u64, random_numbers: &[u8; 36]) -> String {
+    let mut numbers = *random_numbers;
+    let digits = UUID_V4_FORMAT
+        .iter()
+        .enumerate()
+        .filter(|(_, kind)| matches!(kind, UuidElements::Random09AF))
+        .take(UUID_V7_TIMESTAMP_DIGITS)
+        .map(|(i, _)| i);
+    for (n, i) in digits.enumerate() {
+        let shift = 4 * (UUID_V7_TIMESTAMP_DIGITS - 1 - n);
+        numbers[i] = (now_ms >> shift) as u8 & 0b1111;
+    }
+    uuid_from_numbers_with_version(&numbers, '7')
+}
+
+// Formats random_numbers as a UUID of the given version, using the low bits
+// of random_numbers[i] for the i-th character.
+pub fn uuid_from_numbers_with_version(random_numbers: &[u8; 36], version: char) -> String {
     UUID_V4_FORMAT
         .iter()
         .enumerate()
@@ -92,7 +177,7 @@ pub fn uuid_from_numbers(random_numbers: &[u8; 36]) -> String {
             UuidElements::Random89AB => {
                 char::from_digit((random_numbers[i] & 0b11) as u32 + 8, 16).expect(ERROR_MAKE_CHAR)
             }
-            UuidElements::Version => '4',
+            UuidElements::Version => version,
             UuidElements::Hyphen => '-',
         })
         .collect()
@@ -101,6 +186,7 @@ pub fn uuid_from_numbers(random_numbers: &[u8; 36]) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::util::clock::MockClock;
     use regex::Regex;
 
     #[test]
@@ -113,4 +199,90 @@ mod tests {
 
         assert!(re.is_match(&uuid));
     }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_make_random_numbers() {
+        let mut a = [0u8; 36];
+        let mut b = [0u8; 36];
+        make_random_numbers(&mut a).unwrap();
+        make_random_numbers(&mut b).unwrap();
+        assert_ne!(a, b);
+        // Every byte is random, not just the first few.
+        assert!(a[32..] != [0u8; 4] || b[32..] != [0u8; 4]);
+        make_random_numbers(&mut []).unwrap();
+    }
+
+    #[test]
+    fn test_parse() {
+        let valid = |s: &str| assert_eq!(s, Uuid::parse(s).unwrap().as_str());
+        let invalid = |s: &str| match Uuid::parse(s) {
+            Err(UuidError::InvalidFormat(got)) => assert_eq!(s, got),
+            res => panic!("{}: {:?}", s, res),
+        };
+
+        valid("00000000-0000-4000-8000-000000000000");
+        valid("0123abcd-ef01-4a23-b456-789abcdef012");
+        valid("0123ABCD-EF01-4A23-9456-789ABCDEF012");
+        valid("01234567-89ab-7000-a000-000000000000");
+        valid(Uuid::new().unwrap().as_str());
+        valid(&uuidv7().unwrap());
+        assert_eq!(
+            "00000000-0000-4000-8000-000000000000",
+            format!(
+                "{}",
+                Uuid::parse("00000000-0000-4000-8000-000000000000").unwrap()
+            )
+        );
+
+        // Wrong length.
+        invalid("");
+        invalid("00000000-0000-4000-8000-00000000000");
+        invalid("00000000-0000-4000-8000-0000000000000");
+        // Hyphens in the wrong place or missing, or non-hex digits.
+        invalid("0000000-00000-4000-8000-000000000000");
+        invalid("00000000000004000800000000000000000a");
+        invalid("0000000g-0000-4000-8000-000000000000");
+        invalid("00000000-0000-4000-8000-0000000000\u{e9}");
+        // Bad version.
+        invalid("00000000-0000-1000-8000-000000000000");
+        invalid("00000000-0000-0000-8000-000000000000");
+        // Bad variant.
+        invalid("00000000-0000-4000-0000-000000000000");
+        invalid("00000000-0000-4000-c000-000000000000");
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_uuidv7() {
+        assert_eq!(
+            "01234567-89ab-7000-8000-000000000000",
+            uuidv7_from_numbers(0x0123_4567_89ab, &[0u8; 36])
+        );
+        // Only the low 48 bits of the time are kept and the random bits do
+        // not leak into the timestamp, version or variant.
+        assert_eq!(
+            "ffffffff-ffff-7fff-bfff-ffffffffffff",
+            uuidv7_from_numbers(u64::MAX, &[0xffu8; 36])
+        );
+        assert_eq!(
+            "00000000-0001-7fff-bfff-ffffffffffff",
+            uuidv7_from_numbers(1 << 48 | 1, &[0xffu8; 36])
+        );
+
+        let re =
+            Regex::new(r"^[0-9a-f]{8}-[0-9a-f]{4}-7[0-9a-f]{3}-[89ab][0-9a-f]{3}-[0-9a-f]{12}$")
+                .unwrap();
+        assert!(re.is_match(&uuidv7().unwrap()));
+
+        let clock = MockClock::new(0x0123_4567_89ab);
+        let first = uuidv7_with_clock(&clock).unwrap();
+        clock.advance(1);
+        let second = uuidv7_with_clock(&clock).unwrap();
+        assert!(re.is_match(&first));
+        assert!(re.is_match(&second));
+        assert_eq!("01234567-89ab", &first[..13]);
+        assert_eq!("01234567-89ac", &second[..13]);
+        assert!(first < second);
+    }
 }
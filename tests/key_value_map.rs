@@ -0,0 +1,21 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use replicache_client::db::KeyValueMap;
+use replicache_client::kv::memstore::MemStore;
+use replicache_client::util::rlog::LogContext;
+
+#[async_std::test]
+async fn test_key_value_map() {
+    let store = MemStore::new();
+
+    let mut m = KeyValueMap::open(&store, "kv", LogContext::new())
+        .await
+        .unwrap();
+    m.put(b"a", b"1").await.unwrap();
+    m.commit().await.unwrap();
+
+    let m = KeyValueMap::open(&store, "kv", LogContext::new())
+        .await
+        .unwrap();
+    assert_eq!(Some(&b"1"[..]), m.get(b"a"));
+}
@@ -0,0 +1,84 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use replicache_client::kv::memstore::MemStore;
+use replicache_client::sync::test_helpers::{MockClient, MockPullResponse, MockServer};
+use replicache_client::sync::{BeginTryPullRequest, FetchPuller, FetchPusher, TryPushRequest};
+use serde_json::json;
+use str_macro::str;
+
+// Pushes a local mutation and pulls its result back over HTTP, checking the
+// requests the client sends.
+#[async_std::test]
+async fn test_push_then_pull() {
+    let client = MockClient::new("client_id", Box::new(MemStore::new())).await;
+    let server = MockServer::new();
+    let id = client
+        .mutate("add", json!({"n": 1}), &[("a", json!(1))])
+        .await;
+    assert_eq!(Some(json!(1)), client.get("a").await);
+
+    let info = client
+        .push(
+            &FetchPusher::new(&server),
+            TryPushRequest {
+                push_url: str!(MockServer::PUSH_URL),
+                push_auth: str!("push_auth"),
+                schema_version: str!("1"),
+                extra_headers: vec![(str!("X-Tenant"), str!("t1"))],
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(200, info.http_status_code);
+    let pushes = server.pushes();
+    assert_eq!(1, pushes.len());
+    assert_eq!(MockServer::PUSH_URL, pushes[0].url);
+    assert_eq!("push_auth", pushes[0].auth);
+    assert_eq!(
+        vec![(str!("x-tenant"), str!("t1"))],
+        pushes[0].extra_headers
+    );
+    let body: serde_json::Value = serde_json::from_str(&pushes[0].body).unwrap();
+    assert_eq!(json!("client_id"), body["clientID"]);
+    assert_eq!(
+        json!([{"id": id, "name": "add", "args": {"n": 1}}]),
+        body["mutations"]
+    );
+
+    // The server confirms the mutation and patches in its result.
+    server.add_pull_response(MockPullResponse::Ok(
+        serde_json::from_value(json!({
+            "cookie": "c1",
+            "lastMutationID": id,
+            "patch": [
+                {"op": "put", "key": "a", "value": 2},
+                {"op": "put", "key": "b", "value": "x"},
+            ],
+        }))
+        .unwrap(),
+    ));
+    let resp = client
+        .pull(
+            &FetchPuller::new(&server),
+            BeginTryPullRequest {
+                pull_url: str!(MockServer::PULL_URL),
+                pull_auth: str!("pull_auth"),
+                schema_version: str!("1"),
+                ..Default::default()
+            },
+        )
+        .await;
+    assert_eq!(200, resp.http_request_info.http_status_code);
+    assert_ne!("", resp.sync_head);
+    assert_eq!(Some(json!(2)), client.get("a").await);
+    assert_eq!(Some(json!("x")), client.get("b").await);
+
+    let pulls = server.pulls();
+    assert_eq!(1, pulls.len());
+    assert_eq!("pull_auth", pulls[0].auth);
+    assert_eq!(resp.request_id, pulls[0].request_id);
+    let body: serde_json::Value = serde_json::from_str(&pulls[0].body).unwrap();
+    assert_eq!(json!(0), body["lastMutationID"]);
+    assert_eq!(json!("1"), body["schemaVersion"]);
+}
@@ -78,6 +78,7 @@ async fn open_transaction(
             name: fn_name,
             args: Some(serde_json::to_string(&args).unwrap()),
             rebase_opts,
+            mode: None,
         },
     )
     .await
@@ -113,7 +114,7 @@ async fn has(db_name: &str, txn_id: u32, key: &str) -> bool {
         db_name,
         Rpc::Has,
         &HasRequest {
-            transaction_id: txn_id,
+            transaction_id: Some(txn_id),
             key: key.to_string(),
         },
     )
@@ -127,7 +128,7 @@ async fn get(db_name: &str, txn_id: u32, key: &str) -> Option<String> {
         db_name,
         Rpc::Get,
         &GetRequest {
-            transaction_id: txn_id,
+            transaction_id: Some(txn_id),
             key: key.to_string(),
         },
     )
@@ -193,6 +194,7 @@ async fn commit(
         &CommitTransactionRequest {
             transaction_id,
             generate_changed_keys,
+            generate_changed_values: false,
         },
     )
     .await
@@ -284,6 +286,176 @@ async fn test_open_close() {
     );
 }
 
+#[wasm_bindgen_test]
+async fn test_open_refcount_and_close_all() {
+    let open_req = OpenRequest {};
+    let db = &random_db();
+    let db2 = &random_db();
+    let open_dbs = || async {
+        dispatch::<_, String>("", Rpc::Debug, "open_dbs")
+            .await
+            .unwrap()
+    };
+
+    let client_id = dispatch::<_, String>(db, Rpc::Open, &open_req)
+        .await
+        .unwrap();
+    assert_eq!(
+        client_id,
+        dispatch::<_, String>(db, Rpc::Open, &open_req)
+            .await
+            .unwrap()
+    );
+
+    // A repeated open has to ask for the same store and backend.
+    assert_eq!(
+        dispatch::<_, String>(db, Rpc::Open, json!({"backend": "memory"}))
+            .await
+            .unwrap_err(),
+        format!(
+            "\"{}\" is already open with a different store or backend",
+            db
+        )
+    );
+
+    // The first close only drops one reference.
+    dispatch::<_, String>(db, Rpc::Close, "").await.unwrap();
+    assert!(open_dbs().await.contains(db.as_str()));
+    dispatch::<_, String>(db, Rpc::Close, "").await.unwrap();
+    assert!(!open_dbs().await.contains(db.as_str()));
+
+    dispatch::<_, String>(db, Rpc::Open, &open_req)
+        .await
+        .unwrap();
+    dispatch::<_, String>(db, Rpc::Open, &open_req)
+        .await
+        .unwrap();
+    dispatch::<_, String>(db2, Rpc::Open, &open_req)
+        .await
+        .unwrap();
+    assert_eq!(
+        dispatch::<_, String>("", Rpc::CloseAll, "").await.unwrap(),
+        ""
+    );
+    assert_eq!(open_dbs().await, "[]");
+}
+
+#[wasm_bindgen_test]
+async fn test_debug_open_transactions() {
+    use std::collections::HashMap;
+
+    // A write waits for open reads on the same store, so the read and the
+    // write are opened on separate connections.
+    let read_db = &random_db();
+    let write_db = &random_db();
+    for db in &[read_db, write_db] {
+        dispatch::<_, String>(db, Rpc::Open, &OpenRequest {})
+            .await
+            .unwrap();
+    }
+    let open_transactions = || async {
+        dispatch::<_, HashMap<String, Vec<OpenTransactionInfo>>>(
+            "",
+            Rpc::Debug,
+            "open_transactions",
+        )
+        .await
+        .unwrap()
+    };
+    let modes = |txns: &HashMap<String, Vec<OpenTransactionInfo>>, db: &str| {
+        txns[db]
+            .iter()
+            .map(|t| (t.transaction_id, t.mode))
+            .collect::<Vec<_>>()
+    };
+    let txns = open_transactions().await;
+    assert_eq!(modes(&txns, read_db), vec![]);
+    assert_eq!(modes(&txns, write_db), vec![]);
+
+    let read_id = open_transaction(read_db, None, None, None)
+        .await
+        .transaction_id;
+    let write_id = open_transaction(write_db, "foo".to_string().into(), Some(json!([])), None)
+        .await
+        .transaction_id;
+    let txns = open_transactions().await;
+    assert_eq!(
+        modes(&txns, read_db),
+        vec![(read_id, TransactionMode::Read)]
+    );
+    assert_eq!(
+        modes(&txns, write_db),
+        vec![(write_id, TransactionMode::Write)]
+    );
+
+    let _: CloseTransactionResponse = dispatch(
+        read_db,
+        Rpc::CloseTransaction,
+        &CloseTransactionRequest {
+            transaction_id: read_id,
+        },
+    )
+    .await
+    .unwrap();
+    let txns = open_transactions().await;
+    assert_eq!(modes(&txns, read_db), vec![]);
+    assert_eq!(
+        modes(&txns, write_db),
+        vec![(write_id, TransactionMode::Write)]
+    );
+
+    let _: CloseTransactionResponse = dispatch(
+        write_db,
+        Rpc::CloseTransaction,
+        &CloseTransactionRequest {
+            transaction_id: write_id,
+        },
+    )
+    .await
+    .unwrap();
+    for db in &[read_db, write_db] {
+        dispatch::<_, String>(db, Rpc::Close, "").await.unwrap();
+    }
+}
+
+#[wasm_bindgen_test]
+async fn test_verify() {
+    let db = &random_db();
+    dispatch::<_, String>(db, Rpc::Open, &OpenRequest {})
+        .await
+        .unwrap();
+    let report = dispatch::<_, serde_json::Value>(db, Rpc::Verify, json!({"repair": true}))
+        .await
+        .unwrap();
+    assert_eq!(
+        report,
+        json!({"heads": [{"headName": "main", "corrupt": [], "repaired": false}]})
+    );
+    dispatch::<_, String>(db, Rpc::Close, "").await.unwrap();
+}
+
+#[wasm_bindgen_test]
+async fn test_open_backend() {
+    let db = &random_db();
+    assert_eq!(
+        dispatch::<_, String>(db, Rpc::Open, json!({"backend": "js"}))
+            .await
+            .unwrap_err(),
+        "backend \"js\" requires a store"
+    );
+    assert_eq!(
+        dispatch::<_, String>(db, Rpc::Open, json!({"backend": "bogus"}))
+            .await
+            .unwrap_err(),
+        "Unknown backend \"bogus\""
+    );
+    let client_id = dispatch::<_, String>(db, Rpc::Open, json!({"backend": "memory"}))
+        .await
+        .unwrap();
+    assert!(is_valid_client_id(&client_id));
+    assert_eq!(dispatch::<_, String>(db, Rpc::Close, "").await.unwrap(), "");
+}
+
 #[wasm_bindgen_test]
 async fn test_concurrency_within_a_read_tx() {
     let db = &random_db();
@@ -556,6 +728,143 @@ async fn test_get_put_del() {
     close(db, txn_id).await;
 
     assert_eq!(dispatch::<_, String>(db, Rpc::Close, "").await.unwrap(), "");
+
+    // Once closed, requests reject as not open rather than resolving as
+    // missing keys.
+    let err = dispatch::<_, GetResponse>(
+        db,
+        Rpc::Get,
+        GetRequest {
+            transaction_id: None,
+            key: str!("Hello"),
+        },
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(format!("\"{}\" not open", db), js_error_message(&err));
+    assert_eq!(
+        Some(str!("NotOpen")),
+        js_sys::Reflect::get(&err, &JsValue::from_str("kind"))
+            .unwrap()
+            .as_string()
+    );
+}
+
+#[wasm_bindgen_test]
+async fn test_transaction_mode() {
+    let db = &random_db();
+    dispatch::<_, String>(db, Rpc::Open, OpenRequest {})
+        .await
+        .unwrap();
+    let open = |name: Option<&str>, mode| {
+        dispatch::<_, OpenTransactionResponse>(
+            db,
+            Rpc::OpenTransaction,
+            OpenTransactionRequest {
+                name: name.map(str::to_string),
+                args: Some(str!("[]")),
+                rebase_opts: None,
+                mode,
+            },
+        )
+    };
+
+    // The mode defaults from whether a mutator is named. The read is closed
+    // before the write is opened, since the write waits for open reads.
+    let read = open(None, None).await.unwrap();
+    assert_eq!(TransactionMode::Read, read.mode);
+    close(db, read.transaction_id).await;
+    let write = open(Some("foo"), None).await.unwrap();
+    assert_eq!(TransactionMode::Write, write.mode);
+    put(db, write.transaction_id, "k", "v").await;
+    commit(db, write.transaction_id, false).await;
+
+    // Read transactions reject writes.
+    let read = open(None, Some(TransactionMode::Read)).await.unwrap();
+    assert_eq!(TransactionMode::Read, read.mode);
+    assert_eq!(get(db, read.transaction_id, "k").await, Some(str!("v")));
+    let err = dispatch::<_, PutResponse>(
+        db,
+        Rpc::Put,
+        PutRequest {
+            transaction_id: read.transaction_id,
+            key: str!("k"),
+            value: str!("w"),
+        },
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        Some(format!("TransactionIsReadOnly({})", read.transaction_id)),
+        err.as_string()
+    );
+    close(db, read.transaction_id).await;
+
+    // The mode must agree with the name.
+    assert!(open(Some("foo"), Some(TransactionMode::Read))
+        .await
+        .is_err());
+    assert!(open(None, Some(TransactionMode::Write)).await.is_err());
+
+    dispatch::<_, String>(db, Rpc::Close, "").await.unwrap();
+}
+
+#[wasm_bindgen_test]
+async fn test_implicit_transactions() {
+    let db = &random_db();
+    dispatch::<_, String>(db, Rpc::Open, OpenRequest {})
+        .await
+        .unwrap();
+
+    let implicit_has = |key: &'static str| async move {
+        dispatch::<_, HasResponse>(
+            db,
+            Rpc::Has,
+            HasRequest {
+                transaction_id: None,
+                key: key.to_string(),
+            },
+        )
+        .await
+        .unwrap()
+        .has
+    };
+    let implicit_get = |key: &'static str| async move {
+        dispatch::<_, GetResponse>(
+            db,
+            Rpc::Get,
+            GetRequest {
+                transaction_id: None,
+                key: key.to_string(),
+            },
+        )
+        .await
+        .unwrap()
+        .value
+    };
+
+    assert!(!implicit_has("foo").await);
+    assert_eq!(implicit_get("foo").await, None);
+    let txn_id = open_transaction(db, "foo".to_string().into(), Some(json!([])), None)
+        .await
+        .transaction_id;
+    put(db, txn_id, "foo", "\"bar\"").await;
+    commit(db, txn_id, false).await;
+    assert!(implicit_has("foo").await);
+    assert_eq!(implicit_get("foo").await, Some(str!("\"bar\"")));
+
+    // Writes have to be made in a transaction so that they are mutations.
+    let err = dispatch::<_, PutResponse>(db, Rpc::Put, json!({"key": "foo", "value": "\"baz\""}))
+        .await
+        .unwrap_err();
+    assert_eq!(Some(str!("TransactionIdRequired")), err.as_string());
+    let err = dispatch::<_, DelResponse>(db, Rpc::Del, json!({"key": "foo"}))
+        .await
+        .unwrap_err();
+    assert_eq!(Some(str!("TransactionIdRequired")), err.as_string());
+    assert_eq!(implicit_get("foo").await, Some(str!("\"bar\"")));
+
+    assert_eq!(dispatch::<_, String>(db, Rpc::Close, "").await.unwrap(), "");
 }
 
 #[wasm_bindgen_test]
@@ -1391,6 +1700,20 @@ async fn test_set_log_level() {
     assert_eq!("UnknownLogLevel(\"BOOM\")", js_error_message(&response));
     assert_eq!(log::LevelFilter::Error, log::max_level());
 
+    // Malformed requests name the offending field.
+    #[derive(Serialize)]
+    struct Empty {}
+    let response = dispatch::<_, SetLogLevelResponse>(db, Rpc::SetLogLevel, Empty {})
+        .await
+        .unwrap_err();
+    let prop = |name: &str| {
+        js_sys::Reflect::get(&response, &JsValue::from_str(name))
+            .unwrap()
+            .as_string()
+    };
+    assert_eq!(Some(str!("InvalidJson")), prop("kind"));
+    assert_eq!(Some(str!("level")), prop("field"));
+
     log::set_max_level(level);
     dispatch::<_, String>(db, Rpc::Close, "").await.unwrap();
 }
@@ -1408,3 +1731,34 @@ async fn test_make_random_numbers() {
     let mut numbers = [0u8; 4];
     make_random_numbers(&mut numbers).unwrap();
 }
+
+#[wasm_bindgen_test]
+fn test_store_error_from_dom_exception() {
+    use replicache_client::kv::StoreError;
+
+    let from_dom_exception = |name| {
+        let e = web_sys::DomException::new_with_message_and_name("message", name).unwrap();
+        StoreError::from(JsValue::from(e))
+    };
+    assert_eq!(
+        StoreError::QuotaExceeded,
+        from_dom_exception("QuotaExceededError")
+    );
+    assert_eq!(
+        StoreError::TransactionClosed,
+        from_dom_exception("TransactionInactiveError")
+    );
+    assert_eq!(
+        StoreError::TransactionAborted,
+        from_dom_exception("AbortError")
+    );
+    // Anything else keeps its description.
+    assert!(matches!(
+        from_dom_exception("NotFoundError"),
+        StoreError::Str(s) if s.contains("NotFoundError")
+    ));
+    assert!(matches!(
+        StoreError::from(JsValue::from_str("boom")),
+        StoreError::Str(s) if s.contains("boom")
+    ));
+}